@@ -1,5 +1,7 @@
-@abc = private unnamed_addr constant [17 x i8] c"this is a string\00", align 1
+@str.0 = private unnamed_addr constant [17 x i8] c"this is a string\00", align 1
 define i32 @main() {
 entry:
+%var.abc.1 = alloca i8*
+store i8* getelementptr inbounds ([17 x i8], [17 x i8]* @str.0, i32 0, i32 0), i8** %var.abc.1
 ret i32 0
 }
\ No newline at end of file
@@ -1,7 +1,8 @@
-@var0 = private unnamed_addr constant [12 x i8] c"hello world\00", align 1
+@str.0 = private unnamed_addr constant [12 x i8] c"hello world\00", align 1
+@str.1 = private unnamed_addr constant [4 x i8] c"%s\0A\00", align 1
 declare i32 @printf(i8*, ...)
 define i32 @main() {
 entry:
-call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([12 x i8], [12 x i8]* @var0, i32 0, i32 0))
+call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([4 x i8], [4 x i8]* @str.1, i32 0, i32 0), i8* getelementptr inbounds ([12 x i8], [12 x i8]* @str.0, i32 0, i32 0))
 ret i32 0
 }
\ No newline at end of file
@@ -1,7 +1,7 @@
-@var0 = private constant [4 x i8] c"%d\0A\00"
+@str.0 = private unnamed_addr constant [4 x i8] c"%d\0A\00", align 1
 declare i32 @printf(i8*, ...)
 define i32 @main() {
 entry:
-call i32 (i8*, ...) @printf(i8* @var0, i32 777)
+call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([4 x i8], [4 x i8]* @str.0, i32 0, i32 0), i32 777)
 ret i32 0
 }
\ No newline at end of file
@@ -1,8 +1,11 @@
-@var0 = private constant [4 x i8] c"%d\0A\00"
+@str.0 = private unnamed_addr constant [4 x i8] c"%d\0A\00", align 1
 declare i32 @printf(i8*, ...)
-@a = private constant i32 888
 define i32 @main() {
 entry:
-call i32 (i8*, ...) @printf(i8* @var0, i32 888)
+%var.a.1 = alloca i32
+store i32 888, i32* %var.a.1
+store i32 888, i32* %var.a.1
+%load.2 = load i32, i32* %var.a.1
+call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([4 x i8], [4 x i8]* @str.0, i32 0, i32 0), i32 %load.2)
 ret i32 0
 }
\ No newline at end of file
@@ -1,8 +1,12 @@
-@var0 = private unnamed_addr constant [4 x i8] c"abc\00", align 1
+@str.0 = private unnamed_addr constant [4 x i8] c"abc\00", align 1
+@str.1 = private unnamed_addr constant [4 x i8] c"%s\0A\00", align 1
 declare i32 @printf(i8*, ...)
-@a = private unnamed_addr constant [4 x i8] c"abc\00", align 1
 define i32 @main() {
 entry:
-call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([4 x i8], [4 x i8]* @var0, i32 0, i32 0))
+%var.a.1 = alloca i8*
+store i8* getelementptr inbounds ([4 x i8], [4 x i8]* @str.0, i32 0, i32 0), i8** %var.a.1
+store i8* getelementptr inbounds ([4 x i8], [4 x i8]* @str.0, i32 0, i32 0), i8** %var.a.1
+%load.2 = load i8*, i8** %var.a.1
+call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([4 x i8], [4 x i8]* @str.1, i32 0, i32 0), i8* %load.2)
 ret i32 0
 }
\ No newline at end of file
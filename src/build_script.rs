@@ -1,31 +1,54 @@
-use std::{collections::{HashMap, VecDeque}, fs, path::Path, process::Command};
-use crate::{interpreter::{Primitive, Type}, llvm_ir::get_buffer, parse::Statement};
+use std::{collections::{HashMap, VecDeque}, fs, path::{Path, PathBuf}, process::Command};
+use crate::{dag::Dag, diagnostic::Diagnostic, llvm_ir::compile_program, parse::Statement};
 
-pub fn run(statements: &VecDeque<Statement>, variable_map: HashMap<String, (Primitive, Type)>) {
-    fs::write("build/llvm.ll", get_buffer(statements, variable_map)).expect("Unable to write file");
-    let exe_path = Path::new("build/main.exe");
-    if exe_path.exists() {
-        fs::remove_file(exe_path).expect("go fuck yourself");
+//Compiles every block of a program to a native executable, or only to LLVM IR.
+//Args: output: &Path - where to write the executable, or the .ll file when emit_llvm is set.
+//Errors: If the IR cannot be generated or written, or the LLVM toolchain fails.
+pub fn build(dag: &Dag, programs: &HashMap<String, VecDeque<Statement>>, output: &Path, emit_llvm: bool) -> Result<(), Diagnostic> {
+    let ir = compile_program(dag, programs)?;
+    if emit_llvm {
+        return write_file(output, &ir);
     }
-    let clang = Command::new("clang")
-                        .arg("build/llvm.ll")
-                        .arg("-o")
-                        .arg("build/main.exe")
-                        .output()
-                        .expect("Failed to execute command");
-    if !clang.status.success() {
-        let error = String::from_utf8_lossy(&clang.stderr);
-        eprintln!("Error: {}", error);
-    }
-    let execute = Command::new("./build/main.exe")
-                  .output()
-                  .expect("Failed to execute command");
-    if execute.status.success() {
-        let output: std::borrow::Cow<str> = String::from_utf8_lossy(&execute.stdout);
-        println!("Compiled Output:\n{}", output);
+    //The intermediate files get a directory of their own, so neither can be the output, whatever it is named.
+    let temp = std::env::temp_dir().join(format!("sarateese-build-{}", std::process::id()));
+    fs::create_dir_all(&temp).map_err(|err| Diagnostic::new(38, format!("Error writing file {}: {}", temp.display(), err)))?;
+    let ir_path = temp.join("program.ll");
+    let object_path = temp.join("program.o");
+    write_file(&ir_path, &ir)?;
+    //clang can do it in one step, otherwise llc and the system C compiler do the same job.
+    let result = if tool_available("clang") {
+        run_tool(Command::new("clang").arg(&ir_path).arg("-o").arg(output).arg("-lpthread"))
+    } else {
+        run_tool(Command::new("llc").arg("-filetype=obj").arg("-relocation-model=pic").arg(&ir_path).arg("-o").arg(&object_path))
+            .and_then(|_| run_tool(Command::new("cc").arg(&object_path).arg("-o").arg(output).arg("-lpthread")))
+    };
+    let _ = fs::remove_dir_all(&temp);
+    result
+}
+
+//The default place to write a build of source_file: its name without the extension, or with .ll for IR.
+pub fn default_output(source_file: &str, emit_llvm: bool) -> PathBuf {
+    let stem = Path::new(source_file).file_stem().map(PathBuf::from).unwrap_or_else(|| PathBuf::from("a"));
+    if emit_llvm {
+        stem.with_extension("ll")
     } else {
-        let error = String::from_utf8_lossy(&execute.stderr);
-        println!("Std out: {}", String::from_utf8_lossy(&execute.stdout));
-        eprintln!("Error: {}", error);
+        stem
+    }
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<(), Diagnostic> {
+    fs::write(path, contents).map_err(|err| Diagnostic::new(38, format!("Error writing file {}: {}", path.display(), err)))
+}
+
+fn tool_available(name: &str) -> bool {
+    Command::new(name).arg("--version").output().is_ok_and(|output| output.status.success())
+}
+
+fn run_tool(command: &mut Command) -> Result<(), Diagnostic> {
+    let name = command.get_program().to_string_lossy().to_string();
+    let output = command.output().map_err(|err| Diagnostic::new(39, format!("Failed to run {}: {}", name, err)))?;
+    if !output.status.success() {
+        return Err(Diagnostic::new(40, format!("{} failed: {}", name, String::from_utf8_lossy(&output.stderr).trim())));
     }
+    Ok(())
 }
@@ -0,0 +1,368 @@
+use std::sync::LazyLock;
+use rand::Rng;
+use crate::{diagnostic::{Diagnostic, Span}, interpreter::{find_key, missing_key, Primitive, Structs, Type}, llvm_ir::{Codegen, Value}, parse::{Expression, Struct}};
+
+type Run = fn(&mut [Primitive]) -> Result<Option<Primitive>, Diagnostic>;
+type Check = fn(&[Expression]) -> Result<(), Diagnostic>;
+type Lower = fn(&mut Codegen, &[Value]) -> Result<Option<Value>, Diagnostic>;
+
+//What a builtin accepts in one argument.
+#[derive(Clone)]
+pub enum Param {
+    Is(Type),
+    Any,
+    //i32, i64, f32 or f64, the types a cast to a number converts.
+    Number,
+    //Anything but an array, struct or map, the types string converts.
+    Scalar,
+    Array,
+    Map,
+    //An array, a map or a String.
+    Sized,
+    //A map or a String.
+    Searchable,
+    //An element of the array in the first argument.
+    Element,
+    //A key of the map in the first argument, or a String to find in the String in the first argument.
+    Key,
+}
+
+impl Param {
+    //Returns: what the parameter expects, for errors about arguments of the wrong type.
+    pub fn describe(&self) -> String {
+        match self {
+            Param::Is(ty) => ty.to_string(),
+            Param::Any => "any value".to_string(),
+            Param::Number => "a number".to_string(),
+            Param::Scalar => "a value that is not an array, struct or map".to_string(),
+            Param::Array => "an array".to_string(),
+            Param::Map => "a map".to_string(),
+            Param::Sized => "an array, a map or a String".to_string(),
+            Param::Searchable => "a map or a String".to_string(),
+            Param::Element => "an element of the array".to_string(),
+            Param::Key => "a key of the map".to_string(),
+        }
+    }
+
+    //Checks a value against the parameter while the program runs, where element and key types are left to the type checker.
+    //Args: first - the value of the first argument.
+    fn accepts(&self, value: &Primitive, first: &Primitive) -> bool {
+        match (self, value) {
+            (Param::Is(ty), value) => value.has_type(ty),
+            (Param::Number, Primitive::I32(_) | Primitive::I64(_) | Primitive::F32(_) | Primitive::F64(_)) => true,
+            (Param::Scalar, Primitive::Array(_) | Primitive::Struct(..) | Primitive::Map(_)) => false,
+            (Param::Array, Primitive::Array(_)) | (Param::Map, Primitive::Map(_)) => true,
+            (Param::Sized, value) => value.len().is_some(),
+            (Param::Searchable, Primitive::Map(_) | Primitive::String(_)) => true,
+            (Param::Key, value) => !matches!(first, Primitive::String(_)) || matches!(value, Primitive::String(_)),
+            (Param::Any | Param::Scalar | Param::Element, _) => true,
+            _ => false,
+        }
+    }
+
+    //Checks the type of an argument against the parameter while generating code.
+    //Args: first - the type of the first argument, which element and key parameters depend on.
+    //Errors: with what the parameter expects, if found does not fit it.
+    pub fn fits(&self, found: &Type, first: &Type) -> Result<(), String> {
+        let expected = match (self, first) {
+            (Param::Is(ty), _) => ty,
+            (Param::Element, Type::Array(element)) => &**element,
+            (Param::Key, Type::Map(key, _)) => &**key,
+            (Param::Key, Type::String) => &Type::String,
+            _ => {
+                let fits = match self {
+                    Param::Number => matches!(found, Type::I32 | Type::I64 | Type::F32 | Type::F64),
+                    Param::Scalar => !matches!(found, Type::Array(_) | Type::Struct(_) | Type::Map(..)),
+                    Param::Array => matches!(found, Type::Array(_)),
+                    Param::Map => matches!(found, Type::Map(..)),
+                    Param::Sized => matches!(found, Type::Array(_) | Type::Map(..) | Type::String),
+                    Param::Searchable => matches!(found, Type::Map(..) | Type::String),
+                    Param::Is(_) | Param::Any | Param::Element | Param::Key => true,
+                };
+                return if fits { Ok(()) } else { Err(self.describe()) };
+            }
+        };
+        if found == expected {
+            Ok(())
+        } else {
+            Err(expected.to_string())
+        }
+    }
+}
+
+//What a builtin returns, which for some builtins depends on the type of the first argument.
+pub enum Returns {
+    Nothing,
+    Is(Type),
+    //An element of the array in the first argument.
+    Element,
+    //An array of the keys of the map in the first argument.
+    Keys,
+    //A value of the map in the first argument.
+    Value,
+}
+
+//A builtin function, described once so that statements, expressions, the type checker and the LLVM backend all treat it the same way.
+pub struct Builtin {
+    pub name: &'static str,
+    pub params: Vec<Param>,
+    //Whether any number of arguments of any type can follow params, as they do for format.
+    pub variadic: bool,
+    pub returns: Returns,
+    //Whether the first argument is a variable, or an element or field of one, that the builtin changes, as push does.
+    pub changes_first: bool,
+    //Runs the builtin on arguments that already fit params. A changed first argument is written back afterwards.
+    pub run: Run,
+    //Checks the arguments as written, for mistakes that can be found before the program runs.
+    pub check: Option<Check>,
+    //Generates the code for the builtin in the LLVM backend once its arguments are evaluated, None if the backend cannot compile it.
+    pub lower: Option<Lower>,
+}
+
+impl Builtin {
+    //Errors: If there are too few arguments, or too many for a builtin that is not variadic.
+    pub fn expect_arg_count(&self, count: usize) -> Result<(), Diagnostic> {
+        match (self.variadic, self.params.len()) {
+            (false, expected) if count != expected => Err(Diagnostic::new(21, format!("Function {} expects {} argument(s) but found {}", self.name, expected, count))),
+            (true, expected) if count < expected => Err(Diagnostic::new(21, format!("Function {} expects at least {} argument(s) but found {}", self.name, expected, count))),
+            _ => Ok(()),
+        }
+    }
+
+    //Returns: the error for argument i having type found. Casts report a failed cast rather than a mismatched type.
+    pub fn mismatch(&self, i: usize, expected: &str, found: &str) -> Diagnostic {
+        match self.params.get(i) {
+            Some(Param::Number | Param::Scalar) => Diagnostic::new(8, format!("Failed to cast {} to {}", found, self.name)),
+            _ => Diagnostic::new(28, format!("Mismatched type: argument {} of {} expects {} but found {}", i + 1, self.name, expected, found)),
+        }
+    }
+
+    //Runs the builtin once its arguments are evaluated.
+    //Errors: If an argument does not fit its parameter, with the index of that argument, or the builtin itself fails.
+    pub fn call(&self, args: &mut [Primitive]) -> Result<Option<Primitive>, (Option<usize>, Diagnostic)> {
+        for (i, (param, value)) in self.params.iter().zip(args.iter()).enumerate() {
+            if !param.accepts(value, &args[0]) {
+                return Err((Some(i), self.mismatch(i, &param.describe(), &value.type_name())));
+            }
+        }
+        (self.run)(args).map_err(|error| (None, error))
+    }
+}
+
+static REGISTRY: LazyLock<Vec<Builtin>> = LazyLock::new(|| {
+    let strings = |count: usize| vec![Param::Is(Type::String); count];
+    let builtin = |name: &'static str, params: Vec<Param>, returns: Returns, run: Run| Builtin { name, params, variadic: false, returns, changes_first: false, run, check: None, lower: None };
+    vec![
+        Builtin { lower: Some(Codegen::lower_print), ..builtin("print", vec![Param::Any], Returns::Nothing, |args| {
+            println!("{}", args[0]);
+            Ok(None)
+        }) },
+        Builtin { lower: Some(Codegen::lower_sleep), ..builtin("sleep", vec![Param::Is(Type::I32)], Returns::Nothing, |args| {
+            if let Primitive::I32(seconds) = args[0] {
+                std::thread::sleep(std::time::Duration::from_secs(seconds as u64));
+            }
+            Ok(None)
+        }) },
+        Builtin { lower: Some(|codegen, args| codegen.lower_cast(args, Type::I32)), ..builtin("i32", vec![Param::Number], Returns::Is(Type::I32), |args| Ok(Some(Primitive::I32(match args[0] {
+            Primitive::I32(value) => value,
+            Primitive::I64(value) => value as i32,
+            Primitive::F32(value) => value as i32,
+            Primitive::F64(value) => value as i32,
+            _ => unreachable!("casts take a number"),
+        })))) },
+        Builtin { lower: Some(|codegen, args| codegen.lower_cast(args, Type::I64)), ..builtin("i64", vec![Param::Number], Returns::Is(Type::I64), |args| Ok(Some(Primitive::I64(match args[0] {
+            Primitive::I32(value) => value as i64,
+            Primitive::I64(value) => value,
+            Primitive::F32(value) => value as i64,
+            Primitive::F64(value) => value as i64,
+            _ => unreachable!("casts take a number"),
+        })))) },
+        Builtin { lower: Some(|codegen, args| codegen.lower_cast(args, Type::F32)), ..builtin("f32", vec![Param::Number], Returns::Is(Type::F32), |args| Ok(Some(Primitive::F32(match args[0] {
+            Primitive::I32(value) => value as f32,
+            Primitive::I64(value) => value as f32,
+            Primitive::F32(value) => value,
+            Primitive::F64(value) => value as f32,
+            _ => unreachable!("casts take a number"),
+        })))) },
+        Builtin { lower: Some(|codegen, args| codegen.lower_cast(args, Type::F64)), ..builtin("f64", vec![Param::Number], Returns::Is(Type::F64), |args| Ok(Some(Primitive::F64(match args[0] {
+            Primitive::I32(value) => value as f64,
+            Primitive::I64(value) => value as f64,
+            Primitive::F32(value) => value as f64,
+            Primitive::F64(value) => value,
+            _ => unreachable!("casts take a number"),
+        })))) },
+        Builtin { lower: Some(Codegen::lower_string), ..builtin("string", vec![Param::Scalar], Returns::Is(Type::String), |args| Ok(Some(Primitive::String(args[0].to_string())))) },
+        Builtin { lower: Some(Codegen::lower_rand_int), ..builtin("rand_int", vec![Param::Is(Type::I32), Param::Is(Type::I32)], Returns::Is(Type::I32), |args| match (&args[0], &args[1]) {
+            (Primitive::I32(low), Primitive::I32(high)) if low <= high => Ok(Some(Primitive::I32(rand::rng().random_range(*low..=*high)))),
+            (low, high) => Err(Diagnostic::new(20, format!("rand_int expects two i32 bounds with low <= high but found {} and {}", low, high))),
+        }) },
+        Builtin { lower: Some(Codegen::lower_rand_float), ..builtin("rand_float", Vec::new(), Returns::Is(Type::F32), |_| Ok(Some(Primitive::F32(rand::random::<f32>())))) },
+        Builtin { lower: Some(Codegen::lower_len), ..builtin("len", vec![Param::Sized], Returns::Is(Type::I32), |args| Ok(args[0].len().map(|length| Primitive::I32(length as i32)))) },
+        Builtin { changes_first: true, lower: Some(Codegen::lower_push), ..builtin("push", vec![Param::Array, Param::Element], Returns::Nothing, |args| {
+            let [Primitive::Array(values), value] = args else {
+                unreachable!("push takes an array and an element");
+            };
+            values.push(value.clone());
+            Ok(None)
+        }) },
+        Builtin { changes_first: true, lower: Some(Codegen::lower_pop), ..builtin("pop", vec![Param::Array], Returns::Element, |args| match &mut args[0] {
+            Primitive::Array(values) => values.pop().map(Some).ok_or_else(|| Diagnostic::new(54, "Cannot pop from an empty array")),
+            _ => unreachable!("pop takes an array"),
+        }) },
+        builtin("contains", vec![Param::Searchable, Param::Key], Returns::Is(Type::Bool), |args| Ok(Some(Primitive::Bool(match &args[0] {
+            Primitive::Map(entries) => find_key(entries, &args[1]).is_ok(),
+            _ => text(args, 0).contains(text(args, 1)),
+        })))),
+        builtin("keys", vec![Param::Map], Returns::Keys, |args| match &args[0] {
+            Primitive::Map(entries) => Ok(Some(Primitive::Array(entries.iter().map(|(key, _)| key.clone()).collect()))),
+            _ => unreachable!("keys takes a map"),
+        }),
+        Builtin { changes_first: true, ..builtin("remove", vec![Param::Map, Param::Key], Returns::Value, |args| {
+            let [Primitive::Map(entries), key] = args else {
+                unreachable!("remove takes a map and a key");
+            };
+            match find_key(entries, key) {
+                Ok(found) => Ok(Some(entries.remove(found).1)),
+                Err(_) => Err(missing_key(key)),
+            }
+        }) },
+        builtin("substring", vec![Param::Is(Type::String), Param::Is(Type::I32), Param::Is(Type::I32)], Returns::Is(Type::String), substring),
+        builtin("split", strings(2), Returns::Is(Type::Array(Box::new(Type::String))), split),
+        builtin("join", vec![Param::Is(Type::Array(Box::new(Type::String))), Param::Is(Type::String)], Returns::Is(Type::String), join),
+        builtin("trim", strings(1), Returns::Is(Type::String), |args| Ok(Some(Primitive::String(text(args, 0).trim().to_string())))),
+        builtin("to_upper", strings(1), Returns::Is(Type::String), |args| Ok(Some(Primitive::String(text(args, 0).to_uppercase())))),
+        builtin("to_lower", strings(1), Returns::Is(Type::String), |args| Ok(Some(Primitive::String(text(args, 0).to_lowercase())))),
+        builtin("starts_with", strings(2), Returns::Is(Type::Bool), |args| Ok(Some(Primitive::Bool(text(args, 0).starts_with(text(args, 1)))))),
+        builtin("replace", strings(3), Returns::Is(Type::String), |args| Ok(Some(Primitive::String(text(args, 0).replace(text(args, 1), text(args, 2)))))),
+        builtin("parse_i32", strings(1), Returns::Is(Type::Struct(PARSED_I32.to_string())), |args| Ok(Some(parsed(PARSED_I32, text(args, 0).parse::<i32>().map(Primitive::I32), Primitive::I32(0))))),
+        builtin("parse_f64", strings(1), Returns::Is(Type::Struct(PARSED_F64.to_string())), |args| Ok(Some(parsed(PARSED_F64, text(args, 0).parse::<f64>().map(Primitive::F64), Primitive::F64(0.0))))),
+        Builtin { variadic: true, check: Some(check_format), ..builtin("format", strings(1), Returns::Is(Type::String), format) },
+    ]
+});
+
+//Returns: the builtin with this name, or None if name is not one.
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+    REGISTRY.iter().find(|builtin| builtin.name == name)
+}
+
+//The results of parse_i32 and parse_f64. ok is false if the text is not a number, and then error says why and value is 0.
+const PARSED_I32: &str = "ParsedI32";
+const PARSED_F64: &str = "ParsedF64";
+
+//The structs every program can use without declaring them.
+pub fn builtin_structs() -> Structs {
+    [(PARSED_I32, Type::I32), (PARSED_F64, Type::F64)].into_iter().map(|(name, value)| {
+        let fields = vec![("ok".to_string(), Type::Bool), ("value".to_string(), value), ("error".to_string(), Type::String)];
+        (name.to_string(), Struct { name: name.to_string(), fields, span: Span::default() })
+    }).collect()
+}
+
+fn parsed<E: std::fmt::Display>(name: &str, result: Result<Primitive, E>, zero: Primitive) -> Primitive {
+    let (ok, value, error) = match result {
+        Ok(value) => (true, value, String::new()),
+        Err(error) => (false, zero, error.to_string()),
+    };
+    Primitive::Struct(name.to_string(), vec![
+        ("ok".to_string(), Primitive::Bool(ok)),
+        ("value".to_string(), value),
+        ("error".to_string(), Primitive::String(error)),
+    ])
+}
+
+fn text(args: &[Primitive], i: usize) -> &str {
+    match &args[i] {
+        Primitive::String(text) => text,
+        other => unreachable!("argument {} was checked to be a String but is {}", i + 1, other.type_name()),
+    }
+}
+
+//Errors: If start..end is backwards, reaches past the end or splits a character. Like len, positions count bytes.
+fn substring(args: &mut [Primitive]) -> Result<Option<Primitive>, Diagnostic> {
+    let (Primitive::I32(start), Primitive::I32(end)) = (&args[1], &args[2]) else {
+        unreachable!("the bounds of substring were checked to be i32");
+    };
+    let text = text(args, 0);
+    let out_of_range = || Diagnostic::new(65, format!("Substring {}..{} is not a valid range of a String of length {}", start, end, text.len()));
+    let (Ok(start), Ok(end)) = (usize::try_from(*start), usize::try_from(*end)) else {
+        return Err(out_of_range());
+    };
+    match text.get(start..end) {
+        Some(part) => Ok(Some(Primitive::String(part.to_string()))),
+        None if start <= end && end <= text.len() => Err(out_of_range().with_note("a bound falls inside a character that takes more than one byte")),
+        None => Err(out_of_range()),
+    }
+}
+
+//An empty separator splits the text into its characters.
+fn split(args: &mut [Primitive]) -> Result<Option<Primitive>, Diagnostic> {
+    let (text, separator) = (text(args, 0), text(args, 1));
+    let parts: Vec<Primitive> = match separator {
+        "" => text.chars().map(|c| Primitive::String(c.to_string())).collect(),
+        separator => text.split(separator).map(|part| Primitive::String(part.to_string())).collect(),
+    };
+    Ok(Some(Primitive::Array(parts)))
+}
+
+fn join(args: &mut [Primitive]) -> Result<Option<Primitive>, Diagnostic> {
+    let Primitive::Array(parts) = &args[0] else {
+        unreachable!("the parts of join were checked to be an array");
+    };
+    let parts: Vec<String> = parts.iter().map(|part| part.to_string()).collect();
+    Ok(Some(Primitive::String(parts.join(text(args, 1)))))
+}
+
+fn format(args: &mut [Primitive]) -> Result<Option<Primitive>, Diagnostic> {
+    let pieces = format_pieces(text(args, 0))?;
+    let values = &args[1..];
+    if pieces.len() - 1 != values.len() {
+        return Err(placeholder_count(pieces.len() - 1, values.len()));
+    }
+    let mut formatted = pieces[0].clone();
+    for (value, piece) in values.iter().zip(&pieces[1..]) {
+        formatted.push_str(&value.to_string());
+        formatted.push_str(piece);
+    }
+    Ok(Some(Primitive::String(formatted)))
+}
+
+//A format string written as a literal is checked against the number of values given for it.
+fn check_format(args: &[Expression]) -> Result<(), Diagnostic> {
+    let Some(Expression::String(template, span)) = args.first() else {
+        return Ok(());
+    };
+    let placeholders = format_pieces(template).map_err(|error| error.with_span(span.clone()))?.len() - 1;
+    if placeholders != args.len() - 1 {
+        return Err(placeholder_count(placeholders, args.len() - 1).with_span(span.clone()));
+    }
+    Ok(())
+}
+
+//Splits a format string at each {}, turning {{ and }} into single braces.
+//Returns: the text around the placeholders, so there is always one more piece than placeholders.
+//Errors: If a brace is not part of {}, {{ or }}.
+fn format_pieces(template: &str) -> Result<Vec<String>, Diagnostic> {
+    let mut pieces = vec![String::new()];
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('}')) => {
+                chars.next();
+                pieces.push(String::new());
+            }
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                pieces.last_mut().expect("there is always a piece").push(c);
+            }
+            ('{' | '}', _) => {
+                return Err(Diagnostic::new(67, format!("Invalid format string {:?}", template))
+                    .with_note("values go in {}, and {{ or }} write a brace"));
+            }
+            (c, _) => pieces.last_mut().expect("there is always a piece").push(c),
+        }
+    }
+    Ok(pieces)
+}
+
+fn placeholder_count(placeholders: usize, values: usize) -> Diagnostic {
+    Diagnostic::new(66, format!("Format string has {} placeholder(s) but found {} value(s)", placeholders, values))
+}
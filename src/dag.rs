@@ -1,83 +1,174 @@
-use std::collections::{HashMap, HashSet};
-use crate::token_block::TokenBlock;
+use std::{collections::{BTreeSet, HashMap, HashSet}, io::{self, Write}};
+use crate::{diagnostic::Diagnostic, interpreter::Structs, token_block::TokenBlock};
 
 const GREEN: &str = "\x1b[32m";
 const RESET: &str = "\x1b[0m";
-const RED: &str = "\x1b[31m";
 
-pub fn build_dag(token_blocks: &HashSet<TokenBlock>) -> HashMap<String, TokenBlock> {
-    let valid_ids: HashSet<String> = token_blocks.iter().map(|block| block.id.clone()).collect();
-    let mut dag: HashMap<String, TokenBlock> = HashMap::new();
+#[derive(Debug, Clone)]
+pub struct Dag {
+    pub blocks: HashMap<String, TokenBlock>,
+    //Every block id ordered so that each block comes after all the blocks it requires.
+    pub order: Vec<String>,
+    //The ids of the blocks that require each block, sorted.
+    pub children: HashMap<String, Vec<String>>,
+    //The structs declared outside of the blocks, which every block can use.
+    pub structs: Structs,
+}
+
+impl Dag {
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+pub fn build_dag(token_blocks: &HashSet<TokenBlock>) -> Result<Dag, Diagnostic> {
+    let mut blocks: HashMap<String, TokenBlock> = HashMap::new();
     for block in token_blocks {
-        for required_id in block.requires.keys() {
-            if !valid_ids.contains(required_id) {
-                panic!("{}Error[3]: Block {} requires block {} which is not defined!{}", RED, block.id, required_id, RESET);
+        blocks.insert(block.id.clone(), block.clone());
+    }
+    let mut ids: Vec<&String> = blocks.keys().collect();
+    ids.sort();
+    for id in &ids {
+        let block = &blocks[*id];
+        let mut required_ids: Vec<&String> = block.requires.keys().collect();
+        required_ids.sort();
+        for required_id in required_ids {
+            if !blocks.contains_key(required_id) {
+                return Err(Diagnostic::new(3, format!("Block {} requires block {} which is not defined!", block.id, required_id))
+                    .with_span(block.span.clone()));
+            }
+        }
+    }
+    if let Some(cycle) = find_cycle(&blocks) {
+        let block = &blocks[&cycle[0]];
+        return Err(Diagnostic::new(23, format!("Dependency cycle between blocks: {}", cycle.join(" -> ")))
+            .with_span(block.span.clone())
+            .with_note("blocks in a cycle wait on each other forever, remove one of the requires"));
+    }
+    let children = build_children_map(&blocks);
+    let order = topological_order(&blocks, &children);
+    Ok(Dag { blocks, order, children, structs: Structs::new() })
+}
+
+//Depth first search over the requires edges looking for a back edge.
+//Returns: the ids along the first cycle found, starting and ending with the same id.
+fn find_cycle(blocks: &HashMap<String, TokenBlock>) -> Option<Vec<String>> {
+    let mut ids: Vec<&String> = blocks.keys().collect();
+    ids.sort();
+    let mut finished: HashSet<String> = HashSet::new();
+    let mut path: Vec<String> = Vec::new();
+    for id in ids {
+        if let Some(cycle) = visit(id, blocks, &mut path, &mut finished) {
+            return Some(cycle);
+        }
+    }
+    None
+}
+
+fn visit(id: &str, blocks: &HashMap<String, TokenBlock>, path: &mut Vec<String>, finished: &mut HashSet<String>) -> Option<Vec<String>> {
+    if finished.contains(id) {
+        return None;
+    }
+    if let Some(position) = path.iter().position(|on_path| on_path == id) {
+        let mut cycle = path[position..].to_vec();
+        cycle.push(id.to_string());
+        return Some(cycle);
+    }
+    path.push(id.to_string());
+    let mut required_ids: Vec<&String> = blocks[id].requires.keys().collect();
+    required_ids.sort();
+    for required_id in required_ids {
+        if let Some(cycle) = visit(required_id, blocks, path, finished) {
+            return Some(cycle);
+        }
+    }
+    path.pop();
+    finished.insert(id.to_string());
+    None
+}
+
+//Kahn's algorithm, always taking the alphabetically smallest ready block so the order is deterministic.
+fn topological_order(blocks: &HashMap<String, TokenBlock>, children: &HashMap<String, Vec<String>>) -> Vec<String> {
+    let mut in_degree: HashMap<&String, usize> = blocks.iter().map(|(id, block)| (id, block.requires.len())).collect();
+    let mut ready: BTreeSet<&String> = in_degree.iter().filter(|(_, deg)| **deg == 0).map(|(id, _)| *id).collect();
+    let mut order = Vec::new();
+    while let Some(id) = ready.pop_first() {
+        order.push(id.clone());
+        for child in &children[id] {
+            let deg = in_degree.get_mut(child).unwrap();
+            *deg -= 1;
+            if *deg == 0 {
+                ready.insert(child);
             }
         }
-        dag.insert(block.id.clone(), block.clone());
     }
-    dag
+    order
 }
 
-fn build_children_map(dag: &HashMap<String, TokenBlock>) -> HashMap<String, Vec<String>> {
+fn build_children_map(blocks: &HashMap<String, TokenBlock>) -> HashMap<String, Vec<String>> {
     let mut children_map: HashMap<String, Vec<String>> = HashMap::new();
-    for id in dag.keys() {
+    for id in blocks.keys() {
         children_map.insert(id.clone(), Vec::new());
     }
-    for (_id, block) in dag.iter() {
+    for (_id, block) in blocks.iter() {
         for required_id in block.requires.keys() {
             if let Some(vec) = children_map.get_mut(required_id) {
                 vec.push(block.id.clone());
             }
         }
     }
+    for children in children_map.values_mut() {
+        children.sort();
+    }
     children_map
 }
 
-pub fn print_dag(dag: &HashMap<String, TokenBlock>) {
-    println!("{}DAG:{}", GREEN, RESET);
-    let children_map = build_children_map(dag);
+//Writes the blocks as a tree under the blocks they require.
+//Errors: If out cannot be written to.
+pub fn print_dag(dag: &Dag, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "{}DAG:{}", GREEN, RESET)?;
+    let children_map = &dag.children;
     let mut child_ids = HashSet::new();
     for children in children_map.values() {
         for child in children {
             child_ids.insert(child.clone());
         }
     }
-    let mut roots: Vec<String> = dag.keys().filter(|id| !child_ids.contains(*id)).cloned().collect();
+    let mut roots: Vec<String> = dag.blocks.keys().filter(|id| !child_ids.contains(*id)).cloned().collect();
     roots.sort();
-    if roots.is_empty() {
-        roots = dag.keys().cloned().collect();
-        roots.sort();
-    }
     let mut printed = HashSet::new();
     for (i, root) in roots.iter().enumerate() {
         let is_last = i == roots.len() - 1;
-        print_tree(root.clone(), &children_map, dag, "", true, is_last, &mut printed);
+        print_tree(root.clone(), dag, "", true, is_last, &mut printed, out)?;
     }
+    Ok(())
 }
 
 fn print_tree(
     node: String,
-    children_map: &HashMap<String, Vec<String>>,
-    dag: &HashMap<String, TokenBlock>,
+    dag: &Dag,
     prefix: &str,
     is_root: bool,
     is_last: bool,
     printed: &mut HashSet<String>,
-) {
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let location = dag.blocks.get(&node).map(|block| format!(" ({}:{})", block.span.file, block.span.line)).unwrap_or_default();
     if is_root {
-        println!("{}{}Block {}{}", GREEN, prefix, node, RESET);
+        writeln!(out, "{}{}Block {}{}{}", GREEN, prefix, node, location, RESET)?;
     } else {
         let connector = if is_last { "└── " } else { "├── " };
-        println!("{}{}{}Block {}{}", GREEN, prefix, connector, node, RESET);
+        writeln!(out, "{}{}{}Block {}{}{}", GREEN, prefix, connector, node, location, RESET)?;
     }
     if printed.contains(&node) {
-        return;
+        return Ok(());
     }
     printed.insert(node.clone());
-    if let Some(children) = children_map.get(&node) {
-        let mut children = children.clone();
-        children.sort();
+    if let Some(children) = dag.children.get(&node) {
         let count = children.len();
         for (i, child) in children.iter().enumerate() {
             let child_is_last = i == count - 1;
@@ -86,31 +177,80 @@ fn print_tree(
             } else {
                 format!("{}{}", prefix, if is_last { "    " } else { "│   " })
             };
-            print_tree(child.clone(), children_map, dag, &new_prefix, false, child_is_last, printed);
+            print_tree(child.clone(), dag, &new_prefix, false, child_is_last, printed, out)?;
         }
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use std::collections::{HashMap, HashSet, VecDeque};
-    use crate::{dag::{build_dag, print_dag}, token_block::TokenBlock};
+    use crate::{dag::{build_dag, print_dag}, diagnostic::Span, token_block::TokenBlock};
 
     #[test]
     fn test_build_and_print_dag() {
-        let block1 = TokenBlock { id: "a".to_string(), requires: HashMap::new(), tokens: VecDeque::new() };
-        let block2 = TokenBlock { id: "b".to_string(), requires: HashMap::new(), tokens: VecDeque::new() };
-        let block3 = TokenBlock { id: "c".to_string(), requires: HashMap::new(), tokens: VecDeque::new() };
+        let block1 = TokenBlock { id: "a".to_string(), requires: HashMap::new(), tokens: VecDeque::new(), span: Span::default() };
+        let block2 = TokenBlock { id: "b".to_string(), requires: HashMap::new(), tokens: VecDeque::new(), span: Span::default() };
+        let block3 = TokenBlock { id: "c".to_string(), requires: HashMap::new(), tokens: VecDeque::new(), span: Span::default() };
         let mut req = HashMap::new();
         req.insert("a".to_string(), Vec::new());
-        let block4 = TokenBlock { id: "d".to_string(), requires: req, tokens: VecDeque::new() };
+        let block4 = TokenBlock { id: "d".to_string(), requires: req, tokens: VecDeque::new(), span: Span::default() };
         let token_blocks: HashSet<TokenBlock> = vec![block1, block2, block3, block4].into_iter().collect();
-        let dag = build_dag(&token_blocks);
+        let dag = build_dag(&token_blocks).unwrap();
         assert_eq!(dag.len(), 4);
-        assert!(dag.contains_key("a"));
-        assert!(dag.contains_key("b"));
-        assert!(dag.contains_key("c"));
-        assert!(dag.contains_key("d"));
-        print_dag(&dag);
+        assert!(dag.blocks.contains_key("a"));
+        assert!(dag.blocks.contains_key("b"));
+        assert!(dag.blocks.contains_key("c"));
+        assert!(dag.blocks.contains_key("d"));
+        let mut out = Vec::new();
+        print_dag(&dag, &mut out).unwrap();
+        let printed = String::from_utf8(out).unwrap();
+        assert!(printed.contains("Block a"));
+        assert!(printed.contains("└── Block d"));
+    }
+
+    #[test]
+    fn test_missing_required_block() {
+        let mut req = HashMap::new();
+        req.insert("z".to_string(), Vec::new());
+        let block = TokenBlock { id: "a".to_string(), requires: req, tokens: VecDeque::new(), span: Span::default() };
+        let token_blocks: HashSet<TokenBlock> = vec![block].into_iter().collect();
+        let error = build_dag(&token_blocks).unwrap_err();
+        assert_eq!(error.code, 3);
+    }
+
+    fn block(id: &str, requires: &[&str]) -> TokenBlock {
+        let requires = requires.iter().map(|id| (id.to_string(), Vec::new())).collect();
+        TokenBlock { id: id.to_string(), requires, tokens: VecDeque::new(), span: Span::default() }
+    }
+
+    #[test]
+    fn test_topological_order() {
+        let token_blocks: HashSet<TokenBlock> = vec![
+            block("d", &["b", "c"]),
+            block("c", &["a"]),
+            block("b", &["a"]),
+            block("a", &[]),
+            block("e", &[]),
+        ].into_iter().collect();
+        let dag = build_dag(&token_blocks).unwrap();
+        assert_eq!(dag.order, vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(dag.children["a"], vec!["b", "c"]);
+    }
+
+    #[test]
+    fn test_cycle_is_reported() {
+        let token_blocks: HashSet<TokenBlock> = vec![block("a", &["b"]), block("b", &["c"]), block("c", &["a"]), block("d", &[])].into_iter().collect();
+        let error = build_dag(&token_blocks).unwrap_err();
+        assert_eq!(error.code, 23);
+        assert_eq!(error.message, "Dependency cycle between blocks: a -> b -> c -> a");
+    }
+
+    #[test]
+    fn test_self_require_is_a_cycle() {
+        let token_blocks: HashSet<TokenBlock> = vec![block("a", &["a"])].into_iter().collect();
+        let error = build_dag(&token_blocks).unwrap_err();
+        assert_eq!(error.message, "Dependency cycle between blocks: a -> a");
     }
 }
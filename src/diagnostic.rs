@@ -0,0 +1,153 @@
+use std::{fmt, sync::Arc};
+
+const RED: &str = "\x1b[31m";
+const BLUE: &str = "\x1b[34m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Span {
+    pub file: Arc<str>,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    //Builds a span over text[start..end], computing the 1 based line and column of start.
+    pub fn new(file: &Arc<str>, text: &str, start: usize, end: usize) -> Self {
+        let before = &text[..start.min(text.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let col = before[line_start..].chars().count() + 1;
+        Span { file: Arc::clone(file), start, end, line, col }
+    }
+
+    //Returns a span running from the start of self to the end of other.
+    pub fn to(&self, other: &Span) -> Span {
+        Span { end: other.end.max(self.end), ..self.clone() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub name: Arc<str>,
+    pub text: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: &str, text: String) -> Self {
+        let line_starts = std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect();
+        SourceFile { name: Arc::from(name), text, line_starts }
+    }
+
+    //Same as Span::new but uses the cached line starts so spans can be made for every token cheaply.
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        let start = start.min(self.text.len());
+        let line_index = self.line_starts.partition_point(|&line_start| line_start <= start) - 1;
+        let col = self.text[self.line_starts[line_index]..start].chars().count() + 1;
+        Span { file: Arc::clone(&self.name), start, end, line: line_index + 1, col }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub code: u32,
+    pub message: String,
+    pub span: Option<Span>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(code: u32, message: impl Into<String>) -> Self {
+        Diagnostic { code, message: message.into(), span: None, notes: Vec::new() }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    //Attaches span only if the diagnostic does not already point somewhere more specific.
+    pub fn or_span(mut self, span: &Span) -> Self {
+        if self.span.is_none() {
+            self.span = Some(span.clone());
+        }
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    //Renders the diagnostic rustc style, quoting the offending line of source with a caret underneath.
+    //Args: source: Option<&str> - the full text of the file the span points into, if available.
+    pub fn render(&self, source: Option<&str>) -> String {
+        let mut out = format!("{}error[{}]{}: {}\n", RED, self.code, RESET, self.message);
+        let gutter_width = self.span.as_ref().map(|span| span.line.to_string().len()).unwrap_or(0);
+        let gutter = " ".repeat(gutter_width);
+        if let Some(span) = &self.span {
+            out.push_str(&format!("{}{}-->{} {}:{}:{}\n", gutter, BLUE, RESET, span.file, span.line, span.col));
+            if let Some(line_text) = source.and_then(|text| text.lines().nth(span.line - 1)) {
+                let width = line_text.chars().skip(span.col - 1).count().max(1);
+                let underline = span.end.saturating_sub(span.start).clamp(1, width);
+                out.push_str(&format!("{} {}|{}\n", gutter, BLUE, RESET));
+                out.push_str(&format!("{}{} |{} {}\n", BLUE, span.line, RESET, line_text));
+                out.push_str(&format!(
+                    "{} {}|{} {}{}{}{}\n",
+                    gutter, BLUE, RESET, " ".repeat(span.col - 1), RED, "^".repeat(underline), RESET
+                ));
+            }
+        }
+        for note in &self.notes {
+            out.push_str(&format!("{} {}={} note: {}\n", gutter, BLUE, RESET, note));
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Error[{}]: {}", self.code, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::diagnostic::{Diagnostic, SourceFile, Span};
+
+    #[test]
+    fn span_line_and_column() {
+        let file: Arc<str> = Arc::from("test.st");
+        let text = "block a {\n    print(x);\n}";
+        let span = Span::new(&file, text, 20, 21);
+        assert_eq!(span.line, 2);
+        assert_eq!(span.col, 11);
+    }
+
+    #[test]
+    fn source_file_span_matches_span_new() {
+        let source = SourceFile::new("test.st", "block a {\n    print(x);\n}".to_string());
+        assert_eq!(source.span(20, 21), Span::new(&source.name, &source.text, 20, 21));
+        assert_eq!(source.span(0, 5).line, 1);
+        assert_eq!(source.span(24, 25).line, 3);
+    }
+
+    #[test]
+    fn render_points_at_source() {
+        let file: Arc<str> = Arc::from("test.st");
+        let text = "block a {\n    print(x);\n}";
+        let diagnostic = Diagnostic::new(6, "Variable x does not exist")
+            .with_span(Span::new(&file, text, 20, 21))
+            .with_note("variables must be defined before use");
+        let rendered = diagnostic.render(Some(text));
+        assert!(rendered.contains("error[6]"));
+        assert!(rendered.contains("test.st:2:11"));
+        assert!(rendered.contains("2 |\x1b[0m     print(x);"));
+        assert!(rendered.contains(&format!("{}\x1b[31m^", " ".repeat(10))));
+        assert!(rendered.contains("note: variables must be defined before use"));
+    }
+}
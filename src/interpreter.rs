@@ -1,10 +1,5 @@
-use colored::Colorize;
-use rand::Rng;
 
-use crate::parse::{BinaryOperator, Complete, CompleteU, Expression, Statement, UnaryOperator};
-
-const RED: &str = "\x1b[31m";
-const RESET: &str = "\x1b[0m";
+use crate::{builtins::{lookup, Builtin}, diagnostic::Diagnostic, parse::{Access, BinaryOperator, Complete, CompleteU, Expression, Function, Iterable, LoopVariables, Range, Statement, Struct, UnaryOperator}};
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Type {
@@ -15,6 +10,33 @@ pub enum Type {
     F32,
     F64,
     Array(Box<Type>),
+    //A struct declared at the top of the file, by name.
+    Struct(String),
+    //Keys and their values, the key type is one that is_map_key allows.
+    Map(Box<Type>, Box<Type>),
+}
+
+impl Type {
+    //Map keys are kept in order, so only types that compare cleanly can be keys.
+    pub fn is_map_key(&self) -> bool {
+        matches!(self, Type::String | Type::I32 | Type::I64 | Type::Bool)
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Bool => write!(f, "Bool"),
+            Type::String => write!(f, "String"),
+            Type::I32 => write!(f, "i32"),
+            Type::I64 => write!(f, "i64"),
+            Type::F32 => write!(f, "f32"),
+            Type::F64 => write!(f, "f64"),
+            Type::Array(inner) => write!(f, "Array<{}>", inner),
+            Type::Struct(name) => write!(f, "{}", name),
+            Type::Map(key, value) => write!(f, "Map<{}, {}>", key, value),
+        }
+    }
 }
 
 use std::collections::{HashMap, VecDeque};
@@ -27,6 +49,10 @@ pub enum Primitive {
     F64(f64),
     Bool(bool),
     Array(Vec<Primitive>),
+    //The struct's name and its fields in the order they are declared.
+    Struct(String, Vec<(String, Primitive)>),
+    //Entries sorted by key so a map always prints and iterates in the same order.
+    Map(Vec<(Primitive, Primitive)>),
 }
 impl std::fmt::Display for Primitive {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -38,40 +64,88 @@ impl std::fmt::Display for Primitive {
             Primitive::F64(value) => write!(f, "{}", value),
             Primitive::Bool(value) => write!(f, "{}", value),
             Primitive::Array(primitives) => write!(f, "{}", array_display_recusion(primitives)), 
+            Primitive::Struct(name, fields) => {
+                let fields: Vec<String> = fields.iter().map(|(field, value)| format!("{}: {}", field, value)).collect();
+                write!(f, "{} {{ {} }}", name, fields.join(", "))
+            }
+            Primitive::Map(entries) => {
+                let entries: Vec<String> = entries.iter().map(|(key, value)| format!("{}: {}", key, value)).collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
         }
     }
 }
 impl Primitive {
-    pub fn len(&self) -> usize{
-        match self{
-            Primitive::String(literal) => return literal.len(),
-            Primitive::I32(_) => todo!(),
-            Primitive::F32(_) => todo!(),
-            Primitive::I64(_) => todo!(),
-            Primitive::F64(_) => todo!(),
-            Primitive::Bool(_) => todo!(),
-            Primitive::Array(_) => todo!(),
+    //Checks a value against a declared type. Empty arrays and maps match any array or map type.
+    pub fn has_type(&self, ty: &Type) -> bool {
+        match (self, ty) {
+            (Primitive::String(_), Type::String)
+            | (Primitive::I32(_), Type::I32)
+            | (Primitive::F32(_), Type::F32)
+            | (Primitive::I64(_), Type::I64)
+            | (Primitive::F64(_), Type::F64)
+            | (Primitive::Bool(_), Type::Bool) => true,
+            (Primitive::Array(values), Type::Array(inner)) => values.iter().all(|value| value.has_type(inner)),
+            (Primitive::Struct(name, _), Type::Struct(ty)) => name == ty,
+            (Primitive::Map(entries), Type::Map(key, value)) => entries.iter().all(|(k, v)| k.has_type(key) && v.has_type(value)),
+            _ => false,
+        }
+    }
+
+    //The name of the value's type, used in error messages.
+    pub fn type_name(&self) -> String {
+        match self {
+            Primitive::String(_) => "String".to_string(),
+            Primitive::I32(_) => "i32".to_string(),
+            Primitive::F32(_) => "f32".to_string(),
+            Primitive::I64(_) => "i64".to_string(),
+            Primitive::F64(_) => "f64".to_string(),
+            Primitive::Bool(_) => "Bool".to_string(),
+            Primitive::Array(values) => match values.first() {
+                Some(first) => format!("Array<{}>", first.type_name()),
+                None => "Array".to_string(),
+            },
+            Primitive::Struct(name, _) => name.clone(),
+            Primitive::Map(entries) => match entries.first() {
+                Some((key, value)) => format!("Map<{}, {}>", key.type_name(), value.type_name()),
+                None => "Map".to_string(),
+            },
         }
     }
-}
 
-fn array_display_recusion(primitives: &Vec<Primitive>) -> String{
-    let mut string = "[".to_string();
-    for prim in primitives {
-        match prim {
-            Primitive::String(literal) => string = string + &literal,
-            Primitive::I32(literal) => string = string + &literal.to_string() + ", ",
-            Primitive::F32(literal) => string = string + &literal.to_string() + ", ",
-            Primitive::I64(literal) => string = string + &literal.to_string() + ", ",
-            Primitive::F64(literal) => string = string + &literal.to_string() + ", ",
-            Primitive::Bool(literal) => string = string + &literal.to_string() + ", ",
-            Primitive::Array(prims) => string = string + &array_display_recusion(prims) + ", ",
+    //The type of the value, taking an empty array to hold i32 the way the LLVM backend does.
+    //An empty map is taken to be Map<String, i32>.
+    pub fn value_type(&self) -> Type {
+        match self {
+            Primitive::String(_) => Type::String,
+            Primitive::I32(_) => Type::I32,
+            Primitive::F32(_) => Type::F32,
+            Primitive::I64(_) => Type::I64,
+            Primitive::F64(_) => Type::F64,
+            Primitive::Bool(_) => Type::Bool,
+            Primitive::Array(values) => Type::Array(Box::new(values.first().map_or(Type::I32, Primitive::value_type))),
+            Primitive::Struct(name, _) => Type::Struct(name.clone()),
+            Primitive::Map(entries) => match entries.first() {
+                Some((key, value)) => Type::Map(Box::new(key.value_type()), Box::new(value.value_type())),
+                None => Type::Map(Box::new(Type::String), Box::new(Type::I32)),
+            },
         }
     }
-    string.pop();
-    string.pop();
-    string.push(']');
-    return string
+
+    //The number of bytes in a string, elements in an array or entries in a map, None for values that have no length.
+    pub fn len(&self) -> Option<usize> {
+        match self {
+            Primitive::String(literal) => Some(literal.len()),
+            Primitive::Array(values) => Some(values.len()),
+            Primitive::Map(entries) => Some(entries.len()),
+            _ => None,
+        }
+    }
+}
+
+fn array_display_recusion(primitives: &[Primitive]) -> String{
+    let items: Vec<String> = primitives.iter().map(|primitive| primitive.to_string()).collect();
+    format!("[{}]", items.join(", "))
 }
 
 fn combine_variables(local_variable_map: &mut HashMap<String, (Primitive, Type)>, inherited_variables: Vec<HashMap<String, (Primitive, Type)>>) {
@@ -82,365 +156,1358 @@ fn combine_variables(local_variable_map: &mut HashMap<String, (Primitive, Type)>
     }
 }
 
-pub fn interpret(statements: &VecDeque<Statement>, inherited_variables: Vec<HashMap<String, (Primitive, Type)>>) -> HashMap<String, (Primitive, Type)> {
+pub type Variables = HashMap<String, (Primitive, Type)>;
+pub type VariableScopes = Scopes<(Primitive, Type)>;
+pub type Functions<'a> = HashMap<String, &'a Function>;
+pub type Structs = HashMap<String, Struct>;
+
+//The variables of the blocks around the running statement, innermost last.
+//A variable defined in the body of an if or a loop is dropped at its closing brace, and hides outer variables with the same name until then.
+#[derive(Debug, Clone)]
+pub struct Scopes<T> {
+    scopes: Vec<HashMap<String, T>>,
+    //The index of the top level scope, the one whose variables belong to the code being run.
+    top: usize,
+}
+
+impl<T> Default for Scopes<T> {
+    fn default() -> Scopes<T> {
+        Scopes::new(HashMap::new())
+    }
+}
+
+impl<T> Scopes<T> {
+    //Args: top_level - the variables defined before the first statement, such as imports or function parameters.
+    pub fn new(top_level: HashMap<String, T>) -> Scopes<T> {
+        Scopes { scopes: vec![top_level], top: 0 }
+    }
+
+    //Starts a new top level inside the current scopes. The variables defined so far, such as imports, can still be used and changed,
+    //but they are no longer part of the top level, so a block does not pass on variables it only imported.
+    pub fn enter_top_level(&mut self) {
+        self.enter();
+        self.top = self.scopes.len() - 1;
+    }
+
+    pub fn get(&self, name: &str) -> Option<&T> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut T> {
+        self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name))
+    }
+
+    //The variable with this name defined in the innermost scope itself, ignoring outer ones.
+    pub fn local(&self, name: &str) -> Option<&T> {
+        self.scopes.last().and_then(|scope| scope.get(name))
+    }
+
+    //Defines a variable in the innermost scope, replacing one with the same name defined there.
+    pub fn define(&mut self, name: &str, value: T) {
+        self.scopes.last_mut().expect("the top level scope is never left").insert(name.to_string(), value);
+    }
+
+    pub fn enter(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn exit(&mut self) {
+        if self.scopes.len() > self.top + 1 {
+            self.scopes.pop();
+        }
+    }
+
+    pub fn top_level(&self) -> &HashMap<String, T> {
+        &self.scopes[self.top]
+    }
+
+    pub fn into_top_level(mut self) -> HashMap<String, T> {
+        self.scopes.swap_remove(self.top)
+    }
+}
+
+//Deep enough for real recursion while staying well inside a worker thread's stack.
+const MAX_CALL_DEPTH: usize = 512;
+
+//What the code running a statement should do once it finishes.
+pub(crate) enum Flow {
+    Next,
+    Return(Option<Primitive>),
+    //Leave or restart the loop with this label, or the innermost loop for None.
+    Break(Option<String>),
+    Continue(Option<String>),
+}
+
+//The functions and structs visible to running code, and the function call it is running inside of.
+#[derive(Clone, Copy)]
+pub struct Calls<'a> {
+    functions: &'a Functions<'a>,
+    structs: &'a Structs,
+    function: Option<&'a Function>,
+    depth: usize,
+}
+
+impl<'a> Calls<'a> {
+    pub fn new(functions: &'a Functions<'a>, structs: &'a Structs) -> Calls<'a> {
+        Calls { functions, structs, function: None, depth: 0 }
+    }
+}
+
+//Runs a block.
+//Returns: the variables defined at the top level of the block, the ones blocks that require it can import.
+pub fn interpret(statements: &VecDeque<Statement>, inherited_variables: Vec<HashMap<String, (Primitive, Type)>>, structs: &Structs) -> Result<HashMap<String, (Primitive, Type)>, Diagnostic> {
     let mut local_variable_map = HashMap::new();
     combine_variables(&mut local_variable_map, inherited_variables);
-    for i in 0..statements.len() {
-        evaluate_line(&statements[i as usize], &mut local_variable_map);
+    let mut scopes = Scopes::new(local_variable_map);
+    scopes.enter_top_level();
+    let functions = collect_functions(statements)?;
+    let calls = Calls::new(&functions, structs);
+    for statement in statements {
+        if let Some(error) = stray_jump(&evaluate_line(statement, &mut scopes, calls)?) {
+            return Err(error.or_span(statement.span()));
+        }
+    }
+    Ok(scopes.into_top_level())
+}
+
+//Finds every function defined in a block so they can be called before, after and from inside each other.
+//Errors: If a function is defined twice, shares a name with a builtin, or is defined inside another statement.
+pub(crate) fn collect_functions(statements: &VecDeque<Statement>) -> Result<Functions<'_>, Diagnostic> {
+    let mut functions = Functions::new();
+    for statement in statements {
+        if let Statement::DefineFunction(function, span) = statement {
+            if lookup(&function.name).is_some() {
+                return Err(Diagnostic::new(31, format!("Function {} is already defined", function.name))
+                    .with_span(span.clone())
+                    .with_note(format!("{} is a builtin function", function.name)));
+            }
+            if functions.insert(function.name.clone(), function).is_some() {
+                return Err(Diagnostic::new(31, format!("Function {} is already defined", function.name)).with_span(span.clone()));
+            }
+            check_no_nested_functions(&function.block)?;
+        } else {
+            for block in nested_blocks(statement) {
+                check_no_nested_functions(block)?;
+            }
+        }
+    }
+    Ok(functions)
+}
+
+fn check_no_nested_functions(statements: &VecDeque<Statement>) -> Result<(), Diagnostic> {
+    for statement in statements {
+        if let Statement::DefineFunction(_, span) = statement {
+            return Err(Diagnostic::new(32, "Functions can only be defined at the top level of a block").with_span(span.clone()));
+        }
+        for block in nested_blocks(statement) {
+            check_no_nested_functions(block)?;
+        }
     }
-    local_variable_map
+    Ok(())
 }
 
-pub fn evaluate_line(statement: &Statement, local_variable_map: &mut HashMap<String, (Primitive, Type)>) {
+//The statement lists directly inside a statement, such as the body of a loop or the branches of an if.
+fn nested_blocks(statement: &Statement) -> Vec<&VecDeque<Statement>> {
     match statement {
-        Statement::FunctionCall(name, args) => {
-            if name == "print" {
-                println!("{}", args[0].evaluate(local_variable_map))
-            } else if name ==  "sleep" {
-                match args[0].evaluate(local_variable_map) {
-                    Primitive::I32(value) => {
-                        std::thread::sleep(std::time::Duration::from_secs(value as u64));
-                    }
-                    _ => {}
-                }
-            } else {
-                panic!("{}Error[5]: Function: {} does not exist{}", RED, name, RESET);
-            }
-        }
-        Statement::DefineVariable(name, value, variable_type) => {
-            local_variable_map.insert(
-                name.clone(),
-                (
-                    value.clone().evaluate(local_variable_map),
-                    variable_type.clone(),
-                ),
-            );
-        }
-        Statement::WhileLoop(condition, lines) => {
-            let mut literal_condition = condition.evaluate(local_variable_map);
-            match literal_condition {
-                Primitive::Bool(mut value) => {
-                    while value {
-                        for statement in lines {
-                            evaluate_line(statement, local_variable_map);
-                        }
-                        literal_condition = condition.evaluate(&local_variable_map);
-                        match literal_condition {
-                            Primitive::Bool(val) => value = val,
-                            _ => {}
-                        }
-                    }
+        Statement::WhileLoop(_, block, ..) | Statement::ForLoop(_, _, _, block, ..) | Statement::ForEach(_, _, block, ..) | Statement::Elif(_, block, _) => vec![block],
+        Statement::If(_, block, elifs, else_block, _) => {
+            let mut blocks = vec![block, elifs];
+            blocks.extend(else_block);
+            blocks
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn evaluate_condition(condition: &Expression, local_variable_map: &mut VariableScopes, calls: Calls) -> Result<bool, Diagnostic> {
+    match condition.evaluate(local_variable_map, calls)? {
+        Primitive::Bool(value) => Ok(value),
+        other => Err(Diagnostic::new(18, format!("Condition must be a Bool but found {}", other)).with_span(condition.span().clone())),
+    }
+}
+
+pub(crate) fn evaluate_line(statement: &Statement, local_variable_map: &mut VariableScopes, calls: Calls) -> Result<Flow, Diagnostic> {
+    execute_statement(statement, local_variable_map, calls).map_err(|error| error.or_span(statement.span()))
+}
+
+//Runs statements in order until one of them returns, breaks or continues.
+fn execute_block(statements: &VecDeque<Statement>, local_variable_map: &mut VariableScopes, calls: Calls) -> Result<Flow, Diagnostic> {
+    for statement in statements {
+        match evaluate_line(statement, local_variable_map, calls)? {
+            Flow::Next => {}
+            flow => return Ok(flow),
+        }
+    }
+    Ok(Flow::Next)
+}
+
+//Runs the body of an if or a loop in its own scope, dropping the variables it defines when it finishes.
+fn execute_scope(statements: &VecDeque<Statement>, local_variable_map: &mut VariableScopes, calls: Calls) -> Result<Flow, Diagnostic> {
+    local_variable_map.enter();
+    let flow = execute_block(statements, local_variable_map, calls);
+    local_variable_map.exit();
+    flow
+}
+
+//Whether a break or continue aimed at target is handled by the loop with this label.
+fn targets(target: &Option<String>, label: &Option<String>) -> bool {
+    target.is_none() || target == label
+}
+
+//The error for a break or continue that left every loop without being handled.
+fn stray_jump(flow: &Flow) -> Option<Diagnostic> {
+    match flow {
+        Flow::Break(None) => Some(outside_loop("break")),
+        Flow::Continue(None) => Some(outside_loop("continue")),
+        Flow::Break(Some(label)) | Flow::Continue(Some(label)) => Some(unknown_label(label)),
+        _ => None,
+    }
+}
+
+pub(crate) fn outside_loop(keyword: &str) -> Diagnostic {
+    Diagnostic::new(50, format!("{} can only be used inside a loop", keyword))
+}
+
+pub(crate) fn unknown_label(label: &str) -> Diagnostic {
+    Diagnostic::new(51, format!("Unknown loop label '{}", label)).with_note("a label names an enclosing loop, as in 'outer: while ... { break 'outer; }")
+}
+
+fn execute_statement(statement: &Statement, local_variable_map: &mut VariableScopes, calls: Calls) -> Result<Flow, Diagnostic> {
+    match statement {
+        Statement::FunctionCall(name, args, _) => match lookup(name) {
+            Some(builtin) => {
+                call_builtin(builtin, args, local_variable_map, calls)?;
+            }
+            None => {
+                call_function(name, args, local_variable_map, calls)?;
+            }
+        },
+        Statement::DefineVariable(name, value, variable_type, _) => {
+            let literal = value.evaluate(local_variable_map, calls)?;
+            local_variable_map.define(name, (literal, variable_type.clone()));
+        }
+        Statement::WhileLoop(condition, lines, label, _) => {
+            while evaluate_condition(condition, local_variable_map, calls)? {
+                match execute_scope(lines, local_variable_map, calls)? {
+                    Flow::Break(target) if targets(&target, label) => break,
+                    Flow::Continue(target) if targets(&target, label) => {}
+                    Flow::Next => {}
+                    flow => return Ok(flow),
                 }
-                _ => {}
             }
         }
-        Statement::If(condition, statements, elifs, else_) => {
-            match condition.evaluate(local_variable_map) {
-                Primitive::Bool(literal) => {
-                    if literal {
-                        for statement in statements {
-                            evaluate_line(statement, local_variable_map);
-                        }
-                    } else {
-                        'break_when_found: for elif in elifs {
-                            match elif {
-                                Statement::Elif(elif_condition, elif_block) => {
-                                    match elif_condition.evaluate(local_variable_map) {
-                                        Primitive::Bool(elif_literal) => {
-                                            if elif_literal {
-                                                for statement in elif_block {
-                                                    evaluate_line(statement, local_variable_map);
-                                                }
-                                                break 'break_when_found;
-                                            }
-                                        }
-                                        _ => {}
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                        for statement in else_.clone().unwrap() {
-                            evaluate_line(&statement, local_variable_map);
-                        }
+        Statement::If(condition, statements, elifs, else_, _) => {
+            if evaluate_condition(condition, local_variable_map, calls)? {
+                return execute_scope(statements, local_variable_map, calls);
+            }
+            for elif in elifs {
+                if let Statement::Elif(elif_condition, elif_block, _) = elif {
+                    if evaluate_condition(elif_condition, local_variable_map, calls)? {
+                        return execute_scope(elif_block, local_variable_map, calls);
                     }
                 }
-                _ => panic!("compiler made an oopsie woopsie"),
-            }
-        }
-        Statement::ModifyVariable(name, expression) => {
-            let literal = expression.evaluate(local_variable_map);
-            match local_variable_map.get(name) {
-                Some(_) => {
-                    let ty = match literal {
-                        Primitive::Bool(_) => Type::Bool,
-                        Primitive::I32(_) => Type::I32,
-                        Primitive::String(_) => Type::String,
-                        Primitive::F32(_) => Type::F32,
-                        Primitive::I64(_) => Type::I64,
-                        Primitive::F64(_) => Type::F64,
-                        Primitive::Array(_) => todo!(),
-                    };
-
-                    local_variable_map.insert(name.to_string(), (literal, ty));
-                }
-                None => {
-                    let error_message =
-                        format!("ST:NAME ERROR -> name: {} does not exist", name);
-                    panic!("{}", error_message.purple());
-                }
+            }
+            if let Some(else_block) = else_ {
+                return execute_scope(else_block, local_variable_map, calls);
             }
         }
-        Statement::ForLoop(define_variable, condition, increment, lines) => {
-            evaluate_line(define_variable, local_variable_map);
-            let mut evaluated_condition = condition.evaluate(local_variable_map);
-            match evaluated_condition {
-                Primitive::Bool(mut value) => {
-                    while value {
-                        for statement in lines {
-                            evaluate_line(statement, local_variable_map);
-                        }
-                        evaluate_line(increment, local_variable_map);
-                        evaluated_condition = condition.evaluate(local_variable_map);
-                        match evaluated_condition {
-                            Primitive::Bool(updated_value) => value = updated_value,
-                            _ => {}
-                        }
-                    }
+        Statement::ModifyVariable(name, expression, _) => {
+            let literal = match (expression, local_variable_map.get(name)) {
+                (_, None) => return Err(Diagnostic::new(6, format!("Variable {} does not exist", name))),
+                (Expression::Increment(_), Some((value, _))) => step(value, 1)?,
+                (Expression::Decrement(_), Some((value, _))) => step(value, -1)?,
+                _ => expression.evaluate(local_variable_map, calls)?,
+            };
+            //Assignments keep the declared type, the type checker has already made sure the value fits it.
+            match local_variable_map.get_mut(name) {
+                Some((value, _)) => *value = literal,
+                None => return Err(Diagnostic::new(6, format!("Variable {} does not exist", name))),
+            }
+        }
+        Statement::ModifyElement(target, expression, _) => {
+            let value = expression.evaluate(local_variable_map, calls)?;
+            let (name, accesses) = target.place().expect("the parser only builds element assignments to places");
+            let mut positions = index_positions(&accesses, local_variable_map, calls)?;
+            //The last step is taken here rather than by walk_place so that assigning to a key a map does not have yet adds it.
+            let (last, parents) = accesses.split_last().expect("an element assignment has at least one access");
+            let position = matches!(last, Access::Index(_)).then(|| positions.pop().expect("every index was evaluated"));
+            let parent = walk_place(name, parents, positions, local_variable_map)?;
+            match (last, position, parent) {
+                (Access::Index(index), Some(key), Primitive::Map(entries)) => insert_entry(entries, key, value).map_err(|error| error.with_span(index.span().clone()))?,
+                (Access::Index(index), Some(position), parent) => *element_mut(parent, position, index)? = value,
+                (Access::Field(field), _, parent) => *field_mut(parent, field)? = value,
+                (Access::Index(_), None, _) => unreachable!("an index always has a position"),
+            }
+        }
+        Statement::ForLoop(..) => {
+            //The loop variable lives in its own scope around the body, so it is dropped when the loop ends.
+            local_variable_map.enter();
+            let flow = for_loop(statement, local_variable_map, calls);
+            local_variable_map.exit();
+            return flow;
+        }
+        Statement::ForEach(variables, iterable, lines, label, _) => {
+            let items = Items::new(iterable, variables, local_variable_map, calls)?;
+            return for_each(variables, items, lines, label, local_variable_map, calls);
+        }
+        //Functions are collected before the block runs.
+        Statement::DefineFunction(..) => {}
+        Statement::Return(expression, _) => {
+            let Some(function) = calls.function else {
+                return Err(Diagnostic::new(27, "return can only be used inside a function"));
+            };
+            let value = match expression {
+                Some(expression) => Some(expression.evaluate(local_variable_map, calls)?),
+                None => None,
+            };
+            match (&function.return_type, &value) {
+                (Some(ty), Some(value)) if !value.has_type(ty) => {
+                    return Err(mismatched_type(&format!("function {}", function.name), &ty.to_string(), &value.type_name()));
+                }
+                (None, Some(value)) => {
+                    return Err(mismatched_type(&format!("function {}", function.name), "no return value", &value.type_name()));
+                }
+                (Some(ty), None) => {
+                    return Err(mismatched_type(&format!("function {}", function.name), &ty.to_string(), "no return value"));
                 }
                 _ => {}
             }
+            return Ok(Flow::Return(value));
         }
+        Statement::Break(label, _) => return Ok(Flow::Break(label.clone())),
+        Statement::Continue(label, _) => return Ok(Flow::Continue(label.clone())),
         _ => {
-            panic!("compiler found unexpected statement {:?}", statement)
+            return Err(Diagnostic::new(11, format!("Unexpected statement {:?}", statement)));
         }
     }
+    Ok(Flow::Next)
 }
 
-impl CompleteU {
-    fn evaluate(&self, variables: &HashMap<String, (Primitive, Type)>) -> Primitive {
-        match (self.child.evaluate(variables), &self.operator) {
-            (Primitive::Bool(value), UnaryOperator::Not) => Primitive::Bool(!value),
-            (Primitive::I32(value), UnaryOperator::Parenthesis) => Primitive::I32(value),
-            (Primitive::F64(value), UnaryOperator::Parenthesis) => Primitive::F64(value),
-            (Primitive::I64(value), UnaryOperator::Parenthesis) => Primitive::I64(value),
-            (Primitive::F32(value), UnaryOperator::Parenthesis) => Primitive::F32(value),
-            (Primitive::String(value), UnaryOperator::Parenthesis) => Primitive::String(value),
-            (Primitive::Bool(value), UnaryOperator::Parenthesis) => Primitive::Bool(value),
-            _ => {
-                let error_message = format!(
-                    "ST: MISMATCHED-TYPES -> Operator {:?} is not defined for {:?}",
-                    self.operator, self.child
-                );
-                panic!("{}", error_message.purple())
+fn for_loop(statement: &Statement, local_variable_map: &mut VariableScopes, calls: Calls) -> Result<Flow, Diagnostic> {
+    let Statement::ForLoop(define_variable, condition, increment, lines, label, _) = statement else {
+        unreachable!("for_loop is only called with a for loop");
+    };
+    evaluate_line(define_variable, local_variable_map, calls)?;
+    while evaluate_condition(condition, local_variable_map, calls)? {
+        match execute_scope(lines, local_variable_map, calls)? {
+            Flow::Break(target) if targets(&target, label) => break,
+            //The increment still runs before the condition is checked again.
+            Flow::Continue(target) if targets(&target, label) => {}
+            Flow::Next => {}
+            flow => return Ok(flow),
+        }
+        evaluate_line(increment, local_variable_map, calls)?;
+    }
+    Ok(Flow::Next)
+}
+
+//Each pass gets a fresh scope holding the loop variables, which hide any variables with the same names.
+fn for_each(variables: &LoopVariables, items: Items, lines: &VecDeque<Statement>, label: &Option<String>, local_variable_map: &mut VariableScopes, calls: Calls) -> Result<Flow, Diagnostic> {
+    for values in items {
+        local_variable_map.enter();
+        for (variable, value) in variables.names().into_iter().zip(values) {
+            let ty = value.value_type();
+            local_variable_map.define(variable, (value, ty));
+        }
+        let flow = execute_block(lines, local_variable_map, calls);
+        local_variable_map.exit();
+        match flow? {
+            Flow::Break(target) if targets(&target, label) => break,
+            Flow::Continue(target) if targets(&target, label) => {}
+            Flow::Next => {}
+            flow => return Ok(flow),
+        }
+    }
+    Ok(Flow::Next)
+}
+
+//The values a for-each loop gives its variable, worked out before the first iteration so the body cannot change them.
+//Each item holds one value per loop variable.
+enum Items {
+    //The elements of an array, or the keys of a map.
+    Array(std::vec::IntoIter<Primitive>),
+    //The keys and values of a map, for `for (k, v) in m`.
+    Entries(std::vec::IntoIter<(Primitive, Primitive)>),
+    //next is None once the range is used up. wide is true for i64 ranges and false for i32 ones.
+    Range { next: Option<i64>, last: i64, step: i64, wide: bool },
+}
+
+impl Items {
+    //Errors: If the iterable is not an array, map or range, a key and value are taken from anything but a map,
+    //a bound or the step is not an integer, or the step is not positive.
+    fn new(iterable: &Iterable, loop_variables: &LoopVariables, variables: &mut VariableScopes, calls: Calls) -> Result<Items, Diagnostic> {
+        let Range { start, end, inclusive, step } = match (iterable, loop_variables) {
+            (Iterable::Array(expression), _) => {
+                return match (expression.evaluate(variables, calls)?, loop_variables) {
+                    (Primitive::Array(values), LoopVariables::Single(_)) => Ok(Items::Array(values.into_iter())),
+                    (Primitive::Map(entries), LoopVariables::Single(_)) => Ok(Items::Array(entries.into_iter().map(|(key, _)| key).collect::<Vec<_>>().into_iter())),
+                    (Primitive::Map(entries), LoopVariables::Pair(..)) => Ok(Items::Entries(entries.into_iter())),
+                    (other, LoopVariables::Single(_)) => Err(mismatched_type("for loop", "an array, a map or a range", &other.type_name()).with_span(expression.span().clone())),
+                    (other, LoopVariables::Pair(..)) => Err(mismatched_type("for loop", "a map", &other.type_name()).with_span(expression.span().clone())),
+                };
+            }
+            (Iterable::Range(range), LoopVariables::Pair(..)) => return Err(mismatched_type("for loop", "a map", "a range").with_span(range.start.span().to(range.end.span()))),
+            (Iterable::Range(range), LoopVariables::Single(_)) => range.as_ref(),
+        };
+        let mut wide = false;
+        let mut bound = |expression: &Expression| match expression.evaluate(variables, calls)? {
+            Primitive::I32(value) => Ok(value as i64),
+            Primitive::I64(value) => {
+                wide = true;
+                Ok(value)
+            }
+            other => Err(mismatched_type("range", "i32 or i64 bounds", &other.type_name()).with_span(expression.span().clone())),
+        };
+        let first = bound(start)?;
+        let end = bound(end)?;
+        let step = match step {
+            Some(expression) => match bound(expression)? {
+                step if step <= 0 => return Err(Diagnostic::new(57, format!("Range step must be positive but found {}", step)).with_span(expression.span().clone())),
+                step => step,
+            },
+            None => 1,
+        };
+        //An exclusive range that ends at the smallest i64 is empty.
+        let (next, last) = match (inclusive, end.checked_sub(1)) {
+            (true, _) => (Some(first), end),
+            (false, Some(last)) => (Some(first), last),
+            (false, None) => (None, end),
+        };
+        Ok(Items::Range { next, last, step, wide })
+    }
+}
+
+impl Iterator for Items {
+    type Item = Vec<Primitive>;
+
+    fn next(&mut self) -> Option<Vec<Primitive>> {
+        match self {
+            Items::Array(values) => values.next().map(|value| vec![value]),
+            Items::Entries(entries) => entries.next().map(|(key, value)| vec![key, value]),
+            Items::Range { next, last, step, wide } => {
+                let current = next.filter(|current| current <= last)?;
+                //Stopping instead of overflowing lets a range end at the largest value of its type.
+                *next = current.checked_add(*step);
+                Some(vec![if *wide { Primitive::I64(current) } else { Primitive::I32(current as i32) }])
             }
         }
     }
 }
+
+//Calls a user defined function with a fresh frame holding only its parameters.
+//Returns: the value it returned, or None for functions without a return type.
+fn call_function(name: &str, args: &[Expression], local_variable_map: &mut VariableScopes, calls: Calls) -> Result<Option<Primitive>, Diagnostic> {
+    let Some(function) = calls.functions.get(name).copied() else {
+        return Err(Diagnostic::new(5, format!("Function: {} does not exist", name)));
+    };
+    expect_args(name, args, function.params.len())?;
+    if calls.depth >= MAX_CALL_DEPTH {
+        return Err(Diagnostic::new(33, format!("Maximum call depth of {} exceeded in {}", MAX_CALL_DEPTH, name))
+            .with_note("check that the recursion has a base case"));
+    }
+    let mut frame = Variables::new();
+    for ((param, ty), arg) in function.params.iter().zip(args) {
+        let value = arg.evaluate(local_variable_map, calls)?;
+        if !value.has_type(ty) {
+            return Err(mismatched_type(&format!("parameter {} of {}", param, name), &ty.to_string(), &value.type_name()).with_span(arg.span().clone()));
+        }
+        frame.insert(param.clone(), (value, ty.clone()));
+    }
+    let inner = Calls { function: Some(function), depth: calls.depth + 1, ..calls };
+    match execute_block(&function.block, &mut Scopes::new(frame), inner)? {
+        Flow::Return(value) => Ok(value),
+        flow @ (Flow::Break(_) | Flow::Continue(_)) => Err(stray_jump(&flow).unwrap()),
+        Flow::Next => match &function.return_type {
+            Some(ty) => Err(Diagnostic::new(29, format!("Function {} ended without returning a {}", name, ty))),
+            None => Ok(None),
+        },
+    }
+}
+
+//Finds the variable, array element, map value or struct field that a[i].x refers to, evaluating the indices before looking anything up.
+//Args: name - the variable, accesses - the steps into it, outermost first.
+//Errors: If the variable does not exist, something that is not an array or map is indexed, an index is out of bounds,
+//a key is missing or a field does not exist.
+fn place_mut<'v>(name: &str, accesses: &[Access], variables: &'v mut VariableScopes, calls: Calls) -> Result<&'v mut Primitive, Diagnostic> {
+    let positions = index_positions(accesses, variables, calls)?;
+    walk_place(name, accesses, positions, variables)
+}
+
+//Evaluates the indices in a place, outermost first.
+fn index_positions(accesses: &[Access], variables: &mut VariableScopes, calls: Calls) -> Result<Vec<Primitive>, Diagnostic> {
+    let mut positions = Vec::new();
+    for access in accesses {
+        if let Access::Index(index) = access {
+            positions.push(index.evaluate(variables, calls)?);
+        }
+    }
+    Ok(positions)
+}
+
+//Follows accesses into a variable using indices that index_positions has already evaluated.
+fn walk_place<'v>(name: &str, accesses: &[Access], positions: Vec<Primitive>, variables: &'v mut VariableScopes) -> Result<&'v mut Primitive, Diagnostic> {
+    let Some((value, _)) = variables.get_mut(name) else {
+        return Err(Diagnostic::new(6, format!("Variable {} does not exist", name)));
+    };
+    let mut value = value;
+    let mut positions = positions.into_iter();
+    for access in accesses {
+        value = match access {
+            Access::Index(index) => element_mut(value, positions.next().expect("every index was evaluated"), index)?,
+            Access::Field(field) => field_mut(value, field)?,
+        };
+    }
+    Ok(value)
+}
+
+//Finds the element of an array at position, or the value of a map under the key position.
+//Args: index - the expression position came from, which errors point at.
+//Errors: If value is not an array or map, the position is out of bounds or the key is missing.
+fn element_mut<'v>(value: &'v mut Primitive, position: Primitive, index: &Expression) -> Result<&'v mut Primitive, Diagnostic> {
+    if let Primitive::Map(entries) = value {
+        return match find_key(entries, &position) {
+            Ok(found) => Ok(&mut entries[found].1),
+            Err(_) => Err(missing_key(&position).with_span(index.span().clone())),
+        };
+    }
+    let values = elements_mut(value)?;
+    let position = array_index(position, values.len()).map_err(|error| error.with_span(index.span().clone()))?;
+    Ok(&mut values[position])
+}
+
+//Returns: where key is in a map's entries, or Err with where it would go to keep them sorted.
+pub(crate) fn find_key(entries: &[(Primitive, Primitive)], key: &Primitive) -> Result<usize, usize> {
+    entries.binary_search_by(|(existing, _)| compare_keys(existing, key))
+}
+
+//Keys of the same type compare by value. The type checker keeps a map to one key type, so keys of different types are only ordered by type name.
+fn compare_keys(left: &Primitive, right: &Primitive) -> std::cmp::Ordering {
+    match (left, right) {
+        (Primitive::String(left), Primitive::String(right)) => left.cmp(right),
+        (Primitive::I32(left), Primitive::I32(right)) => left.cmp(right),
+        (Primitive::I64(left), Primitive::I64(right)) => left.cmp(right),
+        (Primitive::Bool(left), Primitive::Bool(right)) => left.cmp(right),
+        _ => left.type_name().cmp(&right.type_name()),
+    }
+}
+
+//Adds an entry to a map, replacing the value if the key is already there.
+//Errors: If the key is of a type that cannot be a map key.
+fn insert_entry(entries: &mut Vec<(Primitive, Primitive)>, key: Primitive, value: Primitive) -> Result<(), Diagnostic> {
+    if !key.value_type().is_map_key() {
+        return Err(invalid_key(&key.type_name()));
+    }
+    match find_key(entries, &key) {
+        Ok(found) => entries[found].1 = value,
+        Err(position) => entries.insert(position, (key, value)),
+    }
+    Ok(())
+}
+
+pub(crate) fn missing_key(key: &Primitive) -> Diagnostic {
+    match key {
+        Primitive::String(key) => Diagnostic::new(63, format!("Key {:?} is not in the map", key)),
+        key => Diagnostic::new(63, format!("Key {} is not in the map", key)),
+    }
+}
+
+pub(crate) fn invalid_key(found: &str) -> Diagnostic {
+    Diagnostic::new(64, format!("{} cannot be used as a map key", found))
+}
+
+//Errors: If value is not a struct or has no field with this name.
+fn field_mut<'v>(value: &'v mut Primitive, field: &str) -> Result<&'v mut Primitive, Diagnostic> {
+    match value {
+        Primitive::Struct(name, fields) => match fields.iter_mut().find(|(existing, _)| existing == field) {
+            Some((_, value)) => Ok(value),
+            None => Err(no_field(name, field)),
+        },
+        other => Err(not_a_struct(field, &other.type_name())),
+    }
+}
+
+pub(crate) fn no_field(name: &str, field: &str) -> Diagnostic {
+    Diagnostic::new(59, format!("Struct {} has no field {}", name, field))
+}
+
+pub(crate) fn not_a_struct(field: &str, found: &str) -> Diagnostic {
+    Diagnostic::new(62, format!("Cannot access field {} of {}", field, found))
+}
+
+//Builds a struct from the fields of a literal, putting them in the order they are declared in.
+//Errors: If the struct is not declared, or a field is missing, unknown or given twice.
+fn build_struct(name: &str, mut values: Vec<(String, Primitive)>, structs: &Structs) -> Result<Primitive, Diagnostic> {
+    let Some(declared) = structs.get(name) else {
+        return Err(Diagnostic::new(14, format!("Unknown type {}", name)));
+    };
+    let mut fields = Vec::new();
+    for (field, ty) in &declared.fields {
+        let Some(position) = values.iter().position(|(given, _)| given == field) else {
+            return Err(missing_field(name, field));
+        };
+        let (_, value) = values.remove(position);
+        if !value.has_type(ty) {
+            return Err(mismatched_type(&format!("field {} of {}", field, name), &ty.to_string(), &value.type_name()));
+        }
+        fields.push((field.clone(), value));
+    }
+    match values.first() {
+        Some((field, _)) if declared.field(field).is_some() => Err(repeated_field(name, field)),
+        Some((field, _)) => Err(no_field(name, field)),
+        None => Ok(Primitive::Struct(name.to_string(), fields)),
+    }
+}
+
+pub(crate) fn repeated_field(name: &str, field: &str) -> Diagnostic {
+    Diagnostic::new(61, format!("Field {} appears more than once in struct {}", field, name))
+}
+
+pub(crate) fn missing_field(name: &str, field: &str) -> Diagnostic {
+    Diagnostic::new(60, format!("Struct {} is missing field {}", name, field))
+}
+
+//Evaluates an expression, borrowing a variable or array element instead of copying it when it is one.
+fn with_value<T>(expression: &Expression, variables: &mut VariableScopes, calls: Calls, read: impl FnOnce(&Primitive) -> Result<T, Diagnostic>) -> Result<T, Diagnostic> {
+    match expression.place() {
+        Some((name, accesses)) => read(place_mut(name, &accesses, variables, calls).map_err(|error| error.or_span(expression.span()))?),
+        None => read(&expression.evaluate(variables, calls)?),
+    }
+}
+
+//The variable, or the element or field inside one, that the first argument of push or pop changes.
+//Errors: If the argument is any other expression.
+pub(crate) fn changed_place<'e>(function: &str, arg: &'e Expression) -> Result<(&'e str, Vec<Access<'e>>), Diagnostic> {
+    arg.place().ok_or_else(|| {
+        Diagnostic::new(56, format!("{} can only change a variable or an element of one", function)).with_span(arg.span().clone())
+    })
+}
+
+fn elements_mut(value: &mut Primitive) -> Result<&mut Vec<Primitive>, Diagnostic> {
+    match value {
+        Primitive::Array(values) => Ok(values),
+        other => Err(Diagnostic::new(55, format!("Cannot index into {}", other.type_name()))),
+    }
+}
+
+fn integer_index(index: Primitive) -> Result<i64, Diagnostic> {
+    match index {
+        Primitive::I32(value) => Ok(value as i64),
+        Primitive::I64(value) => Ok(value),
+        other => Err(mismatched_type("array index", "i32 or i64", &other.type_name())),
+    }
+}
+
+//Errors: If index is not an integer or is not a position in an array of this length.
+fn array_index(index: Primitive, length: usize) -> Result<usize, Diagnostic> {
+    let index = integer_index(index)?;
+    match usize::try_from(index) {
+        Ok(position) if position < length => Ok(position),
+        _ => Err(Diagnostic::new(52, format!("Index {} is out of bounds for an array of length {}", index, length))),
+    }
+}
+
+//Args: start, end - the evaluated bounds of a[start..end], None where they were left out.
+//Errors: If a bound is not an integer, or the range is backwards or reaches past the end of the array.
+fn slice_range(start: Option<Primitive>, end: Option<Primitive>, length: usize) -> Result<(usize, usize), Diagnostic> {
+    let start = start.map(integer_index).transpose()?.unwrap_or(0);
+    let end = end.map(integer_index).transpose()?.unwrap_or(length as i64);
+    if 0 <= start && start <= end && end <= length as i64 {
+        Ok((start as usize, end as usize))
+    } else {
+        Err(Diagnostic::new(53, format!("Slice {}..{} is out of range for an array of length {}", start, end, length)))
+    }
+}
+
+fn mismatched_type(what: &str, expected: &str, found: &str) -> Diagnostic {
+    Diagnostic::new(28, format!("Mismatched type: {} expects {} but found {}", what, expected, found))
+}
+
+//Adds amount to a numeric value for ++ and --.
+//Errors: If an integer overflows, reported like the + or - it stands for.
+fn step(value: &Primitive, amount: i32) -> Result<Primitive, Diagnostic> {
+    let operator = if amount > 0 { BinaryOperator::Add } else { BinaryOperator::Subtract };
+    match value {
+        Primitive::I32(value) => value.checked_add(amount).map(Primitive::I32).ok_or_else(|| overflow(&operator)),
+        Primitive::I64(value) => value.checked_add(amount as i64).map(Primitive::I64).ok_or_else(|| overflow(&operator)),
+        Primitive::F32(value) => Ok(Primitive::F32(value + amount as f32)),
+        Primitive::F64(value) => Ok(Primitive::F64(value + amount as f64)),
+        other => Err(Diagnostic::new(17, format!("Operator {} is not defined for {}", if amount > 0 { "++" } else { "--" }, other))),
+    }
+}
+
+fn mismatched_types(operator: &BinaryOperator, left: &str, right: &str) -> Diagnostic {
+    Diagnostic::new(17, format!("Operator {:?} is not defined for {} and {}", operator, left, right))
+}
+
+fn overflow(operator: &BinaryOperator) -> Diagnostic {
+    Diagnostic::new(22, format!("Integer overflow in {:?}", operator))
+}
+
+fn expect_args(name: &str, args: &[Expression], count: usize) -> Result<(), Diagnostic> {
+    if args.len() != count {
+        return Err(Diagnostic::new(21, format!("Function {} expects {} argument(s) but found {}", name, count, args.len())));
+    }
+    Ok(())
+}
+
+fn negate_overflow() -> Diagnostic {
+    Diagnostic::new(22, format!("Integer overflow in {:?}", UnaryOperator::Negate))
+}
+
+fn division_by_zero() -> Diagnostic {
+    Diagnostic::new(19, "Division by zero")
+}
+
+impl CompleteU {
+    fn evaluate(&self, variables: &mut VariableScopes, calls: Calls) -> Result<Primitive, Diagnostic> {
+        match (self.child.evaluate(variables, calls)?, &self.operator) {
+            (Primitive::Bool(value), UnaryOperator::Not) => Ok(Primitive::Bool(!value)),
+            (Primitive::I32(value), UnaryOperator::Negate) => value.checked_neg().map(Primitive::I32).ok_or_else(negate_overflow),
+            (Primitive::I64(value), UnaryOperator::Negate) => value.checked_neg().map(Primitive::I64).ok_or_else(negate_overflow),
+            (Primitive::F32(value), UnaryOperator::Negate) => Ok(Primitive::F32(-value)),
+            (Primitive::F64(value), UnaryOperator::Negate) => Ok(Primitive::F64(-value)),
+            (value, UnaryOperator::Parenthesis) => Ok(value),
+            (value, operator) => Err(Diagnostic::new(17, format!("Operator {:?} is not defined for {}", operator, value))),
+        }
+    }
+}
 impl Complete {
-    fn evaluate(&self, variables: &HashMap<String, (Primitive, Type)>) -> Primitive {
-        match (
-            self.left.evaluate(variables),
-            self.right.evaluate(variables),
-        ) {
+    fn evaluate(&self, variables: &mut VariableScopes, calls: Calls) -> Result<Primitive, Diagnostic> {
+        let left = self.left.evaluate(variables, calls)?;
+        //The right operand of && and || only runs when it can change the result, so `i < len(a) && a[i] == 1` never indexes past the end.
+        match (&left, &self.operator) {
+            (Primitive::Bool(false), BinaryOperator::And) => return Ok(Primitive::Bool(false)),
+            (Primitive::Bool(true), BinaryOperator::Or) => return Ok(Primitive::Bool(true)),
+            _ => {}
+        }
+        match (left, self.right.evaluate(variables, calls)?) {
             (Primitive::Bool(left), Primitive::Bool(right)) => {
                 match self.operator {
-                    BinaryOperator::Or => Primitive::Bool(left || right),
-                    BinaryOperator::And => Primitive::Bool(left && right),
-                    _ => {
-                        let error_message = format!("ST: MISMATCHED-TYPES -> Operator {:?} is not defined for bool and bool", self.operator);
-                        panic!("{}", error_message.purple())
-                    }
+                    BinaryOperator::Or => Ok(Primitive::Bool(left || right)),
+                    BinaryOperator::And => Ok(Primitive::Bool(left && right)),
+                    _ => Err(mismatched_types(&self.operator, "bool", "bool")),
                 }
             }
             (Primitive::I32(left), Primitive::I32(right)) => match self.operator {
-                BinaryOperator::Add => Primitive::I32(left + right),
-                BinaryOperator::Subtract => Primitive::I32(left - right),
-                BinaryOperator::Multiply => Primitive::I32(left * right),
-                BinaryOperator::Divide => Primitive::I32(left / right),
-                BinaryOperator::Equals => Primitive::Bool(left == right),
-                BinaryOperator::LessThan => Primitive::Bool(left < right),
-                BinaryOperator::LessThanOrEqualTo => Primitive::Bool(left <= right),
-                BinaryOperator::GreaterThan => Primitive::Bool(left > right),
-                BinaryOperator::GreaterThanOrEqualTo => Primitive::Bool(left >= right),
-                BinaryOperator::Modulus => Primitive::I32(left % right),
-                BinaryOperator::NotEqual => Primitive::Bool(left != right),
-                _ => {
-                    let error_message = format!(
-                        "ST: MISMATCHED-TYPES -> Operator {:?} is not defined for i32 and i32",
-                        self.operator
-                    );
-                    panic!("{}", error_message.purple())
-                }
+                BinaryOperator::Add => left.checked_add(right).map(Primitive::I32).ok_or_else(|| overflow(&self.operator)),
+                BinaryOperator::Subtract => left.checked_sub(right).map(Primitive::I32).ok_or_else(|| overflow(&self.operator)),
+                BinaryOperator::Multiply => left.checked_mul(right).map(Primitive::I32).ok_or_else(|| overflow(&self.operator)),
+                BinaryOperator::Divide => left.checked_div(right).map(Primitive::I32).ok_or_else(division_by_zero),
+                BinaryOperator::Equals => Ok(Primitive::Bool(left == right)),
+                BinaryOperator::LessThan => Ok(Primitive::Bool(left < right)),
+                BinaryOperator::LessThanOrEqualTo => Ok(Primitive::Bool(left <= right)),
+                BinaryOperator::GreaterThan => Ok(Primitive::Bool(left > right)),
+                BinaryOperator::GreaterThanOrEqualTo => Ok(Primitive::Bool(left >= right)),
+                BinaryOperator::Modulus => left.checked_rem(right).map(Primitive::I32).ok_or_else(division_by_zero),
+                BinaryOperator::NotEqual => Ok(Primitive::Bool(left != right)),
+                _ => Err(mismatched_types(&self.operator, "i32", "i32")),
             },
             (Primitive::F32(left), Primitive::F32(right)) => match self.operator {
-                BinaryOperator::Add => Primitive::F32(left + right),
-                BinaryOperator::Subtract => Primitive::F32(left - right),
-                BinaryOperator::Multiply => Primitive::F32(left * right),
-                BinaryOperator::Divide => Primitive::F32(left / right),
-                BinaryOperator::Equals => Primitive::Bool(left == right),
-                BinaryOperator::LessThan => Primitive::Bool(left < right),
-                BinaryOperator::LessThanOrEqualTo => Primitive::Bool(left <= right),
-                BinaryOperator::GreaterThan => Primitive::Bool(left > right),
-                BinaryOperator::GreaterThanOrEqualTo => Primitive::Bool(left >= right),
-                BinaryOperator::Modulus => Primitive::F32(left % right),
-                BinaryOperator::NotEqual => Primitive::Bool(left != right),
-                _ => {
-                    let error_message = format!(
-                        "ST: MISMATCHED-TYPES -> Operator {:?} is not defined for f32 and f32",
-                        self.operator
-                    );
-                    panic!("{}", error_message.purple())
-                }
+                BinaryOperator::Add => Ok(Primitive::F32(left + right)),
+                BinaryOperator::Subtract => Ok(Primitive::F32(left - right)),
+                BinaryOperator::Multiply => Ok(Primitive::F32(left * right)),
+                BinaryOperator::Divide => Ok(Primitive::F32(left / right)),
+                BinaryOperator::Equals => Ok(Primitive::Bool(left == right)),
+                BinaryOperator::LessThan => Ok(Primitive::Bool(left < right)),
+                BinaryOperator::LessThanOrEqualTo => Ok(Primitive::Bool(left <= right)),
+                BinaryOperator::GreaterThan => Ok(Primitive::Bool(left > right)),
+                BinaryOperator::GreaterThanOrEqualTo => Ok(Primitive::Bool(left >= right)),
+                BinaryOperator::Modulus => Ok(Primitive::F32(left % right)),
+                BinaryOperator::NotEqual => Ok(Primitive::Bool(left != right)),
+                _ => Err(mismatched_types(&self.operator, "f32", "f32")),
             },
             (Primitive::I64(left), Primitive::I64(right)) => match self.operator {
-                BinaryOperator::Add => Primitive::I64(left + right),
-                BinaryOperator::Subtract => Primitive::I64(left - right),
-                BinaryOperator::Multiply => Primitive::I64(left * right),
-                BinaryOperator::Divide => Primitive::I64(left / right),
-                BinaryOperator::Equals => Primitive::Bool(left == right),
-                BinaryOperator::LessThan => Primitive::Bool(left < right),
-                BinaryOperator::LessThanOrEqualTo => Primitive::Bool(left <= right),
-                BinaryOperator::GreaterThan => Primitive::Bool(left > right),
-                BinaryOperator::GreaterThanOrEqualTo => Primitive::Bool(left >= right),
-                BinaryOperator::Modulus => Primitive::I64(left % right),
-                BinaryOperator::NotEqual => Primitive::Bool(left != right),
-                _ => {
-                    let error_message = format!(
-                        "ST: MISMATCHED-TYPES -> Operator {:?} is not defined for i64 and i64",
-                        self.operator
-                    );
-                    panic!("{}", error_message.purple())
-                }
+                BinaryOperator::Add => left.checked_add(right).map(Primitive::I64).ok_or_else(|| overflow(&self.operator)),
+                BinaryOperator::Subtract => left.checked_sub(right).map(Primitive::I64).ok_or_else(|| overflow(&self.operator)),
+                BinaryOperator::Multiply => left.checked_mul(right).map(Primitive::I64).ok_or_else(|| overflow(&self.operator)),
+                BinaryOperator::Divide => left.checked_div(right).map(Primitive::I64).ok_or_else(division_by_zero),
+                BinaryOperator::Equals => Ok(Primitive::Bool(left == right)),
+                BinaryOperator::LessThan => Ok(Primitive::Bool(left < right)),
+                BinaryOperator::LessThanOrEqualTo => Ok(Primitive::Bool(left <= right)),
+                BinaryOperator::GreaterThan => Ok(Primitive::Bool(left > right)),
+                BinaryOperator::GreaterThanOrEqualTo => Ok(Primitive::Bool(left >= right)),
+                BinaryOperator::Modulus => left.checked_rem(right).map(Primitive::I64).ok_or_else(division_by_zero),
+                BinaryOperator::NotEqual => Ok(Primitive::Bool(left != right)),
+                _ => Err(mismatched_types(&self.operator, "i64", "i64")),
             },
             (Primitive::F64(left), Primitive::F64(right)) => match self.operator {
-                BinaryOperator::Add => Primitive::F64(left + right),
-                BinaryOperator::Subtract => Primitive::F64(left - right),
-                BinaryOperator::Multiply => Primitive::F64(left * right),
-                BinaryOperator::Divide => Primitive::F64(left / right),
-                BinaryOperator::Equals => Primitive::Bool(left == right),
-                BinaryOperator::LessThan => Primitive::Bool(left < right),
-                BinaryOperator::LessThanOrEqualTo => Primitive::Bool(left <= right),
-                BinaryOperator::GreaterThan => Primitive::Bool(left > right),
-                BinaryOperator::GreaterThanOrEqualTo => Primitive::Bool(left >= right),
-                BinaryOperator::Modulus => Primitive::F64(left % right),
-                BinaryOperator::NotEqual => Primitive::Bool(left != right),
-                _ => {
-                    let error_message = format!(
-                        "ST: MISMATCHED-TYPES -> Operator {:?} is not defined for f64 and f64",
-                        self.operator
-                    );
-                    panic!("{}", error_message.purple())
-                }
+                BinaryOperator::Add => Ok(Primitive::F64(left + right)),
+                BinaryOperator::Subtract => Ok(Primitive::F64(left - right)),
+                BinaryOperator::Multiply => Ok(Primitive::F64(left * right)),
+                BinaryOperator::Divide => Ok(Primitive::F64(left / right)),
+                BinaryOperator::Equals => Ok(Primitive::Bool(left == right)),
+                BinaryOperator::LessThan => Ok(Primitive::Bool(left < right)),
+                BinaryOperator::LessThanOrEqualTo => Ok(Primitive::Bool(left <= right)),
+                BinaryOperator::GreaterThan => Ok(Primitive::Bool(left > right)),
+                BinaryOperator::GreaterThanOrEqualTo => Ok(Primitive::Bool(left >= right)),
+                BinaryOperator::Modulus => Ok(Primitive::F64(left % right)),
+                BinaryOperator::NotEqual => Ok(Primitive::Bool(left != right)),
+                _ => Err(mismatched_types(&self.operator, "f64", "f64")),
+            },
+            (Primitive::String(left), Primitive::String(right)) => match self.operator {
+                BinaryOperator::Add => Ok(Primitive::String(left + &right)),
+                BinaryOperator::Equals => Ok(Primitive::Bool(left == right)),
+                BinaryOperator::LessThan => Ok(Primitive::Bool(left < right)),
+                BinaryOperator::LessThanOrEqualTo => Ok(Primitive::Bool(left <= right)),
+                BinaryOperator::GreaterThan => Ok(Primitive::Bool(left > right)),
+                BinaryOperator::GreaterThanOrEqualTo => Ok(Primitive::Bool(left >= right)),
+                BinaryOperator::NotEqual => Ok(Primitive::Bool(left != right)),
+                _ => Err(mismatched_types(&self.operator, "String", "String")),
             },
             (Primitive::I64(left), Primitive::I32(right)) => match self.operator {
-                BinaryOperator::Equals => Primitive::Bool(left == right as i64),
-                BinaryOperator::LessThan => Primitive::Bool(left < right as i64),
-                BinaryOperator::LessThanOrEqualTo => Primitive::Bool(left <= right as i64),
-                BinaryOperator::GreaterThan => Primitive::Bool(left > right as i64),
-                BinaryOperator::GreaterThanOrEqualTo => Primitive::Bool(left >= right as i64),
-                BinaryOperator::NotEqual => Primitive::Bool(left != right as i64),
-                _ => {
-                    let error_message = format!(
-                        "ST: MISMATCHED-TYPES -> Operator {:?} is not defined for i64 and i32",
-                        self.operator
-                    );
-                    panic!("{}", error_message.purple())
-                }
+                BinaryOperator::Equals => Ok(Primitive::Bool(left == right as i64)),
+                BinaryOperator::LessThan => Ok(Primitive::Bool(left < right as i64)),
+                BinaryOperator::LessThanOrEqualTo => Ok(Primitive::Bool(left <= right as i64)),
+                BinaryOperator::GreaterThan => Ok(Primitive::Bool(left > right as i64)),
+                BinaryOperator::GreaterThanOrEqualTo => Ok(Primitive::Bool(left >= right as i64)),
+                BinaryOperator::NotEqual => Ok(Primitive::Bool(left != right as i64)),
+                _ => Err(mismatched_types(&self.operator, "i64", "i32")),
             },
-            (a, b) => {
-                let error_message = format!(
-                    "ST: MISMATCHED-TYPES -> Operator {:?} is not defined for {} and {}",
-                    self.operator, a, b
-                );
-                panic!("{}", error_message.purple())
-            }
+            (a, b) => Err(mismatched_types(&self.operator, &a.type_name(), &b.type_name())),
         }
     }
 }
+
+//Evaluates the arguments of a builtin and runs it.
+//A first argument that names a variable, or an element or field of one, is moved out for the call and back after it, so neither len nor push copies an array.
+//Errors: If an argument does not fit the builtin, or the builtin itself fails.
+fn call_builtin(builtin: &Builtin, args: &[Expression], variables: &mut VariableScopes, calls: Calls) -> Result<Option<Primitive>, Diagnostic> {
+    builtin.expect_arg_count(args.len())?;
+    let place = match args.first() {
+        Some(first) if builtin.changes_first => Some(changed_place(builtin.name, first)?),
+        Some(first) => first.place(),
+        None => None,
+    };
+    let mut values = Vec::new();
+    for arg in &args[usize::from(place.is_some())..] {
+        values.push(arg.evaluate(variables, calls)?);
+    }
+    let with_span = |(position, error): (Option<usize>, Diagnostic)| match position {
+        Some(position) => error.with_span(args[position].span().clone()),
+        None => error,
+    };
+    let Some((name, accesses)) = place else {
+        return builtin.call(&mut values).map_err(with_span);
+    };
+    let first = place_mut(name, &accesses, variables, calls).map_err(|error| error.or_span(args[0].span()))?;
+    values.insert(0, std::mem::replace(first, Primitive::Bool(false)));
+    let result = builtin.call(&mut values);
+    *first = values.swap_remove(0);
+    result.map_err(with_span)
+}
+
 impl Expression {
-    pub fn evaluate(&self, variables: &HashMap<String, (Primitive, Type)>) -> Primitive {
+    pub fn evaluate(&self, variables: &mut VariableScopes, calls: Calls) -> Result<Primitive, Diagnostic> {
+        self.evaluate_primitive(variables, calls).map_err(|error| error.or_span(self.span()))
+    }
+
+    fn evaluate_primitive(&self, variables: &mut VariableScopes, calls: Calls) -> Result<Primitive, Diagnostic> {
         match self {
-            Expression::Array(value) => {
+            Expression::Array(value, _) => {
                 let mut array = Vec::new();
                 for exp in value {
-                    array.push(exp.evaluate(variables))
-                }
-                return Primitive::Array(array);
-            }
-            Expression::String(value) => Primitive::String(value.clone()),
-            Expression::Bool(value) => Primitive::Bool(*value),
-            Expression::Variable(name) => match variables.get(name) {
-                Some((value, _)) => value.clone(),
-                None => {
-                    let error_message = format!("{}Error[6]: Variable {} does not exist{}", RED, name, RESET);
-                    panic!("{}", error_message.purple());
+                    array.push(exp.evaluate(variables, calls)?)
                 }
+                Ok(Primitive::Array(array))
+            }
+            Expression::String(value, _) => Ok(Primitive::String(value.clone())),
+            Expression::Bool(value, _) => Ok(Primitive::Bool(*value)),
+            Expression::Variable(name, _) => match variables.get(name) {
+                Some((value, _)) => Ok(value.clone()),
+                None => Err(Diagnostic::new(6, format!("Variable {} does not exist", name))),
             },
-            Expression::Complete(complete) => complete.evaluate(variables),
-            Expression::CompleteU(complete_u) => complete_u.evaluate(variables),
-            Expression::Increment => todo!(),
-            Expression::Decrement => todo!(),
-            Expression::I32(value) => Primitive::I32(*value),
-            Expression::I64(value) => Primitive::I64(*value),
-            Expression::F32(value) => Primitive::F32(*value),
-            Expression::F64(value) => Primitive::F64(*value),
-            Expression::FunctionCall(name, args) => {
-                if name == "i32" {
-                    match args[0].evaluate(variables) {
-                        Primitive::I32(value) => return Primitive::I32(value as i32),
-                        Primitive::I64(value) => return Primitive::I32(value as i32),
-                        Primitive::F32(value) => return Primitive::I32(value as i32),
-                        Primitive::F64(value) => return Primitive::I32(value as i32),
-                        value => panic!("{}Error[8]: Failed to cast {} to i32{}", RED, value, RESET),
-                    }
-                } else if name == "i64" {
-                    match args[0].evaluate(variables) {
-                        Primitive::I32(value) => return Primitive::I64(value as i64),
-                        Primitive::I64(value) => return Primitive::I64(value as i64),
-                        Primitive::F32(value) => return Primitive::I64(value as i64),
-                        Primitive::F64(value) => return Primitive::I64(value as i64),
-                        value => panic!("{}Error[8]: Failed to cast {} to i64{}", RED, value, RESET),
-                    }
-                } else if name == "f64" {
-                    match args[0].evaluate(variables) {
-                        Primitive::I32(value) => return Primitive::F64(value as f64),
-                        Primitive::I64(value) => return Primitive::F64(value as f64),
-                        Primitive::F32(value) => return Primitive::F64(value as f64),
-                        Primitive::F64(value) => return Primitive::F64(value as f64),
-                        value => panic!("{}Error[8]: Failed to cast {} to f64{}", RED, value, RESET),
-                    }
-                } else if name == "f32" {
-                    match args[0].evaluate(variables) {
-                        Primitive::I32(value) => return Primitive::F32(value as f32),
-                        Primitive::I64(value) => return Primitive::F32(value as f32),
-                        Primitive::F32(value) => return Primitive::F32(value as f32),
-                        Primitive::F64(value) => return Primitive::F32(value as f32),
-                        value => panic!("{}Error[8]: Failed to cast {} to f32{}", RED, value, RESET),
+            Expression::Complete(complete, _) => complete.evaluate(variables, calls),
+            Expression::CompleteU(complete_u, _) => complete_u.evaluate(variables, calls),
+            Expression::I32(value, _) => Ok(Primitive::I32(*value)),
+            Expression::I64(value, _) => Ok(Primitive::I64(*value)),
+            Expression::F32(value, _) => Ok(Primitive::F32(*value)),
+            Expression::F64(value, _) => Ok(Primitive::F64(*value)),
+            Expression::Index(target, index, _) => {
+                if let Some((name, accesses)) = self.place() {
+                    return place_mut(name, &accesses, variables, calls).cloned();
+                }
+                let mut target = target.evaluate(variables, calls)?;
+                let position = index.evaluate(variables, calls)?;
+                if let Primitive::Map(_) = target {
+                    return element_mut(&mut target, position, index).cloned();
+                }
+                let values = elements_mut(&mut target)?;
+                let position = array_index(position, values.len()).map_err(|error| error.with_span(index.span().clone()))?;
+                Ok(values.swap_remove(position))
+            }
+            Expression::Field(target, field, _) => {
+                if let Some((name, accesses)) = self.place() {
+                    return place_mut(name, &accesses, variables, calls).cloned();
+                }
+                let mut target = target.evaluate(variables, calls)?;
+                field_mut(&mut target, field).cloned()
+            }
+            Expression::Struct(name, fields, _) => {
+                let mut values = Vec::new();
+                for (field, expression) in fields {
+                    values.push((field.clone(), expression.evaluate(variables, calls)?));
+                }
+                build_struct(name, values, calls.structs)
+            }
+            //Later entries replace earlier ones with the same key.
+            Expression::Map(entries, _) => {
+                let mut map = Vec::new();
+                for (key, value) in entries {
+                    let evaluated = key.evaluate(variables, calls)?;
+                    let value = value.evaluate(variables, calls)?;
+                    insert_entry(&mut map, evaluated, value).map_err(|error| error.with_span(key.span().clone()))?;
+                }
+                Ok(Primitive::Map(map))
+            }
+            Expression::Slice(target, start, end, _) => {
+                let start = start.as_ref().map(|start| start.evaluate(variables, calls)).transpose()?;
+                let end = end.as_ref().map(|end| end.evaluate(variables, calls)).transpose()?;
+                with_value(target, variables, calls, |target| match target {
+                    Primitive::Array(values) => {
+                        let (start, end) = slice_range(start, end, values.len())?;
+                        Ok(Primitive::Array(values[start..end].to_vec()))
                     }
-                } else if name == "string" {
-                    match args[0].evaluate(variables) {
-                        Primitive::I32(value) => return Primitive::String(value.to_string()),
-                        Primitive::I64(value) => return Primitive::String(value.to_string()),
-                        Primitive::F32(value) => return Primitive::String(value.to_string()),
-                        Primitive::F64(value) => return Primitive::String(value.to_string()),
-                        Primitive::String(value) => return Primitive::String(value),
-                        Primitive::Bool(value) => return Primitive::String(value.to_string()),
-                        value => panic!("{}Error[8]: Failed to cast {} to string{}", RED, value, RESET),
+                    other => Err(Diagnostic::new(55, format!("Cannot index into {}", other.type_name()))),
+                })
+            }
+            Expression::FunctionCall(name, args, _) => {
+                let returned = match lookup(name) {
+                    Some(builtin) => call_builtin(builtin, args, variables, calls)?,
+                    None => call_function(name, args, variables, calls)?,
+                };
+                returned.ok_or_else(|| Diagnostic::new(30, format!("Function {} does not return a value", name)))
+            }
+            _ => Err(Diagnostic::new(11, format!("Unexpected expression {:?}", self))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{builtins::builtin_structs, diagnostic::{Diagnostic, SourceFile}, interpreter::{interpret, Primitive, Variables}, parse::{parse, parse_struct}, thread_handler::WORKER_STACK_SIZE, tokenizer::tokenize};
+
+    fn run(text: &str) -> Result<Variables, Diagnostic> {
+        run_with_structs(&[], text)
+    }
+
+    //Args: declarations - struct declarations such as "struct P { x: i32 }" that text can use.
+    fn run_with_structs(declarations: &[&str], text: &str) -> Result<Variables, Diagnostic> {
+        let mut structs = builtin_structs();
+        for declaration in declarations {
+            let source = SourceFile::new("test.st", declaration.to_string());
+            let declared = parse_struct(&mut tokenize(&source, 0, declaration.len())?)?;
+            structs.insert(declared.name.clone(), declared);
+        }
+        let source = SourceFile::new("test.st", text.to_string());
+        let mut tokens = tokenize(&source, 0, text.len())?;
+        let statements = parse(&mut tokens)?;
+        interpret(&statements, Vec::new(), &structs)
+    }
+
+    #[test]
+    fn recursive_function() {
+        let variables = run("
+            let x: i32 = fact(5);
+            fn fact(n: i32) -> i32 {
+                if n < 2 {
+                    return 1;
+                }
+                return n * fact(n - 1);
+            }
+        ").unwrap();
+        assert_eq!(variables["x"].0, Primitive::I32(120));
+    }
+
+    #[test]
+    fn negation() {
+        let variables = run("let a: i32 = -5;\nlet b: i64 = -(i64(a) * 2i64);\nlet c: f32 = -2.5f32;\nlet d: f64 = -c;").unwrap();
+        assert_eq!(variables["a"].0, Primitive::I32(-5));
+        assert_eq!(variables["b"].0, Primitive::I64(10));
+        assert_eq!(variables["c"].0, Primitive::F32(-2.5));
+        assert_eq!(run("let a: i32 = -2147483648;\nlet b: i32 = -a;").unwrap_err().code, 22);
+        assert_eq!(run("let a: Bool = -true;").unwrap_err().code, 17);
+    }
+
+    #[test]
+    fn mismatched_operands_name_their_types() {
+        let error = run("let a: i32 = 3 + \"hello\";").unwrap_err();
+        assert_eq!((error.code, error.message.as_str()), (17, "Operator Add is not defined for i32 and String"));
+    }
+
+    #[test]
+    fn step_overflow() {
+        let error = run("let x: i32 = 2147483647;\nx++;").unwrap_err();
+        assert_eq!((error.code, error.message.as_str()), (22, "Integer overflow in Add"));
+        let error = run("let y: i64 = -9223372036854775807i64 - 1i64;\ny--;").unwrap_err();
+        assert_eq!((error.code, error.message.as_str()), (22, "Integer overflow in Subtract"));
+        let variables = run("let x: i32 = 2147483646;\nx++;\nlet y: i64 = 0i64;\ny--;").unwrap();
+        assert_eq!(variables["x"].0, Primitive::I32(2147483647));
+        assert_eq!(variables["y"].0, Primitive::I64(-1));
+    }
+
+    #[test]
+    fn comparisons_combine_with_logic() {
+        let variables = run("let a: i32 = 1;\nlet b: Bool = a < 2 && a > 0 || a == 5;\nlet c: i32 = 10 - 4 - 3 * 2 % 4;").unwrap();
+        assert_eq!(variables["b"].0, Primitive::Bool(true));
+        assert_eq!(variables["c"].0, Primitive::I32(4));
+    }
+
+    #[test]
+    fn logic_short_circuits() {
+        let variables = run("
+            let a: Array<i32> = [1];
+            let i: i32 = 3;
+            let inside: Bool = i < len(a) && a[i] == 1;
+            let outside: Bool = i >= len(a) || a[i] == 1;
+            let both: Bool = i > 0 && i < 5;
+        ").unwrap();
+        assert_eq!(variables["inside"].0, Primitive::Bool(false));
+        assert_eq!(variables["outside"].0, Primitive::Bool(true));
+        assert_eq!(variables["both"].0, Primitive::Bool(true));
+        assert_eq!(run("let a: Array<i32> = [1];\nlet b: Bool = true && a[3] == 1;").unwrap_err().code, 52);
+    }
+
+    #[test]
+    fn break_and_continue() {
+        let variables = run("let found: i32 = 0;\nlet i: i32 = 2;\nwhile true {\n    if 91 % i == 0 {\n        found = i;\n        break;\n    }\n    i++;\n}\nprint(found);\nlet odd: i32 = 0;\nfor (let j: i32 = 0; j < 10; j++) {\n    if j % 2 == 0 {\n        continue;\n    }\n    odd = odd + j;\n}\nprint(odd);\nlet pairs: i32 = 0;\n'outer: for (let a: i32 = 1; a < 10; a++) {\n    let b: i32 = 0;\n    'inner: while b < 10 {\n        b++;\n        if b > a {\n            continue 'outer;\n        }\n        if a * b == 24 {\n            break 'outer;\n        }\n        pairs++;\n    }\n}\nprint(pairs);").unwrap();
+        assert_eq!(variables["found"].0, Primitive::I32(7));
+        assert_eq!(variables["odd"].0, Primitive::I32(25));
+        assert_eq!(variables["pairs"].0, Primitive::I32(18));
+    }
+
+    #[test]
+    fn jumps_outside_loops_are_errors() {
+        assert_eq!(run("break;").unwrap_err().code, 50);
+        assert_eq!(run("fn f() {\n    continue;\n}\nwhile true {\n    f();\n}").unwrap_err().code, 50);
+        assert_eq!(run("while true {\n    break 'nowhere;\n}").unwrap_err().code, 51);
+    }
+
+    #[test]
+    fn array_operations() {
+        let variables = run("
+            let a: Array<i32> = [3, 1, 4];
+            let grid: Array<Array<i32>> = [[1, 2], [3, 4]];
+            a[1] = 10;
+            grid[1][0] = a[1] + 1;
+            push(a, 5);
+            let length: i32 = len(a);
+            let middle: Array<i32> = a[1..3];
+            middle[0] = 99;
+            let row: Array<i32> = grid[0];
+            push(row, 7);
+            let last: i32 = pop(a);
+            push(grid[0], pop(a));
+            let i: i64 = 1;
+            let corner: i32 = grid[i][1];
+        ").unwrap();
+        let array = |values: &[i32]| Primitive::Array(values.iter().copied().map(Primitive::I32).collect());
+        assert_eq!(variables["a"].0, array(&[3, 10]));
+        assert_eq!(variables["length"].0, Primitive::I32(4));
+        assert_eq!(variables["middle"].0, array(&[99, 4]));
+        assert_eq!(variables["row"].0, array(&[1, 2, 7]));
+        assert_eq!(variables["last"].0, Primitive::I32(5));
+        assert_eq!(variables["grid"].0, Primitive::Array(vec![array(&[1, 2, 4]), array(&[11, 4])]));
+        assert_eq!(variables["corner"].0, Primitive::I32(4));
+        let variables = run("let a: Array<i32> = [1, 2, 3];\nlet head: Array<i32> = a[..1];\nlet tail: Array<i32> = a[1..];\nlet n: i32 = len(\"héllo\");").unwrap();
+        assert_eq!(variables["head"].0, array(&[1]));
+        assert_eq!(variables["tail"].0, array(&[2, 3]));
+        assert_eq!(variables["n"].0, Primitive::I32(6));
+    }
+
+    #[test]
+    fn array_errors() {
+        assert_eq!(run("let a: Array<i32> = [1];\nprint(a[1]);").unwrap_err().code, 52);
+        assert_eq!(run("let a: Array<i32> = [1];\na[-1] = 2;").unwrap_err().code, 52);
+        assert_eq!(run("let a: Array<Array<i32>> = [[1]];\nprint(a[0][3]);").unwrap_err().code, 52);
+        assert_eq!(run("let a: Array<i32> = [1, 2];\nprint(a[2..1]);").unwrap_err().code, 53);
+        assert_eq!(run("let a: Array<i32> = [1, 2];\nprint(a[..3]);").unwrap_err().code, 53);
+        assert_eq!(run("let a: Array<i32> = [];\nlet b: i32 = pop(a);").unwrap_err().code, 54);
+        assert_eq!(run("let a: i32 = 1;\nprint(a[0]);").unwrap_err().code, 55);
+        assert_eq!(run("push([1], 2);").unwrap_err().code, 56);
+        let error = run("let a: Array<i32> = [1];\nprint(a[5]);").unwrap_err();
+        assert_eq!(error.message, "Index 5 is out of bounds for an array of length 1");
+    }
+
+    #[test]
+    fn for_each_loops() {
+        let variables = run("
+            let total: i32 = 0;
+            let a: Array<i32> = [1, 2, 3];
+            for x in a {
+                push(a, x);
+                total = total + x;
+                x = 0;
+            }
+            let evens: Array<i64> = [];
+            let n: i64 = 10;
+            for i in (0..=n).step(2) {
+                push(evens, i);
+            }
+            let x: String = \"outer\";
+            let found: i32 = 0;
+            'rows: for row in [[1, 2], [3, 4]] {
+                for x in row {
+                    if x == 3 {
+                        found = x;
+                        break 'rows;
                     }
-                } else if name == "rand_int" {
-                    return Primitive::I32(rand::rng().random_range(args[0].evaluate(variables).len() as i32..args[1].evaluate(variables).len() as i32));
-                } else if name == "rand_float" {
-                    return Primitive::F32(rand::random::<f32>());
-                } else {
-                    let error_message =
-                        format!("ST:NAME ERROR -> Function: {} does not exist", name);
-                    panic!("{}", error_message.purple());
                 }
-            },
-            _ => {
-                panic!("compiler did an oopsie");
             }
+            let last: i32 = 0;
+            for k in 2147483640..=2147483647 {
+                last = k;
+            }
+            for k in 3..3 {
+                last = 0;
+            }
+        ").unwrap();
+        assert_eq!(variables["total"].0, Primitive::I32(6));
+        assert_eq!(variables["a"].0, Primitive::Array([1, 2, 3, 1, 2, 3].map(Primitive::I32).to_vec()));
+        assert_eq!(variables["evens"].0, Primitive::Array([0, 2, 4, 6, 8, 10].map(Primitive::I64).to_vec()));
+        assert_eq!(variables["x"].0, Primitive::String("outer".to_string()));
+        assert_eq!(variables["found"].0, Primitive::I32(3));
+        assert_eq!(variables["last"].0, Primitive::I32(2147483647));
+        assert!(!variables.contains_key("row") && !variables.contains_key("k"));
+        assert_eq!(run("for i in (0..3).step(0) {\n}").unwrap_err().code, 57);
+        assert_eq!(run("for i in 5 {\n}").unwrap_err().code, 28);
+    }
+
+    #[test]
+    fn block_scopes() {
+        let variables = run("
+            let x: i32 = 1;
+            let seen: String = \"\";
+            let count: i32 = 0;
+            if true {
+                let x: String = \"inner\";
+                seen = x;
+                let y: i32 = 2;
+                count = count + y;
+            } else {
+                let z: i32 = 3;
+            }
+            for (let i: i32 = 0; i < 3; i++) {
+                let doubled: i32 = i * 2;
+                count = count + doubled;
+            }
+            while count < 10 {
+                let step: i32 = 1;
+                count = count + step;
+            }
+        ").unwrap();
+        assert_eq!(variables["x"].0, Primitive::I32(1));
+        assert_eq!(variables["seen"].0, Primitive::String("inner".to_string()));
+        assert_eq!(variables["count"].0, Primitive::I32(10));
+        for name in ["y", "z", "i", "doubled", "step"] {
+            assert!(!variables.contains_key(name), "{} leaked out of its scope", name);
+        }
+        assert_eq!(run("if true {\n    let y: i32 = 2;\n}\nprint(y);").unwrap_err().code, 6);
+    }
+
+    #[test]
+    fn function_frames_are_local() {
+        let error = run("
+            let x: i32 = 1;
+            fn read_x() -> i32 {
+                return x;
+            }
+            let y: i32 = read_x();
+        ").unwrap_err();
+        assert_eq!(error.code, 6);
+        let variables = run("
+            fn set(x: i32) {
+                x = 5;
+            }
+            let x: i32 = 1;
+            set(2);
+        ").unwrap();
+        assert_eq!(variables["x"].0, Primitive::I32(1));
+    }
+
+    #[test]
+    fn function_type_errors() {
+        assert_eq!(run("fn f(a: i32) {\n}\nf(\"a\");").unwrap_err().code, 28);
+        assert_eq!(run("fn f() -> i32 {\n    return \"a\";\n}\nlet x: i32 = f();").unwrap_err().code, 28);
+        assert_eq!(run("fn f() -> i32 {\n}\nf();").unwrap_err().code, 29);
+        assert_eq!(run("fn f() {\n}\nlet x: i32 = f();").unwrap_err().code, 30);
+        assert_eq!(run("fn f(a: i32) {\n}\nf();").unwrap_err().code, 21);
+    }
+
+    #[test]
+    fn function_definition_errors() {
+        assert_eq!(run("return 1;").unwrap_err().code, 27);
+        assert_eq!(run("fn f() {\n}\nfn f() {\n}").unwrap_err().code, 31);
+        assert_eq!(run("fn print() {\n}").unwrap_err().code, 31);
+        assert_eq!(run("if true {\n    fn f() {\n    }\n}").unwrap_err().code, 32);
+    }
+
+    #[test]
+    fn unbounded_recursion_is_an_error() {
+        let text = "fn f(n: i32) -> i32 {\n    return f(n);\n}\nlet x: i32 = f(1);";
+        let result = std::thread::Builder::new()
+            .stack_size(WORKER_STACK_SIZE)
+            .spawn(move || run(text))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(result.unwrap_err().code, 33);
+    }
+
+    #[test]
+    fn structs() {
+        let declarations = ["struct Point { x: f64, y: f64 }", "struct Shape { name: String, corners: Array<Point> }"];
+        let variables = run_with_structs(&declarations, "
+            let p: Point = Point { y: 2.5, x: 1.0 };
+            let q: Point = p;
+            q.x = q.x + 8.0;
+            let s: Shape = Shape { name: \"line\", corners: [p, q] };
+            s.corners[1].y = 0.5;
+            push(s.corners, Point { x: 0.0, y: 0.0 });
+            let last: Point = pop(s.corners);
+            let y: f64 = s.corners[1].y;
+        ").unwrap();
+        assert_eq!(variables["p"].0.to_string(), "Point { x: 1, y: 2.5 }");
+        assert_eq!(variables["q"].0.to_string(), "Point { x: 9, y: 2.5 }");
+        assert_eq!(variables["s"].0.to_string(), "Shape { name: line, corners: [Point { x: 1, y: 2.5 }, Point { x: 9, y: 0.5 }] }");
+        assert_eq!(variables["last"].0.to_string(), "Point { x: 0, y: 0 }");
+        assert_eq!(variables["y"].0, Primitive::F64(0.5));
+        for (text, code) in [
+            ("let p: Point = Point { x: 1.0 };", 60),
+            ("let p: Point = Point { x: 1.0, y: 2.0, z: 3.0 };", 59),
+            ("let p: Point = Point { x: 1.0, y: 2.0, x: 3.0 };", 61),
+            ("let p: Point = Point { x: 1, y: 2.0 };", 28),
+            ("let p: Nope = Nope { x: 1 };", 14),
+            ("let p: Point = Point { x: 1.0, y: 2.0 };\nprint(p.z);", 59),
+            ("let a: i32 = 1;\nprint(a.x);", 62),
+            ("let p: Point = Point { x: 1.0, y: 2.0 };\nprint(string(p));", 8),
+        ] {
+            assert_eq!(run_with_structs(&declarations, text).unwrap_err().code, code, "{}", text);
+        }
+    }
+
+    #[test]
+    fn maps() {
+        let variables = run("
+            let m: Map<String, i32> = {\"b\": 2, \"a\": 1, \"c\": 0, \"c\": 3};
+            m[\"d\"] = m[\"a\"] + m[\"c\"];
+            m[\"a\"] = 10;
+            let removed: i32 = remove(m, \"b\");
+            let has_b: Bool = contains(m, \"b\");
+            let has_d: Bool = contains(m, \"d\");
+            let names: Array<String> = keys(m);
+            let size: i32 = len(m);
+            let pairs: String = \"\";
+            let total: i32 = 0;
+            for (k, v) in m {
+                m[k] = 0;
+                total = total + v;
+            }
+            for k in m {
+                pairs = k;
+            }
+            let grid: Map<i32, Array<Map<Bool, i64>>> = {2: [{true: i64(1)}], 1: []};
+            grid[2][0][false] = i64(5);
+            let empty: Map<String, f64> = {};
+        ").unwrap();
+        assert_eq!(variables["m"].0.to_string(), "{a: 0, c: 0, d: 0}");
+        assert_eq!(variables["removed"].0, Primitive::I32(2));
+        assert_eq!((&variables["has_b"].0, &variables["has_d"].0), (&Primitive::Bool(false), &Primitive::Bool(true)));
+        assert_eq!(variables["names"].0.to_string(), "[a, c, d]");
+        assert_eq!(variables["size"].0, Primitive::I32(3));
+        assert_eq!(variables["total"].0, Primitive::I32(17));
+        assert_eq!(variables["pairs"].0.to_string(), "d");
+        assert_eq!(variables["grid"].0.to_string(), "{1: [], 2: [{false: 5, true: 1}]}");
+        assert_eq!(variables["empty"].0.to_string(), "{}");
+        for (text, code) in [
+            ("let m: Map<String, i32> = {\"a\": 1};\nprint(m[\"b\"]);", 63),
+            ("let m: Map<String, i32> = {};\nremove(m, \"a\");", 63),
+            ("let m: Map<f64, i32> = {};", 64),
+            ("print({1.5: 1});", 64),
+            ("let m: Map<String, i32> = {};\nprint(string(m));", 8),
+            ("let a: Array<i32> = [1];\nfor (i, x) in a {\n}", 28),
+            ("for (i, x) in 0..3 {\n}", 28),
+            ("print(contains([1], 1));", 28),
+            ("let a: Array<i32> = [1];\nprint(a[\"x\"]);", 28),
+        ] {
+            assert_eq!(run(text).unwrap_err().code, code, "{}", text);
+        }
+        let error = run("let m: Map<String, i32> = {};\nprint(m[\"missing\"]);").unwrap_err();
+        assert_eq!(error.message, "Key \"missing\" is not in the map");
+    }
+
+    #[test]
+    fn builtins_are_called_like_functions() {
+        let variables = run("
+            rand_int(1, 2);
+            let r: i32 = rand_int(3, 3);
+            let cast: i32 = i32(2.9) + i32(i64(4)) + i32(f32(1));
+            let a: Array<i32> = [1, 2];
+            let sizes: Array<i32> = [len(a), len(\"héllo\"), len({1: true})];
+            pop(a);
+        ").unwrap();
+        assert_eq!(variables["r"].0, Primitive::I32(3));
+        assert_eq!(variables["cast"].0, Primitive::I32(7));
+        assert_eq!(variables["sizes"].0.to_string(), "[2, 6, 1]");
+        assert_eq!(variables["a"].0.to_string(), "[1]");
+        for (text, code) in [
+            ("let x: i32 = print(1);", 30),
+            ("let x: i32 = 1 + sleep(0);", 30),
+            ("print(i32(\"a\"));", 8),
+            ("print(string([1]));", 8),
+            ("rand_int(2, 1);", 20),
+            ("let a: Array<i32> = [];\npop(a);", 54),
+            ("push(1, 2);", 56),
+            ("let a: i32 = 1;\npush(a, 2);", 28),
+            ("sleep(\"a\");", 28),
+            ("print();", 21),
+            ("fn rand_int() {\n}", 31),
+        ] {
+            assert_eq!(run(text).unwrap_err().code, code, "{}", text);
+        }
+    }
+
+    #[test]
+    fn strings() {
+        let variables = run("
+            let name: String = \"  Ada Lovelace \";
+            let trimmed: String = trim(name);
+            let greeting: String = \"hi \" + to_lower(trimmed) + \"!\";
+            let first: String = substring(trimmed, 0, 3);
+            let words: Array<String> = split(trimmed, \" \");
+            let letters: Array<String> = split(\"héj\", \"\");
+            let joined: String = join(words, \"-\");
+            let shout: String = to_upper(replace(trimmed, \"a\", \"4\"));
+            let checks: Array<Bool> = [contains(trimmed, \"Love\"), starts_with(trimmed, \"Ada\"), \"abc\" < \"abd\", \"b\" >= \"abc\", first == \"Ada\", first != \"Ada\"];
+            let line: String = format(\"{} is {} ({{ok}})\", first, 36);
+            let number: ParsedI32 = parse_i32(\"-42\");
+            let bad: ParsedI32 = parse_i32(\"4x\");
+            let float: ParsedF64 = parse_f64(\"2.5\");
+        ").unwrap();
+        assert_eq!(variables["greeting"].0.to_string(), "hi ada lovelace!");
+        assert_eq!(variables["first"].0.to_string(), "Ada");
+        assert_eq!(variables["words"].0.to_string(), "[Ada, Lovelace]");
+        assert_eq!(variables["letters"].0.to_string(), "[h, é, j]");
+        assert_eq!(variables["joined"].0.to_string(), "Ada-Lovelace");
+        assert_eq!(variables["shout"].0.to_string(), "AD4 LOVEL4CE");
+        assert_eq!(variables["checks"].0.to_string(), "[true, true, true, true, true, false]");
+        assert_eq!(variables["line"].0.to_string(), "Ada is 36 ({ok})");
+        assert_eq!(variables["number"].0.to_string(), "ParsedI32 { ok: true, value: -42, error:  }");
+        assert_eq!(variables["bad"].0.to_string(), "ParsedI32 { ok: false, value: 0, error: invalid digit found in string }");
+        assert_eq!(variables["float"].0.to_string(), "ParsedF64 { ok: true, value: 2.5, error:  }");
+        for (text, code) in [
+            ("print(substring(\"abc\", 2, 4));", 65),
+            ("print(substring(\"abc\", 2, 1));", 65),
+            ("print(substring(\"abc\", -1, 1));", 65),
+            ("print(substring(\"é\", 0, 1));", 65),
+            ("print(format(\"{} {}\", 1));", 66),
+            ("print(format(\"{x}\", 1));", 67),
+            ("print(trim(1));", 28),
+            ("print(\"a\" - \"b\");", 17),
+            ("print(join([1], \",\"));", 28),
+            ("print(replace(\"a\", \"b\"));", 21),
+            ("fn trim(s: String) {\n}", 31),
+        ] {
+            assert_eq!(run(text).unwrap_err().code, code, "{}", text);
         }
     }
 }
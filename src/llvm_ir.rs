@@ -1,84 +1,1756 @@
-use std::collections::{HashMap, VecDeque};
-
-use crate::{interpreter::{Primitive, Type}, parse::Statement};
-
-pub fn get_buffer(statements: &VecDeque<Statement>, variable_map: HashMap<String, (Primitive, Type)>) -> String{
-    let mut llvm_statements = VecDeque::new();
-    llvm_statements.push_back("define i32 @main() {\nentry:\n".to_string());
-    let var_index: u32 = 0;
-    for statement in statements{
-        match statement{
-            Statement::FunctionCall(name, args) => {
-                if name == "print"{                    
-                    if !llvm_statements.contains(&"declare i32 @printf(i8*, ...)\n".to_string()){
-                        llvm_statements.push_front("declare i32 @printf(i8*, ...)\n".to_string());
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use crate::{builtins::{lookup, Param}, dag::Dag, diagnostic::{Diagnostic, Span}, interpreter::{changed_place, collect_functions, missing_field, no_field, not_a_struct, outside_loop, repeated_field, unknown_label, Primitive, Scopes, Structs, Type}, parse::{Access, BinaryOperator, Complete, CompleteU, Expression, Function, Iterable, LoopVariables, Statement, UnaryOperator}, thread_handler::WORKER_STACK_SIZE, token_block::import_variables};
+
+//Arrays are a heap allocated length and a pointer to their elements, whatever the element type.
+const ARRAY_TYPE: &str = "%array = type { i64, i8* }";
+//Large enough for any float printed without an exponent, up to MAX_DECIMALS decimals.
+const BUFFER_SIZE: usize = 512;
+//The smallest subnormal f64 needs 324 decimals before its digits start.
+const MAX_DECIMALS: usize = 345;
+
+//An SSA register or constant together with the type it holds.
+#[derive(Debug, Clone)]
+pub(crate) struct Value {
+    repr: String,
+    ty: Type,
+}
+
+//The stack slot a variable lives in.
+#[derive(Debug, Clone)]
+struct Slot {
+    pointer: String,
+    ty: Type,
+}
+
+//The state of the function currently being generated.
+#[derive(Default)]
+struct FunctionState {
+    allocas: Vec<String>,
+    body: Vec<String>,
+    //Whether the current basic block already ends in a br, ret or unreachable.
+    terminated: bool,
+    variables: Scopes<Slot>,
+    //Some(return type) while generating a user function, None in main.
+    return_type: Option<Option<Type>>,
+    name: String,
+    //The loops around the statement being generated, innermost last.
+    loops: Vec<LoopTargets>,
+}
+
+//Where break and continue jump to for one loop.
+struct LoopTargets {
+    label: Option<String>,
+    next: String,
+    end: String,
+}
+
+//A user function as it is called from the generated code.
+#[derive(Clone)]
+struct Signature {
+    symbol: String,
+    params: Vec<Type>,
+    return_type: Option<Type>,
+}
+
+#[derive(Default)]
+pub(crate) struct Codegen {
+    globals: Vec<String>,
+    declarations: BTreeSet<String>,
+    //Helper and user functions, emitted before main.
+    functions: Vec<String>,
+    strings: HashMap<String, String>,
+    helpers: BTreeSet<String>,
+    current: FunctionState,
+    signatures: HashMap<String, Signature>,
+    structs: Structs,
+    next_register: usize,
+    next_label: usize,
+    uses_rand: bool,
+}
+
+//Generates a program that runs a single list of statements.
+//Args: variable_map - values the statements can use without defining them, such as variables imported from other blocks.
+//Returns: the textual LLVM IR of the program.
+pub fn get_buffer(statements: &VecDeque<Statement>, variable_map: HashMap<String, (Primitive, Type)>) -> Result<String, Diagnostic> {
+    let mut codegen = Codegen::default();
+    let mut names: Vec<&String> = variable_map.keys().collect();
+    names.sort();
+    for name in names {
+        let (primitive, ty) = &variable_map[name];
+        let value = codegen.expression(&constant(primitive), Some(ty))?;
+        let slot = codegen.define_slot(name, ty);
+        codegen.store(&value, &slot.pointer);
+    }
+    codegen.block(None, statements)?;
+    Ok(codegen.finish())
+}
+
+//Generates a program that runs every block of a file on its own thread, the way thread_handler::parallel does.
+//Each block becomes a function, and a generated scheduler in main starts them all and makes each one wait for the blocks it requires.
+//Args: programs - the parsed statements of every block.
+//Returns: the textual LLVM IR of the program.
+pub fn compile_program(dag: &Dag, programs: &HashMap<String, VecDeque<Statement>>) -> Result<String, Diagnostic> {
+    let mut codegen = Codegen { structs: dag.structs.clone(), ..Codegen::default() };
+    let mut exports: HashMap<String, HashMap<String, Slot>> = HashMap::new();
+    for id in &dag.order {
+        let block = &dag.blocks[id];
+        let imported = import_variables(block, &exports).map_err(|error| error.with_note(format!("raised in block {}", id)))?;
+        let variables = codegen.block_function(id, &programs[id], &imported)
+            .map_err(|error| error.or_span(&block.span).with_note(format!("raised in block {}", id)))?;
+        exports.insert(id.clone(), variables);
+    }
+    codegen.scheduler(dag);
+    Ok(codegen.finish())
+}
+
+//Turns a value known at compile time back into an expression so it can be generated like any other.
+fn constant(primitive: &Primitive) -> Expression {
+    let span = Span::default();
+    match primitive {
+        Primitive::String(value) => Expression::String(value.clone(), span),
+        Primitive::I32(value) => Expression::I32(*value, span),
+        Primitive::F32(value) => Expression::F32(*value, span),
+        Primitive::I64(value) => Expression::I64(*value, span),
+        Primitive::F64(value) => Expression::F64(*value, span),
+        Primitive::Bool(value) => Expression::Bool(*value, span),
+        Primitive::Array(values) => Expression::Array(values.iter().map(constant).collect(), span),
+        Primitive::Struct(name, fields) => Expression::Struct(name.clone(), fields.iter().map(|(field, value)| (field.clone(), constant(value))).collect(), span),
+        Primitive::Map(entries) => Expression::Map(entries.iter().map(|(key, value)| (constant(key), constant(value))).collect(), span),
+    }
+}
+
+fn llvm_type(ty: &Type) -> &'static str {
+    match ty {
+        Type::I32 => "i32",
+        Type::I64 => "i64",
+        Type::F32 => "float",
+        Type::F64 => "double",
+        Type::Bool => "i1",
+        Type::String => "i8*",
+        Type::Array(_) => "%array*",
+        //A struct is a pointer to its fields, cast to the struct's own type whenever a field is used.
+        Type::Struct(_) => "i8*",
+        //Maps are not compiled, every expression that makes one is rejected. This only keeps a declared but never filled Array<Map<K, V>> working.
+        Type::Map(..) => "i8*",
+    }
+}
+
+//The size in bytes of an array element of this type.
+fn element_size(ty: &Type) -> usize {
+    match ty {
+        Type::Bool => 1,
+        Type::I32 | Type::F32 => 4,
+        Type::I64 | Type::F64 | Type::String | Type::Array(_) | Type::Struct(_) | Type::Map(..) => 8,
+    }
+}
+
+//A name for a type that can be used inside a symbol, such as arr.i32 for Array<i32>.
+fn mangle(ty: &Type) -> String {
+    match ty {
+        Type::Array(inner) => format!("arr.{}", mangle(inner)),
+        Type::Struct(name) => format!("struct.{}", name),
+        Type::Map(key, value) => format!("map.{}.{}", mangle(key), mangle(value)),
+        other => other.to_string().to_lowercase(),
+    }
+}
+
+//Escapes text for a c"..." constant. Everything but printable ASCII is written as a hex escape.
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_graphic() && byte != b'"' && byte != b'\\' || byte == b' ' {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("\\{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+fn unsupported(what: &str) -> Diagnostic {
+    Diagnostic::new(37, format!("Cannot compile {} to LLVM IR", what))
+}
+
+impl Codegen {
+    fn finish(mut self) -> String {
+        let mut main = String::from("define i32 @main() {\nentry:\n");
+        for alloca in &self.current.allocas {
+            main.push_str(alloca);
+            main.push('\n');
+        }
+        if self.uses_rand {
+            self.declare("declare i64 @time(i64*)");
+            self.declare("declare void @srand(i32)");
+            main.push_str("%seed = call i64 @time(i64* null)\n%seed.32 = trunc i64 %seed to i32\ncall void @srand(i32 %seed.32)\n");
+        }
+        for line in &self.current.body {
+            main.push_str(line);
+            main.push('\n');
+        }
+        main.push_str("ret i32 0\n}");
+        let mut buffer = String::new();
+        for line in self.globals.iter().chain(self.declarations.iter()) {
+            buffer.push_str(line);
+            buffer.push('\n');
+        }
+        for function in &self.functions {
+            buffer.push_str(function);
+            buffer.push('\n');
+        }
+        buffer.push_str(&main);
+        buffer
+    }
+
+    //Adds a finished function to the module, with its allocas hoisted into the entry block.
+    //Args: header - everything before the opening brace, such as define void @f(i32 %a).
+    fn define(&mut self, header: &str, state: &FunctionState) {
+        let mut text = format!("{} {{\nentry:\n", header);
+        for line in state.allocas.iter().chain(state.body.iter()) {
+            text.push_str(line);
+            text.push('\n');
+        }
+        text.push('}');
+        self.functions.push(text);
+    }
+
+    fn register(&mut self, hint: &str) -> String {
+        self.next_register += 1;
+        format!("%{}.{}", hint, self.next_register)
+    }
+
+    fn label(&mut self, hint: &str) -> String {
+        self.next_label += 1;
+        format!("{}.{}", hint, self.next_label)
+    }
+
+    fn emit(&mut self, instruction: String) {
+        if self.current.terminated {
+            //Code after a return is unreachable but still has to be in a basic block.
+            let label = self.label("dead");
+            self.start_block(&label);
+        }
+        self.current.body.push(instruction);
+    }
+
+    fn terminate(&mut self, instruction: String) {
+        self.emit(instruction);
+        self.current.terminated = true;
+    }
+
+    fn start_block(&mut self, label: &str) {
+        self.current.body.push(format!("{}:", label));
+        self.current.terminated = false;
+    }
+
+    fn branch(&mut self, label: &str) {
+        if !self.current.terminated {
+            self.terminate(format!("br label %{}", label));
+        }
+    }
+
+    fn declare(&mut self, declaration: &str) {
+        self.declarations.insert(declaration.to_string());
+    }
+
+    //Returns: an i8* constant pointing at a null terminated copy of text.
+    fn string_constant(&mut self, text: &str) -> String {
+        let length = text.len() + 1;
+        let name = match self.strings.get(text) {
+            Some(name) => name.clone(),
+            None => {
+                let name = format!("@str.{}", self.strings.len());
+                self.globals.push(format!("{} = private unnamed_addr constant [{} x i8] c\"{}\\00\", align 1", name, length, escape(text)));
+                self.strings.insert(text.to_string(), name.clone());
+                name
+            }
+        };
+        format!("getelementptr inbounds ([{} x i8], [{} x i8]* {}, i32 0, i32 0)", length, length, name)
+    }
+
+    fn printf(&mut self, format: &str, args: &[&Value]) {
+        self.declare("declare i32 @printf(i8*, ...)");
+        let format = self.string_constant(format);
+        let mut call = format!("call i32 (i8*, ...) @printf(i8* {}", format);
+        for arg in args {
+            call.push_str(&format!(", {} {}", llvm_type(&arg.ty), arg.repr));
+        }
+        call.push(')');
+        self.emit(call);
+    }
+
+    //Reuses the slot of a variable defined again in the same scope with the same type, so loops do not allocate a slot per pass.
+    fn define_slot(&mut self, name: &str, ty: &Type) -> Slot {
+        if let Some(slot) = self.current.variables.local(name) {
+            if slot.ty == *ty {
+                return slot.clone();
+            }
+        }
+        self.new_slot(name, ty)
+    }
+
+    //Gives a variable a new stack slot even if it already has one of the same type.
+    fn new_slot(&mut self, name: &str, ty: &Type) -> Slot {
+        let pointer = self.register(&format!("var.{}", name));
+        self.current.allocas.push(format!("{} = alloca {}", pointer, llvm_type(ty)));
+        let slot = Slot { pointer, ty: ty.clone() };
+        self.current.variables.define(name, slot.clone());
+        slot
+    }
+
+    fn slot(&self, name: &str) -> Result<Slot, Diagnostic> {
+        self.current.variables.get(name).cloned().ok_or_else(|| Diagnostic::new(6, format!("Variable {} does not exist", name)))
+    }
+
+    fn load(&mut self, slot: &Slot) -> Value {
+        let register = self.register("load");
+        let ty = llvm_type(&slot.ty);
+        self.emit(format!("{} = load {}, {}* {}", register, ty, ty, slot.pointer));
+        Value { repr: register, ty: slot.ty.clone() }
+    }
+
+    fn store(&mut self, value: &Value, pointer: &str) {
+        let ty = llvm_type(&value.ty);
+        self.emit(format!("store {} {}, {}* {}", ty, value.repr, ty, pointer));
+    }
+
+    //Stops the program with an error message when condition is true.
+    fn guard(&mut self, condition: &str, message: &str) {
+        self.runtime_error_helper();
+        let fail = self.label("fail");
+        let ok = self.label("ok");
+        self.terminate(format!("br i1 {}, label %{}, label %{}", condition, fail, ok));
+        self.start_block(&fail);
+        let message = self.string_constant(message);
+        self.emit(format!("call void @runtime_error(i8* {})", message));
+        self.terminate("unreachable".to_string());
+        self.start_block(&ok);
+    }
+
+    fn runtime_error_helper(&mut self) {
+        if !self.helpers.insert("runtime_error".to_string()) {
+            return;
+        }
+        self.declare("declare i32 @dprintf(i32, i8*, ...)");
+        self.declare("declare void @exit(i32)");
+        let format = self.string_constant("%s\n");
+        self.functions.push(format!(
+            "define void @runtime_error(i8* %message) {{\nentry:\ncall i32 (i32, i8*, ...) @dprintf(i32 2, i8* {}, i8* %message)\ncall void @exit(i32 1)\nunreachable\n}}",
+            format
+        ));
+    }
+
+    //Generates the functions and statements of one block into main.
+    fn block(&mut self, id: Option<&str>, statements: &VecDeque<Statement>) -> Result<(), Diagnostic> {
+        let functions = collect_functions(statements)?;
+        let mut names: Vec<&String> = functions.keys().collect();
+        names.sort();
+        self.signatures.clear();
+        for name in &names {
+            let function = functions[*name];
+            let symbol = match id {
+                Some(id) => format!("@\"fn.{}.{}\"", id, name),
+                None => format!("@\"fn.{}\"", name),
+            };
+            let params = function.params.iter().map(|(_, ty)| ty.clone()).collect();
+            self.signatures.insert(name.to_string(), Signature { symbol, params, return_type: function.return_type.clone() });
+        }
+        for name in names {
+            self.function(functions[name])?;
+        }
+        self.statements(statements)
+    }
+
+    //Generates @"block.<id>", which runs the statements of a block and then publishes its variables.
+    //Args: imported - the exported globals of required blocks, by the name they are imported as.
+    //Returns: the globals holding every variable the block defines.
+    fn block_function(&mut self, id: &str, statements: &VecDeque<Statement>, imported: &HashMap<String, Slot>) -> Result<HashMap<String, Slot>, Diagnostic> {
+        let outer = std::mem::take(&mut self.current);
+        let result = self.block_body(id, statements, imported);
+        let state = std::mem::replace(&mut self.current, outer);
+        let exports = result?;
+        self.define(&format!("define void @\"block.{}\"()", id), &state);
+        Ok(exports)
+    }
+
+    fn block_body(&mut self, id: &str, statements: &VecDeque<Statement>, imported: &HashMap<String, Slot>) -> Result<HashMap<String, Slot>, Diagnostic> {
+        let mut aliases: Vec<&String> = imported.keys().collect();
+        aliases.sort();
+        for alias in aliases {
+            let source = &imported[alias];
+            let value = self.load(source);
+            //Every block that requires this one gets its own copy, so they can change it at the same time.
+            let value = self.deep_copy(&value)?;
+            let slot = self.define_slot(alias, &source.ty);
+            self.store(&value, &slot.pointer);
+        }
+        self.current.variables.enter_top_level();
+        self.block(Some(id), statements)?;
+        //A block only starts once the blocks it requires have finished, so dependents can read these globals without locking.
+        let mut exports = HashMap::new();
+        let mut names: Vec<String> = self.current.variables.top_level().keys().cloned().collect();
+        names.sort();
+        for name in names {
+            let slot = self.current.variables.top_level()[&name].clone();
+            let global = format!("@\"export.{}.{}\"", id, name);
+            self.globals.push(format!("{} = internal global {} zeroinitializer", global, llvm_type(&slot.ty)));
+            let value = self.load(&slot);
+            self.store(&value, &global);
+            exports.insert(name, Slot { pointer: global, ty: slot.ty });
+        }
+        self.terminate("ret void".to_string());
+        Ok(exports)
+    }
+
+    //Generates the scheduler: a runner per block that waits for its required blocks, and a main that starts every runner on a pthread and joins them.
+    fn scheduler(&mut self, dag: &Dag) {
+        let count = dag.order.len();
+        let done = format!("[{} x i1]", count);
+        self.globals.push("@scheduler.mutex = internal global [64 x i8] zeroinitializer".to_string());
+        self.globals.push("@scheduler.cond = internal global [64 x i8] zeroinitializer".to_string());
+        self.globals.push(format!("@scheduler.done = internal global {} zeroinitializer", done));
+        for declaration in [
+            "declare i32 @pthread_mutex_lock(i8*)",
+            "declare i32 @pthread_mutex_unlock(i8*)",
+            "declare i32 @pthread_cond_wait(i8*, i8*)",
+            "declare i32 @pthread_cond_broadcast(i8*)",
+            "declare i32 @pthread_attr_init(i8*)",
+            "declare i32 @pthread_attr_setstacksize(i8*, i64)",
+            "declare i32 @pthread_create(i64*, i8*, i8* (i8*)*, i8*)",
+            "declare i32 @pthread_join(i64, i8**)",
+        ] {
+            self.declare(declaration);
+        }
+        let mutex = "i8* getelementptr inbounds ([64 x i8], [64 x i8]* @scheduler.mutex, i32 0, i32 0)";
+        let cond = "i8* getelementptr inbounds ([64 x i8], [64 x i8]* @scheduler.cond, i32 0, i32 0)";
+        self.functions.push(format!(
+            "define void @scheduler.wait(i32 %block) {{\nentry:\n%done.pointer = getelementptr {done}, {done}* @scheduler.done, i32 0, i32 %block\ncall i32 @pthread_mutex_lock({mutex})\nbr label %check\ncheck:\n%done = load i1, i1* %done.pointer\nbr i1 %done, label %ready, label %wait\nwait:\ncall i32 @pthread_cond_wait({cond}, {mutex})\nbr label %check\nready:\ncall i32 @pthread_mutex_unlock({mutex})\nret void\n}}"
+        ));
+        self.functions.push(format!(
+            "define void @scheduler.finish(i32 %block) {{\nentry:\n%done.pointer = getelementptr {done}, {done}* @scheduler.done, i32 0, i32 %block\ncall i32 @pthread_mutex_lock({mutex})\nstore i1 true, i1* %done.pointer\ncall i32 @pthread_cond_broadcast({cond})\ncall i32 @pthread_mutex_unlock({mutex})\nret void\n}}"
+        ));
+        let index: HashMap<&String, usize> = dag.order.iter().enumerate().map(|(i, id)| (id, i)).collect();
+        for (i, id) in dag.order.iter().enumerate() {
+            let mut requires: Vec<usize> = dag.blocks[id].requires.keys().map(|required| index[required]).collect();
+            requires.sort();
+            let mut text = format!("define i8* @\"run.{}\"(i8* %argument) {{\nentry:\n", id);
+            for required in requires {
+                text.push_str(&format!("call void @scheduler.wait(i32 {})\n", required));
+            }
+            text.push_str(&format!("call void @\"block.{}\"()\ncall void @scheduler.finish(i32 {})\nret i8* null\n}}", id, i));
+            self.functions.push(text);
+        }
+        let threads = self.register("threads");
+        self.current.allocas.push(format!("{} = alloca [{} x i64]", threads, count));
+        let attributes = self.register("attributes");
+        self.current.allocas.push(format!("{} = alloca [64 x i8]", attributes));
+        let attributes_pointer = self.register("attributes.pointer");
+        self.emit(format!("{} = getelementptr [64 x i8], [64 x i8]* {}, i32 0, i32 0", attributes_pointer, attributes));
+        self.emit(format!("call i32 @pthread_attr_init(i8* {})", attributes_pointer));
+        self.emit(format!("call i32 @pthread_attr_setstacksize(i8* {}, i64 {})", attributes_pointer, WORKER_STACK_SIZE));
+        let mut handles = Vec::new();
+        for (i, id) in dag.order.iter().enumerate() {
+            let handle = self.register("thread");
+            self.emit(format!("{} = getelementptr [{} x i64], [{} x i64]* {}, i32 0, i32 {}", handle, count, count, threads, i));
+            self.emit(format!("call i32 @pthread_create(i64* {}, i8* {}, i8* (i8*)* @\"run.{}\", i8* null)", handle, attributes_pointer, id));
+            handles.push(handle);
+        }
+        for handle in handles {
+            let thread = self.register("thread.id");
+            self.emit(format!("{} = load i64, i64* {}", thread, handle));
+            self.emit(format!("call i32 @pthread_join(i64 {}, i8** null)", thread));
+        }
+    }
+
+    fn loop_body(&mut self, body: &VecDeque<Statement>, targets: LoopTargets) -> Result<(), Diagnostic> {
+        self.current.loops.push(targets);
+        let result = self.scope(body);
+        self.current.loops.pop();
+        result
+    }
+
+    //Generates the body of an if or a loop. The variables it defines go out of scope at its closing brace.
+    fn scope(&mut self, body: &VecDeque<Statement>) -> Result<(), Diagnostic> {
+        self.current.variables.enter();
+        let result = self.statements(body);
+        self.current.variables.exit();
+        result
+    }
+
+    fn function(&mut self, function: &Function) -> Result<(), Diagnostic> {
+        let signature = self.signatures[&function.name].clone();
+        let outer = std::mem::replace(&mut self.current, FunctionState {
+            return_type: Some(function.return_type.clone()),
+            name: function.name.clone(),
+            ..FunctionState::default()
+        });
+        let mut params = Vec::new();
+        for (name, ty) in &function.params {
+            let argument = format!("%arg.{}", name);
+            params.push(format!("{} {}", llvm_type(ty), argument));
+            let slot = self.define_slot(name, ty);
+            self.store(&Value { repr: argument, ty: ty.clone() }, &slot.pointer);
+        }
+        let result = self.statements(&function.block);
+        if !self.current.terminated {
+            match &function.return_type {
+                None => self.terminate("ret void".to_string()),
+                Some(ty) => {
+                    self.guard("true", &format!("Error[29]: Function {} ended without returning a {}", function.name, ty));
+                    self.terminate("unreachable".to_string());
+                }
+            }
+        }
+        let state = std::mem::replace(&mut self.current, outer);
+        result?;
+        let return_type = signature.return_type.as_ref().map(llvm_type).unwrap_or("void");
+        self.define(&format!("define {} {}({})", return_type, signature.symbol, params.join(", ")), &state);
+        Ok(())
+    }
+
+    fn statements(&mut self, statements: &VecDeque<Statement>) -> Result<(), Diagnostic> {
+        for statement in statements {
+            self.statement(statement).map_err(|error| error.or_span(statement.span()))?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), Diagnostic> {
+        match statement {
+            Statement::DefineVariable(name, expression, ty, _) => {
+                let value = self.owned(expression, Some(ty))?;
+                let slot = self.define_slot(name, ty);
+                self.store(&value, &slot.pointer);
+            }
+            Statement::ModifyVariable(name, expression, _) => {
+                let slot = self.slot(name)?;
+                let value = match expression {
+                    Expression::Increment(_) | Expression::Decrement(_) => {
+                        let amount = if matches!(expression, Expression::Increment(_)) { 1 } else { -1 };
+                        let current = self.load(&slot);
+                        self.step(&current, amount)?
+                    }
+                    _ => self.owned(expression, Some(&slot.ty))?,
+                };
+                self.store(&value, &slot.pointer);
+            }
+            Statement::ModifyElement(target, expression, _) => {
+                let (name, accesses) = target.place().expect("the parser only builds element assignments to places");
+                let slot = self.slot(name)?;
+                let mut element = slot.ty.clone();
+                for access in &accesses {
+                    element = match (access, element) {
+                        (Access::Index(_), Type::Array(inner)) => *inner,
+                        (Access::Index(_), other) => return Err(Diagnostic::new(55, format!("Cannot index into {}", other))),
+                        (Access::Field(field), Type::Struct(name)) => self.field_of(&name, field)?.1,
+                        (Access::Field(field), other) => return Err(not_a_struct(field, &other.to_string())),
+                    };
+                }
+                //The value is computed before the indices, like the interpreter does.
+                let value = self.owned(expression, Some(&element))?;
+                if value.ty != element {
+                    let what = match accesses.last() {
+                        Some(Access::Field(field)) => format!("field {}", field),
+                        _ => format!("element of {}", name),
+                    };
+                    return Err(Diagnostic::new(28, format!("Mismatched type: {} expects {} but found {}", what, element, value.ty)).with_span(expression.span().clone()));
+                }
+                let mut pointer = slot;
+                for access in accesses {
+                    let outer = self.load(&pointer);
+                    pointer = match access {
+                        Access::Index(index) => self.element_pointer(&outer, index)?,
+                        Access::Field(field) => self.field_pointer(&outer, field)?,
+                    };
+                }
+                self.store(&value, &pointer.pointer);
+            }
+            Statement::FunctionCall(name, args, span) => {
+                self.call(name, args, span)?;
+            }
+            Statement::WhileLoop(condition, body, label, _) => {
+                let check = self.label("while.cond");
+                let inside = self.label("while.body");
+                let end = self.label("while.end");
+                self.branch(&check);
+                self.start_block(&check);
+                let value = self.condition(condition)?;
+                self.terminate(format!("br i1 {}, label %{}, label %{}", value.repr, inside, end));
+                self.start_block(&inside);
+                self.loop_body(body, LoopTargets { label: label.clone(), next: check.clone(), end: end.clone() })?;
+                self.branch(&check);
+                self.start_block(&end);
+            }
+            Statement::ForLoop(define_variable, condition, increment, body, label, _) => {
+                let check = self.label("for.cond");
+                let inside = self.label("for.body");
+                let step = self.label("for.step");
+                let end = self.label("for.end");
+                self.current.variables.enter();
+                self.statement(define_variable)?;
+                self.branch(&check);
+                self.start_block(&check);
+                let value = self.condition(condition)?;
+                self.terminate(format!("br i1 {}, label %{}, label %{}", value.repr, inside, end));
+                self.start_block(&inside);
+                self.loop_body(body, LoopTargets { label: label.clone(), next: step.clone(), end: end.clone() })?;
+                self.branch(&step);
+                self.start_block(&step);
+                self.statement(increment)?;
+                self.branch(&check);
+                self.start_block(&end);
+                self.current.variables.exit();
+            }
+            Statement::ForEach(LoopVariables::Single(variable), iterable, body, label, _) => self.for_each(variable, iterable, body, label)?,
+            Statement::ForEach(LoopVariables::Pair(..), ..) => return Err(unsupported("a for loop over the keys and values of a map")),
+            Statement::If(condition, body, elifs, else_body, _) => {
+                let end = self.label("if.end");
+                let mut branches = vec![(condition, body)];
+                for elif in elifs {
+                    if let Statement::Elif(elif_condition, elif_body, _) = elif {
+                        branches.push((elif_condition, elif_body));
                     }
-                    let primitive = args[0].evaluate(&variable_map);
-                    match &primitive{
-                        Primitive::String(value) => {
-                            llvm_define_variable(&mut llvm_statements, &primitive, &var_index, None);
-                            llvm_statements.push_back(format!("call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([{} x i8], [{} x i8]* @var{}, i32 0, i32 0))\n", value.len() + 1, value.len() + 1, var_index))
-                        },
-                        Primitive::I32(value) => {
-                            llvm_define_variable(&mut llvm_statements, &primitive, &var_index, None);
-                            llvm_statements.push_back(format!("call i32 (i8*, ...) @printf(i8* @var{}, i32 {})\n", var_index, value));
-                        }
-                        Primitive::F32(_) => todo!(),
-                        Primitive::I64(_) => todo!(),
-                        Primitive::F64(_) => todo!(),
-                        Primitive::Bool(_) => todo!(),
-                        Primitive::Array(_) => todo!(),
+                }
+                for (condition, body) in branches {
+                    let then = self.label("if.then");
+                    let next = self.label("if.next");
+                    let value = self.condition(condition)?;
+                    self.terminate(format!("br i1 {}, label %{}, label %{}", value.repr, then, next));
+                    self.start_block(&then);
+                    self.scope(body)?;
+                    self.branch(&end);
+                    self.start_block(&next);
+                }
+                if let Some(else_body) = else_body {
+                    self.scope(else_body)?;
+                }
+                self.branch(&end);
+                self.start_block(&end);
+            }
+            Statement::Elif(..) => return Err(unsupported("an elif without an if")),
+            //Functions are generated by block before any statement runs.
+            Statement::DefineFunction(..) => {}
+            Statement::Break(target, _) | Statement::Continue(target, _) => {
+                let keyword = if matches!(statement, Statement::Break(..)) { "break" } else { "continue" };
+                let found = self.current.loops.iter().rev().find(|targets| target.is_none() || &targets.label == target);
+                let jump = match (found, target) {
+                    (Some(targets), _) if keyword == "break" => targets.end.clone(),
+                    (Some(targets), _) => targets.next.clone(),
+                    (None, Some(label)) if !self.current.loops.is_empty() => return Err(unknown_label(label)),
+                    (None, _) => return Err(outside_loop(keyword)),
+                };
+                self.terminate(format!("br label %{}", jump));
+            }
+            Statement::Return(expression, _) => {
+                let Some(return_type) = self.current.return_type.clone() else {
+                    return Err(Diagnostic::new(27, "return can only be used inside a function"));
+                };
+                match (expression, return_type) {
+                    (Some(expression), Some(ty)) => {
+                        let value = self.expression(expression, Some(&ty))?;
+                        self.terminate(format!("ret {} {}", llvm_type(&value.ty), value.repr));
                     }
-                } else {
-                    todo!()
+                    (None, None) => self.terminate("ret void".to_string()),
+                    (None, Some(ty)) => return Err(Diagnostic::new(28, format!("Mismatched type: function {} expects {} but found no return value", self.current.name, ty))),
+                    (Some(expression), None) => {
+                        return Err(Diagnostic::new(28, format!("Mismatched type: function {} expects no return value but found a value", self.current.name)).with_span(expression.span().clone()));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    //Generates a for-each loop. Arrays are walked with a hidden index and ranges with a hidden counter,
+    //so assigning to the loop variable in the body does not change which values come next.
+    fn for_each(&mut self, variable: &str, iterable: &Iterable, body: &VecDeque<Statement>, label: &Option<String>) -> Result<(), Diagnostic> {
+        let check = self.label("each.cond");
+        let inside = self.label("each.body");
+        let step = self.label("each.step");
+        let end = self.label("each.end");
+        let targets = LoopTargets { label: label.clone(), next: step.clone(), end: end.clone() };
+        let result = match iterable {
+            Iterable::Array(expression) => {
+                //Like the interpreter, the loop runs over a copy so the body can change the array without changing the loop.
+                let array = self.owned(expression, None)?;
+                let Type::Array(element) = array.ty.clone() else {
+                    return Err(Diagnostic::new(28, format!("Mismatched type: for loop expects an array or a range but found {}", array.ty)).with_span(expression.span().clone()));
+                };
+                let length = self.array_length(&array);
+                let counter = self.register("each.index");
+                self.current.allocas.push(format!("{} = alloca i64", counter));
+                self.emit(format!("store i64 0, i64* {}", counter));
+                self.current.variables.enter();
+                let slot = self.new_slot(variable, &element);
+                self.branch(&check);
+                self.start_block(&check);
+                let index = self.load(&Slot { pointer: counter.clone(), ty: Type::I64 });
+                let more = self.register("more");
+                self.emit(format!("{} = icmp slt i64 {}, {}", more, index.repr, length));
+                self.terminate(format!("br i1 {}, label %{}, label %{}", more, inside, end));
+                self.start_block(&inside);
+                let data = self.array_data(&array, &element);
+                let pointer = self.register("element");
+                let ty = llvm_type(&element);
+                self.emit(format!("{} = getelementptr {}, {}* {}, i64 {}", pointer, ty, ty, data, index.repr));
+                let item = self.load(&Slot { pointer, ty: *element.clone() });
+                self.store(&item, &slot.pointer);
+                let result = self.loop_body(body, targets);
+                self.branch(&step);
+                self.start_block(&step);
+                let index = self.load(&Slot { pointer: counter.clone(), ty: Type::I64 });
+                let next = self.register("each.next");
+                self.emit(format!("{} = add i64 {}, 1", next, index.repr));
+                self.emit(format!("store i64 {}, i64* {}", next, counter));
+                self.branch(&check);
+                result
+            }
+            Iterable::Range(range) => {
+                let mut bounds = Vec::new();
+                for bound in [&range.start, &range.end].into_iter().chain(&range.step) {
+                    let value = self.expression(bound, None)?;
+                    if !matches!(value.ty, Type::I32 | Type::I64) {
+                        return Err(Diagnostic::new(28, format!("Mismatched type: range expects i32 or i64 bounds but found {}", value.ty)).with_span(bound.span().clone()));
+                    }
+                    bounds.push(value);
+                }
+                //Ranges are i64 if any bound or the step is, the same rule the type checker uses.
+                let ty = if bounds.iter().any(|bound| bound.ty == Type::I64) { Type::I64 } else { Type::I32 };
+                let mut widened = Vec::new();
+                for bound in bounds {
+                    widened.push(self.cast(bound, &ty)?);
+                }
+                let start = widened[0].clone();
+                let last = widened[1].clone();
+                let amount = match widened.get(2) {
+                    Some(amount) => {
+                        let invalid = self.register("step.invalid");
+                        self.emit(format!("{} = icmp sle {} {}, 0", invalid, llvm_type(&ty), amount.repr));
+                        self.guard(&invalid, "Error[57]: Range step must be positive");
+                        amount.clone()
+                    }
+                    None => Value { repr: "1".to_string(), ty: ty.clone() },
+                };
+                let counter = self.register("each.counter");
+                self.current.allocas.push(format!("{} = alloca {}", counter, llvm_type(&ty)));
+                let counter = Slot { pointer: counter, ty: ty.clone() };
+                self.store(&start, &counter.pointer);
+                self.current.variables.enter();
+                let slot = self.new_slot(variable, &ty);
+                self.branch(&check);
+                self.start_block(&check);
+                let current = self.load(&counter);
+                let more = self.register("more");
+                let predicate = if range.inclusive { "sle" } else { "slt" };
+                self.emit(format!("{} = icmp {} {} {}, {}", more, predicate, llvm_type(&ty), current.repr, last.repr));
+                self.terminate(format!("br i1 {}, label %{}, label %{}", more, inside, end));
+                self.start_block(&inside);
+                self.store(&current, &slot.pointer);
+                let result = self.loop_body(body, targets);
+                self.branch(&step);
+                self.start_block(&step);
+                //The loop stops instead of overflowing, so a range can end at the largest value of its type.
+                let current = self.load(&counter);
+                let name = format!("@llvm.sadd.with.overflow.{}", llvm_type(&ty));
+                self.declare(&format!("declare {{{ty}, i1}} {name}({ty}, {ty})", ty = llvm_type(&ty)));
+                let pair = self.register("each.checked");
+                self.emit(format!("{pair} = call {{{ty}, i1}} {name}({ty} {}, {ty} {})", current.repr, amount.repr, ty = llvm_type(&ty)));
+                let next = self.register("each.next");
+                self.emit(format!("{} = extractvalue {{{}, i1}} {}, 0", next, llvm_type(&ty), pair));
+                let overflowed = self.register("each.overflowed");
+                self.emit(format!("{} = extractvalue {{{}, i1}} {}, 1", overflowed, llvm_type(&ty), pair));
+                self.store(&Value { repr: next, ty: ty.clone() }, &counter.pointer);
+                self.terminate(format!("br i1 {}, label %{}, label %{}", overflowed, end, check));
+                result
+            }
+        };
+        self.start_block(&end);
+        //The loop variable lives in a scope around the body, entered once the iterable has been generated.
+        self.current.variables.exit();
+        result
+    }
+
+    fn condition(&mut self, condition: &Expression) -> Result<Value, Diagnostic> {
+        let value = self.expression(condition, Some(&Type::Bool))?;
+        if value.ty != Type::Bool {
+            return Err(Diagnostic::new(18, format!("Condition must be a Bool but found {}", value.ty)).with_span(condition.span().clone()));
+        }
+        Ok(value)
+    }
+
+    //Adds amount to a numeric value for ++ and --, exiting with Error[22] if an integer overflows.
+    fn step(&mut self, value: &Value, amount: i32) -> Result<Value, Diagnostic> {
+        let register = self.register("step");
+        let instruction = match value.ty {
+            Type::I32 | Type::I64 => {
+                let operator = if amount > 0 { BinaryOperator::Add } else { BinaryOperator::Subtract };
+                return Ok(self.checked_arithmetic(register, "sadd", value, &Value { repr: amount.to_string(), ty: value.ty.clone() }, &operator));
+            }
+            Type::F32 | Type::F64 => format!("{} = fadd {} {}, {}", register, llvm_type(&value.ty), value.repr, if amount > 0 { "1.0" } else { "-1.0" }),
+            _ => return Err(Diagnostic::new(17, format!("Operator {} is not defined for {}", if amount > 0 { "++" } else { "--" }, value.ty))),
+        };
+        self.emit(instruction);
+        Ok(Value { repr: register, ty: value.ty.clone() })
+    }
+
+    //Args: expected - the type the value is assigned to, used to give empty arrays an element type.
+    fn expression(&mut self, expression: &Expression, expected: Option<&Type>) -> Result<Value, Diagnostic> {
+        self.expression_value(expression, expected).map_err(|error| error.or_span(expression.span()))
+    }
+
+    fn expression_value(&mut self, expression: &Expression, expected: Option<&Type>) -> Result<Value, Diagnostic> {
+        match expression {
+            Expression::I32(value, _) => Ok(Value { repr: value.to_string(), ty: Type::I32 }),
+            Expression::I64(value, _) => Ok(Value { repr: value.to_string(), ty: Type::I64 }),
+            //Floating point constants are written as the hex bits of a double so they round trip exactly.
+            Expression::F32(value, _) => Ok(Value { repr: format!("0x{:016X}", (*value as f64).to_bits()), ty: Type::F32 }),
+            Expression::F64(value, _) => Ok(Value { repr: format!("0x{:016X}", value.to_bits()), ty: Type::F64 }),
+            Expression::Bool(value, _) => Ok(Value { repr: value.to_string(), ty: Type::Bool }),
+            Expression::String(value, _) => Ok(Value { repr: self.string_constant(value), ty: Type::String }),
+            Expression::Variable(name, _) => {
+                let slot = self.slot(name)?;
+                Ok(self.load(&slot))
+            }
+            Expression::Array(items, _) => {
+                let expected_element = match expected {
+                    Some(Type::Array(inner)) => Some(inner.as_ref()),
+                    _ => None,
+                };
+                let mut values = Vec::new();
+                for item in items {
+                    values.push(self.owned(item, expected_element)?);
                 }
+                let element = match (values.first(), expected_element) {
+                    (Some(first), _) => first.ty.clone(),
+                    (None, Some(element)) => element.clone(),
+                    (None, None) => Type::I32,
+                };
+                Ok(self.array(&values, &element))
+            }
+            Expression::Complete(complete, _) => self.binary(complete),
+            Expression::CompleteU(complete, _) => self.unary(complete),
+            Expression::FunctionCall(name, args, span) => match self.call(name, args, span)? {
+                Some(value) => Ok(value),
+                None => Err(Diagnostic::new(30, format!("Function {} does not return a value", name))),
             },
-            Statement::DefineVariable(name, expression, _) => {
-                llvm_define_variable(&mut llvm_statements, &expression.evaluate(&variable_map), &var_index, Some(name.to_string()));
+            Expression::Index(target, index, _) => {
+                let array = self.expression(target, None)?;
+                let pointer = self.element_pointer(&array, index)?;
+                Ok(self.load(&pointer))
             }
-            _ => {
-                
+            Expression::Slice(target, start, end, _) => {
+                let array = self.expression(target, None)?;
+                if !matches!(array.ty, Type::Array(_)) {
+                    return Err(Diagnostic::new(55, format!("Cannot index into {}", array.ty)));
+                }
+                let length = self.array_length(&array);
+                let start = match start {
+                    Some(start) => self.index_value(start)?,
+                    None => "0".to_string(),
+                };
+                let end = match end {
+                    Some(end) => self.index_value(end)?,
+                    None => length.clone(),
+                };
+                let negative = self.register("negative");
+                self.emit(format!("{} = icmp slt i64 {}, 0", negative, start));
+                let backwards = self.register("backwards");
+                self.emit(format!("{} = icmp sgt i64 {}, {}", backwards, start, end));
+                let past_end = self.register("past.end");
+                self.emit(format!("{} = icmp sgt i64 {}, {}", past_end, end, length));
+                let either = self.register("invalid");
+                self.emit(format!("{} = or i1 {}, {}", either, negative, backwards));
+                let invalid = self.register("invalid");
+                self.emit(format!("{} = or i1 {}, {}", invalid, either, past_end));
+                self.guard(&invalid, "Error[53]: Slice is out of range for the array");
+                self.slice(&array, &start, &end)
+            }
+            Expression::Field(target, field, _) => {
+                let value = self.expression(target, None)?;
+                let pointer = self.field_pointer(&value, field)?;
+                Ok(self.load(&pointer))
+            }
+            Expression::Struct(name, fields, _) => self.struct_literal(name, fields),
+            Expression::Map(..) => Err(unsupported("a map")),
+            Expression::Increment(_) | Expression::Decrement(_) => {
+                Err(Diagnostic::new(11, format!("Unexpected expression {:?}", expression)))
             }
         }
     }
-    llvm_statements.push_back("ret i32 0\n}".to_string());
-    let mut buffer = "".to_string();
-    for statement in llvm_statements{
-        buffer.push_str(&statement);
+
+    //Like expression, but copies an array or struct that belongs to a variable so that changing one does not change the other.
+    fn owned(&mut self, expression: &Expression, expected: Option<&Type>) -> Result<Value, Diagnostic> {
+        let value = self.expression(expression, expected)?;
+        if shares_storage(expression) {
+            self.deep_copy(&value)
+        } else {
+            Ok(value)
+        }
+    }
+
+    //Copies an array or struct, and everything inside it, so the copy shares nothing with value. Other values are returned as they are.
+    fn deep_copy(&mut self, value: &Value) -> Result<Value, Diagnostic> {
+        match &value.ty {
+            Type::Array(_) => self.copy_array(value),
+            Type::Struct(name) => {
+                let helper = self.copy_struct_helper(name)?;
+                let register = self.register("copy");
+                self.emit(format!("{} = call i8* {}(i8* {})", register, helper, value.repr));
+                Ok(Value { repr: register, ty: value.ty.clone() })
+            }
+            _ => Ok(value.clone()),
+        }
+    }
+
+    //Returns: an i64 register holding the number of elements in an array.
+    fn array_length(&mut self, array: &Value) -> String {
+        let field = self.register("length.field");
+        self.emit(format!("{} = getelementptr %array, %array* {}, i32 0, i32 0", field, array.repr));
+        let length = self.register("length");
+        self.emit(format!("{} = load i64, i64* {}", length, field));
+        length
+    }
+
+    //Returns: a register pointing at the first element of an array, typed as a pointer to its element type.
+    fn array_data(&mut self, array: &Value, element: &Type) -> String {
+        let field = self.register("data.field");
+        self.emit(format!("{} = getelementptr %array, %array* {}, i32 0, i32 1", field, array.repr));
+        let raw = self.register("data.raw");
+        self.emit(format!("{} = load i8*, i8** {}", raw, field));
+        let data = self.register("data");
+        self.emit(format!("{} = bitcast i8* {} to {}*", data, raw, llvm_type(element)));
+        data
+    }
+
+    //Returns: an i64 register or constant holding an array index or slice bound.
+    fn index_value(&mut self, index: &Expression) -> Result<String, Diagnostic> {
+        let value = self.expression(index, None)?;
+        match value.ty {
+            Type::I64 => Ok(value.repr),
+            Type::I32 => Ok(self.cast(value, &Type::I64)?.repr),
+            other => Err(Diagnostic::new(28, format!("Mismatched type: array index expects i32 or i64 but found {}", other)).with_span(index.span().clone())),
+        }
+    }
+
+    //Returns: the slot of array[index], after exiting with Error[52] when index is out of bounds.
+    fn element_pointer(&mut self, array: &Value, index: &Expression) -> Result<Slot, Diagnostic> {
+        let Type::Array(element) = &array.ty else {
+            return Err(Diagnostic::new(55, format!("Cannot index into {}", array.ty)));
+        };
+        let index = self.index_value(index)?;
+        let length = self.array_length(array);
+        //Negative indices are huge when compared unsigned, so one comparison catches both ends.
+        let outside = self.register("outside");
+        self.emit(format!("{} = icmp uge i64 {}, {}", outside, index, length));
+        self.guard(&outside, "Error[52]: Index is out of bounds for the array");
+        let data = self.array_data(array, element);
+        let pointer = self.register("element");
+        let ty = llvm_type(element);
+        self.emit(format!("{} = getelementptr {}, {}* {}, i64 {}", pointer, ty, ty, data, index));
+        Ok(Slot { pointer, ty: *element.clone() })
+    }
+
+    //Appends a value to an array, growing its elements with realloc.
+    fn push(&mut self, array: &Value, value: &Value) {
+        self.declare("declare i8* @realloc(i8*, i64)");
+        let length = self.array_length(array);
+        let grown_length = self.register("length.grown");
+        self.emit(format!("{} = add i64 {}, 1", grown_length, length));
+        let bytes = self.register("bytes");
+        self.emit(format!("{} = mul i64 {}, {}", bytes, grown_length, element_size(&value.ty)));
+        let field = self.register("data.field");
+        self.emit(format!("{} = getelementptr %array, %array* {}, i32 0, i32 1", field, array.repr));
+        let raw = self.register("data.raw");
+        self.emit(format!("{} = load i8*, i8** {}", raw, field));
+        let grown = self.register("data.grown");
+        self.emit(format!("{} = call i8* @realloc(i8* {}, i64 {})", grown, raw, bytes));
+        self.emit(format!("store i8* {}, i8** {}", grown, field));
+        let data = self.array_data(array, &value.ty);
+        let pointer = self.register("element");
+        let ty = llvm_type(&value.ty);
+        self.emit(format!("{} = getelementptr {}, {}* {}, i64 {}", pointer, ty, ty, data, length));
+        self.store(value, &pointer);
+        let length_field = self.register("length.field");
+        self.emit(format!("{} = getelementptr %array, %array* {}, i32 0, i32 0", length_field, array.repr));
+        self.emit(format!("store i64 {}, i64* {}", grown_length, length_field));
+    }
+
+    //Removes and returns the last element of an array, exiting with Error[54] when it is empty.
+    fn pop(&mut self, array: &Value) -> Value {
+        let Type::Array(element) = &array.ty else {
+            unreachable!("pop takes an array");
+        };
+        let length = self.array_length(array);
+        let empty = self.register("empty");
+        self.emit(format!("{} = icmp eq i64 {}, 0", empty, length));
+        self.guard(&empty, "Error[54]: Cannot pop from an empty array");
+        let last = self.register("last");
+        self.emit(format!("{} = sub i64 {}, 1", last, length));
+        let data = self.array_data(array, element);
+        let pointer = self.register("element");
+        let ty = llvm_type(element);
+        self.emit(format!("{} = getelementptr {}, {}* {}, i64 {}", pointer, ty, ty, data, last));
+        let value = self.load(&Slot { pointer, ty: *element.clone() });
+        let length_field = self.register("length.field");
+        self.emit(format!("{} = getelementptr %array, %array* {}, i32 0, i32 0", length_field, array.repr));
+        self.emit(format!("store i64 {}, i64* {}", last, length_field));
+        value
+    }
+
+    fn copy_array(&mut self, array: &Value) -> Result<Value, Diagnostic> {
+        let length = self.array_length(array);
+        self.slice(array, "0", &length)
+    }
+
+    //Copies array[start..end] into a new array. Nested arrays and structs are copied too, so the result shares nothing with array.
+    //Args: start, end - i64 registers or constants that are already known to be in range.
+    fn slice(&mut self, array: &Value, start: &str, end: &str) -> Result<Value, Diagnostic> {
+        let helper = self.slice_helper(&array.ty)?;
+        let register = self.register("slice");
+        self.emit(format!("{} = call %array* {}(%array* {}, i64 {}, i64 {})", register, helper, array.repr, start, end));
+        Ok(Value { repr: register, ty: array.ty.clone() })
+    }
+
+    //Returns: the name of a function that copies part of an array of the given type.
+    fn slice_helper(&mut self, ty: &Type) -> Result<String, Diagnostic> {
+        let name = format!("@slice.{}", mangle(ty));
+        if !self.helpers.insert(name.clone()) {
+            return Ok(name);
+        }
+        let Type::Array(element) = ty else {
+            unreachable!("slice_helper is only called for arrays");
+        };
+        self.declare("declare i8* @malloc(i64)");
+        self.array_type();
+        let outer = std::mem::take(&mut self.current);
+        let element_type = llvm_type(element);
+        self.emit("%count = sub i64 %slice.end, %slice.start".to_string());
+        self.emit(format!("%bytes = mul i64 %count, {}", element_size(element)));
+        self.emit("%copy.raw = call i8* @malloc(i64 16)".to_string());
+        self.emit("%copy = bitcast i8* %copy.raw to %array*".to_string());
+        self.emit("%copy.data.raw = call i8* @malloc(i64 %bytes)".to_string());
+        self.emit("%copy.length.field = getelementptr %array, %array* %copy, i32 0, i32 0".to_string());
+        self.emit("store i64 %count, i64* %copy.length.field".to_string());
+        self.emit("%copy.data.field = getelementptr %array, %array* %copy, i32 0, i32 1".to_string());
+        self.emit("store i8* %copy.data.raw, i8** %copy.data.field".to_string());
+        self.emit(format!("%copy.data = bitcast i8* %copy.data.raw to {}*", element_type));
+        self.emit("%data.field = getelementptr %array, %array* %array, i32 0, i32 1".to_string());
+        self.emit("%data.raw = load i8*, i8** %data.field".to_string());
+        self.emit(format!("%data = bitcast i8* %data.raw to {}*", element_type));
+        self.terminate("br label %loop".to_string());
+        self.start_block("loop");
+        self.emit("%index = phi i64 [0, %entry], [%next, %body.end]".to_string());
+        self.emit("%more = icmp slt i64 %index, %count".to_string());
+        self.terminate("br i1 %more, label %body, label %end".to_string());
+        self.start_block("body");
+        self.emit("%from = add i64 %slice.start, %index".to_string());
+        self.emit(format!("%element.pointer = getelementptr {}, {}* %data, i64 %from", element_type, element_type));
+        let value = self.load(&Slot { pointer: "%element.pointer".to_string(), ty: *element.clone() });
+        let value = self.deep_copy(&value);
+        let value = match value {
+            Ok(value) => value,
+            Err(error) => {
+                self.current = outer;
+                return Err(error);
+            }
+        };
+        self.emit(format!("%target.pointer = getelementptr {}, {}* %copy.data, i64 %index", element_type, element_type));
+        self.store(&value, "%target.pointer");
+        self.terminate("br label %body.end".to_string());
+        self.start_block("body.end");
+        self.emit("%next = add i64 %index, 1".to_string());
+        self.terminate("br label %loop".to_string());
+        self.start_block("end");
+        self.terminate("ret %array* %copy".to_string());
+        let state = std::mem::replace(&mut self.current, outer);
+        self.define(&format!("define %array* {}(%array* %array, i64 %slice.start, i64 %slice.end)", name), &state);
+        Ok(name)
+    }
+
+    //Returns: the position and type of a field in the declaration of a struct.
+    //Errors: If the struct is not declared or has no such field.
+    fn field_of(&self, name: &str, field: &str) -> Result<(usize, Type), Diagnostic> {
+        let Some(declared) = self.structs.get(name) else {
+            return Err(Diagnostic::new(14, format!("Unknown type {}", name)));
+        };
+        match declared.fields.iter().position(|(existing, _)| existing == field) {
+            Some(position) => Ok((position, declared.fields[position].1.clone())),
+            None => Err(no_field(name, field)),
+        }
+    }
+
+    //Returns: the LLVM type holding the fields of a struct, such as %"struct.Point", declared the first time it is used.
+    fn struct_type(&mut self, name: &str) -> Result<String, Diagnostic> {
+        let Some(declared) = self.structs.get(name) else {
+            return Err(Diagnostic::new(14, format!("Unknown type {}", name)));
+        };
+        let ty = format!("%\"struct.{}\"", name);
+        if self.helpers.insert(ty.clone()) {
+            let fields: Vec<&str> = declared.fields.iter().map(|(_, ty)| llvm_type(ty)).collect();
+            let declaration = format!("{} = type {{ {} }}", ty, fields.join(", "));
+            if declared.fields.iter().any(|(_, ty)| matches!(ty, Type::Array(_))) {
+                self.array_type();
+            }
+            self.globals.insert(0, declaration);
+        }
+        Ok(ty)
+    }
+
+    //Returns: the slot of a field of a struct.
+    fn field_pointer(&mut self, value: &Value, field: &str) -> Result<Slot, Diagnostic> {
+        let Type::Struct(name) = &value.ty else {
+            return Err(not_a_struct(field, &value.ty.to_string()));
+        };
+        let (position, ty) = self.field_of(name, field)?;
+        let struct_type = self.struct_type(name)?;
+        let fields = self.register("fields");
+        self.emit(format!("{} = bitcast i8* {} to {}*", fields, value.repr, struct_type));
+        let pointer = self.register("field");
+        self.emit(format!("{} = getelementptr {}, {}* {}, i32 0, i32 {}", pointer, struct_type, struct_type, fields, position));
+        Ok(Slot { pointer, ty })
+    }
+
+    //Returns: an i8* register pointing at new, uninitialised room for the fields of a struct.
+    fn allocate_struct(&mut self, name: &str) -> Result<Value, Diagnostic> {
+        let struct_type = self.struct_type(name)?;
+        self.declare("declare i8* @malloc(i64)");
+        let register = self.register("struct");
+        //The size of the type is the address of the second element of an array of it starting at null.
+        self.emit(format!("{} = call i8* @malloc(i64 ptrtoint ({}* getelementptr ({}, {}* null, i32 1) to i64))", register, struct_type, struct_type, struct_type));
+        Ok(Value { repr: register, ty: Type::Struct(name.to_string()) })
+    }
+
+    //Builds a struct from a literal. The fields are evaluated in the order they are written, like the interpreter does.
+    fn struct_literal(&mut self, name: &str, fields: &[(String, Expression)]) -> Result<Value, Diagnostic> {
+        let mut values = Vec::new();
+        for (i, (field, expression)) in fields.iter().enumerate() {
+            if fields[..i].iter().any(|(earlier, _)| earlier == field) {
+                return Err(repeated_field(name, field).with_span(expression.span().clone()));
+            }
+            let (_, ty) = self.field_of(name, field).map_err(|error| error.with_span(expression.span().clone()))?;
+            let value = self.owned(expression, Some(&ty))?;
+            if value.ty != ty {
+                return Err(Diagnostic::new(28, format!("Mismatched type: field {} of {} expects {} but found {}", field, name, ty, value.ty)).with_span(expression.span().clone()));
+            }
+            values.push((field, value));
+        }
+        let declared = &self.structs[name];
+        if let Some((field, _)) = declared.fields.iter().find(|(field, _)| !fields.iter().any(|(given, _)| given == field)) {
+            return Err(missing_field(name, field));
+        }
+        let result = self.allocate_struct(name)?;
+        for (field, value) in values {
+            let pointer = self.field_pointer(&result, field)?;
+            self.store(&value, &pointer.pointer);
+        }
+        Ok(result)
+    }
+
+    //Returns: the name of a function that copies a struct and everything inside it.
+    fn copy_struct_helper(&mut self, name: &str) -> Result<String, Diagnostic> {
+        let helper = format!("@copy.{}", mangle(&Type::Struct(name.to_string())));
+        if !self.helpers.insert(helper.clone()) {
+            return Ok(helper);
+        }
+        let Some(declared) = self.structs.get(name).cloned() else {
+            return Err(Diagnostic::new(14, format!("Unknown type {}", name)));
+        };
+        let outer = std::mem::take(&mut self.current);
+        let result = self.copy_struct_body(name, &declared.fields);
+        let state = std::mem::replace(&mut self.current, outer);
+        result?;
+        self.define(&format!("define i8* {}(i8* %struct)", helper), &state);
+        Ok(helper)
+    }
+
+    fn copy_struct_body(&mut self, name: &str, fields: &[(String, Type)]) -> Result<(), Diagnostic> {
+        let source = Value { repr: "%struct".to_string(), ty: Type::Struct(name.to_string()) };
+        let copy = self.allocate_struct(name)?;
+        for (field, _) in fields {
+            let from = self.field_pointer(&source, field)?;
+            let value = self.load(&from);
+            let value = self.deep_copy(&value)?;
+            let to = self.field_pointer(&copy, field)?;
+            self.store(&value, &to.pointer);
+        }
+        self.terminate(format!("ret i8* {}", copy.repr));
+        Ok(())
+    }
+
+    fn array_type(&mut self) {
+        if !self.globals.iter().any(|global| global == ARRAY_TYPE) {
+            self.globals.insert(0, ARRAY_TYPE.to_string());
+        }
     }
-    buffer
-}
 
-fn llvm_define_variable(llvm_statements: &mut VecDeque<String>, primitive: &Primitive, var_index: &u32, name: Option<String>){
-    match primitive{
-        Primitive::String(value) => {
-            match name{
-                Some(name) => llvm_statements.push_front(format!("@{} = private unnamed_addr constant [{} x i8] c\"{}\\00\", align 1\n", name, value.len() + 1, value))
-                ,
-                None => return llvm_statements.push_front(format!("@var{} = private unnamed_addr constant [{} x i8] c\"{}\\00\", align 1\n", var_index, value.len() + 1, value))
+    fn array(&mut self, values: &[Value], element: &Type) -> Value {
+        self.declare("declare i8* @malloc(i64)");
+        self.array_type();
+        let raw = self.register("array.raw");
+        self.emit(format!("{} = call i8* @malloc(i64 16)", raw));
+        let array = self.register("array");
+        self.emit(format!("{} = bitcast i8* {} to %array*", array, raw));
+        let data = self.register("array.data");
+        self.emit(format!("{} = call i8* @malloc(i64 {})", data, values.len() * element_size(element)));
+        let ty = llvm_type(element);
+        let elements = self.register("array.elements");
+        self.emit(format!("{} = bitcast i8* {} to {}*", elements, data, ty));
+        for (i, value) in values.iter().enumerate() {
+            let pointer = self.register("array.element");
+            self.emit(format!("{} = getelementptr {}, {}* {}, i64 {}", pointer, ty, ty, elements, i));
+            self.store(value, &pointer);
+        }
+        let length = self.register("array.length");
+        self.emit(format!("{} = getelementptr %array, %array* {}, i32 0, i32 0", length, array));
+        self.emit(format!("store i64 {}, i64* {}", values.len(), length));
+        let data_field = self.register("array.data.field");
+        self.emit(format!("{} = getelementptr %array, %array* {}, i32 0, i32 1", data_field, array));
+        self.emit(format!("store i8* {}, i8** {}", data, data_field));
+        Value { repr: array, ty: Type::Array(Box::new(element.clone())) }
+    }
+
+    fn unary(&mut self, complete: &CompleteU) -> Result<Value, Diagnostic> {
+        let child = self.expression(&complete.child, None)?;
+        match (&complete.operator, &child.ty) {
+            (UnaryOperator::Parenthesis, _) => Ok(child),
+            (UnaryOperator::Not, Type::Bool) => {
+                let register = self.register("not");
+                self.emit(format!("{} = xor i1 {}, true", register, child.repr));
+                Ok(Value { repr: register, ty: Type::Bool })
+            }
+            (UnaryOperator::Negate, Type::I32 | Type::I64) => {
+                let zero = Value { repr: "0".to_string(), ty: child.ty.clone() };
+                let register = self.register("neg");
+                Ok(self.checked_arithmetic(register, "ssub", &zero, &child, &UnaryOperator::Negate))
             }
-        },
-        Primitive::I32(value) => {
-            match name{
-                Some(name) => llvm_statements.push_front(format!("@{} = private constant i32 {}\n", name, value))
-                ,
-                None => llvm_statements.push_front(format!("@var{} = private constant [4 x i8] c\"%d\\0A\\00\"\n", var_index))
+            (UnaryOperator::Negate, Type::F32 | Type::F64) => {
+                let register = self.register("neg");
+                self.emit(format!("{} = fneg {} {}", register, llvm_type(&child.ty), child.repr));
+                Ok(Value { repr: register, ty: child.ty.clone() })
             }
-        },
-        Primitive::F32(_) => todo!(),
-        Primitive::I64(_) => todo!(),
-        Primitive::F64(_) => todo!(),
-        Primitive::Bool(_) => todo!(),
-        Primitive::Array(_) => todo!(),
+            (operator, ty) => Err(Diagnostic::new(17, format!("Operator {:?} is not defined for {}", operator, ty))),
+        }
     }
+
+    //Calls an llvm.*.with.overflow intrinsic and exits with Error[22] if it overflows.
+    //Args: register: String - receives the result. intrinsic: &str - sadd, ssub or smul. operator - named in the runtime error.
+    fn checked_arithmetic(&mut self, register: String, intrinsic: &str, left: &Value, right: &Value, operator: &dyn std::fmt::Debug) -> Value {
+        let ty = llvm_type(&left.ty);
+        let name = format!("@llvm.{}.with.overflow.{}", intrinsic, ty);
+        self.declare(&format!("declare {{{}, i1}} {}({}, {})", ty, name, ty, ty));
+        let pair = self.register("checked");
+        self.emit(format!("{} = call {{{}, i1}} {}({} {}, {} {})", pair, ty, name, ty, left.repr, ty, right.repr));
+        let overflowed = self.register("overflowed");
+        self.emit(format!("{} = extractvalue {{{}, i1}} {}, 1", overflowed, ty, pair));
+        self.guard(&overflowed, &format!("Error[22]: Integer overflow in {:?}", operator));
+        self.emit(format!("{} = extractvalue {{{}, i1}} {}, 0", register, ty, pair));
+        Value { repr: register, ty: left.ty.clone() }
+    }
+
+    //Mirrors the operand combinations Complete::evaluate accepts in the interpreter.
+    fn binary(&mut self, complete: &Complete) -> Result<Value, Diagnostic> {
+        let left = self.expression(&complete.left, None)?;
+        let operator = &complete.operator;
+        if left.ty == Type::Bool && matches!(operator, BinaryOperator::And | BinaryOperator::Or) {
+            return self.logic(left, complete);
+        }
+        let mut right = self.expression(&complete.right, None)?;
+        let comparison = match operator {
+            BinaryOperator::Equals => Some(("eq", "oeq")),
+            BinaryOperator::NotEqual => Some(("ne", "une")),
+            BinaryOperator::LessThan => Some(("slt", "olt")),
+            BinaryOperator::LessThanOrEqualTo => Some(("sle", "ole")),
+            BinaryOperator::GreaterThan => Some(("sgt", "ogt")),
+            BinaryOperator::GreaterThanOrEqualTo => Some(("sge", "oge")),
+            _ => None,
+        };
+        if left.ty == Type::I64 && right.ty == Type::I32 {
+            if comparison.is_none() {
+                return Err(mismatched_types(operator, &left.ty, &right.ty));
+            }
+            let widened = self.register("sext");
+            self.emit(format!("{} = sext i32 {} to i64", widened, right.repr));
+            right = Value { repr: widened, ty: Type::I64 };
+        }
+        if left.ty != right.ty {
+            return Err(mismatched_types(operator, &left.ty, &right.ty));
+        }
+        let ty = llvm_type(&left.ty);
+        let register = self.register("op");
+        match (&left.ty, operator, comparison) {
+            (Type::I32 | Type::I64, _, Some((predicate, _))) => {
+                self.emit(format!("{} = icmp {} {} {}, {}", register, predicate, ty, left.repr, right.repr));
+                Ok(Value { repr: register, ty: Type::Bool })
+            }
+            (Type::F32 | Type::F64, _, Some((_, predicate))) => {
+                self.emit(format!("{} = fcmp {} {} {}, {}", register, predicate, ty, left.repr, right.repr));
+                Ok(Value { repr: register, ty: Type::Bool })
+            }
+            (Type::I32 | Type::I64, BinaryOperator::Add | BinaryOperator::Subtract | BinaryOperator::Multiply, _) => {
+                let intrinsic = match operator {
+                    BinaryOperator::Add => "sadd",
+                    BinaryOperator::Subtract => "ssub",
+                    _ => "smul",
+                };
+                Ok(self.checked_arithmetic(register, intrinsic, &left, &right, operator))
+            }
+            (Type::I32 | Type::I64, BinaryOperator::Divide | BinaryOperator::Modulus, _) => {
+                //checked_div and checked_rem also fail for MIN / -1, which the interpreter reports as division by zero.
+                let zero = self.register("is.zero");
+                self.emit(format!("{} = icmp eq {} {}, 0", zero, ty, right.repr));
+                let minimum = if left.ty == Type::I32 { i32::MIN as i64 } else { i64::MIN };
+                let is_minimum = self.register("is.min");
+                self.emit(format!("{} = icmp eq {} {}, {}", is_minimum, ty, left.repr, minimum));
+                let is_negative_one = self.register("is.neg.one");
+                self.emit(format!("{} = icmp eq {} {}, -1", is_negative_one, ty, right.repr));
+                let overflows = self.register("overflows");
+                self.emit(format!("{} = and i1 {}, {}", overflows, is_minimum, is_negative_one));
+                let invalid = self.register("invalid");
+                self.emit(format!("{} = or i1 {}, {}", invalid, zero, overflows));
+                self.guard(&invalid, "Error[19]: Division by zero");
+                let instruction = if *operator == BinaryOperator::Divide { "sdiv" } else { "srem" };
+                self.emit(format!("{} = {} {} {}, {}", register, instruction, ty, left.repr, right.repr));
+                Ok(Value { repr: register, ty: left.ty.clone() })
+            }
+            (Type::String, BinaryOperator::Add, _) => Ok(self.concatenate(&left, &right)),
+            (Type::String, _, Some((predicate, _))) => {
+                //strcmp orders by unsigned bytes, the same order Rust gives Strings.
+                self.declare("declare i32 @strcmp(i8*, i8*)");
+                let order = self.register("strcmp");
+                self.emit(format!("{} = call i32 @strcmp(i8* {}, i8* {})", order, left.repr, right.repr));
+                self.emit(format!("{} = icmp {} i32 {}, 0", register, predicate, order));
+                Ok(Value { repr: register, ty: Type::Bool })
+            }
+            (Type::F32 | Type::F64, _, None) => {
+                let instruction = match operator {
+                    BinaryOperator::Add => "fadd",
+                    BinaryOperator::Subtract => "fsub",
+                    BinaryOperator::Multiply => "fmul",
+                    BinaryOperator::Divide => "fdiv",
+                    BinaryOperator::Modulus => "frem",
+                    _ => return Err(mismatched_types(operator, &left.ty, &right.ty)),
+                };
+                self.emit(format!("{} = {} {} {}, {}", register, instruction, ty, left.repr, right.repr));
+                Ok(Value { repr: register, ty: left.ty.clone() })
+            }
+            _ => Err(mismatched_types(operator, &left.ty, &right.ty)),
+        }
+    }
+
+    //Returns: a new heap string holding left followed by right.
+    //Generates && and ||, running the right operand only when the left one does not decide the result, like the interpreter.
+    fn logic(&mut self, left: Value, complete: &Complete) -> Result<Value, Diagnostic> {
+        let and = complete.operator == BinaryOperator::And;
+        let (left_end, right_start, right_end, end) = (self.label("logic.left"), self.label("logic.right"), self.label("logic.right.end"), self.label("logic.end"));
+        //The left operand can itself branch, so the phi names a block that is known to come right after it.
+        self.branch(&left_end);
+        self.start_block(&left_end);
+        let (if_true, if_false) = if and { (&right_start, &end) } else { (&end, &right_start) };
+        self.terminate(format!("br i1 {}, label %{}, label %{}", left.repr, if_true, if_false));
+        self.start_block(&right_start);
+        let right = self.expression(&complete.right, None)?;
+        if right.ty != Type::Bool {
+            return Err(mismatched_types(&complete.operator, &left.ty, &right.ty));
+        }
+        self.branch(&right_end);
+        self.start_block(&right_end);
+        self.branch(&end);
+        self.start_block(&end);
+        let register = self.register("logic");
+        self.emit(format!("{} = phi i1 [{}, %{}], [{}, %{}]", register, !and, left_end, right.repr, right_end));
+        Ok(Value { repr: register, ty: Type::Bool })
+    }
+
+    fn concatenate(&mut self, left: &Value, right: &Value) -> Value {
+        self.declare("declare i64 @strlen(i8*)");
+        let left_length = self.register("strlen");
+        self.emit(format!("{} = call i64 @strlen(i8* {})", left_length, left.repr));
+        let right_length = self.register("strlen");
+        self.emit(format!("{} = call i64 @strlen(i8* {})", right_length, right.repr));
+        let length = self.register("length");
+        self.emit(format!("{} = add i64 {}, {}", length, left_length, right_length));
+        let size = self.register("size");
+        self.emit(format!("{} = add i64 {}, 1", size, length));
+        let joined = self.register("string");
+        self.declare("declare i8* @malloc(i64)");
+        self.emit(format!("{} = call i8* @malloc(i64 {})", joined, size));
+        self.declare("declare i8* @strcpy(i8*, i8*)");
+        self.emit(format!("call i8* @strcpy(i8* {}, i8* {})", joined, left.repr));
+        self.declare("declare i8* @strcat(i8*, i8*)");
+        self.emit(format!("call i8* @strcat(i8* {}, i8* {})", joined, right.repr));
+        Value { repr: joined, ty: Type::String }
+    }
+
+    //Generates a call to a builtin or a user function.
+    //Returns: the value the call produces, None for functions that do not return one.
+    //Errors: If the builtin has no code generated for it here, or an argument does not fit its parameter.
+    fn call(&mut self, name: &str, args: &[Expression], span: &Span) -> Result<Option<Value>, Diagnostic> {
+        let Some(builtin) = lookup(name) else {
+            return self.call_function(name, args, span);
+        };
+        let Some(lower) = builtin.lower else {
+            return Err(unsupported(&format!("the builtin {}", name)));
+        };
+        builtin.expect_arg_count(args.len())?;
+        if builtin.changes_first {
+            changed_place(name, &args[0])?;
+        }
+        let mut values: Vec<Value> = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            let first = values.first().map(|value| value.ty.clone());
+            let value = match (builtin.params.get(i), &first) {
+                (Some(Param::Is(ty)), _) => self.expression(arg, Some(ty))?,
+                //The element is stored in the array, so it must not share storage with a variable.
+                (Some(Param::Element), Some(Type::Array(element))) => self.owned(arg, Some(element))?,
+                _ => self.expression(arg, None)?,
+            };
+            if let Some(param) = builtin.params.get(i) {
+                if let Err(expected) = param.fits(&value.ty, first.as_ref().unwrap_or(&value.ty)) {
+                    return Err(builtin.mismatch(i, &expected, &value.ty.to_string()).with_span(arg.span().clone()));
+                }
+            }
+            values.push(value);
+        }
+        lower(self, &values)
+    }
+
+    //The code for each builtin, which the registry points to. The arguments already fit the builtin's params.
+    pub(crate) fn lower_print(&mut self, args: &[Value]) -> Result<Option<Value>, Diagnostic> {
+        self.print(&args[0], true)?;
+        Ok(None)
+    }
+
+    pub(crate) fn lower_sleep(&mut self, args: &[Value]) -> Result<Option<Value>, Diagnostic> {
+        self.declare("declare i32 @sleep(i32)");
+        self.emit(format!("call i32 @sleep(i32 {})", args[0].repr));
+        Ok(None)
+    }
+
+    pub(crate) fn lower_cast(&mut self, args: &[Value], target: Type) -> Result<Option<Value>, Diagnostic> {
+        self.cast(args[0].clone(), &target).map(Some)
+    }
+
+    pub(crate) fn lower_string(&mut self, args: &[Value]) -> Result<Option<Value>, Diagnostic> {
+        self.string_value(args[0].clone()).map(Some)
+    }
+
+    pub(crate) fn lower_rand_int(&mut self, args: &[Value]) -> Result<Option<Value>, Diagnostic> {
+        let (low, high) = (&args[0], &args[1]);
+        self.uses_rand = true;
+        self.declare("declare i32 @rand()");
+        let reversed = self.register("reversed");
+        self.emit(format!("{} = icmp sgt i32 {}, {}", reversed, low.repr, high.repr));
+        self.guard(&reversed, "Error[20]: rand_int expects two i32 bounds with low <= high");
+        //The range is computed in i64 so rand_int(i32 MIN, i32 MAX) does not overflow.
+        let low_wide = self.register("low");
+        self.emit(format!("{} = sext i32 {} to i64", low_wide, low.repr));
+        let high_wide = self.register("high");
+        self.emit(format!("{} = sext i32 {} to i64", high_wide, high.repr));
+        let difference = self.register("difference");
+        self.emit(format!("{} = sub i64 {}, {}", difference, high_wide, low_wide));
+        let range = self.register("range");
+        self.emit(format!("{} = add i64 {}, 1", range, difference));
+        let random = self.register("rand");
+        self.emit(format!("{} = call i32 @rand()", random));
+        let random_wide = self.register("rand.wide");
+        self.emit(format!("{} = sext i32 {} to i64", random_wide, random));
+        let offset = self.register("offset");
+        self.emit(format!("{} = urem i64 {}, {}", offset, random_wide, range));
+        let sum = self.register("sum");
+        self.emit(format!("{} = add i64 {}, {}", sum, low_wide, offset));
+        let result = self.register("rand.int");
+        self.emit(format!("{} = trunc i64 {} to i32", result, sum));
+        Ok(Some(Value { repr: result, ty: Type::I32 }))
+    }
+
+    pub(crate) fn lower_rand_float(&mut self, _args: &[Value]) -> Result<Option<Value>, Diagnostic> {
+        self.uses_rand = true;
+        self.declare("declare i32 @rand()");
+        let random = self.register("rand");
+        self.emit(format!("{} = call i32 @rand()", random));
+        let float = self.register("rand.float");
+        self.emit(format!("{} = sitofp i32 {} to float", float, random));
+        //RAND_MAX + 1 keeps the result in [0, 1) like the interpreter.
+        let scaled = self.register("rand.scaled");
+        self.emit(format!("{} = fdiv float {}, 0x41E0000000000000", scaled, float));
+        Ok(Some(Value { repr: scaled, ty: Type::F32 }))
+    }
+
+    pub(crate) fn lower_len(&mut self, args: &[Value]) -> Result<Option<Value>, Diagnostic> {
+        let length = match &args[0].ty {
+            Type::Array(_) => self.array_length(&args[0]),
+            Type::String => {
+                self.declare("declare i64 @strlen(i8*)");
+                let length = self.register("strlen");
+                self.emit(format!("{} = call i64 @strlen(i8* {})", length, args[0].repr));
+                length
+            }
+            other => return Err(unsupported(&format!("len of {}", other))),
+        };
+        let result = self.register("len");
+        self.emit(format!("{} = trunc i64 {} to i32", result, length));
+        Ok(Some(Value { repr: result, ty: Type::I32 }))
+    }
+
+    pub(crate) fn lower_push(&mut self, args: &[Value]) -> Result<Option<Value>, Diagnostic> {
+        self.push(&args[0], &args[1]);
+        Ok(None)
+    }
+
+    pub(crate) fn lower_pop(&mut self, args: &[Value]) -> Result<Option<Value>, Diagnostic> {
+        Ok(Some(self.pop(&args[0])))
+    }
+
+    //Generates a call to a function the program defines.
+    fn call_function(&mut self, name: &str, args: &[Expression], span: &Span) -> Result<Option<Value>, Diagnostic> {
+        let Some(signature) = self.signatures.get(name).cloned() else {
+            return Err(Diagnostic::new(5, format!("Function: {} does not exist", name)).with_span(span.clone()));
+        };
+        expect_args(name, args, signature.params.len())?;
+        let mut arguments = Vec::new();
+        for (arg, ty) in args.iter().zip(&signature.params) {
+            let value = self.owned(arg, Some(ty))?;
+            if value.ty != *ty {
+                return Err(Diagnostic::new(28, format!("Mismatched type: argument of {} expects {} but found {}", name, ty, value.ty)).with_span(arg.span().clone()));
+            }
+            arguments.push(format!("{} {}", llvm_type(ty), value.repr));
+        }
+        match &signature.return_type {
+            Some(ty) => {
+                let register = self.register("call");
+                self.emit(format!("{} = call {} {}({})", register, llvm_type(ty), signature.symbol, arguments.join(", ")));
+                Ok(Some(Value { repr: register, ty: ty.clone() }))
+            }
+            None => {
+                self.emit(format!("call void {}({})", signature.symbol, arguments.join(", ")));
+                Ok(None)
+            }
+        }
+    }
+
+    fn cast(&mut self, value: Value, target: &Type) -> Result<Value, Diagnostic> {
+        if value.ty == *target {
+            return Ok(value);
+        }
+        let instruction = match (&value.ty, target) {
+            (Type::I32, Type::I64) => "sext",
+            (Type::I64, Type::I32) => "trunc",
+            (Type::I32 | Type::I64, Type::F32 | Type::F64) => "sitofp",
+            (Type::F32 | Type::F64, Type::I32 | Type::I64) => "fptosi",
+            (Type::F32, Type::F64) => "fpext",
+            (Type::F64, Type::F32) => "fptrunc",
+            (from, _) => return Err(Diagnostic::new(8, format!("Failed to cast {} to {}", from, target))),
+        };
+        let register = self.register("cast");
+        self.emit(format!("{} = {} {} {} to {}", register, instruction, llvm_type(&value.ty), value.repr, llvm_type(target)));
+        Ok(Value { repr: register, ty: target.clone() })
+    }
+
+    //Formats a value into a new heap string, the way the interpreter's string() does.
+    fn string_value(&mut self, value: Value) -> Result<Value, Diagnostic> {
+        let text = match &value.ty {
+            Type::String => return Ok(value),
+            Type::Array(_) | Type::Struct(_) | Type::Map(..) => return Err(Diagnostic::new(8, format!("Failed to cast {} to string", value.ty))),
+            Type::Bool => return Ok(self.bool_text(&value)),
+            Type::F32 | Type::F64 => self.float_text(&value),
+            Type::I32 => self.snprintf("%d", &value),
+            Type::I64 => self.snprintf("%lld", &value),
+        };
+        let length = self.register("strlen");
+        self.declare("declare i64 @strlen(i8*)");
+        self.emit(format!("{} = call i64 @strlen(i8* {})", length, text));
+        let size = self.register("size");
+        self.emit(format!("{} = add i64 {}, 1", size, length));
+        let copy = self.register("string");
+        self.declare("declare i8* @malloc(i64)");
+        self.emit(format!("{} = call i8* @malloc(i64 {})", copy, size));
+        self.declare("declare i8* @strcpy(i8*, i8*)");
+        self.emit(format!("call i8* @strcpy(i8* {}, i8* {})", copy, text));
+        Ok(Value { repr: copy, ty: Type::String })
+    }
+
+    //Returns: an i8* to a stack buffer holding the formatted integer.
+    fn snprintf(&mut self, format: &str, value: &Value) -> String {
+        let buffer = self.buffer();
+        self.declare("declare i32 @snprintf(i8*, i64, i8*, ...)");
+        let format = self.string_constant(format);
+        self.emit(format!("call i32 (i8*, i64, i8*, ...) @snprintf(i8* {}, i64 {}, i8* {}, {} {})", buffer, BUFFER_SIZE, format, llvm_type(&value.ty), value.repr));
+        buffer
+    }
+
+    //Returns: an i8* to a new text buffer in the entry block of the current function.
+    fn buffer(&mut self) -> String {
+        let array = self.register("buffer");
+        self.current.allocas.push(format!("{} = alloca [{} x i8]", array, BUFFER_SIZE));
+        let pointer = self.register("buffer.start");
+        self.emit(format!("{} = getelementptr [{} x i8], [{} x i8]* {}, i32 0, i32 0", pointer, BUFFER_SIZE, BUFFER_SIZE, array));
+        pointer
+    }
+
+    fn bool_text(&mut self, value: &Value) -> Value {
+        let yes = self.string_constant("true");
+        let no = self.string_constant("false");
+        let register = self.register("bool.text");
+        self.emit(format!("{} = select i1 {}, i8* {}, i8* {}", register, value.repr, yes, no));
+        Value { repr: register, ty: Type::String }
+    }
+
+    //Formats a float with the fewest decimals that read back as the same value, like Rust's Display.
+    //Returns: an i8* to a stack buffer holding the text.
+    fn float_text(&mut self, value: &Value) -> String {
+        let (helper, ty, parse, widen) = match value.ty {
+            Type::F32 => ("@format.f32", "float", "strtof", "fpext float %value to double"),
+            _ => ("@format.f64", "double", "strtod", "fadd double %value, 0.0"),
+        };
+        if self.helpers.insert(helper.to_string()) {
+            self.declare("declare i32 @snprintf(i8*, i64, i8*, ...)");
+            self.declare(&format!("declare {} @{}(i8*, i8**)", ty, parse));
+            let format = self.string_constant("%.*f");
+            self.functions.push(format!(
+                "define void {helper}({ty} %value, i8* %buffer) {{\nentry:\n%wide = {widen}\nbr label %try\ntry:\n%precision = phi i32 [0, %entry], [%next, %retry]\ncall i32 (i8*, i64, i8*, ...) @snprintf(i8* %buffer, i64 {BUFFER_SIZE}, i8* {format}, i32 %precision, double %wide)\n%parsed = call {ty} @{parse}(i8* %buffer, i8** null)\n%same = fcmp oeq {ty} %parsed, %value\n%last = icmp sge i32 %precision, {MAX_DECIMALS}\n%done = or i1 %same, %last\nbr i1 %done, label %finish, label %retry\nretry:\n%next = add i32 %precision, 1\nbr label %try\nfinish:\nret void\n}}"
+            ));
+        }
+        let buffer = self.buffer();
+        self.emit(format!("call void {}({} {}, i8* {})", helper, ty, value.repr, buffer));
+        buffer
+    }
+
+    //Prints a value the way the interpreter's Display does.
+    fn print(&mut self, value: &Value, newline: bool) -> Result<(), Diagnostic> {
+        let end = if newline { "\n" } else { "" };
+        match &value.ty {
+            Type::I32 => self.printf(&format!("%d{}", end), &[value]),
+            Type::I64 => self.printf(&format!("%lld{}", end), &[value]),
+            Type::String => self.printf(&format!("%s{}", end), &[value]),
+            Type::Bool => {
+                let text = self.bool_text(value);
+                self.printf(&format!("%s{}", end), &[&text]);
+            }
+            Type::F32 | Type::F64 => {
+                let text = Value { repr: self.float_text(value), ty: Type::String };
+                self.printf(&format!("%s{}", end), &[&text]);
+            }
+            Type::Map(..) => return Err(unsupported("a map")),
+            Type::Array(_) | Type::Struct(_) => {
+                let helper = match &value.ty {
+                    Type::Struct(name) => self.print_struct_helper(name)?,
+                    _ => self.print_array_helper(&value.ty)?,
+                };
+                self.emit(format!("call void {}({} {})", helper, llvm_type(&value.ty), value.repr));
+                if newline {
+                    self.printf("\n", &[]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    //Returns: the name of a function that prints a struct as Name { field: value, ... } without a newline.
+    fn print_struct_helper(&mut self, name: &str) -> Result<String, Diagnostic> {
+        let helper = format!("@print.{}", mangle(&Type::Struct(name.to_string())));
+        if !self.helpers.insert(helper.clone()) {
+            return Ok(helper);
+        }
+        let Some(declared) = self.structs.get(name).cloned() else {
+            return Err(Diagnostic::new(14, format!("Unknown type {}", name)));
+        };
+        let outer = std::mem::take(&mut self.current);
+        let result = self.print_struct_body(name, &declared.fields);
+        let state = std::mem::replace(&mut self.current, outer);
+        result?;
+        self.define(&format!("define void {}(i8* %struct)", helper), &state);
+        Ok(helper)
+    }
+
+    fn print_struct_body(&mut self, name: &str, fields: &[(String, Type)]) -> Result<(), Diagnostic> {
+        let value = Value { repr: "%struct".to_string(), ty: Type::Struct(name.to_string()) };
+        for (i, (field, _)) in fields.iter().enumerate() {
+            let separator = if i == 0 { format!("{} {{ ", name) } else { ", ".to_string() };
+            self.printf(&format!("{}{}: ", separator, field), &[]);
+            let pointer = self.field_pointer(&value, field)?;
+            let field_value = self.load(&pointer);
+            self.print(&field_value, false)?;
+        }
+        self.printf(" }", &[]);
+        self.terminate("ret void".to_string());
+        Ok(())
+    }
+
+    //Returns: the name of a function that prints an array of the given type without a newline.
+    fn print_array_helper(&mut self, ty: &Type) -> Result<String, Diagnostic> {
+        let name = format!("@print.{}", mangle(ty));
+        if !self.helpers.insert(name.clone()) {
+            return Ok(name);
+        }
+        let Type::Array(element) = ty else {
+            unreachable!("print_array_helper is only called for arrays");
+        };
+        let outer = std::mem::take(&mut self.current);
+        let element_type = llvm_type(element);
+        self.emit("%length.field = getelementptr %array, %array* %array, i32 0, i32 0".to_string());
+        self.emit("%length = load i64, i64* %length.field".to_string());
+        self.emit("%data.field = getelementptr %array, %array* %array, i32 0, i32 1".to_string());
+        self.emit("%data.raw = load i8*, i8** %data.field".to_string());
+        self.emit(format!("%data = bitcast i8* %data.raw to {}*", element_type));
+        self.printf("[", &[]);
+        self.terminate("br label %loop".to_string());
+        self.start_block("loop");
+        self.emit("%index = phi i64 [0, %entry], [%next, %body.end]".to_string());
+        self.emit("%more = icmp slt i64 %index, %length".to_string());
+        self.terminate("br i1 %more, label %body, label %end".to_string());
+        self.start_block("body");
+        let first = self.string_constant("");
+        let separator = self.string_constant(", ");
+        self.emit("%first = icmp eq i64 %index, 0".to_string());
+        self.emit(format!("%separator = select i1 %first, i8* {}, i8* {}", first, separator));
+        self.printf("%s", &[&Value { repr: "%separator".to_string(), ty: Type::String }]);
+        self.emit(format!("%element.pointer = getelementptr {}, {}* %data, i64 %index", element_type, element_type));
+        self.emit(format!("%element = load {}, {}* %element.pointer", element_type, element_type));
+        let printed = self.print(&Value { repr: "%element".to_string(), ty: *element.clone() }, false);
+        if let Err(error) = printed {
+            self.current = outer;
+            return Err(error);
+        }
+        self.terminate("br label %body.end".to_string());
+        self.start_block("body.end");
+        self.emit("%next = add i64 %index, 1".to_string());
+        self.terminate("br label %loop".to_string());
+        self.start_block("end");
+        self.printf("]", &[]);
+        self.terminate("ret void".to_string());
+        let state = std::mem::replace(&mut self.current, outer);
+        self.define(&format!("define void {}(%array* %array)", name), &state);
+        Ok(name)
+    }
+}
+
+//Whether an expression can evaluate to an array that a variable still holds, rather than a new one.
+fn shares_storage(expression: &Expression) -> bool {
+    match expression {
+        Expression::Array(..) | Expression::Slice(..) | Expression::FunctionCall(..) => false,
+        Expression::CompleteU(complete, _) => shares_storage(&complete.child),
+        _ => true,
+    }
+}
+
+fn mismatched_types(operator: &BinaryOperator, left: &Type, right: &Type) -> Diagnostic {
+    Diagnostic::new(17, format!("Operator {:?} is not defined for {} and {}", operator, left, right))
+}
+
+fn expect_args(name: &str, args: &[Expression], count: usize) -> Result<(), Diagnostic> {
+    if args.len() != count {
+        return Err(Diagnostic::new(21, format!("Function {} expects {} argument(s) but found {}", name, count, args.len())));
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
     use crate::{
-        interpreter::{Primitive, Type}, llvm_ir::get_buffer, parse::{Expression, Statement}
+        diagnostic::{SourceFile, Span}, interpreter::{Primitive, Type}, llvm_ir::{compile_program, get_buffer}, load_dag, parse::{parse, Expression, Statement},
+        tokenizer::tokenize, type_check::check_program
     };
-    use std::{collections::{HashMap, VecDeque}, fs};
+    use std::{collections::{HashMap, VecDeque}, fs, io::Write, process::{Command, Stdio}};
     #[test]
     fn hello_world() {
         let mut statements = VecDeque::new();
@@ -86,11 +1758,12 @@ mod test {
             Statement::FunctionCall(
                 "print".to_owned(),
                 vec![
-                    Expression::String("hello world".to_owned()),
-                ]
+                    Expression::String("hello world".to_owned(), Span::default()),
+                ],
+                Span::default()
             )
         );
-        let actual = get_buffer(&statements, HashMap::new());
+        let actual = get_buffer(&statements, HashMap::new()).unwrap();
         let expected = fs::read_to_string("llvm_tests/hello_world.ll").expect("go fuck yourself").replace("\r", "");
         assert_eq!(actual, expected);
     }
@@ -101,11 +1774,12 @@ mod test {
         statements.push_back(
             Statement::DefineVariable(
                 "abc".to_owned(),
-                Expression::String("this is a string".to_owned()),
-                Type::String
+                Expression::String("this is a string".to_owned(), Span::default()),
+                Type::String,
+                Span::default()
             )
         );
-        let actual = get_buffer(&statements, HashMap::new());
+        let actual = get_buffer(&statements, HashMap::new()).unwrap();
         let expected = fs::read_to_string("llvm_tests/define_string_variable.ll").expect("go fuck yourself").replace("\r", "");
         assert_eq!(actual, expected);
     }
@@ -116,21 +1790,23 @@ mod test {
         statements.push_back(
             Statement::DefineVariable(
                 "a".to_owned(),
-                Expression::String("abc".to_owned()),
-                Type::String
+                Expression::String("abc".to_owned(), Span::default()),
+                Type::String,
+                Span::default()
             )
         );
         statements.push_back(
             Statement::FunctionCall(
                 "print".to_owned(),
                 vec![
-                    Expression::Variable("a".to_owned()),
-                ]
+                    Expression::Variable("a".to_owned(), Span::default()),
+                ],
+                Span::default()
             )
         );
         let mut variable_map = HashMap::new();
         variable_map.insert("a".to_string(), (Primitive::String("abc".to_string()), Type::String));
-        let actual = get_buffer(&statements, variable_map);
+        let actual = get_buffer(&statements, variable_map).unwrap();
         let expected = fs::read_to_string("llvm_tests/print_string_variable.ll").expect("go fuck yourself").replace("\r", "");
         assert_eq!(actual, expected);
     }
@@ -142,11 +1818,12 @@ mod test {
             Statement::FunctionCall(
                 "print".to_owned(),
                 vec![
-                    Expression::I32(777),
-                ]
+                    Expression::I32(777, Span::default()),
+                ],
+                Span::default()
             )
         );
-        let actual = get_buffer(&statements, HashMap::new());
+        let actual = get_buffer(&statements, HashMap::new()).unwrap();
         let expected = fs::read_to_string("llvm_tests/print_i32.ll").expect("go fuck yourself").replace("\r", "");
         assert_eq!(actual, expected);
     }
@@ -155,15 +1832,328 @@ mod test {
     fn print_i32_variable(){
         let mut statements = VecDeque::new();
         statements.push_back(
-            Statement::DefineVariable("a".to_string(), Expression::I32(888), Type::I32)
+            Statement::DefineVariable("a".to_string(), Expression::I32(888, Span::default()), Type::I32, Span::default())
         );
         statements.push_back(
-            Statement::FunctionCall("print".to_owned(), vec![Expression::Variable("a".to_string())])
+            Statement::FunctionCall("print".to_owned(), vec![Expression::Variable("a".to_string(), Span::default())], Span::default())
         );
         let mut variable_map: HashMap<String, (Primitive, Type)> = HashMap::new();
         variable_map.insert("a".to_string(), (Primitive::I32(888), Type::I32));
-        let actual = get_buffer(&statements, variable_map);
+        let actual = get_buffer(&statements, variable_map).unwrap();
         let expected = fs::read_to_string("llvm_tests/print_i32_variable.ll").expect("go fuck yourself").replace("\r", "");
         assert_eq!(actual, expected);
     }
-}
\ No newline at end of file
+
+    fn lli_available() -> bool {
+        Command::new("lli").arg("--version").output().is_ok_and(|output| output.status.success())
+    }
+
+    //Runs IR with lli and returns its exit code and stdout.
+    fn execute(ir: &str) -> (i32, String) {
+        let mut child = Command::new("lli").stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+        child.stdin.take().unwrap().write_all(ir.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.code().is_some(), "lli crashed: {}", String::from_utf8_lossy(&output.stderr));
+        (output.status.code().unwrap(), String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn compile(text: &str) -> String {
+        let source = SourceFile::new("test.st", text.to_string());
+        let mut tokens = tokenize(&source, 0, text.len()).unwrap();
+        let statements = parse(&mut tokens).unwrap();
+        get_buffer(&statements, HashMap::new()).unwrap()
+    }
+
+    fn compile_file(path: &str) -> String {
+        compile_blocks(SourceFile::new(path, fs::read_to_string(path).unwrap()))
+    }
+
+    fn compile_blocks(source: SourceFile) -> String {
+        let dag = load_dag(&source, false).unwrap();
+        let programs = check_program(&dag).unwrap();
+        compile_program(&dag, &programs).unwrap()
+    }
+
+    #[test]
+    fn control_flow_and_arithmetic() {
+        if !lli_available() {
+            return;
+        }
+        let ir = compile("
+            let total: i64 = 0;
+            for (let i: i32 = 0; i < 10; i++) {
+                if i % 3 == 0 {
+                    total = total + i64(i);
+                } elif i == 4 {
+                    print(\"four\");
+                } else {
+                    total = total - i64(1);
+                }
+            }
+            print(total);
+            let x: f64 = 0.5;
+            let two: f64 = 2.0;
+            let three: f64 = 3.0;
+            while x < f64(4) {
+                x = x * two;
+            }
+            print(x / three);
+            print(f32(x) / f32(3));
+            print(!(x > two) || false);
+            print(string(i32(x)));
+            let grid: Array<Array<i32>> = [[1, 2], []];
+            print(grid);
+            let halves: Array<f32> = [f32(3) / f32(2), f32(2)];
+            print(halves);
+        ");
+        assert_eq!(execute(&ir), (0, "four\n13\n1.3333333333333333\n1.3333334\nfalse\n4\n[[1, 2], []]\n[1.5, 2]\n".to_string()));
+    }
+
+    #[test]
+    fn functions() {
+        if !lli_available() {
+            return;
+        }
+        let ir = compile("
+            fn fact(n: i64) -> i64 {
+                if n < 2 {
+                    return i64(1);
+                }
+                return n * fact(n - i64(1));
+            }
+            fn greet(name: String) {
+                print(name);
+            }
+            greet(\"hi\");
+            print(fact(i64(20)));
+        ");
+        assert_eq!(execute(&ir), (0, "hi\n2432902008176640000\n".to_string()));
+    }
+
+    #[test]
+    fn escaped_and_unicode_strings() {
+        let ir = compile(r#"let s: String = "tab\there \"quoted\"\nhéllo \u{1F600}"; print(s); print(r"C:\dir");"#);
+        assert!(ir.contains(r#"[30 x i8] c"tab\09here \22quoted\22\0Ah\C3\A9llo \F0\9F\98\80\00""#), "{}", ir);
+        if !lli_available() {
+            return;
+        }
+        assert_eq!(execute(&ir), (0, "tab\there \"quoted\"\nhéllo \u{1F600}\nC:\\dir\n".to_string()));
+    }
+
+    #[test]
+    fn negation_and_literals() {
+        if !lli_available() {
+            return;
+        }
+        let ir = compile("let a: i32 = -2147483648;\nlet b: i64 = 0xFFi64 * -2;\nlet c: f32 = 2.5f32;\nprint(a);\nprint(b);\nprint(-c);\nprint(1e-9);\nprint(-(a + 1));");
+        assert_eq!(execute(&ir), (0, "-2147483648\n-510\n-2.5\n0.000000001\n2147483647\n".to_string()));
+        let (code, output) = execute(&compile("let a: i32 = -2147483648;\nprint(1);\nprint(-a);"));
+        assert_eq!((code, output.as_str()), (1, "1\n"));
+    }
+
+    #[test]
+    fn break_and_continue() {
+        if !lli_available() {
+            return;
+        }
+        let ir = compile("let found: i32 = 0;\nlet i: i32 = 2;\nwhile true {\n    if 91 % i == 0 {\n        found = i;\n        break;\n    }\n    i++;\n}\nprint(found);\nlet odd: i32 = 0;\nfor (let j: i32 = 0; j < 10; j++) {\n    if j % 2 == 0 {\n        continue;\n    }\n    odd = odd + j;\n}\nprint(odd);\nlet pairs: i32 = 0;\n'outer: for (let a: i32 = 1; a < 10; a++) {\n    let b: i32 = 0;\n    'inner: while b < 10 {\n        b++;\n        if b > a {\n            continue 'outer;\n        }\n        if a * b == 24 {\n            break 'outer;\n        }\n        pairs++;\n    }\n}\nprint(pairs);");
+        assert_eq!(execute(&ir), (0, "7\n25\n18\n".to_string()));
+    }
+
+    #[test]
+    fn arrays() {
+        if !lli_available() {
+            return;
+        }
+        let ir = compile("let a: Array<i32> = [3, 1, 4];\nlet grid: Array<Array<i32>> = [[1, 2], [3, 4]];\na[1] = 10;\ngrid[1][0] = a[1] + 1;\npush(a, 5);\nprint(len(a));\nlet middle: Array<i32> = a[1..3];\nmiddle[0] = 99;\nprint(a);\nprint(middle);\nlet row: Array<i32> = grid[0];\npush(row, 7);\nprint(row);\nprint(pop(a));\npush(grid[0], pop(a));\nprint(grid);\nlet copy: Array<Array<i32>> = grid[..];\ncopy[0][0] = 0;\nlet i: i64 = 1;\nprint(grid[i][0..1]);\nprint(grid[0][0]);\nprint(len(\"héllo\"));");
+        assert_eq!(execute(&ir), (0, "4\n[3, 10, 4, 5]\n[99, 4]\n[1, 2, 7]\n5\n[[1, 2, 4], [11, 4]]\n[11]\n1\n6\n".to_string()));
+        for (text, output) in [
+            ("let a: Array<i32> = [1];\nprint(1);\nprint(a[1]);", "1\n"),
+            ("let a: Array<i32> = [1];\nlet i: i32 = -1;\na[i] = 2;", ""),
+            ("let a: Array<i32> = [1, 2];\nprint(a[1..3]);", ""),
+            ("let a: Array<i32> = [];\nprint(pop(a));", ""),
+        ] {
+            assert_eq!(execute(&compile(text)), (1, output.to_string()), "{}", text);
+        }
+    }
+
+    #[test]
+    fn for_each_loops() {
+        if !lli_available() {
+            return;
+        }
+        let ir = compile("let total: i32 = 0;\nlet a: Array<i32> = [1, 2, 3];\nfor x in a {\n    push(a, x);\n    total = total + x;\n    x = 0;\n}\nprint(total);\nprint(a);\nlet n: i64 = 10;\nfor i in (0..=n).step(4) {\n    print(i);\n}\nlet x: String = \"outer\";\n'rows: for row in [[1, 2], [3, 4]] {\n    for x in row {\n        if x == 2 {\n            continue 'rows;\n        }\n        if x == 4 {\n            break 'rows;\n        }\n        print(x);\n    }\n}\nprint(x);\nfor k in 2147483646..=2147483647 {\n    print(k);\n}\nfor k in 3..3 {\n    print(k);\n}");
+        assert_eq!(execute(&ir), (0, "6\n[1, 2, 3, 1, 2, 3]\n0\n4\n8\n1\n3\nouter\n2147483646\n2147483647\n".to_string()));
+        let (code, output) = execute(&compile("let s: i32 = 0;\nfor i in (0..3).step(s) {\n}"));
+        assert_eq!((code, output.as_str()), (1, ""));
+    }
+
+    #[test]
+    fn block_scopes() {
+        if !lli_available() {
+            return;
+        }
+        let ir = compile("let x: i32 = 1;\nif true {\n    let x: String = \"inner\";\n    print(x);\n}\nprint(x);\nfor (let i: i32 = 0; i < 2; i++) {\n    let x: i32 = i + 10;\n    print(x);\n}\nprint(x);");
+        assert_eq!(execute(&ir), (0, "inner\n1\n10\n11\n1\n".to_string()));
+    }
+
+    #[test]
+    fn structs() {
+        if !lli_available() {
+            return;
+        }
+        let text = "struct Point { x: f64, y: f64 }
+struct Shape {
+    name: String,
+    corners: Array<Point>,
+}
+block a {
+    let p: Point = Point { y: 2.5, x: 1.0 };
+    let q: Point = p;
+    q.x = q.x + 8.0;
+    let s: Shape = Shape { name: \"line\", corners: [p, q] };
+    s.corners[1].y = 0.5;
+    push(s.corners, Point { x: 0.0, y: 0.0 });
+    print(pop(s.corners));
+    print(p);
+    print(s);
+}
+block b requires[a[s]] {
+    s.corners[0].x = 4.0;
+    print(s.corners[0]);
+}
+block c requires[a[s]] {
+    print(s.corners[0].x);
+}";
+        let ir = compile_blocks(SourceFile::new("test.st", text.to_string()));
+        let (code, output) = execute(&ir);
+        assert_eq!(code, 0);
+        let mut lines: Vec<&str> = output.lines().collect();
+        //b and c run at the same time, so only the lines of a have a fixed order.
+        lines[3..].sort();
+        assert_eq!(lines, vec![
+            "Point { x: 0, y: 0 }",
+            "Point { x: 1, y: 2.5 }",
+            "Shape { name: line, corners: [Point { x: 1, y: 2.5 }, Point { x: 9, y: 0.5 }] }",
+            "1",
+            "Point { x: 4, y: 2.5 }",
+        ]);
+    }
+
+    #[test]
+    fn runtime_errors_exit() {
+        if !lli_available() {
+            return;
+        }
+        let (code, output) = execute(&compile("let x: i32 = 2147483647;\nprint(1);\nx = x + 1;\nprint(2);"));
+        assert_eq!((code, output.as_str()), (1, "1\n"));
+        let (code, _) = execute(&compile("let x: i32 = 0;\nx = 5 / x;"));
+        assert_eq!(code, 1);
+        let (code, output) = execute(&compile("let x: i32 = 2147483646;\nx++;\nprint(x);\nx++;\nprint(2);"));
+        assert_eq!((code, output.as_str()), (1, "2147483647\n"));
+        let (code, output) = execute(&compile("let y: i64 = -9223372036854775807i64;\ny--;\nprint(y);\ny--;\nprint(2);"));
+        assert_eq!((code, output.as_str()), (1, "-9223372036854775808\n"));
+    }
+
+    #[test]
+    fn blocks_run_in_parallel() {
+        if !lli_available() {
+            return;
+        }
+        let text = "block a {\n    sleep(1);\n    let x: i32 = 40;\n}\nblock b {\n    sleep(1);\n    let y: String = \"done\";\n}\nblock c requires[a[x as total], b[y]] {\n    print(total + 2);\n    print(y);\n}";
+        let ir = compile_blocks(SourceFile::new("test.st", text.to_string()));
+        let start = std::time::Instant::now();
+        assert_eq!(execute(&ir), (0, "42\ndone\n".to_string()));
+        let elapsed = start.elapsed().as_secs_f64();
+        assert!(elapsed < 1.9, "a and b should sleep at the same time, took {}s", elapsed);
+    }
+
+    #[test]
+    fn test_files_compile() {
+        if !lli_available() {
+            return;
+        }
+        let (code, output) = execute(&compile_file("test_files/variable_locality_test.st"));
+        assert_eq!((code, output.as_str()), (0, "2.1\n"));
+        let (code, output) = execute(&compile_file("test_files/sarateese.st"));
+        assert_eq!(code, 0);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[..3], ["6857", "true", "[[1, 2, 3, 4]]"]);
+        let random: f32 = lines[3].parse().unwrap();
+        assert!((0.0..1.0).contains(&random));
+        for path in ["test_files/parallel_timings_test.st", "test_files/second_parallel_timings_test.st"] {
+            let mut child = Command::new("llvm-as").args(["-o", "/dev/null"]).stdin(Stdio::piped()).spawn().unwrap();
+            child.stdin.take().unwrap().write_all(compile_file(path).as_bytes()).unwrap();
+            assert!(child.wait().unwrap().success(), "{} did not compile", path);
+        }
+    }
+
+    #[test]
+    fn maps_are_not_compiled() {
+        for text in ["let m: Map<String, i32> = {\"a\": 1};", "let a: Array<Map<String, i32>> = [];\nfor (k, v) in a[0] {\n}"] {
+            let source = SourceFile::new("test.st", text.to_string());
+            let statements = parse(&mut tokenize(&source, 0, text.len()).unwrap()).unwrap();
+            assert_eq!(get_buffer(&statements, HashMap::new()).unwrap_err().code, 37, "{}", text);
+        }
+    }
+
+    #[test]
+    fn string_operators() {
+        let text = "let a: String = \"ab\";\nprint(trim(a));";
+        let source = SourceFile::new("test.st", text.to_string());
+        let statements = parse(&mut tokenize(&source, 0, text.len()).unwrap()).unwrap();
+        assert_eq!(get_buffer(&statements, HashMap::new()).unwrap_err().code, 37);
+        if !lli_available() {
+            return;
+        }
+        let ir = compile("
+            let a: String = \"ab\";
+            let b: String = a + \"c\" + string(1);
+            print(b);
+            print(a < b);
+            print(a == \"ab\");
+            print(\"b\" <= a);
+            print(len(a + a));
+        ");
+        assert_eq!(execute(&ir), (0, "abc1\ntrue\ntrue\nfalse\n4\n".to_string()));
+    }
+
+    #[test]
+    fn builtin_calls() {
+        for (text, code) in [("let x: i32 = print(1);", 30), ("print(keys({1: 2}));", 37), ("print(len());", 21), ("pop([1]);", 56),
+            ("let a: Array<i32> = [1]; push(a, \"x\");", 28), ("sleep(1.5);", 28), ("print(i32(\"1\"));", 8), ("print(len(1));", 28)] {
+            let source = SourceFile::new("test.st", text.to_string());
+            let statements = parse(&mut tokenize(&source, 0, text.len()).unwrap()).unwrap();
+            assert_eq!(get_buffer(&statements, HashMap::new()).unwrap_err().code, code, "{}", text);
+        }
+        if !lli_available() {
+            return;
+        }
+        let ir = compile("
+            rand_int(1, 2);
+            let a: Array<i32> = [1, 2];
+            pop(a);
+            print(len(a) + rand_int(5, 5));
+        ");
+        assert_eq!(execute(&ir), (0, "6\n".to_string()));
+    }
+
+    #[test]
+    fn logic_short_circuits() {
+        if !lli_available() {
+            return;
+        }
+        let ir = compile("
+            let a: Array<i32> = [1];
+            for (let i: i32 = 0; i < 3; i++) {
+                if i < len(a) && a[i] == 1 {
+                    print(\"found\");
+                }
+                print(i >= len(a) || a[i] == 2);
+            }
+            let t: Bool = true;
+            print((t && !t) || (t && (t || a[5] == 0)));
+        ");
+        assert_eq!(execute(&ir), (0, "found\nfalse\ntrue\ntrue\ntrue\n".to_string()));
+    }
+}
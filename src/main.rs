@@ -1,62 +1,387 @@
-use std::{collections::HashSet, env, fs};
-use dag::{build_dag, print_dag};
-use thread_handler::parallel;
-use token_block::{extract_block_meta, split_blocks, TokenBlock};
+use std::{collections::HashSet, env, fs, io::{self, StdoutLock, Write}, path::PathBuf, process};
+use build_script::{build, default_output};
+use builtins::builtin_structs;
+use dag::{build_dag, print_dag, Dag};
+use diagnostic::{Diagnostic, SourceFile};
+use parse::{parse, parse_struct};
+use thread_handler::{default_jobs, parallel, FailurePolicy};
+use token_block::{extract_block_meta, extract_struct, split_blocks, TokenBlock};
 use tokenizer::tokenize;
+use type_check::check_program;
 
+mod builtins;
 mod interpreter;
 mod parse;
 pub mod tokenizer;
-mod llvm_ir;
+pub mod llvm_ir;
 pub mod build_script;
 pub mod dag;
+pub mod repl;
+pub mod diagnostic;
 pub mod token_block;
 pub mod thread_handler;
+pub mod type_check;
 
-const RED: &str = "\x1b[31m";
-const RESET: &str = "\x1b[0m";
+const USAGE: &str = "Usage: compiler [command] [options] <file>
+
+Commands:
+    run       Interpret the program, running independent blocks in parallel (default)
+    check     Tokenize, parse and type check the program without running it
+    build     Compile the program to a native executable with LLVM
+    dag       Print the dependency graph between blocks
+    tokens    Print the tokens of every block
+    ast       Print the parsed statements of every block
+    repl      Start an interactive session, no file needed
+
+Options:
+    -v, -vv              Print more about what is happening
+    -j, --jobs <n>       Number of worker threads for run
+    -k, --keep-going     Keep running blocks that do not depend on a failed block
+    -o <path>            Where build writes its output
+    --emit-llvm          Make build write LLVM IR instead of an executable
+    -h, --help           Print this message";
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        panic!("{}Error[1]: File Name Required{}", RED, RESET);
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_options(&args) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            if let Err(diagnostics) = write_stdout(|out| writeln!(out, "{}", USAGE)) {
+                for diagnostic in diagnostics {
+                    eprint!("{}", diagnostic.render(None));
+                }
+                process::exit(1);
+            }
+            return;
+        }
+        Err(diagnostic) => {
+            eprint!("{}", diagnostic.render(None));
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if options.command == Subcommand::Repl {
+        repl::run();
+        return;
     }
-    let file_name = &args[1];
-    let very_verbose = args.len() > 2 && args[2] == "-vv";
-    let verbose = args.len() > 2 && (args[2] == "-v" || very_verbose);
-    let text = read_file(file_name);
-    let string_blocks = split_blocks(&text);
-    let mut token_blocks = HashSet::new();
-    for block in string_blocks {
-        let (id, requires, content) = extract_block_meta(&block);
-        if !token_blocks.insert(TokenBlock::new(id.clone(), requires, tokenize(&content))) {
-            panic!("{}Error[2]: Duplicate Block ID: {}{}", RED, id, RESET);
+    let source = match read_file(&options.file) {
+        Ok(source) => source,
+        Err(diagnostic) => {
+            eprint!("{}", diagnostic.render(None));
+            process::exit(1);
         }
+    };
+    if let Err(diagnostics) = run_command(&source, &options) {
+        for diagnostic in diagnostics {
+            eprint!("{}", diagnostic.render(Some(&source.text)));
+        }
+        process::exit(1);
     }
-    if very_verbose {
-        for block in &token_blocks {
-            println!("{:?}", block);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Subcommand {
+    Run,
+    Check,
+    Build,
+    Dag,
+    Tokens,
+    Ast,
+    Repl,
+}
+
+impl Subcommand {
+    fn from_name(name: &str) -> Option<Subcommand> {
+        match name {
+            "run" => Some(Subcommand::Run),
+            "check" => Some(Subcommand::Check),
+            "build" => Some(Subcommand::Build),
+            "dag" => Some(Subcommand::Dag),
+            "tokens" => Some(Subcommand::Tokens),
+            "ast" => Some(Subcommand::Ast),
+            "repl" => Some(Subcommand::Repl),
+            _ => None,
         }
     }
-    let dag = build_dag(&token_blocks);
-    if very_verbose {
-        print_dag(&dag);
+}
+
+#[derive(Debug)]
+struct Options {
+    command: Subcommand,
+    file: String,
+    verbose: bool,
+    very_verbose: bool,
+    jobs: usize,
+    policy: FailurePolicy,
+    output: Option<String>,
+    emit_llvm: bool,
+}
+
+//Reads the command, flags and file name, in any order.
+//Args: args: &[String] - the command line arguments after the program name.
+//Returns: Options - what to do and how, or None if --help was given.
+//Errors: If a flag is unknown or does not apply to the command, --jobs is not given a positive number, or there is not exactly one file.
+fn parse_options(args: &[String]) -> Result<Option<Options>, Diagnostic> {
+    let mut command = None;
+    let mut file = None;
+    let mut options = Options {
+        command: Subcommand::Run,
+        file: String::new(),
+        verbose: false,
+        very_verbose: false,
+        jobs: default_jobs(),
+        policy: FailurePolicy::FailFast,
+        output: None,
+        emit_llvm: false,
+    };
+    //Flags that only make sense for one command, checked once the command is known.
+    let mut run_flags = Vec::new();
+    let mut build_flags = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-v" => options.verbose = true,
+            "-vv" => {
+                options.verbose = true;
+                options.very_verbose = true;
+            }
+            "--keep-going" | "-k" => {
+                options.policy = FailurePolicy::KeepGoing;
+                run_flags.push(arg);
+            }
+            "--jobs" | "-j" => {
+                let value = args.next().map(String::as_str).unwrap_or("");
+                options.jobs = match value.parse::<usize>() {
+                    Ok(jobs) if jobs > 0 => jobs,
+                    _ => return Err(Diagnostic::new(24, format!("Invalid value for --jobs: '{}'", value))
+                        .with_note("--jobs expects a positive number of worker threads")),
+                };
+                run_flags.push(arg);
+            }
+            "-o" => {
+                match args.next() {
+                    Some(path) => options.output = Some(path.clone()),
+                    None => return Err(Diagnostic::new(12, "Expected an output path after -o but found nothing")),
+                }
+                build_flags.push(arg);
+            }
+            "--emit-llvm" => {
+                options.emit_llvm = true;
+                build_flags.push(arg);
+            }
+            flag if flag.starts_with('-') => return Err(Diagnostic::new(25, format!("Unknown flag: {}", flag))),
+            name => match Subcommand::from_name(name) {
+                Some(subcommand) if command.is_none() => command = Some(subcommand),
+                _ if file.is_none() => file = Some(name.to_string()),
+                _ => return Err(Diagnostic::new(41, format!("Unexpected argument {}", name))),
+            },
+        }
+    }
+    options.command = command.unwrap_or(Subcommand::Run);
+    if let Some(flag) = run_flags.iter().find(|_| options.command != Subcommand::Run) {
+        return Err(Diagnostic::new(42, format!("{} can only be used with the run command", flag)));
+    }
+    if let Some(flag) = build_flags.iter().find(|_| options.command != Subcommand::Build) {
+        return Err(Diagnostic::new(42, format!("{} can only be used with the build command", flag)));
+    }
+    match (options.command, file) {
+        (Subcommand::Repl, Some(file)) => return Err(Diagnostic::new(41, format!("Unexpected argument {}", file))),
+        (Subcommand::Repl, None) => {}
+        (_, file) => options.file = file.ok_or_else(|| Diagnostic::new(1, "File Name Required"))?,
+    }
+    Ok(Some(options))
+}
+
+//Carries out the command on a source file.
+//Errors: every diagnostic that stopped the command, or that a block raised while running.
+fn run_command(source: &SourceFile, options: &Options) -> Result<(), Vec<Diagnostic>> {
+    let dag = load_dag(source, options.very_verbose)?;
+    if options.very_verbose && options.command != Subcommand::Dag {
+        write_stdout(|out| print_dag(&dag, out))?;
+    }
+    match options.command {
+        Subcommand::Repl => unreachable!("the REPL does not read a file"),
+        Subcommand::Dag => write_stdout(|out| print_dag(&dag, out))?,
+        Subcommand::Tokens => write_stdout(|out| {
+            for id in &dag.order {
+                writeln!(out, "block {}:", id)?;
+                for token in &dag.blocks[id].tokens {
+                    writeln!(out, "    {}:{} {:?}", token.span.line, token.span.col, token.token)?;
+                }
+            }
+            Ok(())
+        })?,
+        Subcommand::Ast => {
+            let mut programs = Vec::new();
+            for id in &dag.order {
+                programs.push((id, parse(&mut dag.blocks[id].tokens.clone()).map_err(|error| vec![error])?));
+            }
+            write_stdout(|out| {
+                for (id, statements) in &programs {
+                    writeln!(out, "block {}:", id)?;
+                    writeln!(out, "{:#?}", statements)?;
+                }
+                Ok(())
+            })?;
+        }
+        Subcommand::Check => {
+            check_program(&dag)?;
+            if options.verbose {
+                write_stdout(|out| writeln!(out, "No errors found in {} block(s)", dag.len()))?;
+            }
+        }
+        Subcommand::Build => {
+            let programs = check_program(&dag)?;
+            let output = options.output.as_ref().map(PathBuf::from).unwrap_or_else(|| default_output(&options.file, options.emit_llvm));
+            build(&dag, &programs, &output, options.emit_llvm).map_err(|error| vec![error])?;
+            if options.verbose {
+                write_stdout(|out| writeln!(out, "Wrote {}", output.display()))?;
+            }
+        }
+        Subcommand::Run => {
+            let programs = check_program(&dag)?;
+            let report = parallel(dag, programs, options.jobs, options.policy, options.verbose);
+            if !report.is_success() {
+                //Rendered here so the summary comes after every failure, there is nothing left for main to print.
+                for (_id, diagnostic) in &report.failed {
+                    eprint!("{}", diagnostic.render(Some(&source.text)));
+                }
+                eprintln!("{}", report.summary());
+                return Err(Vec::new());
+            } else if options.verbose {
+                write_stdout(|out| writeln!(out, "{}", report.summary()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+//Splits a source file into blocks and struct declarations, tokenizes them and orders the blocks by their requirements.
+//Errors: the first diagnostic raised while splitting, tokenizing or ordering the blocks.
+//Args: print_blocks: bool - print every block as it is found.
+fn load_dag(source: &SourceFile, print_blocks: bool) -> Result<Dag, Vec<Diagnostic>> {
+    let string_blocks = split_blocks(&source.text);
+    let mut token_blocks: HashSet<TokenBlock> = HashSet::new();
+    let mut structs = builtin_structs();
+    for (offset, block) in string_blocks {
+        if let Some((start, end)) = extract_struct(source, offset, &block).map_err(|error| vec![error])? {
+            let mut tokens = tokenize(source, start, end).map_err(|error| vec![error])?;
+            let declared = parse_struct(&mut tokens).map_err(|error| vec![error])?;
+            if let Some(existing) = structs.get(&declared.name) {
+                let note = if builtin_structs().contains_key(&declared.name) {
+                    format!("{} is a builtin struct", existing.name)
+                } else {
+                    format!("struct {} is first defined on line {}", existing.name, existing.span.line)
+                };
+                return Err(vec![Diagnostic::new(58, format!("Struct {} is already defined", declared.name)).with_span(declared.span).with_note(note)]);
+            }
+            structs.insert(declared.name.clone(), declared);
+            continue;
+        }
+        let meta = extract_block_meta(source, offset, &block).map_err(|error| vec![error])?;
+        let tokens = tokenize(source, meta.content_offset, meta.content_offset + meta.content.len()).map_err(|error| vec![error])?;
+        let token_block = TokenBlock::new(meta.id.clone(), meta.requires, tokens, meta.span.clone());
+        if let Some(existing) = token_blocks.get(&token_block) {
+            return Err(vec![Diagnostic::new(2, format!("Duplicate Block ID: {}", meta.id))
+                .with_span(meta.span)
+                .with_note(format!("block {} is first defined on line {}", existing.id, existing.span.line))]);
+        }
+        token_blocks.insert(token_block);
+    }
+    if print_blocks {
+        write_stdout(|out| {
+            for block in &token_blocks {
+                writeln!(out, "{:?}", block)?;
+            }
+            Ok(())
+        })?;
+    }
+    let mut dag = build_dag(&token_blocks).map_err(|error| vec![error])?;
+    dag.structs = structs;
+    Ok(dag)
+}
+
+//Writes command output through one lock on stdout.
+//A reader that stops early, such as head, closes the pipe, which ends the program normally rather than as an error.
+//Errors: If stdout cannot be written to for any other reason.
+fn write_stdout(write: impl FnOnce(&mut StdoutLock) -> io::Result<()>) -> Result<(), Vec<Diagnostic>> {
+    let mut out = io::stdout().lock();
+    match write(&mut out).and_then(|()| out.flush()) {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == io::ErrorKind::BrokenPipe => process::exit(0),
+        Err(error) => Err(vec![Diagnostic::new(70, format!("Error writing to stdout: {}", error))]),
     }
-    parallel(dag, verbose);
-    // run(&statements, compiler.variable_map);
 }
 
 //Reads the raw text of a file.
 //Args: file_name: &str - the name of the file to read.
-//Returns: String - the contents of the file.
-//Exits: If there is an error reading the file.
-fn read_file(file_name: &str) -> String {
+//Returns: SourceFile - the name and contents of the file.
+//Errors: If there is an error reading the file.
+fn read_file(file_name: &str) -> Result<SourceFile, Diagnostic> {
     match fs::read_to_string(file_name) {
-        Ok(contents) => {
-            contents
-        }
-        Err(err) => {
-            panic!("{}Error[4]: Error reading file {}: {}{}", RED, file_name, err, RESET);
-        }
+        Ok(contents) => Ok(SourceFile::new(file_name, contents)),
+        Err(err) => Err(Diagnostic::new(4, format!("Error reading file {}: {}", file_name, err))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{parse_options, Options, Subcommand};
+
+    fn options(args: &[&str]) -> Options {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        parse_options(&args).unwrap().unwrap()
     }
-}
\ No newline at end of file
+
+    fn error_code(args: &[&str]) -> u32 {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        parse_options(&args).unwrap_err().code
+    }
+
+    #[test]
+    fn subcommands_and_flags_in_any_order() {
+        let parsed = options(&["build", "-o", "out", "main.st", "--emit-llvm", "-v"]);
+        assert_eq!(parsed.command, Subcommand::Build);
+        assert_eq!(parsed.file, "main.st");
+        assert_eq!(parsed.output.as_deref(), Some("out"));
+        assert!(parsed.emit_llvm && parsed.verbose);
+        let parsed = options(&["-k", "main.st", "-j", "3", "run"]);
+        assert_eq!(parsed.command, Subcommand::Run);
+        assert_eq!(parsed.jobs, 3);
+        //A file can share its name with a command once the command is given.
+        let parsed = options(&["check", "dag"]);
+        assert_eq!((parsed.command, parsed.file.as_str()), (Subcommand::Check, "dag"));
+    }
+
+    #[test]
+    fn repl_needs_no_file() {
+        assert_eq!(options(&["repl"]).command, Subcommand::Repl);
+    }
+
+    #[test]
+    fn run_is_the_default_command() {
+        let parsed = options(&["main.st", "-vv"]);
+        assert_eq!(parsed.command, Subcommand::Run);
+        assert!(parsed.very_verbose);
+    }
+
+    #[test]
+    fn help() {
+        let args = vec!["run".to_string(), "--help".to_string()];
+        assert!(parse_options(&args).unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert_eq!(error_code(&[]), 1);
+        assert_eq!(error_code(&["check"]), 1);
+        assert_eq!(error_code(&["main.st", "--fast"]), 25);
+        assert_eq!(error_code(&["main.st", "-j", "0"]), 24);
+        assert_eq!(error_code(&["main.st", "other.st"]), 41);
+        assert_eq!(error_code(&["check", "main.st", "--emit-llvm"]), 42);
+        assert_eq!(error_code(&["build", "main.st", "-k"]), 42);
+        assert_eq!(error_code(&["build", "main.st", "-o"]), 12);
+        assert_eq!(error_code(&["repl", "main.st"]), 41);
+    }
+}
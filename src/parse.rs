@@ -1,36 +1,92 @@
-use core::panic;
 use std::collections::VecDeque;
-use crate::{interpreter::Type, tokenizer::{MathOp, Token}};
+use crate::{diagnostic::{Diagnostic, Span}, interpreter::{invalid_key, repeated_field, Type}, tokenizer::{MathOp, SpannedToken, Token}};
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Statement {
-    DefineVariable(String, Expression, Type),
-    WhileLoop(Expression, VecDeque<Statement>),
-    If(Expression, VecDeque<Statement>, VecDeque<Statement>, Option<VecDeque<Statement>>),
-    Elif(Expression, VecDeque<Statement>),
-    ForLoop(Box<Statement>, Expression, Box<Statement>, VecDeque<Statement>),
-    ModifyVariable(String, Expression),
-    _DefineFunction(String, Vec<Type>, VecDeque<Statement>),
-    FunctionCall(String, Vec<Expression>),
+    DefineVariable(String, Expression, Type, Span),
+    //The label, if any, is the name a break or continue uses to target this loop from inside a nested one.
+    WhileLoop(Expression, VecDeque<Statement>, Option<String>, Span),
+    If(Expression, VecDeque<Statement>, VecDeque<Statement>, Option<VecDeque<Statement>>, Span),
+    Elif(Expression, VecDeque<Statement>, Span),
+    ForLoop(Box<Statement>, Expression, Box<Statement>, VecDeque<Statement>, Option<String>, Span),
+    //for x in items { ... } or for (k, v) in map { ... }, where the loop variables only exist inside the body.
+    ForEach(LoopVariables, Iterable, VecDeque<Statement>, Option<String>, Span),
+    ModifyVariable(String, Expression, Span),
+    //Assigns to an element or field inside a variable, such as a[i].x = v. The target is always a place, see Expression::place.
+    ModifyElement(Expression, Expression, Span),
+    DefineFunction(Function, Span),
+    FunctionCall(String, Vec<Expression>, Span),
+    Return(Option<Expression>, Span),
+    Break(Option<String>, Span),
+    Continue(Option<String>, Span),
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Expression {
-    String(String),
-    Bool(bool),
-    Variable(String),
-    I32(i32),
-    I64(i64),
-    F32(f32),
-    F64(f64),
-    Array(Vec<Expression>),
-    Complete(Complete),
-    BinaryOperator(BinaryOperator),
-    UnaryOperator(UnaryOperator),
-    Increment,
-    Decrement,
-    FunctionCall(String, Vec<Expression>),
-    CompleteU(CompleteU),
+    String(String, Span),
+    Bool(bool, Span),
+    Variable(String, Span),
+    I32(i32, Span),
+    I64(i64, Span),
+    F32(f32, Span),
+    F64(f64, Span),
+    Array(Vec<Expression>, Span),
+    Complete(Complete, Span),
+    Increment(Span),
+    Decrement(Span),
+    FunctionCall(String, Vec<Expression>, Span),
+    CompleteU(CompleteU, Span),
+    Index(Box<Expression>, Box<Expression>, Span),
+    //a[start..end], where a missing start or end means the start or end of the array.
+    Slice(Box<Expression>, Option<Box<Expression>>, Option<Box<Expression>>, Span),
+    //Point { x: 1.0, y: 2.0 }, with the fields in the order they are written.
+    Struct(String, Vec<(String, Expression)>, Span),
+    //p.x
+    Field(Box<Expression>, String, Span),
+    //{"a": 1, "b": 2}, with the entries in the order they are written.
+    Map(Vec<(Expression, Expression)>, Span),
+}
+
+//One step from a variable into a part of it, such as [i] or .x in a[i].x.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Access<'e> {
+    Index(&'e Expression),
+    Field(&'e str),
+}
+
+//The variables a for-each loop sets on each pass.
+#[derive(PartialEq, Debug, Clone)]
+pub enum LoopVariables {
+    //for x in items, where x is an element of an array, a key of a map or a number in a range.
+    Single(String),
+    //for (k, v) in map
+    Pair(String, String),
+}
+
+impl LoopVariables {
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            LoopVariables::Single(name) => vec![name],
+            LoopVariables::Pair(key, value) => vec![key, value],
+        }
+    }
+}
+
+//What a for-each loop runs over.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Iterable {
+    //An array or a map, which only the interpreter can tell apart.
+    Array(Expression),
+    Range(Box<Range>),
+}
+
+//start..end, start..=end or (start..end).step(step). The bounds and step are evaluated once, before the first iteration.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Range {
+    pub start: Expression,
+    pub end: Expression,
+    pub inclusive: bool,
+    pub step: Option<Expression>,
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -53,6 +109,7 @@ pub enum BinaryOperator {
 #[derive(PartialEq, Debug, Clone)]
 pub enum UnaryOperator {
     Not,
+    Negate,
     Parenthesis,
 }
 
@@ -69,53 +126,102 @@ pub struct CompleteU {
     pub child: Box<Expression>,
 }
 
+impl Statement {
+    pub fn span(&self) -> &Span {
+        match self {
+            Statement::DefineVariable(.., span)
+            | Statement::WhileLoop(.., span)
+            | Statement::If(.., span)
+            | Statement::Elif(.., span)
+            | Statement::ForLoop(.., span)
+            | Statement::ForEach(.., span)
+            | Statement::ModifyVariable(.., span)
+            | Statement::ModifyElement(.., span)
+            | Statement::DefineFunction(.., span)
+            | Statement::FunctionCall(.., span)
+            | Statement::Return(.., span)
+            | Statement::Break(.., span)
+            | Statement::Continue(.., span) => span,
+        }
+    }
+}
+
 impl Expression {
-    fn _get_precidence(&self) -> u8 {
+    pub fn span(&self) -> &Span {
+        match self {
+            Expression::String(_, span)
+            | Expression::Bool(_, span)
+            | Expression::Variable(_, span)
+            | Expression::I32(_, span)
+            | Expression::I64(_, span)
+            | Expression::F32(_, span)
+            | Expression::F64(_, span)
+            | Expression::Array(_, span)
+            | Expression::Complete(_, span)
+            | Expression::Increment(span)
+            | Expression::Decrement(span)
+            | Expression::FunctionCall(_, _, span)
+            | Expression::CompleteU(_, span)
+            | Expression::Index(.., span)
+            | Expression::Slice(.., span)
+            | Expression::Struct(.., span)
+            | Expression::Field(.., span)
+            | Expression::Map(_, span) => span,
+        }
+    }
+
+    //Splits a variable or a part of one, such as a[i].x, into the variable name and the steps into it outermost first.
+    //Returns: None for anything else, which assignments, push and pop cannot change.
+    pub fn place(&self) -> Option<(&str, Vec<Access<'_>>)> {
         match self {
-            Expression::Complete(opp) => opp.operator.get_precidence(),
-            Expression::BinaryOperator(opp) => opp.get_precidence(),
-            _ => 255,
+            Expression::Variable(name, _) => Some((name, Vec::new())),
+            Expression::Index(target, index, _) => {
+                let (name, mut accesses) = target.place()?;
+                accesses.push(Access::Index(index));
+                Some((name, accesses))
+            }
+            Expression::Field(target, field, _) => {
+                let (name, mut accesses) = target.place()?;
+                accesses.push(Access::Field(field));
+                Some((name, accesses))
+            }
+            _ => None,
         }
     }
 }
 
 impl BinaryOperator {
-    fn get_precidence(&self) -> u8 {
+    //Operators with a higher precedence bind tighter. Unary operators bind tighter than all of them.
+    fn precedence(&self) -> u8 {
         match self {
-            BinaryOperator::Add => 1,
-            BinaryOperator::Subtract => 1,
-            BinaryOperator::Divide => 2,
-            BinaryOperator::Multiply => 2,
-            BinaryOperator::Equals => 0,
-            BinaryOperator::NotEqual => 0,
-            BinaryOperator::LessThan => 0,
-            BinaryOperator::LessThanOrEqualTo => 0,
-            BinaryOperator::GreaterThan => 0,
-            BinaryOperator::GreaterThanOrEqualTo => 0,
-            BinaryOperator::Modulus => 2,
-            BinaryOperator::And => 0,
-            BinaryOperator::Or => 0,
+            BinaryOperator::Or => 1,
+            BinaryOperator::And => 2,
+            BinaryOperator::Equals | BinaryOperator::NotEqual => 3,
+            BinaryOperator::LessThan
+            | BinaryOperator::LessThanOrEqualTo
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::GreaterThanOrEqualTo => 4,
+            BinaryOperator::Add | BinaryOperator::Subtract => 5,
+            BinaryOperator::Multiply | BinaryOperator::Divide | BinaryOperator::Modulus => 6,
         }
     }
-}
 
-impl From<&MathOp> for Expression {
-    fn from(op: &MathOp) -> Expression {
+    fn from_math_op(op: &MathOp) -> Option<BinaryOperator> {
         match op {
-            MathOp::NotEqual => Expression::BinaryOperator(BinaryOperator::NotEqual),
-            MathOp::Modulus => Expression::BinaryOperator(BinaryOperator::Modulus),
-            MathOp::Multiply => Expression::BinaryOperator(BinaryOperator::Multiply),
-            MathOp::Divide => Expression::BinaryOperator(BinaryOperator::Divide),
-            MathOp::Subtract => Expression::BinaryOperator(BinaryOperator::Subtract),
-            MathOp::Add => Expression::BinaryOperator(BinaryOperator::Add),
-            MathOp::Equals => Expression::BinaryOperator(BinaryOperator::Equals),
-            MathOp::LessThan => Expression::BinaryOperator(BinaryOperator::LessThan),
-            MathOp::LessThanOrEqualTo => Expression::BinaryOperator(BinaryOperator::LessThanOrEqualTo),
-            MathOp::GreaterThan => Expression::BinaryOperator(BinaryOperator::GreaterThan),
-            MathOp::GreaterThanOrEqualTo => Expression::BinaryOperator(BinaryOperator::GreaterThanOrEqualTo),
-            MathOp::And => Expression::BinaryOperator(BinaryOperator::And),
-            MathOp::Or => Expression::BinaryOperator(BinaryOperator::Or),
-            MathOp::Not => Expression::UnaryOperator(UnaryOperator::Not),
+            MathOp::NotEqual => Some(BinaryOperator::NotEqual),
+            MathOp::Modulus => Some(BinaryOperator::Modulus),
+            MathOp::Multiply => Some(BinaryOperator::Multiply),
+            MathOp::Divide => Some(BinaryOperator::Divide),
+            MathOp::Subtract => Some(BinaryOperator::Subtract),
+            MathOp::Add => Some(BinaryOperator::Add),
+            MathOp::Equals => Some(BinaryOperator::Equals),
+            MathOp::LessThan => Some(BinaryOperator::LessThan),
+            MathOp::LessThanOrEqualTo => Some(BinaryOperator::LessThanOrEqualTo),
+            MathOp::GreaterThan => Some(BinaryOperator::GreaterThan),
+            MathOp::GreaterThanOrEqualTo => Some(BinaryOperator::GreaterThanOrEqualTo),
+            MathOp::And => Some(BinaryOperator::And),
+            MathOp::Or => Some(BinaryOperator::Or),
+            MathOp::Not => None,
         }
     }
 }
@@ -140,347 +246,844 @@ impl From<(&UnaryOperator, &Expression)> for CompleteU {
 }
 
 impl Complete {
-    fn apply_precidence(self) -> Complete {
-        match *self.left {
-            Expression::Complete(c) => {
-                if self.operator.get_precidence() > c.operator.get_precidence() {
-                    Complete {
-                        operator: c.operator,
-                        left: c.left,
-                        right: Box::new(Expression::Complete(
-                            Complete { 
-                                operator: self.operator, 
-                                left: c.right, 
-                                right: self.right 
-                            }.apply_precidence()
-                        )),
-                    }
-                } else {
-                    Complete { 
-                        operator: self.operator, 
-                        left: Box::new(Expression::Complete(c)), 
-                        right: self.right 
-                    }
-                }
-            }
-            _ => self.clone(),
-        }
+    pub fn span(&self) -> Span {
+        self.left.span().to(self.right.span())
     }
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub struct Function {
     pub name: String,
+    pub params: Vec<(String, Type)>,
+    //None for functions that do not return a value.
+    pub return_type: Option<Type>,
     pub block: VecDeque<Statement>,
 }
 
+//A struct declared at the top of a file, outside of any block.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Struct {
+    pub name: String,
+    //The fields in the order they are declared, which is the order they are printed in.
+    pub fields: Vec<(String, Type)>,
+    pub span: Span,
+}
+
+impl Struct {
+    pub fn field(&self, name: &str) -> Option<&Type> {
+        self.fields.iter().find(|(field, _)| field == name).map(|(_, ty)| ty)
+    }
+}
+
 fn is_type_keyword(s: &str) -> bool {
-    s == "i32" || s == "i64" || s == "f32" || s == "f64" || s == "Bool" || s == "String" || s.starts_with("Array<")
+    s == "i32" || s == "i64" || s == "f32" || s == "f64" || s == "Bool" || s == "String" || s.starts_with("Array<") || s.starts_with("Map<")
 }
 
-pub fn parse(tokens: &mut VecDeque<Token>) -> VecDeque<Statement> {
+pub fn parse(tokens: &mut VecDeque<SpannedToken>) -> Result<VecDeque<Statement>, Diagnostic> {
     let mut statements = VecDeque::new();
-    while tokens.len() > 0 && tokens[0] != Token::CloseBlock {
-        statements.push_back(parse_next_statement(tokens));
+    while !tokens.is_empty() && tokens[0].token != Token::CloseBlock {
+        statements.push_back(parse_next_statement(tokens)?);
     }
-    statements
+    Ok(statements)
 }
 
-fn parse_next_statement(tokens: &mut VecDeque<Token>) -> Statement {
-    let token = tokens.pop_front().unwrap();
+fn parse_next_statement(tokens: &mut VecDeque<SpannedToken>) -> Result<Statement, Diagnostic> {
+    let SpannedToken { token, span: start } = next_token(tokens)?;
     match token {
         Token::Let => {
-            let var_token = tokens.pop_front().expect("expected variable name after let");
-            let var_name = if let Token::Identifier(name) = var_token { name } else { panic!("expected identifier after let") };
-            eat_token(tokens, Token::Colon);
-            let type_token = tokens.pop_front().expect("expected type after colon");
-            let type_hint = if let Token::Identifier(type_str) = type_token { parse_type_hint(&type_str) } else { panic!("expected type identifier after colon") };
-            eat_token(tokens, Token::Assign);
-            let expression = parse_expression(tokens, Some(type_hint.clone()));
-            Statement::DefineVariable(var_name, expression, type_hint)
+            let var_token = next_token(tokens)?;
+            let var_name = match var_token.token {
+                Token::Identifier(name) => name,
+                other => return Err(Diagnostic::new(12, format!("Expected a variable name after let but found {:?}", other)).with_span(var_token.span)),
+            };
+            eat_token(tokens, Token::Colon)?;
+            let (type_hint, _) = parse_type_token(tokens)?;
+            eat_token(tokens, Token::Assign)?;
+            let expression = parse_expression(tokens, Some(type_hint.clone()))?;
+            let span = start.to(expression.span());
+            Ok(Statement::DefineVariable(var_name, expression, type_hint, span))
         }
         Token::Identifier(ident_1) => {
             if is_type_keyword(&ident_1) {
-                panic!("old variable definition syntax is disallowed, use let");
+                return Err(old_syntax_error(start));
             }
-            let next = tokens.pop_front().unwrap();
-            match next {
-                Token::Identifier(_) => {
-                    // This branch is for function definitions using old syntax.
-                    // They are disallowed.
-                    panic!("old variable definition syntax is disallowed, use let");
-                }
+            let next = next_token(tokens)?;
+            match next.token {
+                // This branch is for function definitions using old syntax.
+                // They are disallowed.
+                Token::Identifier(_) => Err(old_syntax_error(start)),
                 Token::OpenParen => {
-                    let args = parse_function_args(tokens);
-                    eat_token(tokens, Token::EndLine);
-                    Statement::FunctionCall(ident_1, args)
+                    let (args, close) = parse_function_args(tokens)?;
+                    eat_token(tokens, Token::EndLine)?;
+                    Ok(Statement::FunctionCall(ident_1, args, start.to(&close)))
                 }
                 Token::MathOp(MathOp::LessThan) => {
-                    let token3 = tokens.pop_front().unwrap();
-                    match token3 {
+                    let token3 = next_token(tokens)?;
+                    match token3.token {
                         Token::Identifier(ident_3) => {
-                            let type_hint = parse_type_hint(&ident_1);
-                            eat_token(tokens, Token::MathOp(MathOp::GreaterThan));
-                            let expression = parse_expression(tokens, None);
-                            Statement::DefineVariable(ident_3, expression, type_hint)
+                            let type_hint = parse_type_hint(&ident_1).map_err(|error| error.with_span(start.clone()))?;
+                            eat_token(tokens, Token::MathOp(MathOp::GreaterThan))?;
+                            let expression = parse_expression(tokens, None)?;
+                            let span = start.to(expression.span());
+                            Ok(Statement::DefineVariable(ident_3, expression, type_hint, span))
                         }
-                        _ => panic!("Unexpected token: {:?}", token3)
+                        _ => Err(unexpected_token(&token3)),
                     }
                 }
                 Token::Increment => {
-                    let next_token = tokens.pop_front().unwrap();
-                    match next_token {
-                        Token::EndLine => {},
-                        Token::CloseParen => { tokens.push_front(next_token); },
-                        _ => { panic!("unexpected token {:?}", next_token) }
-                    }
-                    Statement::ModifyVariable(ident_1, Expression::Increment)
+                    eat_step_terminator(tokens)?;
+                    let span = start.to(&next.span);
+                    Ok(Statement::ModifyVariable(ident_1, Expression::Increment(next.span), span))
                 }
                 Token::Decrement => {
-                    let next_token = tokens.pop_front().unwrap();
-                    match next_token {
-                        Token::EndLine => {},
-                        Token::CloseParen => { tokens.push_front(next_token); },
-                        _ => { panic!("unexpected token {:?}", next_token) }
-                    }
-                    Statement::ModifyVariable(ident_1, Expression::Decrement)
+                    eat_step_terminator(tokens)?;
+                    let span = start.to(&next.span);
+                    Ok(Statement::ModifyVariable(ident_1, Expression::Decrement(next.span), span))
                 }
                 Token::Assign => {
-                    let expression = parse_expression(tokens, None);
-                    Statement::ModifyVariable(ident_1, expression)
+                    let expression = parse_expression(tokens, None)?;
+                    let span = start.to(expression.span());
+                    Ok(Statement::ModifyVariable(ident_1, expression, span))
+                }
+                Token::OpenBracket | Token::Dot => {
+                    tokens.push_front(next);
+                    let target = parse_indexing(tokens, Expression::Variable(ident_1, start.clone()))?;
+                    if !matches!(target, Expression::Index(..) | Expression::Field(..)) {
+                        return Err(Diagnostic::new(56, "assignment can only change a variable or an element of one").with_span(target.span().clone()));
+                    }
+                    eat_token(tokens, Token::Assign)?;
+                    let expression = parse_expression(tokens, None)?;
+                    let span = start.to(expression.span());
+                    Ok(Statement::ModifyElement(target, expression, span))
                 }
-                other => { panic!("Unexpected token {:?}", other) }
+                _ => Err(unexpected_token(&next)),
             }
         }
         Token::If => {
-            let condition = parse_expression(tokens, None);
-            eat_token(tokens, Token::OpenBlock);
-            let body = parse(tokens);
-            eat_token(tokens, Token::CloseBlock);
+            let condition = parse_expression(tokens, None)?;
+            let span = start.to(condition.span());
+            eat_token(tokens, Token::OpenBlock)?;
+            let body = parse(tokens)?;
+            eat_token(tokens, Token::CloseBlock)?;
             let mut elifs = VecDeque::new();
-            while tokens.len() > 0 && tokens[0] == Token::Elif {
-                eat_token(tokens, Token::Elif);
-                let elif_condition = parse_expression(tokens, None);
-                eat_token(tokens, Token::OpenBlock);
-                let elif_body = parse(tokens);
-                eat_token(tokens, Token::CloseBlock);
-                elifs.push_back(Statement::Elif(elif_condition, elif_body));
+            while !tokens.is_empty() && tokens[0].token == Token::Elif {
+                let elif_start = next_token(tokens)?.span;
+                let elif_condition = parse_expression(tokens, None)?;
+                let elif_span = elif_start.to(elif_condition.span());
+                eat_token(tokens, Token::OpenBlock)?;
+                let elif_body = parse(tokens)?;
+                eat_token(tokens, Token::CloseBlock)?;
+                elifs.push_back(Statement::Elif(elif_condition, elif_body, elif_span));
             }
             let mut else_body = None;
-            if tokens.len() > 0 && tokens[0] == Token::Else {
-                eat_token(tokens, Token::Else);
-                eat_token(tokens, Token::OpenBlock);
-                else_body = Some(parse(tokens));
-                eat_token(tokens, Token::CloseBlock);
+            if !tokens.is_empty() && tokens[0].token == Token::Else {
+                eat_token(tokens, Token::Else)?;
+                eat_token(tokens, Token::OpenBlock)?;
+                else_body = Some(parse(tokens)?);
+                eat_token(tokens, Token::CloseBlock)?;
             }
-            Statement::If(condition, body, elifs, else_body)
+            Ok(Statement::If(condition, body, elifs, else_body, span))
         }
         Token::ForLoop => {
-            eat_token(tokens, Token::OpenParen);
+            let pair = matches!(
+                (tokens.front().map(|next| &next.token), tokens.get(1).map(|next| &next.token), tokens.get(2).map(|next| &next.token)),
+                (Some(Token::OpenParen), Some(Token::Identifier(_)), Some(Token::Comma))
+            );
+            if pair || matches!(tokens.front(), Some(SpannedToken { token: Token::Identifier(_), .. })) {
+                return parse_for_each(tokens, start);
+            }
+            eat_token(tokens, Token::OpenParen)?;
             // Expect new syntax for variable definition in for-loop initializer.
-            let variable = parse_next_statement(tokens);
-            let condition = parse_expression(tokens, None);
-            let increment = parse_next_statement(tokens);
-            eat_token(tokens, Token::CloseParen);
-            eat_token(tokens, Token::OpenBlock);
-            let block = parse(tokens);
-            Statement::ForLoop(Box::new(variable), condition, Box::new(increment), block)
+            let variable = parse_next_statement(tokens)?;
+            if let Some(SpannedToken { token: Token::Comma, span }) = tokens.front() {
+                return Err(Diagnostic::new(12, "Expected EndLine but found Comma")
+                    .with_span(span.clone())
+                    .with_note("the parts of a for loop are separated by ;, as in for (let i: i32 = 0; i < 10; i++)"));
+            }
+            let condition = parse_expression(tokens, None)?;
+            let increment = parse_next_statement(tokens)?;
+            let span = start.to(increment.span());
+            eat_token(tokens, Token::CloseParen)?;
+            eat_token(tokens, Token::OpenBlock)?;
+            let block = parse(tokens)?;
+            eat_token(tokens, Token::CloseBlock)?;
+            Ok(Statement::ForLoop(Box::new(variable), condition, Box::new(increment), block, None, span))
+        }
+        Token::DefineFunction => {
+            let name_token = next_token(tokens)?;
+            let name = match name_token.token {
+                Token::Identifier(name) => name,
+                other => return Err(Diagnostic::new(12, format!("Expected a function name after fn but found {:?}", other)).with_span(name_token.span)),
+            };
+            eat_token(tokens, Token::OpenParen)?;
+            let (params, mut span) = parse_function_params(tokens)?;
+            let mut return_type = None;
+            if tokens.front().map(|next| &next.token) == Some(&Token::Arrow) {
+                tokens.pop_front();
+                let (type_hint, type_span) = parse_type_token(tokens)?;
+                return_type = Some(type_hint);
+                span = type_span;
+            }
+            eat_token(tokens, Token::OpenBlock)?;
+            let block = parse(tokens)?;
+            eat_token(tokens, Token::CloseBlock)?;
+            Ok(Statement::DefineFunction(Function { name, params, return_type, block }, start.to(&span)))
+        }
+        Token::Return => {
+            if let Some(SpannedToken { token: Token::EndLine, .. }) = tokens.front() {
+                tokens.pop_front();
+                return Ok(Statement::Return(None, start));
+            }
+            let expression = parse_expression(tokens, None)?;
+            let span = start.to(expression.span());
+            Ok(Statement::Return(Some(expression), span))
         }
         Token::WhileLoop => {
-            let condition = parse_expression(tokens, None);
-            eat_token(tokens, Token::OpenBlock);
-            let block = parse(tokens);
-            eat_token(tokens, Token::CloseBlock);
-            Statement::WhileLoop(condition, block)
+            let condition = parse_expression(tokens, None)?;
+            let span = start.to(condition.span());
+            eat_token(tokens, Token::OpenBlock)?;
+            let block = parse(tokens)?;
+            eat_token(tokens, Token::CloseBlock)?;
+            Ok(Statement::WhileLoop(condition, block, None, span))
+        }
+        Token::Label(label) => {
+            eat_token(tokens, Token::Colon)?;
+            match tokens.front() {
+                Some(SpannedToken { token: Token::WhileLoop | Token::ForLoop, .. }) => {}
+                Some(next) => return Err(Diagnostic::new(12, format!("Expected a loop after the label '{} but found {:?}", label, next.token)).with_span(next.span.clone())),
+                None => return Err(Diagnostic::new(12, format!("Expected a loop after the label '{} but found the end of the block", label)).with_span(start)),
+            }
+            match parse_next_statement(tokens)? {
+                Statement::WhileLoop(condition, block, _, span) => Ok(Statement::WhileLoop(condition, block, Some(label), start.to(&span))),
+                Statement::ForLoop(variable, condition, increment, block, _, span) => Ok(Statement::ForLoop(variable, condition, increment, block, Some(label), start.to(&span))),
+                Statement::ForEach(variable, iterable, block, _, span) => Ok(Statement::ForEach(variable, iterable, block, Some(label), start.to(&span))),
+                other => Ok(other),
+            }
         }
-        other => { panic!("found {:?} trying to parse the start of a line", other) }
+        Token::Break | Token::Continue => {
+            let label = match tokens.front() {
+                Some(SpannedToken { token: Token::Label(label), .. }) => Some(label.clone()),
+                _ => None,
+            };
+            let mut span = start;
+            if label.is_some() {
+                span = span.to(&next_token(tokens)?.span);
+            }
+            eat_token(tokens, Token::EndLine)?;
+            Ok(if token == Token::Break { Statement::Break(label, span) } else { Statement::Continue(label, span) })
+        }
+        other => Err(Diagnostic::new(11, format!("Unexpected token {:?} at the start of a line", other)).with_span(start)),
     }
 }
 
-fn parse_expression(tokens: &mut VecDeque<Token>, expected_type: Option<Type>) -> Expression {
-    let mut stack: Vec<Expression> = Vec::new();
-    while tokens.len() > 0 {
-        match tokens.pop_front().unwrap() {
-            Token::ConstantNumber(value) => {
-                if expected_type.is_none() {
-                    stack_helper(&mut stack, Expression::I32(value.parse::<i32>().unwrap()));
-                } else {
-                    match expected_type.as_ref().unwrap() {
-                        Type::I32 => { stack_helper(&mut stack, Expression::I32(value.parse::<i32>().unwrap())); }
-                        Type::I64 => { stack_helper(&mut stack, Expression::I64(value.parse::<i64>().unwrap())); }
-                        Type::F32 => { stack_helper(&mut stack, Expression::F32(value.parse::<f32>().unwrap())); }
-                        Type::F64 => { stack_helper(&mut stack, Expression::F64(value.parse::<f64>().unwrap())); }
-                        _ => {}
-                    }
+//Parses the rest of `for x in items { ... }` or `for (k, v) in map { ... }` after the for.
+fn parse_for_each(tokens: &mut VecDeque<SpannedToken>, start: Span) -> Result<Statement, Diagnostic> {
+    let name = |tokens: &mut VecDeque<SpannedToken>| match next_token(tokens)? {
+        SpannedToken { token: Token::Identifier(name), .. } => Ok(name),
+        other => Err(unexpected_token(&other)),
+    };
+    let variable = match tokens.front().map(|next| &next.token) {
+        Some(Token::OpenParen) => {
+            tokens.pop_front();
+            let key = name(tokens)?;
+            eat_token(tokens, Token::Comma)?;
+            let value = name(tokens)?;
+            eat_token(tokens, Token::CloseParen)?;
+            LoopVariables::Pair(key, value)
+        }
+        _ => LoopVariables::Single(name(tokens)?),
+    };
+    eat_token(tokens, Token::In)?;
+    let iterable = parse_iterable(tokens)?;
+    let span = start.to(&peek_span(tokens).unwrap_or_default());
+    eat_token(tokens, Token::OpenBlock)?;
+    let block = parse(tokens)?;
+    eat_token(tokens, Token::CloseBlock)?;
+    Ok(Statement::ForEach(variable, iterable, block, None, span))
+}
+
+//Parses an array expression, a range, or a parenthesised range followed by .step(n).
+fn parse_iterable(tokens: &mut VecDeque<SpannedToken>) -> Result<Iterable, Diagnostic> {
+    if !is_parenthesised_range(tokens) {
+        let first = parse_binary(tokens, None, 0)?;
+        return match tokens.front().map(|next| &next.token) {
+            Some(Token::DotDot | Token::DotDotEq) => parse_range(tokens, first).map(|range| Iterable::Range(Box::new(range))),
+            _ => Ok(Iterable::Array(first)),
+        };
+    }
+    eat_token(tokens, Token::OpenParen)?;
+    let first = parse_binary(tokens, None, 0)?;
+    let mut range = parse_range(tokens, first)?;
+    eat_token(tokens, Token::CloseParen)?;
+    if tokens.front().map(|next| &next.token) == Some(&Token::Dot) {
+        tokens.pop_front();
+        let method = next_token(tokens)?;
+        if method.token != Token::Identifier("step".to_string()) {
+            return Err(Diagnostic::new(12, format!("Expected step but found {:?}", method.token))
+                .with_span(method.span)
+                .with_note("ranges only have a step method, as in (0..10).step(2)"));
+        }
+        eat_token(tokens, Token::OpenParen)?;
+        range.step = Some(parse_binary(tokens, None, 0)?);
+        eat_token(tokens, Token::CloseParen)?;
+    }
+    Ok(Iterable::Range(Box::new(range)))
+}
+
+//Parses the .. or ..= and the end of a range whose start has already been parsed.
+fn parse_range(tokens: &mut VecDeque<SpannedToken>, start: Expression) -> Result<Range, Diagnostic> {
+    let inclusive = next_token(tokens)?.token == Token::DotDotEq;
+    let end = parse_binary(tokens, None, 0)?;
+    Ok(Range { start, end, inclusive, step: None })
+}
+
+//Whether the tokens start with a ( whose contents are a range, such as (0..n), rather than a parenthesised expression.
+fn is_parenthesised_range(tokens: &VecDeque<SpannedToken>) -> bool {
+    if tokens.front().map(|next| &next.token) != Some(&Token::OpenParen) {
+        return false;
+    }
+    let mut depth = 0;
+    for spanned in tokens {
+        match spanned.token {
+            Token::OpenParen | Token::OpenBracket => depth += 1,
+            Token::CloseParen | Token::CloseBracket => {
+                depth -= 1;
+                if depth == 0 {
+                    return false;
                 }
             }
-            Token::Identifier(name) => {
-                let next_token = tokens.pop_front().unwrap();
-                match next_token {
-                    Token::OpenParen => {
-                        let args = parse_function_args(tokens);
-                        stack_helper(&mut stack, Expression::FunctionCall(name, args));
-                    }
-                    _ => {
-                        tokens.push_front(next_token);
-                        stack_helper(&mut stack, Expression::Variable(name))
-                    }
-                }
+            Token::DotDot | Token::DotDotEq if depth == 1 => return true,
+            Token::OpenBlock | Token::EndLine => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+//Parses an expression up to a ; (which is consumed) or a closing ), ], comma or { (which are not).
+//Args: expected_type: Option<Type> - the declared type of the variable being defined, which number literals take on.
+pub(crate) fn parse_expression(tokens: &mut VecDeque<SpannedToken>, expected_type: Option<Type>) -> Result<Expression, Diagnostic> {
+    if let Some(SpannedToken { token: token @ (Token::Increment | Token::Decrement), .. }) = tokens.front() {
+        let increment = *token == Token::Increment;
+        let span = next_token(tokens)?.span;
+        if tokens.front().map(|next| &next.token) == Some(&Token::EndLine) {
+            tokens.pop_front();
+        }
+        return Ok(if increment { Expression::Increment(span) } else { Expression::Decrement(span) });
+    }
+    let expression = parse_binary(tokens, expected_type.as_ref(), 0)?;
+    match tokens.front() {
+        Some(SpannedToken { token: Token::EndLine, .. }) => {
+            tokens.pop_front();
+            Ok(expression)
+        }
+        next if ends_expression(next) => Ok(expression),
+        Some(next) => Err(unexpected_token(next)),
+        None => Ok(expression),
+    }
+}
+
+//Precedence climbing: parses operands joined by operators of at least min_precedence, so equal levels fold to the left
+//and tighter levels are parsed by the recursive call for the right operand.
+fn parse_binary(tokens: &mut VecDeque<SpannedToken>, expected_type: Option<&Type>, min_precedence: u8) -> Result<Expression, Diagnostic> {
+    let mut left = parse_unary(tokens, expected_type)?;
+    while let Some(operator) = tokens.front().and_then(|next| match &next.token {
+        Token::MathOp(op) => BinaryOperator::from_math_op(op),
+        _ => None,
+    }) {
+        let precedence = operator.precedence();
+        if precedence < min_precedence {
+            break;
+        }
+        tokens.pop_front();
+        let right = parse_binary(tokens, expected_type, precedence + 1)?;
+        let span = left.span().to(right.span());
+        left = Expression::Complete(Complete { operator, left: Box::new(left), right: Box::new(right) }, span);
+    }
+    Ok(left)
+}
+
+//Parses a prefix operator applied to an operand, or a single operand.
+fn parse_unary(tokens: &mut VecDeque<SpannedToken>, expected_type: Option<&Type>) -> Result<Expression, Diagnostic> {
+    if ends_expression(tokens.front()) && !is_map_literal(tokens) {
+        let error = Diagnostic::new(15, "Expected an expression");
+        return Err(match tokens.front() {
+            Some(next) => error.with_span(next.span.clone()),
+            None => error,
+        });
+    }
+    let SpannedToken { token, span } = next_token(tokens)?;
+    let operator = match token {
+        Token::MathOp(MathOp::Not) => UnaryOperator::Not,
+        Token::MathOp(MathOp::Subtract) => {
+            //Folding the minus into a literal lets -2147483648 fit in an i32.
+            if let Some(SpannedToken { token: Token::ConstantNumber(value), span: number_span }) = tokens.front().cloned() {
+                tokens.pop_front();
+                return number_literal(&format!("-{}", value), expected_type, span.to(&number_span));
             }
-            Token::String(literal) => { stack_helper(&mut stack, Expression::String(literal)); }
-            Token::Boolean(literal) => stack_helper(&mut stack, Expression::Bool(literal)),
-            Token::MathOp(opp) => { stack.push(Expression::from(&opp)); }
-            Token::EndLine => return stack[0].clone(),
-            Token::CloseParen => { tokens.push_front(Token::CloseParen); return stack[0].clone(); }
-            Token::Comma => { tokens.push_front(Token::Comma); return stack[0].clone(); }
-            Token::CloseBracket => { tokens.push_front(Token::CloseBracket); return stack[0].clone(); }
-            Token::OpenParen => {
-                stack_helper(&mut stack, Expression::CompleteU(CompleteU {
-                    operator: UnaryOperator::Parenthesis,
-                    child: Box::new(parse_expression(tokens, None)),
-                }));
-                eat_token(tokens, Token::CloseParen);
+            UnaryOperator::Negate
+        }
+        Token::MathOp(op) => return Err(Diagnostic::new(15, format!("Operator {:?} is missing its left operand", op)).with_span(span)),
+        token => {
+            let operand = parse_operand(tokens, SpannedToken { token, span }, expected_type)?;
+            return parse_indexing(tokens, operand);
+        }
+    };
+    if ends_expression(tokens.front()) {
+        return Err(Diagnostic::new(15, format!("Operator {:?} is missing its operand", operator)).with_span(span));
+    }
+    let child = parse_unary(tokens, expected_type)?;
+    let span = span.to(child.span());
+    Ok(Expression::CompleteU(CompleteU { operator, child: Box::new(child) }, span))
+}
+
+//Parses a literal, variable, call, array or parenthesised expression starting with first.
+fn parse_operand(tokens: &mut VecDeque<SpannedToken>, first: SpannedToken, expected_type: Option<&Type>) -> Result<Expression, Diagnostic> {
+    let SpannedToken { token, span } = first;
+    match token {
+        Token::ConstantNumber(value) => number_literal(&value, expected_type, span),
+        Token::Identifier(name) => {
+            if tokens.front().map(|next| &next.token) == Some(&Token::OpenParen) {
+                tokens.pop_front();
+                let (args, close) = parse_function_args(tokens)?;
+                let call_span = span.to(&close);
+                Ok(Expression::FunctionCall(name, args, call_span))
+            } else if is_struct_literal(&name, tokens) {
+                parse_struct_literal(tokens, name, span)
+            } else {
+                Ok(Expression::Variable(name, span))
             }
-            Token::Increment => {
-                if tokens.len() > 0 {
-                    let next = tokens.pop_front().unwrap();
-                    match next {
-                        Token::EndLine => {},
-                        _ => tokens.push_front(next),
+        }
+        Token::String(literal) => Ok(Expression::String(literal, span)),
+        Token::Boolean(literal) => Ok(Expression::Bool(literal, span)),
+        Token::OpenParen => {
+            let child = parse_binary(tokens, expected_type, 0)?;
+            let close = peek_span(tokens);
+            eat_token(tokens, Token::CloseParen)?;
+            let paren_span = span.to(&close.unwrap_or_else(|| child.span().clone()));
+            Ok(Expression::CompleteU(CompleteU {
+                operator: UnaryOperator::Parenthesis,
+                child: Box::new(child),
+            }, paren_span))
+        }
+        Token::OpenBracket => {
+            let mut data = Vec::new();
+            let close = if tokens.front().map(|next| &next.token) == Some(&Token::CloseBracket) {
+                next_token(tokens)?.span
+            } else {
+                loop {
+                    data.push(parse_binary(tokens, None, 0)?);
+                    let next = next_token(tokens)?;
+                    match next.token {
+                        Token::CloseBracket => break next.span,
+                        Token::Comma => {}
+                        _ => return Err(unexpected_token(&next)),
                     }
                 }
-                return Expression::Increment;
-            }
-            Token::Decrement => {
-                if tokens.len() > 0 {
-                    let next = tokens.pop_front().unwrap();
-                    match next {
-                        Token::EndLine => {},
-                        _ => tokens.push_front(next),
+            };
+            let array_span = span.to(&close);
+            Ok(Expression::Array(data, array_span))
+        }
+        Token::OpenBlock => {
+            let mut entries = Vec::new();
+            //The entries may end with a trailing comma.
+            while tokens.front().map(|next| &next.token) != Some(&Token::CloseBlock) {
+                let key = parse_binary(tokens, None, 0)?;
+                eat_token(tokens, Token::Colon)?;
+                entries.push((key, parse_binary(tokens, None, 0)?));
+                match tokens.front().map(|next| &next.token) {
+                    Some(Token::Comma) => {
+                        tokens.pop_front();
                     }
+                    Some(Token::CloseBlock) => {}
+                    _ => return Err(unexpected_token(&next_token(tokens)?)),
                 }
-                return Expression::Decrement;
             }
-            Token::OpenBracket => {
-                let mut data = Vec::new();
-                if tokens[0] == Token::CloseBracket {
-                    eat_token(tokens, Token::CloseBracket);
-                    stack_helper(&mut stack, Expression::Array(data));
-                } else {
-                    loop {
-                        data.push(parse_expression(tokens, None));
-                        let next_token = tokens.pop_front().unwrap();
-                        match next_token {
-                            Token::CloseBracket => break,
-                            _ => {}
-                        }
-                    }
-                    stack_helper(&mut stack, Expression::Array(data));
+            let close = next_token(tokens)?;
+            Ok(Expression::Map(entries, span.to(&close.span)))
+        }
+        other => Err(unexpected_token(&SpannedToken { token: other, span })),
+    }
+}
+
+//Whether name { starts a struct literal rather than the body of an if or loop whose condition ends in name.
+//Struct names start with an uppercase letter and a literal always starts with `field:`, which no statement does.
+fn is_struct_literal(name: &str, tokens: &VecDeque<SpannedToken>) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+        && tokens.front().map(|next| &next.token) == Some(&Token::OpenBlock)
+        && matches!(tokens.get(1).map(|next| &next.token), Some(Token::Identifier(_)))
+        && tokens.get(2).map(|next| &next.token) == Some(&Token::Colon)
+}
+
+//Whether the { at the front starts a map literal, {} or {key: value, ...}, where an operand is expected.
+//A statement in a block never has a : outside of brackets, except in `let x: T` and before a loop label.
+fn is_map_literal(tokens: &VecDeque<SpannedToken>) -> bool {
+    if tokens.front().map(|next| &next.token) != Some(&Token::OpenBlock) {
+        return false;
+    }
+    match tokens.get(1).map(|next| &next.token) {
+        Some(Token::CloseBlock) => return true,
+        Some(Token::Let | Token::Label(_)) => return false,
+        _ => {}
+    }
+    let mut depth = 0;
+    for spanned in tokens.iter().skip(1) {
+        match spanned.token {
+            Token::OpenParen | Token::OpenBracket | Token::OpenBlock => depth += 1,
+            Token::CloseParen | Token::CloseBracket | Token::CloseBlock if depth > 0 => depth -= 1,
+            Token::Colon if depth == 0 => return true,
+            Token::EndLine | Token::CloseParen | Token::CloseBracket | Token::CloseBlock => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+//Parses the { field: value, ... } of a struct literal whose name has already been read.
+fn parse_struct_literal(tokens: &mut VecDeque<SpannedToken>, name: String, start: Span) -> Result<Expression, Diagnostic> {
+    eat_token(tokens, Token::OpenBlock)?;
+    let mut fields = Vec::new();
+    loop {
+        let next = next_token(tokens)?;
+        match next.token {
+            Token::CloseBlock if !fields.is_empty() => return Ok(Expression::Struct(name, fields, start.to(&next.span))),
+            Token::Identifier(field) => {
+                eat_token(tokens, Token::Colon)?;
+                fields.push((field, parse_binary(tokens, None, 0)?));
+                let next = next_token(tokens)?;
+                match next.token {
+                    Token::Comma => {}
+                    Token::CloseBlock => return Ok(Expression::Struct(name, fields, start.to(&next.span))),
+                    _ => return Err(unexpected_token(&next)),
                 }
             }
-            Token::OpenBlock => { tokens.push_front(Token::OpenBlock); return stack[0].clone(); }
-            a => { panic!("Unexpected Token: {:?}", a) }
+            _ => return Err(unexpected_token(&next)),
         }
     }
-    stack[0].clone()
 }
 
-fn stack_helper(stack: &mut Vec<Expression>, expression: Expression) {
-    let mut right = expression;
+//Parses a struct declaration such as `struct Point { x: f64, y: f64 }`, which must be all the tokens.
+//Errors: If the name does not start with an uppercase letter, there are no fields, or a field is declared twice.
+pub fn parse_struct(tokens: &mut VecDeque<SpannedToken>) -> Result<Struct, Diagnostic> {
+    eat_token(tokens, Token::Struct)?;
+    let name_token = next_token(tokens)?;
+    let name = match name_token.token {
+        Token::Identifier(name) if matches!(parse_type_hint(&name), Ok(Type::Struct(_))) => name,
+        other => return Err(Diagnostic::new(12, format!("Expected a struct name but found {:?}", other))
+            .with_span(name_token.span)
+            .with_note("struct names start with an uppercase letter and cannot be a builtin type, as in `struct Point { x: f64, y: f64 }`")),
+    };
+    eat_token(tokens, Token::OpenBlock)?;
+    let mut fields: Vec<(String, Type)> = Vec::new();
     loop {
-        if stack.len() > 0 {
-            let operator = stack.pop().unwrap();
-            match operator {
-                Expression::BinaryOperator(binary_operator) => {
-                    if stack.len() > 0 {
-                        let left = stack.pop().unwrap();
-                        right = Expression::Complete(Complete::from((&binary_operator, &left, &right)).apply_precidence());
-                    } else {
-                        stack.push(Expression::BinaryOperator(binary_operator));
-                        stack.push(right);
-                        break;
-                    }
+        let next = next_token(tokens)?;
+        match next.token {
+            Token::CloseBlock if !fields.is_empty() => break,
+            Token::Identifier(field) => {
+                if fields.iter().any(|(existing, _)| *existing == field) {
+                    return Err(repeated_field(&name, &field).with_span(next.span));
                 }
-                Expression::UnaryOperator(unary_operator) => {
-                    right = Expression::CompleteU(CompleteU { operator: unary_operator, child: Box::new(right) })
+                eat_token(tokens, Token::Colon)?;
+                let (ty, _) = parse_type_token(tokens)?;
+                fields.push((field, ty));
+                let next = next_token(tokens)?;
+                match next.token {
+                    Token::Comma => {}
+                    Token::CloseBlock => break,
+                    _ => return Err(unexpected_token(&next)),
                 }
-                _ => {
-                    stack.push(operator);
-                    stack.push(right);
-                    break;
+            }
+            other => return Err(Diagnostic::new(12, format!("Expected a field name but found {:?}", other))
+                .with_span(next.span)
+                .with_note("a struct has at least one field, written `name: type`")),
+        }
+    }
+    if let Some(extra) = tokens.front() {
+        return Err(unexpected_token(extra));
+    }
+    Ok(Struct { name, fields, span: name_token.span })
+}
+
+//Parses any number of [index], [start..end] and .field after an operand, so a[1][2] indexes the result of a[1].
+fn parse_indexing(tokens: &mut VecDeque<SpannedToken>, mut target: Expression) -> Result<Expression, Diagnostic> {
+    while let Some(Token::OpenBracket | Token::Dot) = tokens.front().map(|next| &next.token) {
+        if tokens.pop_front().is_some_and(|next| next.token == Token::Dot) {
+            let field = next_token(tokens)?;
+            let Token::Identifier(name) = field.token else {
+                return Err(Diagnostic::new(12, format!("Expected a field name but found {:?}", field.token)).with_span(field.span));
+            };
+            let span = target.span().to(&field.span);
+            target = Expression::Field(Box::new(target), name, span);
+            continue;
+        }
+        let start = match tokens.front().map(|next| &next.token) {
+            Some(Token::DotDot) => None,
+            _ => Some(Box::new(parse_binary(tokens, None, 0)?)),
+        };
+        //Some(end) for a slice, where end is None if it runs to the end of the array.
+        let range = match tokens.front().map(|next| &next.token) {
+            Some(Token::DotDot) => {
+                tokens.pop_front();
+                match tokens.front().map(|next| &next.token) {
+                    Some(Token::CloseBracket) => Some(None),
+                    _ => Some(Some(Box::new(parse_binary(tokens, None, 0)?))),
                 }
             }
-        } else {
-            stack.push(right);
-            break;
+            _ => None,
+        };
+        let close = peek_span(tokens).unwrap_or_default();
+        eat_token(tokens, Token::CloseBracket)?;
+        let span = target.span().to(&close);
+        target = match (start, range) {
+            (start, Some(end)) => Expression::Slice(Box::new(target), start, end, span),
+            (Some(index), None) => Expression::Index(Box::new(target), index, span),
+            (None, None) => unreachable!("a [ followed by .. is always a slice"),
+        };
+    }
+    Ok(target)
+}
+
+//Whether token can only follow an expression, so there is no operand to parse at it.
+fn ends_expression(token: Option<&SpannedToken>) -> bool {
+    matches!(
+        token.map(|next| &next.token),
+        None | Some(Token::EndLine | Token::CloseParen | Token::Comma | Token::CloseBracket | Token::OpenBlock)
+    )
+}
+
+fn next_token(tokens: &mut VecDeque<SpannedToken>) -> Result<SpannedToken, Diagnostic> {
+    tokens.pop_front().ok_or_else(|| Diagnostic::new(15, "Unexpected end of block"))
+}
+
+fn peek_span(tokens: &VecDeque<SpannedToken>) -> Option<Span> {
+    tokens.front().map(|token| token.span.clone())
+}
+
+fn eat_token(tokens: &mut VecDeque<SpannedToken>, expected: Token) -> Result<(), Diagnostic> {
+    match tokens.front() {
+        Some(spanned) if spanned.token == expected => {
+            tokens.pop_front();
+            Ok(())
         }
+        Some(spanned) => Err(Diagnostic::new(12, format!("Expected {:?} but found {:?}", expected, spanned.token)).with_span(spanned.span.clone())),
+        None => Err(Diagnostic::new(12, format!("Expected {:?} but found the end of the block", expected))),
     }
 }
 
-fn eat_token(tokens: &mut VecDeque<Token>, expected: Token) {
-    if tokens.len() == 0 || tokens[0] != expected {
-        panic!("Tried to eat a {:?}, but found {:?}", expected, tokens);
+//Consumes the ; after i++ or i--, leaving a ) in place for the increment of a for loop.
+fn eat_step_terminator(tokens: &mut VecDeque<SpannedToken>) -> Result<(), Diagnostic> {
+    let next = next_token(tokens)?;
+    match next.token {
+        Token::EndLine => Ok(()),
+        Token::CloseParen => {
+            tokens.push_front(next);
+            Ok(())
+        }
+        _ => Err(unexpected_token(&next)),
     }
-    tokens.pop_front();
 }
 
-fn parse_type_hint(ident: &str) -> Type {
+fn unexpected_token(token: &SpannedToken) -> Diagnostic {
+    Diagnostic::new(11, format!("Unexpected token {:?}", token.token)).with_span(token.span.clone())
+}
+
+fn old_syntax_error(span: Span) -> Diagnostic {
+    Diagnostic::new(13, "Old variable definition syntax is disallowed, use let")
+        .with_span(span)
+        .with_note("variables are defined with `let name: type = value;`")
+}
+
+//Turns the text of a number token into a literal. A suffix such as i64 picks the type, otherwise the type being defined does,
+//falling back to i32 for integers and f64 for literals with a fraction or exponent.
+//Args: expected_type: Option<&Type> - the declared type of the variable the literal is assigned to, if any.
+//Errors: If the literal is malformed or its value does not fit in its type.
+fn number_literal(text: &str, expected_type: Option<&Type>, span: Span) -> Result<Expression, Diagnostic> {
+    let invalid = |note: &str| Diagnostic::new(16, format!("Invalid number literal {}", text)).with_span(span.clone()).with_note(note);
+    let (sign, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", text),
+    };
+    let radix = match unsigned.get(..2) {
+        Some("0x") => 16,
+        Some("0b") => 2,
+        Some("0o") => 8,
+        _ => 10,
+    };
+    let unprefixed = if radix == 10 { unsigned } else { &unsigned[2..] };
+    //Hex digits include the f of f32, so only integer suffixes can follow a radix prefix.
+    let suffix = ["i32", "i64", "f32", "f64"].into_iter().find(|suffix| unprefixed.ends_with(suffix) && (radix == 10 || suffix.starts_with('i')));
+    let body = &unprefixed[..unprefixed.len() - suffix.map_or(0, str::len)];
+    let is_float = radix == 10 && body.contains(['.', 'e', 'E']);
+    let ty = match (suffix.map(parse_type_hint), expected_type) {
+        (Some(ty), _) => ty?,
+        (None, Some(ty @ (Type::I32 | Type::I64 | Type::F32 | Type::F64))) => ty.clone(),
+        (None, _) if is_float => Type::F64,
+        (None, _) => Type::I32,
+    };
+    let out_of_range = || Diagnostic::new(49, format!("Number literal {} is out of range for {}", text, ty)).with_span(span.clone());
+    match ty {
+        Type::F32 | Type::F64 if radix != 10 => Err(invalid("hex, binary and octal literals are always integers")),
+        Type::F32 => match format!("{}{}", sign, body).parse::<f32>() {
+            Ok(value) if value.is_finite() => Ok(Expression::F32(value, span)),
+            Ok(_) => Err(out_of_range()),
+            Err(_) => Err(invalid("valid suffixes are i32, i64, f32 and f64")),
+        },
+        Type::F64 => match format!("{}{}", sign, body).parse::<f64>() {
+            Ok(value) if value.is_finite() => Ok(Expression::F64(value, span)),
+            Ok(_) => Err(out_of_range()),
+            Err(_) => Err(invalid("valid suffixes are i32, i64, f32 and f64")),
+        },
+        _ if is_float => Err(invalid(&format!("{} cannot hold a fraction or exponent, use a float type such as f64", ty))),
+        _ if body.is_empty() || !body.chars().all(|c| c.is_digit(radix)) => Err(invalid("valid suffixes are i32, i64, f32 and f64")),
+        _ => {
+            //Every digit is valid here, so the only way to fail is a value too large even for u128.
+            let magnitude = u128::from_str_radix(body, radix).map_err(|_| out_of_range())?;
+            let magnitude = i128::try_from(magnitude).map_err(|_| out_of_range())?;
+            let value = if sign.is_empty() { magnitude } else { -magnitude };
+            match ty {
+                Type::I64 => i64::try_from(value).map(|value| Expression::I64(value, span.clone())).map_err(|_| out_of_range()),
+                _ => i32::try_from(value).map(|value| Expression::I32(value, span.clone())).map_err(|_| out_of_range()),
+            }
+        }
+    }
+}
+
+fn parse_type_hint(ident: &str) -> Result<Type, Diagnostic> {
     match ident {
-        "i32" => Type::I32,
-        "i64" => Type::I64,
-        "f32" => Type::F32,
-        "f64" => Type::F64,
-        "Bool" => Type::Bool,
-        "String" => Type::String,
-        thing => {
-            if thing.starts_with("Array<") {
-                Type::Array(Box::new(parse_type_hint(&thing[6..thing.len()-1])))
-            } else {
-                panic!("unexpected type: {}", ident)
+        "i32" => Ok(Type::I32),
+        "i64" => Ok(Type::I64),
+        "f32" => Ok(Type::F32),
+        "f64" => Ok(Type::F64),
+        "Bool" => Ok(Type::Bool),
+        "String" => Ok(Type::String),
+        thing => match thing.split_once('<').and_then(|(name, rest)| Some((name, rest.strip_suffix('>')?))) {
+            Some((name, arguments)) => {
+                let arguments = split_type_arguments(arguments).into_iter().map(|argument| parse_type_hint(argument.trim())).collect::<Result<Vec<Type>, Diagnostic>>()?;
+                match (name, arguments.as_slice()) {
+                    ("Array", [inner]) => Ok(Type::Array(Box::new(inner.clone()))),
+                    ("Map", [key, _]) if !key.is_map_key() => Err(invalid_key(&key.to_string())),
+                    ("Map", [key, value]) => Ok(Type::Map(Box::new(key.clone()), Box::new(value.clone()))),
+                    _ => Err(Diagnostic::new(14, format!("Unknown type {}", ident))),
+                }
+            }
+            //Any other capitalised name is a struct, the type checker makes sure it is declared.
+            None if thing.starts_with(|c: char| c.is_ascii_uppercase()) && thing.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => Ok(Type::Struct(thing.to_string())),
+            None => Err(Diagnostic::new(14, format!("Unknown type {}", ident))),
+        },
+    }
+}
+
+//Splits the inside of <...> at the commas that are not nested in another <...>, so String, Array<i32> gives two types.
+fn split_type_arguments(arguments: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in arguments.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&arguments[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&arguments[start..]);
+    parts
+}
+
+//Parses a type name such as i32 or Array<String>.
+//Returns: the type and the span of its name.
+fn parse_type_token(tokens: &mut VecDeque<SpannedToken>) -> Result<(Type, Span), Diagnostic> {
+    let type_token = next_token(tokens)?;
+    match type_token.token {
+        Token::Identifier(type_str) => match parse_type_hint(&type_str) {
+            Ok(type_hint) => Ok((type_hint, type_token.span)),
+            Err(error) => Err(error.with_span(type_token.span)),
+        },
+        other => Err(Diagnostic::new(12, format!("Expected a type but found {:?}", other)).with_span(type_token.span)),
+    }
+}
+
+//Parses `name: type` pairs up to and including the closing parenthesis of a function definition.
+//Returns: the parameters and the span of the closing parenthesis.
+fn parse_function_params(tokens: &mut VecDeque<SpannedToken>) -> Result<(Vec<(String, Type)>, Span), Diagnostic> {
+    let mut params = Vec::new();
+    loop {
+        let next = next_token(tokens)?;
+        match next.token {
+            Token::CloseParen => return Ok((params, next.span)),
+            Token::Comma if !params.is_empty() => {}
+            Token::Identifier(name) => {
+                eat_token(tokens, Token::Colon)?;
+                let (type_hint, _) = parse_type_token(tokens)?;
+                params.push((name, type_hint));
             }
+            _ => return Err(unexpected_token(&next)),
         }
     }
 }
 
-fn parse_function_args(tokens: &mut VecDeque<Token>) -> Vec<Expression> {
+//Parses comma separated arguments up to and including the closing parenthesis.
+//Returns: the arguments and the span of the closing parenthesis.
+//Errors: If a comma is not between two arguments, or two arguments have no comma between them.
+fn parse_function_args(tokens: &mut VecDeque<SpannedToken>) -> Result<(Vec<Expression>, Span), Diagnostic> {
     let mut args = Vec::new();
     loop {
-        let next_token = tokens.pop_front().unwrap();
-        match next_token {
-            Token::CloseParen => return args,
-            _ => { tokens.push_front(next_token); args.push(parse_expression(tokens, None)); }
+        let next = next_token(tokens)?;
+        match next.token {
+            Token::CloseParen if args.is_empty() => return Ok((args, next.span)),
+            Token::Comma | Token::CloseParen => return Err(unexpected_token(&next)),
+            _ => {
+                tokens.push_front(next);
+                args.push(parse_expression(tokens, None)?);
+            }
+        }
+        let next = next_token(tokens)?;
+        match next.token {
+            Token::CloseParen => return Ok((args, next.span)),
+            Token::Comma => {}
+            _ => return Err(unexpected_token(&next)),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{CompleteU, Statement, Type, UnaryOperator};
+    use super::{CompleteU, Iterable, LoopVariables, Range, Statement, Type, UnaryOperator};
     use crate::{
-        parse::{parse, parse_expression, BinaryOperator, Complete, Expression},
-        tokenizer::{MathOp, Token},
+        diagnostic::{Diagnostic, SourceFile, Span},
+        parse::{parse, parse_expression, parse_struct, BinaryOperator, Complete, Expression},
+        tokenizer::{tokenize, MathOp, SpannedToken, Token},
     };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
     use std::collections::VecDeque;
 
+    fn spanned(tokens: Vec<Token>) -> VecDeque<SpannedToken> {
+        tokens.into_iter().map(|token| SpannedToken { token, span: Span::default() }).collect()
+    }
+
     #[test]
     fn hello_world() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Identifier("print".to_string()),
             Token::OpenParen,
             Token::String("hello world".to_string()),
             Token::CloseParen,
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![Statement::FunctionCall(
             "print".to_string(),
-            vec![Expression::String("hello world".to_string())],
+            vec![Expression::String("hello world".to_string(), Span::default())],
+            Span::default(),
         )];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn bool_test() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("peepaw".to_string()),
             Token::Colon,
@@ -488,18 +1091,19 @@ mod test {
             Token::Assign,
             Token::Boolean(true),
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![Statement::DefineVariable(
             "peepaw".to_string(),
-            Expression::Bool(true),
+            Expression::Bool(true, Span::default()),
             Type::Bool,
+            Span::default(),
         )];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn print_variable_test() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("eee".to_string()),
             Token::Colon,
@@ -512,12 +1116,13 @@ mod test {
             Token::Identifier("eee".to_string()),
             Token::CloseParen,
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![
-            Statement::DefineVariable("eee".to_string(), Expression::Bool(true), Type::Bool),
+            Statement::DefineVariable("eee".to_string(), Expression::Bool(true, Span::default()), Type::Bool, Span::default()),
             Statement::FunctionCall(
                 "print".to_string(),
-                vec![Expression::Variable("eee".to_string())],
+                vec![Expression::Variable("eee".to_string(), Span::default())],
+                Span::default(),
             ),
         ];
         assert_eq!(actual, VecDeque::from(expected));
@@ -525,7 +1130,7 @@ mod test {
 
     #[test]
     fn print_string_test() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("ee".to_string()),
             Token::Colon,
@@ -538,16 +1143,18 @@ mod test {
             Token::Identifier("ee".to_string()),
             Token::CloseParen,
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![
             Statement::DefineVariable(
                 "ee".to_string(),
-                Expression::String("yeet?".to_string()),
+                Expression::String("yeet?".to_string(), Span::default()),
                 Type::String,
+                Span::default(),
             ),
             Statement::FunctionCall(
                 "print".to_string(),
-                vec![Expression::Variable("ee".to_string())],
+                vec![Expression::Variable("ee".to_string(), Span::default())],
+                Span::default(),
             ),
         ];
         assert_eq!(actual, VecDeque::from(expected));
@@ -555,7 +1162,7 @@ mod test {
 
     #[test]
     fn simple_while_loop() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::WhileLoop,
             Token::Boolean(true),
             Token::OpenBlock,
@@ -565,20 +1172,23 @@ mod test {
             Token::CloseParen,
             Token::EndLine,
             Token::CloseBlock,
-        ]));
+        ])).unwrap();
         let expected = vec![Statement::WhileLoop(
-            Expression::Bool(true),
+            Expression::Bool(true, Span::default()),
             VecDeque::from([Statement::FunctionCall(
                 "print".to_string(),
-                vec![Expression::String("6".to_string())],
+                vec![Expression::String("6".to_string(), Span::default())],
+                Span::default(),
             )]),
+            None,
+            Span::default(),
         )];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn change_variable() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("i".to_string()),
             Token::Colon,
@@ -612,21 +1222,21 @@ mod test {
             Token::Assign,
             Token::Boolean(false),
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![
-            Statement::DefineVariable("i".to_string(), Expression::I32(0), Type::I32),
-            Statement::ModifyVariable("i".to_string(), Expression::I32(1)),
-            Statement::DefineVariable("e".to_string(), Expression::String("hello".to_string()), Type::String),
-            Statement::ModifyVariable("e".to_string(), Expression::String("bye".to_string())),
-            Statement::DefineVariable("yes".to_string(), Expression::Bool(true), Type::Bool),
-            Statement::ModifyVariable("yes".to_string(), Expression::Bool(false)),
+            Statement::DefineVariable("i".to_string(), Expression::I32(0, Span::default()), Type::I32, Span::default()),
+            Statement::ModifyVariable("i".to_string(), Expression::I32(1, Span::default()), Span::default()),
+            Statement::DefineVariable("e".to_string(), Expression::String("hello".to_string(), Span::default()), Type::String, Span::default()),
+            Statement::ModifyVariable("e".to_string(), Expression::String("bye".to_string(), Span::default()), Span::default()),
+            Statement::DefineVariable("yes".to_string(), Expression::Bool(true, Span::default()), Type::Bool, Span::default()),
+            Statement::ModifyVariable("yes".to_string(), Expression::Bool(false, Span::default()), Span::default()),
         ];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn math_test() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("e".to_string()),
             Token::Colon,
@@ -640,7 +1250,7 @@ mod test {
             Token::MathOp(MathOp::Add),
             Token::ConstantNumber("4".to_string()),
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![Statement::DefineVariable(
             "e".to_string(),
             Expression::Complete(Complete {
@@ -649,21 +1259,22 @@ mod test {
                     operator: BinaryOperator::Add,
                     left: Box::new(Expression::Complete(Complete {
                         operator: BinaryOperator::Add,
-                        left: Box::new(Expression::I32(4)),
-                        right: Box::new(Expression::I32(4)),
-                    })),
-                    right: Box::new(Expression::I32(4)),
-                })),
-                right: Box::new(Expression::I32(4)),
-            }),
+                        left: Box::new(Expression::I32(4, Span::default())),
+                        right: Box::new(Expression::I32(4, Span::default())),
+                    }, Span::default())),
+                    right: Box::new(Expression::I32(4, Span::default())),
+                }, Span::default())),
+                right: Box::new(Expression::I32(4, Span::default())),
+            }, Span::default()),
             Type::I32,
+            Span::default(),
         )];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn oop_test() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("e".to_string()),
             Token::Colon,
@@ -677,30 +1288,31 @@ mod test {
             Token::MathOp(MathOp::Multiply),
             Token::ConstantNumber("4".to_string()),
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![Statement::DefineVariable(
             "e".to_string(),
             Expression::Complete(Complete {
                 operator: BinaryOperator::Subtract,
                 left: Box::new(Expression::Complete(Complete {
                     operator: BinaryOperator::Add,
-                    left: Box::new(Expression::I32(1)),
-                    right: Box::new(Expression::I32(2)),
-                })),
+                    left: Box::new(Expression::I32(1, Span::default())),
+                    right: Box::new(Expression::I32(2, Span::default())),
+                }, Span::default())),
                 right: Box::new(Expression::Complete(Complete {
                     operator: BinaryOperator::Multiply,
-                    left: Box::new(Expression::I32(3)),
-                    right: Box::new(Expression::I32(4)),
-                })),
-            }),
+                    left: Box::new(Expression::I32(3, Span::default())),
+                    right: Box::new(Expression::I32(4, Span::default())),
+                }, Span::default())),
+            }, Span::default()),
             Type::I32,
+            Span::default(),
         )];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn simple_print_add() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Identifier("print".to_string()),
             Token::OpenParen,
             Token::ConstantNumber("1".to_string()),
@@ -708,21 +1320,22 @@ mod test {
             Token::ConstantNumber("6".to_string()),
             Token::CloseParen,
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![Statement::FunctionCall(
             "print".to_string(),
             vec![Expression::Complete(Complete {
                 operator: BinaryOperator::Add,
-                left: Box::new(Expression::I32(1)),
-                right: Box::new(Expression::I32(6)),
-            })],
+                left: Box::new(Expression::I32(1, Span::default())),
+                right: Box::new(Expression::I32(6, Span::default())),
+            }, Span::default())],
+            Span::default(),
         )];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn variable_adding() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("e".to_string()),
             Token::Colon,
@@ -744,17 +1357,18 @@ mod test {
             Token::Identifier("ee".to_string()),
             Token::CloseParen,
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![
-            Statement::DefineVariable("e".to_string(), Expression::I32(1), Type::I32),
-            Statement::DefineVariable("ee".to_string(), Expression::I32(2), Type::I32),
+            Statement::DefineVariable("e".to_string(), Expression::I32(1, Span::default()), Type::I32, Span::default()),
+            Statement::DefineVariable("ee".to_string(), Expression::I32(2, Span::default()), Type::I32, Span::default()),
             Statement::FunctionCall(
                 "print".to_string(),
                 vec![Expression::Complete(Complete {
                     operator: BinaryOperator::Add,
-                    left: Box::new(Expression::Variable("e".to_string())),
-                    right: Box::new(Expression::Variable("ee".to_string())),
-                })],
+                    left: Box::new(Expression::Variable("e".to_string(), Span::default())),
+                    right: Box::new(Expression::Variable("ee".to_string(), Span::default())),
+                }, Span::default())],
+                Span::default(),
             ),
         ];
         assert_eq!(actual, VecDeque::from(expected));
@@ -762,7 +1376,7 @@ mod test {
 
     #[test]
     fn basic_if() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("e".to_string()),
             Token::Colon,
@@ -781,21 +1395,23 @@ mod test {
             Token::CloseParen,
             Token::EndLine,
             Token::CloseBlock,
-        ]));
+        ])).unwrap();
         let expected = vec![
-            Statement::DefineVariable("e".to_string(), Expression::I32(6), Type::I32),
+            Statement::DefineVariable("e".to_string(), Expression::I32(6, Span::default()), Type::I32, Span::default()),
             Statement::If(
                 Expression::Complete(Complete {
                     operator: BinaryOperator::Equals,
-                    left: Box::new(Expression::Variable("e".to_string())),
-                    right: Box::new(Expression::I32(6)),
-                }),
+                    left: Box::new(Expression::Variable("e".to_string(), Span::default())),
+                    right: Box::new(Expression::I32(6, Span::default())),
+                }, Span::default()),
                 VecDeque::from([Statement::FunctionCall(
                     "print".to_string(),
-                    vec![Expression::Variable("e".to_string())],
+                    vec![Expression::Variable("e".to_string(), Span::default())],
+                    Span::default(),
                 )]),
                 VecDeque::new(),
                 None,
+                Span::default(),
             ),
         ];
         assert_eq!(actual, VecDeque::from(expected));
@@ -803,7 +1419,7 @@ mod test {
 
     #[test]
     fn for_loop() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::ForLoop,
             Token::OpenParen,
             Token::Let,
@@ -827,33 +1443,38 @@ mod test {
             Token::CloseParen,
             Token::EndLine,
             Token::CloseBlock,
-        ]));
+        ])).unwrap();
         let expected = vec![Statement::ForLoop(
             Box::new(Statement::DefineVariable(
                 "i".to_string(),
-                Expression::I32(0),
+                Expression::I32(0, Span::default()),
                 Type::I32,
+                Span::default(),
             )),
             Expression::Complete(Complete {
                 operator: BinaryOperator::LessThan,
-                left: Box::new(Expression::Variable("i".to_string())),
-                right: Box::new(Expression::I32(10)),
-            }),
+                left: Box::new(Expression::Variable("i".to_string(), Span::default())),
+                right: Box::new(Expression::I32(10, Span::default())),
+            }, Span::default()),
             Box::new(Statement::ModifyVariable(
                 "i".to_string(),
-                Expression::Increment,
+                Expression::Increment(Span::default()),
+                Span::default(),
             )),
             VecDeque::from([Statement::FunctionCall(
                 "print".to_string(),
-                vec![Expression::Variable("i".to_string())],
+                vec![Expression::Variable("i".to_string(), Span::default())],
+                Span::default(),
             )]),
+            None,
+            Span::default(),
         )];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn double_if() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::If,
             Token::Boolean(true),
             Token::OpenBlock,
@@ -867,27 +1488,30 @@ mod test {
             Token::EndLine,
             Token::CloseBlock,
             Token::CloseBlock,
-        ]));
+        ])).unwrap();
         let expected = vec![Statement::If(
-            Expression::Bool(true),
+            Expression::Bool(true, Span::default()),
             VecDeque::from([Statement::If(
-                Expression::Bool(false),
+                Expression::Bool(false, Span::default()),
                 VecDeque::from([Statement::FunctionCall(
                     "print".to_string(),
-                    vec![Expression::String("a".to_string())],
+                    vec![Expression::String("a".to_string(), Span::default())],
+                    Span::default(),
                 )]),
                 VecDeque::new(),
                 None,
+                Span::default(),
             )]),
             VecDeque::new(),
             None,
+            Span::default(),
         )];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn more_if() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::If,
             Token::Boolean(true),
             Token::OpenBlock,
@@ -910,38 +1534,43 @@ mod test {
             Token::EndLine,
             Token::CloseBlock,
             Token::CloseBlock,
-        ]));
+        ])).unwrap();
         let expected = vec![Statement::If(
-            Expression::Bool(true),
+            Expression::Bool(true, Span::default()),
             VecDeque::from([
                 Statement::If(
-                    Expression::Bool(false),
+                    Expression::Bool(false, Span::default()),
                     VecDeque::from([Statement::FunctionCall(
                         "print".to_string(),
-                        vec![Expression::String("a".to_string())],
+                        vec![Expression::String("a".to_string(), Span::default())],
+                        Span::default(),
                     )]),
                     VecDeque::new(),
                     None,
+                    Span::default(),
                 ),
                 Statement::If(
-                    Expression::Bool(true),
+                    Expression::Bool(true, Span::default()),
                     VecDeque::from([Statement::FunctionCall(
                         "print".to_string(),
-                        vec![Expression::String("n".to_string())],
+                        vec![Expression::String("n".to_string(), Span::default())],
+                        Span::default(),
                     )]),
                     VecDeque::new(),
                     None,
+                    Span::default(),
                 ),
             ]),
             VecDeque::new(),
             None,
+            Span::default(),
         )];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn increment_test() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("w".to_string()),
             Token::Colon,
@@ -952,17 +1581,17 @@ mod test {
             Token::Identifier("w".to_string()),
             Token::Decrement,
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![
-            Statement::DefineVariable("w".to_string(), Expression::I32(68), Type::I32),
-            Statement::ModifyVariable("w".to_string(), Expression::Decrement),
+            Statement::DefineVariable("w".to_string(), Expression::I32(68, Span::default()), Type::I32, Span::default()),
+            Statement::ModifyVariable("w".to_string(), Expression::Decrement(Span::default()), Span::default()),
         ];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn if_elif_elif_else() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("i".to_string()),
             Token::Colon,
@@ -991,35 +1620,38 @@ mod test {
             Token::Else,
             Token::OpenBlock,
             Token::CloseBlock,
-        ]));
+        ])).unwrap();
         let expected = vec![
-            Statement::DefineVariable("i".to_string(), Expression::I32(6), Type::I32),
+            Statement::DefineVariable("i".to_string(), Expression::I32(6, Span::default()), Type::I32, Span::default()),
             Statement::If(
                 Expression::Complete(Complete {
                     operator: BinaryOperator::Equals,
-                    left: Box::new(Expression::Variable("i".to_string())),
-                    right: Box::new(Expression::I32(6)),
-                }),
+                    left: Box::new(Expression::Variable("i".to_string(), Span::default())),
+                    right: Box::new(Expression::I32(6, Span::default())),
+                }, Span::default()),
                 VecDeque::from([]),
                 VecDeque::from([
                     Statement::Elif(
                         Expression::Complete(Complete {
                             operator: BinaryOperator::Equals,
-                            left: Box::new(Expression::Variable("i".to_string())),
-                            right: Box::new(Expression::I32(7)),
-                        }),
+                            left: Box::new(Expression::Variable("i".to_string(), Span::default())),
+                            right: Box::new(Expression::I32(7, Span::default())),
+                        }, Span::default()),
                         VecDeque::from([]),
+                        Span::default(),
                     ),
                     Statement::Elif(
                         Expression::Complete(Complete {
                             operator: BinaryOperator::Equals,
-                            left: Box::new(Expression::Variable("i".to_string())),
-                            right: Box::new(Expression::I32(6)),
-                        }),
+                            left: Box::new(Expression::Variable("i".to_string(), Span::default())),
+                            right: Box::new(Expression::I32(6, Span::default())),
+                        }, Span::default()),
                         VecDeque::from([]),
+                        Span::default(),
                     ),
                 ]),
                 Some(VecDeque::new()),
+                Span::default(),
             ),
         ];
         assert_eq!(actual, VecDeque::from(expected));
@@ -1027,7 +1659,7 @@ mod test {
 
     #[test]
     fn i32_i64_f32_f64() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("i".to_string()),
             Token::Colon,
@@ -1056,19 +1688,19 @@ mod test {
             Token::Assign,
             Token::ConstantNumber("64".to_string()),
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![
-            Statement::DefineVariable("i".to_string(), Expression::I32(31), Type::I32),
-            Statement::DefineVariable("e".to_string(), Expression::I64(63), Type::I64),
-            Statement::DefineVariable("f".to_string(), Expression::F32(32.0), Type::F32),
-            Statement::DefineVariable("g".to_string(), Expression::F64(64.0), Type::F64),
+            Statement::DefineVariable("i".to_string(), Expression::I32(31, Span::default()), Type::I32, Span::default()),
+            Statement::DefineVariable("e".to_string(), Expression::I64(63, Span::default()), Type::I64, Span::default()),
+            Statement::DefineVariable("f".to_string(), Expression::F32(32.0, Span::default()), Type::F32, Span::default()),
+            Statement::DefineVariable("g".to_string(), Expression::F64(64.0, Span::default()), Type::F64, Span::default()),
         ];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn f32_test() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("e".to_string()),
             Token::Colon,
@@ -1076,18 +1708,19 @@ mod test {
             Token::Assign,
             Token::ConstantNumber("32".to_string()),
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![Statement::DefineVariable(
             "e".to_string(),
-            Expression::F32(32.0),
+            Expression::F32(32.0, Span::default()),
             Type::F32,
+            Span::default(),
         )];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn simple_cast() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("i".to_string()),
             Token::Colon,
@@ -1105,16 +1738,18 @@ mod test {
             Token::Identifier("i".to_string()),
             Token::CloseParen,
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![
-            Statement::DefineVariable("i".to_string(), Expression::I32(6), Type::I32),
+            Statement::DefineVariable("i".to_string(), Expression::I32(6, Span::default()), Type::I32, Span::default()),
             Statement::DefineVariable(
                 "e".to_string(),
                 Expression::FunctionCall(
                     "i64".to_string(),
-                    vec![Expression::Variable("i".to_string())],
+                    vec![Expression::Variable("i".to_string(), Span::default())],
+                    Span::default(),
                 ),
                 Type::I64,
+                Span::default(),
             ),
         ];
         assert_eq!(actual, VecDeque::from(expected));
@@ -1122,7 +1757,7 @@ mod test {
 
     #[test]
     fn cast_in_while() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("i".to_string()),
             Token::Colon,
@@ -1139,19 +1774,22 @@ mod test {
             Token::CloseParen,
             Token::OpenBlock,
             Token::CloseBlock,
-        ]));
+        ])).unwrap();
         let expected = vec![
-            Statement::DefineVariable("i".to_string(), Expression::I64(6), Type::I64),
+            Statement::DefineVariable("i".to_string(), Expression::I64(6, Span::default()), Type::I64, Span::default()),
             Statement::WhileLoop(
                 Expression::Complete(Complete {
                     operator: BinaryOperator::NotEqual,
-                    left: Box::new(Expression::Variable("i".to_string())),
+                    left: Box::new(Expression::Variable("i".to_string(), Span::default())),
                     right: Box::new(Expression::FunctionCall(
                         "i64".to_string(),
-                        vec![Expression::I32(2)],
+                        vec![Expression::I32(2, Span::default())],
+                        Span::default(),
                     )),
-                }),
+                }, Span::default()),
                 VecDeque::new(),
+                None,
+                Span::default(),
             ),
         ];
         assert_eq!(actual, VecDeque::from(expected));
@@ -1159,7 +1797,7 @@ mod test {
 
     #[test]
     fn statement_after_loop() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("i".to_string()),
             Token::Colon,
@@ -1176,13 +1814,14 @@ mod test {
             Token::Identifier("i".to_string()),
             Token::CloseParen,
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![
-            Statement::DefineVariable("i".to_string(), Expression::I64(9), Type::I64),
-            Statement::WhileLoop(Expression::Bool(false), VecDeque::new()),
+            Statement::DefineVariable("i".to_string(), Expression::I64(9, Span::default()), Type::I64, Span::default()),
+            Statement::WhileLoop(Expression::Bool(false, Span::default()), VecDeque::new(), None, Span::default()),
             Statement::FunctionCall(
                 "print".to_string(),
-                vec![Expression::Variable("i".to_string())],
+                vec![Expression::Variable("i".to_string(), Span::default())],
+                Span::default(),
             ),
         ];
         assert_eq!(actual, VecDeque::from(expected));
@@ -1190,7 +1829,7 @@ mod test {
 
     #[test]
     fn order_of_ops_parenthesis_1() {
-        let mut tokens = VecDeque::from([
+        let mut tokens = spanned(vec![
             Token::OpenParen,
             Token::ConstantNumber("1".to_string()),
             Token::MathOp(MathOp::Add),
@@ -1199,25 +1838,25 @@ mod test {
             Token::MathOp(MathOp::Multiply),
             Token::ConstantNumber("3".to_string()),
         ]);
-        let actual = parse_expression(&mut tokens, Some(Type::I32));
+        let actual = parse_expression(&mut tokens, Some(Type::I32)).unwrap();
         let expected = Expression::Complete(Complete {
             operator: BinaryOperator::Multiply,
             left: Box::new(Expression::CompleteU(CompleteU {
                 operator: UnaryOperator::Parenthesis,
                 child: Box::new(Expression::Complete(Complete {
                     operator: BinaryOperator::Add,
-                    left: Box::new(Expression::I32(1)),
-                    right: Box::new(Expression::I32(2)),
-                })),
-            })),
-            right: Box::new(Expression::I32(3)),
-        });
+                    left: Box::new(Expression::I32(1, Span::default())),
+                    right: Box::new(Expression::I32(2, Span::default())),
+                }, Span::default())),
+            }, Span::default())),
+            right: Box::new(Expression::I32(3, Span::default())),
+        }, Span::default());
         assert_eq!(actual, expected);
     }
 
     #[test]
     fn order_of_ops_parenthesis_2() {
-        let mut tokens = VecDeque::from([
+        let mut tokens = spanned(vec![
             Token::ConstantNumber("3".to_string()),
             Token::MathOp(MathOp::Multiply),
             Token::OpenParen,
@@ -1226,25 +1865,25 @@ mod test {
             Token::ConstantNumber("6".to_string()),
             Token::CloseParen,
         ]);
-        let actual = parse_expression(&mut tokens, Some(Type::I32));
+        let actual = parse_expression(&mut tokens, Some(Type::I32)).unwrap();
         let expected = Expression::Complete(Complete {
             operator: BinaryOperator::Multiply,
-            left: Box::new(Expression::I32(3)),
+            left: Box::new(Expression::I32(3, Span::default())),
             right: Box::new(Expression::CompleteU(CompleteU {
                 operator: UnaryOperator::Parenthesis,
                 child: Box::new(Expression::Complete(Complete {
                     operator: BinaryOperator::Add,
-                    left: Box::new(Expression::I32(2)),
-                    right: Box::new(Expression::I32(6)),
-                })),
-            })),
-        });
+                    left: Box::new(Expression::I32(2, Span::default())),
+                    right: Box::new(Expression::I32(6, Span::default())),
+                }, Span::default())),
+            }, Span::default())),
+        }, Span::default());
         assert_eq!(actual, expected);
     }
 
     #[test]
     fn complex_print_1() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Identifier("print".to_string()),
             Token::OpenParen,
             Token::OpenParen,
@@ -1256,7 +1895,7 @@ mod test {
             Token::ConstantNumber("3".to_string()),
             Token::CloseParen,
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![Statement::FunctionCall(
             "print".to_string(),
             vec![Expression::Complete(Complete {
@@ -1265,19 +1904,20 @@ mod test {
                     operator: UnaryOperator::Parenthesis,
                     child: Box::new(Expression::Complete(Complete {
                         operator: BinaryOperator::Add,
-                        left: Box::new(Expression::I32(1)),
-                        right: Box::new(Expression::I32(2)),
-                    })),
-                })),
-                right: Box::new(Expression::I32(3)),
-            })],
+                        left: Box::new(Expression::I32(1, Span::default())),
+                        right: Box::new(Expression::I32(2, Span::default())),
+                    }, Span::default())),
+                }, Span::default())),
+                right: Box::new(Expression::I32(3, Span::default())),
+            }, Span::default())],
+            Span::default(),
         )];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn boolean_operators() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Identifier("print".to_string()),
             Token::OpenParen,
             Token::Boolean(false),
@@ -1300,23 +1940,25 @@ mod test {
             Token::Boolean(true),
             Token::CloseParen,
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![
             Statement::FunctionCall(
                 "print".to_string(),
                 vec![Expression::Complete(Complete {
                     operator: BinaryOperator::And,
-                    left: Box::new(Expression::Bool(false)),
-                    right: Box::new(Expression::Bool(true)),
-                })],
+                    left: Box::new(Expression::Bool(false, Span::default())),
+                    right: Box::new(Expression::Bool(true, Span::default())),
+                }, Span::default())],
+                Span::default(),
             ),
             Statement::FunctionCall(
                 "print".to_string(),
                 vec![Expression::Complete(Complete {
                     operator: BinaryOperator::Or,
-                    left: Box::new(Expression::Bool(false)),
-                    right: Box::new(Expression::Bool(true)),
-                })],
+                    left: Box::new(Expression::Bool(false, Span::default())),
+                    right: Box::new(Expression::Bool(true, Span::default())),
+                }, Span::default())],
+                Span::default(),
             ),
             Statement::FunctionCall(
                 "print".to_string(),
@@ -1324,10 +1966,11 @@ mod test {
                     operator: BinaryOperator::And,
                     left: Box::new(Expression::CompleteU(CompleteU {
                         operator: UnaryOperator::Not,
-                        child: Box::new(Expression::Bool(false)),
-                    })),
-                    right: Box::new(Expression::Bool(true)),
-                })],
+                        child: Box::new(Expression::Bool(false, Span::default())),
+                    }, Span::default())),
+                    right: Box::new(Expression::Bool(true, Span::default())),
+                }, Span::default())],
+                Span::default(),
             ),
         ];
         assert_eq!(actual, VecDeque::from(expected));
@@ -1335,7 +1978,7 @@ mod test {
 
     #[test]
     fn one_dim_array() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("a".to_string()),
             Token::Colon,
@@ -1347,18 +1990,19 @@ mod test {
             Token::ConstantNumber("42".to_string()),
             Token::CloseBracket,
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![Statement::DefineVariable(
             "a".to_string(),
-            Expression::Array(vec![Expression::I32(6), Expression::I32(42)]),
+            Expression::Array(vec![Expression::I32(6, Span::default()), Expression::I32(42, Span::default())], Span::default()),
             Type::Array(Box::new(Type::I32)),
+            Span::default(),
         )];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn empty_one_dim_array() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("a".to_string()),
             Token::Colon,
@@ -1367,18 +2011,19 @@ mod test {
             Token::OpenBracket,
             Token::CloseBracket,
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![Statement::DefineVariable(
             "a".to_string(),
-            Expression::Array(vec![]),
+            Expression::Array(vec![], Span::default()),
             Type::Array(Box::new(Type::String)),
+            Span::default(),
         )];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn empty_two_dim_array() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Let,
             Token::Identifier("a".to_string()),
             Token::Colon,
@@ -1389,18 +2034,19 @@ mod test {
             Token::CloseBracket,
             Token::CloseBracket,
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![Statement::DefineVariable(
             "a".to_string(),
-            Expression::Array(vec![Expression::Array(vec![])]),
+            Expression::Array(vec![Expression::Array(vec![], Span::default())], Span::default()),
             Type::Array(Box::new(Type::Array(Box::new(Type::String)))),
+            Span::default(),
         )];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn complex_logic() {
-        let actual = parse(&mut VecDeque::from([
+        let actual = parse(&mut spanned(vec![
             Token::Identifier("print".to_string()),
             Token::OpenParen,
             Token::OpenParen,
@@ -1412,7 +2058,7 @@ mod test {
             Token::Boolean(true),
             Token::CloseParen,
             Token::EndLine,
-        ]));
+        ])).unwrap();
         let expected = vec![Statement::FunctionCall(
             "print".to_string(),
             vec![Expression::Complete(Complete {
@@ -1421,19 +2067,20 @@ mod test {
                     operator: UnaryOperator::Parenthesis,
                     child: Box::new(Expression::Complete(Complete {
                         operator: BinaryOperator::Or,
-                        left: Box::new(Expression::Bool(false)),
-                        right: Box::new(Expression::Bool(true)),
-                    })),
-                })),
-                right: Box::new(Expression::Bool(true)),
-            })],
+                        left: Box::new(Expression::Bool(false, Span::default())),
+                        right: Box::new(Expression::Bool(true, Span::default())),
+                    }, Span::default())),
+                }, Span::default())),
+                right: Box::new(Expression::Bool(true, Span::default())),
+            }, Span::default())],
+            Span::default(),
         )];
         assert_eq!(actual, VecDeque::from(expected));
     }
 
     #[test]
     fn let_definition_test() {
-        let tokens = VecDeque::from([
+        let tokens = spanned(vec![
             Token::Let,
             Token::Identifier("a".to_string()),
             Token::Colon,
@@ -1442,8 +2089,474 @@ mod test {
             Token::ConstantNumber("1".to_string()),
             Token::EndLine,
         ]);
-        let parsed = parse(&mut tokens.clone());
-        let expected = vec![Statement::DefineVariable("a".to_string(), Expression::I32(1), Type::I32)];
+        let parsed = parse(&mut tokens.clone()).unwrap();
+        let expected = vec![Statement::DefineVariable("a".to_string(), Expression::I32(1, Span::default()), Type::I32, Span::default())];
         assert_eq!(parsed, VecDeque::from(expected));
     }
+
+    #[test]
+    fn old_definition_syntax_error() {
+        let error = parse(&mut spanned(vec![
+            Token::Identifier("i32".to_string()),
+            Token::Identifier("a".to_string()),
+            Token::Assign,
+            Token::ConstantNumber("1".to_string()),
+            Token::EndLine,
+        ])).unwrap_err();
+        assert_eq!(error.code, 13);
+    }
+
+    #[test]
+    fn missing_token_error() {
+        let error = parse(&mut spanned(vec![
+            Token::Let,
+            Token::Identifier("a".to_string()),
+            Token::Identifier("i32".to_string()),
+        ])).unwrap_err();
+        assert_eq!(error.code, 12);
+    }
+
+    #[test]
+    fn unknown_type_error() {
+        let error = parse(&mut spanned(vec![
+            Token::Let,
+            Token::Identifier("a".to_string()),
+            Token::Colon,
+            Token::Identifier("u8".to_string()),
+            Token::Assign,
+            Token::ConstantNumber("1".to_string()),
+            Token::EndLine,
+        ])).unwrap_err();
+        assert_eq!(error.code, 14);
+    }
+
+    #[test]
+    fn multiple_function_args() {
+        let actual = parse(&mut spanned(vec![
+            Token::Identifier("sleep".to_string()),
+            Token::OpenParen,
+            Token::ConstantNumber("1".to_string()),
+            Token::Comma,
+            Token::ConstantNumber("2".to_string()),
+            Token::CloseParen,
+            Token::EndLine,
+        ])).unwrap();
+        let expected = vec![Statement::FunctionCall(
+            "sleep".to_string(),
+            vec![Expression::I32(1, Span::default()), Expression::I32(2, Span::default())],
+            Span::default(),
+        )];
+        assert_eq!(actual, VecDeque::from(expected));
+    }
+
+    #[test]
+    fn spans_point_into_source() {
+        let source = SourceFile::new("test.st", "let a: i32 = 1;\nprint(a + 22);".to_string());
+        let mut tokens = tokenize(&source, 0, source.text.len()).unwrap();
+        let statements = parse(&mut tokens).unwrap();
+        let define_span = statements[0].span();
+        assert_eq!((define_span.line, define_span.col, define_span.start, define_span.end), (1, 1, 0, 14));
+        match &statements[1] {
+            Statement::FunctionCall(_, args, span) => {
+                assert_eq!((span.line, span.col), (2, 1));
+                assert_eq!(&source.text[span.start..span.end], "print(a + 22)");
+                let arg_span = args[0].span();
+                assert_eq!(&source.text[arg_span.start..arg_span.end], "a + 22");
+                assert_eq!((arg_span.line, arg_span.col), (2, 7));
+            }
+            other => panic!("expected a function call but found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn error_spans_point_into_source() {
+        let source = SourceFile::new("test.st", "let a: i32 = 1;\nlet b i32 = 2;".to_string());
+        let mut tokens = tokenize(&source, 0, source.text.len()).unwrap();
+        let error = parse(&mut tokens).unwrap_err();
+        let span = error.span.unwrap();
+        assert_eq!(error.code, 12);
+        assert_eq!((span.line, span.col), (2, 7));
+    }
+
+    //Parses one statement and returns the value it defines or the first argument it prints.
+    fn value_of(text: &str) -> Result<Expression, Diagnostic> {
+        let source = SourceFile::new("test.st", text.to_string());
+        let mut tokens = tokenize(&source, 0, text.len())?;
+        match parse(&mut tokens)?.pop_front() {
+            Some(Statement::DefineVariable(_, expression, ..)) => Ok(expression),
+            Some(Statement::FunctionCall(_, mut args, _)) => Ok(args.remove(0)),
+            other => panic!("expected a definition or call but found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn number_literals() {
+        assert!(matches!(value_of("print(10i64);"), Ok(Expression::I64(10, _))));
+        assert!(matches!(value_of("print(2.5f32);"), Ok(Expression::F32(2.5, _))));
+        assert!(matches!(value_of("print(3.5);"), Ok(Expression::F64(3.5, _))));
+        assert!(matches!(value_of("print(1e-9);"), Ok(Expression::F64(value, _)) if value == 1e-9));
+        assert!(matches!(value_of("print(0xFF);"), Ok(Expression::I32(255, _))));
+        assert!(matches!(value_of("print(0b1010i64);"), Ok(Expression::I64(10, _))));
+        assert!(matches!(value_of("print(0o17);"), Ok(Expression::I32(15, _))));
+        assert!(matches!(value_of("let x: f32 = 1.5E3;"), Ok(Expression::F32(1500.0, _))));
+        assert!(matches!(value_of("let x: f64 = 2;"), Ok(Expression::F64(2.0, _))));
+        assert!(matches!(value_of("let x: i64 = 1_000_000_000_000;"), Ok(Expression::I64(1_000_000_000_000, _))));
+        assert!(matches!(value_of("let x: i32 = -2147483648;"), Ok(Expression::I32(i32::MIN, _))));
+        assert!(matches!(value_of("let x: i64 = -0x10;"), Ok(Expression::I64(-16, _))));
+    }
+
+    #[test]
+    fn invalid_number_literals() {
+        for (text, code) in [
+            ("print(2147483648);", 49),
+            ("let x: i64 = 9223372036854775808;", 49),
+            ("let x: i32 = -2147483649;", 49),
+            ("let x: f32 = 1e39;", 49),
+            ("print(1e400);", 49),
+            ("print(340282366920938463463374607431768211456);", 49),
+            ("let x: i32 = 3.5;", 16),
+            ("print(1.5i32);", 16),
+            ("print(10abc);", 16),
+            ("print(1e);", 16),
+            ("let x: f64 = 0x10;", 16),
+            ("print(0x);", 16),
+            ("print(0b102);", 16),
+        ] {
+            assert_eq!(value_of(text).unwrap_err().code, code, "{}", text);
+        }
+    }
+
+    #[test]
+    fn unary_minus() {
+        match value_of("print(-a * b);").unwrap() {
+            Expression::Complete(Complete { operator: BinaryOperator::Multiply, left, .. }, _) => {
+                assert!(matches!(*left, Expression::CompleteU(CompleteU { operator: UnaryOperator::Negate, .. }, _)));
+            }
+            other => panic!("expected a multiplication but found {:?}", other),
+        }
+        match value_of("print(a - -1);").unwrap() {
+            Expression::Complete(Complete { operator: BinaryOperator::Subtract, right, .. }, _) => assert!(matches!(*right, Expression::I32(-1, _))),
+            other => panic!("expected a subtraction but found {:?}", other),
+        }
+        assert!(matches!(value_of("print(-(a + 1));"), Ok(Expression::CompleteU(CompleteU { operator: UnaryOperator::Negate, .. }, _))));
+        assert_eq!(value_of("print(-);").unwrap_err().code, 15);
+    }
+
+    //Renders an expression with every operation in explicit parentheses, ignoring spans and source parentheses.
+    fn shape(expression: &Expression) -> String {
+        match expression {
+            Expression::Complete(complete, _) => format!("({} {:?} {})", shape(&complete.left), complete.operator, shape(&complete.right)),
+            Expression::CompleteU(CompleteU { operator: UnaryOperator::Parenthesis, child }, _) => shape(child),
+            Expression::CompleteU(complete, _) => format!("({:?} {})", complete.operator, shape(&complete.child)),
+            Expression::Variable(name, _) => name.clone(),
+            Expression::I32(value, _) => value.to_string(),
+            Expression::Index(target, index, _) => format!("{}[{}]", shape(target), shape(index)),
+            Expression::Field(target, field, _) => format!("{}.{}", shape(target), field),
+            Expression::Struct(name, fields, _) => {
+                let fields: Vec<String> = fields.iter().map(|(field, value)| format!("{}: {}", field, shape(value))).collect();
+                format!("{} {{ {} }}", name, fields.join(", "))
+            }
+            Expression::Slice(target, start, end, _) => {
+                let bound = |bound: &Option<Box<Expression>>| bound.as_deref().map(shape).unwrap_or_default();
+                format!("{}[{}..{}]", shape(target), bound(start), bound(end))
+            }
+            Expression::String(value, _) => format!("{:?}", value),
+            Expression::Map(entries, _) => {
+                let entries: Vec<String> = entries.iter().map(|(key, value)| format!("{}: {}", shape(key), shape(value))).collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            other => panic!("unexpected expression {:?}", other),
+        }
+    }
+
+    fn shape_of(text: &str) -> String {
+        shape(&value_of(&format!("print({});", text)).unwrap())
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(shape_of("a < b && c < d"), "((a LessThan b) And (c LessThan d))");
+        assert_eq!(shape_of("a || b && c"), "(a Or (b And c))");
+        assert_eq!(shape_of("a && b || c && d"), "((a And b) Or (c And d))");
+        assert_eq!(shape_of("a == b < c"), "(a Equals (b LessThan c))");
+        assert_eq!(shape_of("a + b * c - d"), "((a Add (b Multiply c)) Subtract d)");
+        assert_eq!(shape_of("a - b - c"), "((a Subtract b) Subtract c)");
+        assert_eq!(shape_of("a / b % c * d"), "(((a Divide b) Modulus c) Multiply d)");
+        assert_eq!(shape_of("!a && -b < c"), "((Not a) And ((Negate b) LessThan c))");
+        assert_eq!(shape_of("(a || b) && c"), "((a Or b) And c)");
+    }
+
+    #[test]
+    fn expression_errors() {
+        for (text, code) in [("print(a +);", 15), ("print(* a);", 15), ("print(!);", 15), ("print(a b);", 11), ("print((a + b);", 15), ("print([1 2]);", 11)] {
+            assert_eq!(value_of(text).unwrap_err().code, code, "{}", text);
+        }
+    }
+
+    #[test]
+    fn indexing_and_slices() {
+        assert_eq!(shape_of("a[i][j] + b[1]"), "(a[i][j] Add b[1])");
+        assert_eq!(shape_of("-a[0] * 2"), "((Negate a[0]) Multiply 2)");
+        assert_eq!(shape_of("a[1..n - 1]"), "a[1..(n Subtract 1)]");
+        assert_eq!(shape_of("a[..2][1..]"), "a[..2][1..]");
+        assert_eq!(shape_of("a[b[0]]"), "a[b[0]]");
+        let source = SourceFile::new("test.st", "grid[i][j + 1] = 5;".to_string());
+        let statements = parse(&mut tokenize(&source, 0, source.text.len()).unwrap()).unwrap();
+        let Statement::ModifyElement(target, Expression::I32(5, _), _) = &statements[0] else {
+            panic!("expected an element assignment but found {:?}", statements[0]);
+        };
+        assert_eq!(shape(target), "grid[i][(j Add 1)]");
+        for (text, code) in [("print(a[]);", 15), ("print(a[1);", 12), ("print(a[1..2..3]);", 12)] {
+            assert_eq!(value_of(text).unwrap_err().code, code, "{}", text);
+        }
+        let source = SourceFile::new("test.st", "a[0] 5;".to_string());
+        assert_eq!(parse(&mut tokenize(&source, 0, source.text.len()).unwrap()).unwrap_err().code, 12);
+    }
+
+    fn statements_of(text: &str) -> Result<VecDeque<Statement>, Diagnostic> {
+        let source = SourceFile::new("test.st", text.to_string());
+        parse(&mut tokenize(&source, 0, text.len())?)
+    }
+
+    #[test]
+    fn commas_between_arguments() {
+        for text in ["print(,1);", "print(1,,2);", "print(1,);", "print(1 2);", "print(,);"] {
+            let error = statements_of(text).unwrap_err();
+            assert_eq!(error.code, 11, "{}", text);
+        }
+        let error = statements_of("print(1,);").unwrap_err();
+        assert_eq!(error.message, "Unexpected token CloseParen");
+        assert_eq!(error.span.unwrap().col, 9);
+        for text in ["print();", "print(1);", "print(format(\"{} {}\", 1, 2));"] {
+            assert!(statements_of(text).is_ok(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn for_each_loops() {
+        let statements = statements_of("for x in a[1..] {\n}\nfor i in 0..=n - 1 {\n}\n'outer: for i in (0..10).step(2) {\n}\nfor i in (a) {\n}").unwrap();
+        let Statement::ForEach(variable, Iterable::Array(array), _, None, _) = &statements[0] else {
+            panic!("expected a loop over an array but found {:?}", statements[0]);
+        };
+        assert_eq!((variable.names(), shape(array).as_str()), (vec!["x"], "a[1..]"));
+        let Statement::ForEach(_, Iterable::Range(range), ..) = &statements[1] else {
+            panic!("expected an inclusive range but found {:?}", statements[1]);
+        };
+        let Range { start, end, inclusive: true, step: None } = range.as_ref() else {
+            panic!("expected an inclusive range but found {:?}", range);
+        };
+        assert_eq!((shape(start).as_str(), shape(end).as_str()), ("0", "(n Subtract 1)"));
+        let Statement::ForEach(_, Iterable::Range(range), _, Some(label), _) = &statements[2] else {
+            panic!("expected a labeled range with a step but found {:?}", statements[2]);
+        };
+        let Range { inclusive: false, step: Some(step), .. } = range.as_ref() else {
+            panic!("expected a range with a step but found {:?}", range);
+        };
+        assert_eq!((shape(step).as_str(), label.as_str()), ("2", "outer"));
+        assert!(matches!(&statements[3], Statement::ForEach(_, Iterable::Array(_), ..)));
+        for text in ["for x of a {\n}", "for i in (0..3).skip(1) {\n}", "for (let i: i32 = 0, i < 3, i++) {\n}", "for i in 0.. {\n}"] {
+            let code = statements_of(text).unwrap_err().code;
+            assert!(code == 12 || code == 15, "{} gave {}", text, code);
+        }
+    }
+
+    //The operator table the parser should follow, loosest first, written out independently of BinaryOperator::precedence.
+    const LEVELS: [&[(&str, &str)]; 6] = [
+        &[("||", "Or")],
+        &[("&&", "And")],
+        &[("==", "Equals"), ("!=", "NotEqual")],
+        &[("<", "LessThan"), ("<=", "LessThanOrEqualTo"), (">", "GreaterThan"), (">=", "GreaterThanOrEqualTo")],
+        &[("+", "Add"), ("-", "Subtract")],
+        &[("*", "Multiply"), ("/", "Divide"), ("%", "Modulus")],
+    ];
+
+    enum Tree {
+        Leaf(String),
+        Unary(&'static str, &'static str, Box<Tree>),
+        Binary(usize, &'static str, &'static str, Box<Tree>, Box<Tree>),
+    }
+
+    fn random_tree(rng: &mut StdRng, depth: u32) -> Tree {
+        match if depth == 0 { 0 } else { rng.random_range(0..5) } {
+            0 if rng.random_bool(0.5) => Tree::Leaf(["a", "b", "c", "d"][rng.random_range(0..4)].to_string()),
+            0 => Tree::Leaf(rng.random_range(1..100).to_string()),
+            1 => {
+                let (symbol, name) = if rng.random_bool(0.5) { ("!", "Not") } else { ("-", "Negate") };
+                Tree::Unary(symbol, name, Box::new(random_tree(rng, depth - 1)))
+            }
+            _ => {
+                let level = rng.random_range(0..LEVELS.len());
+                let (symbol, name) = LEVELS[level][rng.random_range(0..LEVELS[level].len())];
+                Tree::Binary(level, symbol, name, Box::new(random_tree(rng, depth - 1)), Box::new(random_tree(rng, depth - 1)))
+            }
+        }
+    }
+
+    //Writes the tree with only the parentheses the reference table requires.
+    fn source(tree: &Tree) -> String {
+        let operand = |child: &Tree, needs_parens: bool| if needs_parens { format!("({})", source(child)) } else { source(child) };
+        match tree {
+            Tree::Leaf(text) => text.clone(),
+            Tree::Unary(symbol, _, child) => {
+                let child = operand(child, matches!(**child, Tree::Binary(..)));
+                //A space keeps - -a from being read as the -- token.
+                if child.starts_with('-') { format!("{} {}", symbol, child) } else { format!("{}{}", symbol, child) }
+            }
+            Tree::Binary(level, symbol, _, left, right) => {
+                let left = operand(left, matches!(**left, Tree::Binary(child, ..) if child < *level));
+                let right = operand(right, matches!(**right, Tree::Binary(child, ..) if child <= *level));
+                format!("{} {} {}", left, symbol, right)
+            }
+        }
+    }
+
+    fn expected_shape(tree: &Tree) -> String {
+        match tree {
+            Tree::Leaf(text) => text.clone(),
+            //A minus directly before a number is folded into the literal.
+            Tree::Unary("-", _, child) if matches!(&**child, Tree::Leaf(text) if text.starts_with(|c: char| c.is_ascii_digit())) => format!("-{}", expected_shape(child)),
+            Tree::Unary(_, name, child) => format!("({} {})", name, expected_shape(child)),
+            Tree::Binary(_, _, name, left, right) => format!("({} {} {})", expected_shape(left), name, expected_shape(right)),
+        }
+    }
+
+    #[test]
+    fn random_expressions_match_reference() {
+        let mut rng = StdRng::seed_from_u64(17);
+        for _ in 0..2000 {
+            let tree = random_tree(&mut rng, 5);
+            let text = source(&tree);
+            let source_file = SourceFile::new("test.st", text.clone());
+            let mut tokens = tokenize(&source_file, 0, text.len()).unwrap();
+            let parsed = parse_expression(&mut tokens, None).unwrap_or_else(|error| panic!("{}: {:?}", text, error));
+            assert!(tokens.is_empty(), "{}", text);
+            assert_eq!(shape(&parsed), expected_shape(&tree), "{}", text);
+        }
+    }
+
+    #[test]
+    fn labeled_loops() {
+        let source = SourceFile::new("test.st", "'outer: while true {\n    for (let i: i32 = 0; i < 3; i++) {\n        break 'outer;\n    }\n    continue;\n}".to_string());
+        let statements = parse(&mut tokenize(&source, 0, source.text.len()).unwrap()).unwrap();
+        let Statement::WhileLoop(_, body, Some(label), span) = &statements[0] else {
+            panic!("expected a labeled while loop but found {:?}", statements[0]);
+        };
+        assert_eq!((label.as_str(), span.col), ("outer", 1));
+        let Statement::ForLoop(_, _, _, inner, None, _) = &body[0] else {
+            panic!("expected an unlabeled for loop but found {:?}", body[0]);
+        };
+        assert!(matches!(&inner[0], Statement::Break(Some(label), _) if label == "outer"));
+        assert!(matches!(&body[1], Statement::Continue(None, _)));
+        for text in ["'outer: print(1);", "'outer while true {\n}", "break 'outer"] {
+            let source = SourceFile::new("test.st", text.to_string());
+            assert_eq!(parse(&mut tokenize(&source, 0, text.len()).unwrap()).unwrap_err().code, 12, "{}", text);
+        }
+    }
+
+    #[test]
+    fn define_function() {
+        let source = SourceFile::new("test.st", "fn add(a: i32, b: Array<i64>) -> i32 {\n    return a;\n}\nfn log() {\n    return;\n}".to_string());
+        let mut tokens = tokenize(&source, 0, source.text.len()).unwrap();
+        let statements = parse(&mut tokens).unwrap();
+        match &statements[0] {
+            Statement::DefineFunction(function, span) => {
+                assert_eq!(function.name, "add");
+                assert_eq!(function.params, vec![("a".to_string(), Type::I32), ("b".to_string(), Type::Array(Box::new(Type::I64)))]);
+                assert_eq!(function.return_type, Some(Type::I32));
+                assert!(matches!(function.block[0], Statement::Return(Some(Expression::Variable(..)), _)));
+                assert_eq!(&source.text[span.start..span.end], "fn add(a: i32, b: Array<i64>) -> i32");
+            }
+            other => panic!("expected a function definition but found {:?}", other),
+        }
+        match &statements[1] {
+            Statement::DefineFunction(function, _) => {
+                assert_eq!(function.return_type, None);
+                assert!(matches!(function.block[0], Statement::Return(None, _)));
+            }
+            other => panic!("expected a function definition but found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn struct_declarations() {
+        let source = SourceFile::new("test.st", "struct Point { x: f64, y: Array<Point>, }".to_string());
+        let declared = parse_struct(&mut tokenize(&source, 0, source.text.len()).unwrap()).unwrap();
+        assert_eq!(declared.name, "Point");
+        assert_eq!(declared.fields, vec![("x".to_string(), Type::F64), ("y".to_string(), Type::Array(Box::new(Type::Struct("Point".to_string()))))]);
+        for (text, code) in [
+            ("struct point { x: f64 }", 12),
+            ("struct String { x: f64 }", 12),
+            ("struct P { }", 12),
+            ("struct P { x: f64, x: i32 }", 61),
+            ("struct P { x: f64 } y", 11),
+            ("struct P { x: thing }", 14),
+        ] {
+            let source = SourceFile::new("test.st", text.to_string());
+            assert_eq!(parse_struct(&mut tokenize(&source, 0, text.len()).unwrap()).unwrap_err().code, code, "{}", text);
+        }
+    }
+
+    #[test]
+    fn struct_literals_and_fields() {
+        assert_eq!(shape_of("Point { x: 1, y: a + 2, }.x"), "Point { x: 1, y: (a Add 2) }.x");
+        assert_eq!(shape_of("lines[0].from.x * 2"), "(lines[0].from.x Multiply 2)");
+        assert_eq!(shape_of("a.b[1]"), "a.b[1]");
+        //A capitalised condition followed by a block is not a struct literal.
+        let statements = statements_of("if a == B {
+    x = 1;
+}").unwrap();
+        assert!(matches!(&statements[0], Statement::If(Expression::Complete(..), ..)), "{:?}", statements[0]);
+        let statements = statements_of("p.x = 1;
+lines[i].to.y = p.x;").unwrap();
+        let shapes: Vec<String> = statements.iter().map(|statement| match statement {
+            Statement::ModifyElement(target, value, _) => format!("{} = {}", shape(target), shape(value)),
+            other => panic!("expected an element assignment but found {:?}", other),
+        }).collect();
+        assert_eq!(shapes, vec!["p.x = 1", "lines[i].to.y = p.x"]);
+        assert_eq!(value_of("let p: Point = Point { x: 1 y: 2 };").unwrap_err().code, 11);
+        assert_eq!(value_of("print(p.1);").unwrap_err().code, 12);
+        assert!(matches!(value_of("let p: Point = Point { x: 1 };"), Ok(Expression::Struct(..))));
+    }
+
+    #[test]
+    fn maps() {
+        assert_eq!(shape_of("{\"a\": 1, \"b\": x + 1,}"), "{\"a\": 1, \"b\": (x Add 1)}");
+        assert_eq!(shape_of("{}"), "{}");
+        assert_eq!(shape_of("{1: {2: m[\"k\"]}}[1]"), "{1: {2: m[\"k\"]}}[1]");
+        let statements = statements_of("let m: Map<String, Array<Map<i32, f64>>> = {};
+for (k, v) in m {
+}
+'outer: for k in m {
+}").unwrap();
+        let Statement::DefineVariable(_, _, ty, _) = &statements[0] else {
+            panic!("expected a definition but found {:?}", statements[0]);
+        };
+        assert_eq!(ty.to_string(), "Map<String, Array<Map<i32, f64>>>");
+        let Statement::ForEach(variables, Iterable::Array(map), ..) = &statements[1] else {
+            panic!("expected a loop over a map but found {:?}", statements[1]);
+        };
+        assert_eq!((variables, shape(map).as_str()), (&LoopVariables::Pair("k".to_string(), "v".to_string()), "m"));
+        assert!(matches!(&statements[2], Statement::ForEach(LoopVariables::Single(_), _, _, Some(_), _)));
+        //A block body is never a map literal.
+        let statements = statements_of("if a {
+    let x: i32 = 1;
+}
+while b {
+    'inner: while c {
+    }
+}").unwrap();
+        assert!(matches!((&statements[0], &statements[1]), (Statement::If(..), Statement::WhileLoop(..))));
+        for (text, code) in [
+            ("let m: Map<f64, i32> = {};", 64),
+            ("let m: Map<String> = {};", 14),
+            ("let a: Array<i32, i32> = [];", 14),
+            ("let m: Map<String, Foo<i32>> = {};", 14),
+            ("print({\"a\" 1});", 15),
+            ("print({\"a\": 1 \"b\": 2});", 11),
+            ("print({,});", 15),
+        ] {
+            assert_eq!(value_of(text).unwrap_err().code, code, "{}", text);
+        }
+        assert_eq!(statements_of("for (k, v m {\n}").unwrap_err().code, 12);
+    }
 }
@@ -0,0 +1,399 @@
+use std::{collections::{HashMap, VecDeque}, fs, io::{self, BufRead, Write}};
+use crate::{
+    builtins::{builtin_structs, lookup, Returns}, diagnostic::{Diagnostic, SourceFile}, interpreter::{collect_functions, evaluate_line, interpret, Calls, Functions, Scopes, Structs, Variables, VariableScopes},
+    load_dag, parse::{parse, parse_expression, parse_struct, Expression, Function, Statement, Struct}, thread_handler::WORKER_STACK_SIZE, token_block::import_variables, tokenizer::{tokenize, Token},
+    type_check::{check_program, check_statements, check_structs, expression_type, VariableTypes}
+};
+
+const REPL_FILE: &str = "<repl>";
+const HELP: &str = ":vars           list every variable
+:type <expr>    show the type of an expression without running it
+:ast <code>     show the parsed statements or expression
+:load <file>    run every block of a file in dependency order and keep their variables
+:reset          forget every variable and function
+:help           show this message
+:quit           leave the REPL";
+
+//What a line of input turned out to be.
+#[derive(Debug)]
+enum Input {
+    Statements(VecDeque<Statement>),
+    Expression(Expression),
+    Struct(Struct),
+}
+
+//The variables, functions and structs that live from one line of input to the next.
+pub struct Repl {
+    variables: Variables,
+    functions: HashMap<String, Function>,
+    structs: Structs,
+    //The text of the last file loaded, so errors raised inside it can quote the right line.
+    loaded: Option<SourceFile>,
+}
+
+impl Default for Repl {
+    fn default() -> Repl {
+        Repl { variables: Variables::new(), functions: HashMap::new(), structs: builtin_structs(), loaded: None }
+    }
+}
+
+//Reads statements from stdin until it closes or :quit is entered.
+//The session runs on a thread with the stack of a block, so deep recursion reaches the call depth limit instead of overflowing.
+pub fn run() {
+    let session = std::thread::Builder::new()
+        .stack_size(WORKER_STACK_SIZE)
+        .spawn(session)
+        .expect("failed to start the REPL thread");
+    let _ = session.join();
+}
+
+fn session() {
+    println!("Sarateese REPL, :help for commands");
+    let mut repl = Repl::default();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        let mut input = String::new();
+        //Keep reading while a block is still open.
+        loop {
+            print!("{}", if input.is_empty() { ">> " } else { ".. " });
+            let _ = io::stdout().flush();
+            match lines.next() {
+                Some(Ok(line)) => {
+                    input.push_str(&line);
+                    input.push('\n');
+                }
+                _ => return,
+            }
+            if !is_incomplete(&input) {
+                break;
+            }
+        }
+        if matches!(input.trim(), ":quit" | ":q") {
+            return;
+        }
+        match repl.eval(&input) {
+            Ok(Some(output)) => println!("{}", output),
+            Ok(None) => {}
+            Err(diagnostics) => {
+                for diagnostic in diagnostics {
+                    eprint!("{}", repl.render(&diagnostic, &input));
+                }
+            }
+        }
+    }
+}
+
+//Whether text has more opening braces than closing ones, ignoring braces inside strings and comments.
+fn is_incomplete(text: &str) -> bool {
+    let mut depth = 0;
+    for line in text.lines() {
+        let mut in_string = false;
+        let mut raw = false;
+        let mut previous = ' ';
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    raw = !in_string && previous == 'r';
+                    in_string = !in_string;
+                }
+                '\\' if in_string && !raw => {
+                    chars.next();
+                }
+                '/' if !in_string && chars.peek() == Some(&'/') => break,
+                '{' if !in_string => depth += 1,
+                '}' if !in_string => depth -= 1,
+                _ => {}
+            }
+            previous = c;
+        }
+    }
+    depth > 0
+}
+
+impl Repl {
+    //Runs one complete piece of input: a REPL command, statements, or an expression whose value is shown.
+    //Returns: the text to show the user, if any.
+    //Errors: every diagnostic raised while parsing, checking or running the input.
+    pub fn eval(&mut self, input: &str) -> Result<Option<String>, Vec<Diagnostic>> {
+        let trimmed = input.trim();
+        if let Some(command) = trimmed.strip_prefix(':') {
+            let (name, argument) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+            //Where the argument starts in input, so the spans of what it parses to point into the line render quotes.
+            let start = input.trim_end().len() - argument.trim().len();
+            return self.command(name, input, start);
+        }
+        match parse_input(input, 0).map_err(|error| vec![error])? {
+            Input::Statements(statements) => self.run_statements(statements).map(|_| None),
+            Input::Expression(expression) => self.run_expression(expression),
+            Input::Struct(declared) => self.declare(declared).map(|_| None),
+        }
+    }
+
+    fn command(&mut self, name: &str, input: &str, start: usize) -> Result<Option<String>, Vec<Diagnostic>> {
+        let argument = input[start..].trim_end();
+        let expects = |what: &str| vec![Diagnostic::new(44, format!("REPL command :{} expects {}", name, what))];
+        match name {
+            "vars" => {
+                let mut names: Vec<&String> = self.variables.keys().collect();
+                names.sort();
+                let lines: Vec<String> = names.iter().map(|name| {
+                    let (value, ty) = &self.variables[*name];
+                    format!("{}: {} = {}", name, ty, value)
+                }).collect();
+                Ok(Some(lines.join("\n")).filter(|text| !text.is_empty()))
+            }
+            "type" if !argument.is_empty() => {
+                let Input::Expression(expression) = parse_input(input, start).map_err(|error| vec![error])? else {
+                    return Err(expects("an expression"));
+                };
+                let functions = function_refs(&self.functions);
+                expression_type(&expression, self.variable_types(), &functions, &self.structs).map(Some).map_err(|error| vec![error])
+            }
+            "ast" if !argument.is_empty() => match parse_input(input, start).map_err(|error| vec![error])? {
+                Input::Statements(statements) => Ok(Some(format!("{:#?}", statements))),
+                Input::Expression(expression) => Ok(Some(format!("{:#?}", expression))),
+                Input::Struct(declared) => Ok(Some(format!("{:#?}", declared))),
+            },
+            "load" if !argument.is_empty() => self.load(argument).map(|_| None),
+            "reset" => {
+                *self = Repl::default();
+                Ok(None)
+            }
+            "help" => Ok(Some(HELP.to_string())),
+            "type" | "ast" => Err(expects("an expression")),
+            "load" => Err(expects("a file name")),
+            _ => Err(vec![Diagnostic::new(43, format!("Unknown REPL command :{}", name)).with_note("enter :help to see every command")]),
+        }
+    }
+
+    fn run_statements(&mut self, statements: VecDeque<Statement>) -> Result<(), Vec<Diagnostic>> {
+        //Functions are checked as a group first, so a line can define a function and call it.
+        let new_functions: Vec<Function> = collect_functions(&statements).map_err(|error| vec![error])?.into_values().cloned().collect();
+        let mut functions = function_refs(&self.functions);
+        for function in &new_functions {
+            functions.insert(function.name.clone(), function);
+        }
+        check_statements(&statements, self.variable_types(), &functions, &self.structs)?;
+        let calls = Calls::new(&functions, &self.structs);
+        in_scope(&mut self.variables, |variables| {
+            for statement in &statements {
+                evaluate_line(statement, variables, calls)?;
+            }
+            Ok(())
+        }).map_err(|error| vec![error])?;
+        for function in new_functions {
+            self.functions.insert(function.name.clone(), function);
+        }
+        Ok(())
+    }
+
+    fn run_expression(&mut self, expression: Expression) -> Result<Option<String>, Vec<Diagnostic>> {
+        //Calls that do not return anything, such as print, are run as statements instead of shown.
+        if let Expression::FunctionCall(name, args, span) = &expression {
+            let returns_nothing = match self.functions.get(name) {
+                Some(function) => function.return_type.is_none(),
+                None => lookup(name).is_some_and(|builtin| matches!(builtin.returns, Returns::Nothing)),
+            };
+            if returns_nothing {
+                let statement = Statement::FunctionCall(name.clone(), args.clone(), span.clone());
+                return self.run_statements(VecDeque::from([statement])).map(|_| None);
+            }
+        }
+        let functions = function_refs(&self.functions);
+        expression_type(&expression, self.variable_types(), &functions, &self.structs).map_err(|error| vec![error])?;
+        let value = in_scope(&mut self.variables, |variables| expression.evaluate(variables, Calls::new(&functions, &self.structs))).map_err(|error| vec![error])?;
+        Ok(Some(value.to_string()))
+    }
+
+    //Declares a struct for later lines, replacing any struct with the same name.
+    //Errors: If the struct is a builtin one or a field has a type that is not declared.
+    fn declare(&mut self, declared: Struct) -> Result<(), Vec<Diagnostic>> {
+        if builtin_structs().contains_key(&declared.name) {
+            return Err(vec![Diagnostic::new(58, format!("Struct {} is already defined", declared.name))
+                .with_span(declared.span)
+                .with_note(format!("{} is a builtin struct", declared.name))]);
+        }
+        let mut structs = self.structs.clone();
+        structs.insert(declared.name.clone(), declared);
+        let errors = check_structs(&structs);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        self.structs = structs;
+        Ok(())
+    }
+
+    //Runs every block of a file one after another, in dependency order, and keeps the variables they define.
+    //Variables from later blocks replace ones with the same name from earlier blocks.
+    fn load(&mut self, file_name: &str) -> Result<(), Vec<Diagnostic>> {
+        let text = fs::read_to_string(file_name)
+            .map_err(|err| vec![Diagnostic::new(4, format!("Error reading file {}: {}", file_name, err))])?;
+        let source = SourceFile::new(file_name, text);
+        self.loaded = Some(source.clone());
+        let dag = load_dag(&source, false)?;
+        let mut programs = check_program(&dag)?;
+        let mut exports: HashMap<String, Variables> = HashMap::new();
+        for id in &dag.order {
+            let statements = programs.remove(id).unwrap_or_default();
+            let in_block = |error: Diagnostic| vec![error.or_span(&dag.blocks[id].span).with_note(format!("raised in block {}", id))];
+            let imported = import_variables(&dag.blocks[id], &exports).map_err(in_block)?;
+            let variables = interpret(&statements, vec![imported], &dag.structs).map_err(in_block)?;
+            exports.insert(id.clone(), variables);
+        }
+        for id in &dag.order {
+            self.variables.extend(exports.remove(id).unwrap_or_default());
+        }
+        self.structs.extend(dag.structs);
+        Ok(())
+    }
+
+    //Renders a diagnostic, quoting the input or the loaded file depending on where it was raised.
+    pub fn render(&self, diagnostic: &Diagnostic, input: &str) -> String {
+        let source = match (&diagnostic.span, &self.loaded) {
+            (Some(span), Some(loaded)) if span.file == loaded.name => Some(loaded.text.as_str()),
+            (Some(span), _) if &*span.file == REPL_FILE => Some(input),
+            _ => None,
+        };
+        diagnostic.render(source)
+    }
+
+    fn variable_types(&self) -> VariableTypes {
+        self.variables.iter().map(|(name, (_, ty))| (name.clone(), ty.clone())).collect()
+    }
+}
+
+//Runs code with the REPL's variables as the top level scope, keeping what it defines there even if it fails part way.
+fn in_scope<T>(variables: &mut Variables, run: impl FnOnce(&mut VariableScopes) -> T) -> T {
+    let mut scopes = Scopes::new(std::mem::take(variables));
+    let result = run(&mut scopes);
+    *variables = scopes.into_top_level();
+    result
+}
+
+fn function_refs(functions: &HashMap<String, Function>) -> Functions<'_> {
+    functions.iter().map(|(name, function)| (name.clone(), function)).collect()
+}
+
+//Parses input as a struct declaration, statements, or a single expression if it is not valid statements.
+//Args: start: usize - where the code starts in input, which is after the name of a command such as :type.
+//Errors: the statement parse error when the input is neither.
+fn parse_input(input: &str, start: usize) -> Result<Input, Diagnostic> {
+    let source = SourceFile::new(REPL_FILE, input.to_string());
+    let mut tokens = tokenize(&source, start, input.len())?;
+    if tokens.front().map(|first| &first.token) == Some(&Token::Struct) {
+        return parse_struct(&mut tokens).map(Input::Struct);
+    }
+    let mut statement_tokens = tokens.clone();
+    let statement_error = match parse(&mut statement_tokens) {
+        Ok(statements) if statement_tokens.is_empty() => return Ok(Input::Statements(statements)),
+        Ok(_) => Diagnostic::new(11, format!("Unexpected token {:?}", statement_tokens[0].token)).with_span(statement_tokens[0].span.clone()),
+        Err(error) => error,
+    };
+    let mut expression_tokens = tokens;
+    match parse_expression(&mut expression_tokens, None) {
+        Ok(expression) if expression_tokens.is_empty() => Ok(Input::Expression(expression)),
+        _ => Err(statement_error),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{interpreter::Primitive, repl::{is_incomplete, Repl}};
+
+    fn eval(repl: &mut Repl, input: &str) -> Option<String> {
+        repl.eval(input).unwrap()
+    }
+
+    #[test]
+    fn variables_persist_between_lines() {
+        let mut repl = Repl::default();
+        assert_eq!(eval(&mut repl, "let x: i32 = 40;"), None);
+        assert_eq!(eval(&mut repl, "x = x + 1;"), None);
+        assert_eq!(eval(&mut repl, "x + 1"), Some("42".to_string()));
+        assert_eq!(eval(&mut repl, "x == 41;"), Some("true".to_string()));
+        assert_eq!(repl.variables["x"].0, Primitive::I32(41));
+    }
+
+    #[test]
+    fn functions_persist_between_lines() {
+        let mut repl = Repl::default();
+        eval(&mut repl, "fn double(n: i32) -> i32 {\n    return n * 2;\n}");
+        eval(&mut repl, "fn show(n: i32) {\n    print(n);\n}");
+        assert_eq!(eval(&mut repl, "double(4)"), Some("8".to_string()));
+        assert_eq!(eval(&mut repl, "show(4)"), None);
+    }
+
+    #[test]
+    fn commands() {
+        let mut repl = Repl::default();
+        eval(&mut repl, "let b: Bool = true;\nlet a: Array<i32> = [1, 2];");
+        assert_eq!(eval(&mut repl, ":vars"), Some("a: Array<i32> = [1, 2]\nb: Bool = true".to_string()));
+        assert_eq!(eval(&mut repl, ":type !b"), Some("Bool".to_string()));
+        assert_eq!(eval(&mut repl, ":type i64(1)"), Some("i64".to_string()));
+        assert_eq!(eval(&mut repl, ":type []"), Some("Array<_>".to_string()));
+        assert!(eval(&mut repl, ":ast 1 + 2").unwrap().starts_with("Complete("));
+        eval(&mut repl, ":reset");
+        assert_eq!(eval(&mut repl, ":vars"), None);
+        assert_eq!(repl.eval(":frobnicate").unwrap_err()[0].code, 43);
+        assert_eq!(repl.eval(":type").unwrap_err()[0].code, 44);
+        eval(&mut repl, "let x: i32 = 1;");
+        let error = &repl.eval(":type x * 2.0").unwrap_err()[0];
+        let span = error.span.as_ref().unwrap();
+        assert_eq!((span.col, &":type x * 2.0"[span.start..span.end]), (7, "x * 2.0"));
+    }
+
+    #[test]
+    fn errors_leave_state_unchanged() {
+        let mut repl = Repl::default();
+        eval(&mut repl, "let x: i32 = 1;");
+        assert_eq!(repl.eval("let y: i32 = \"a\";").unwrap_err()[0].code, 28);
+        assert_eq!(repl.eval("let = 1;").unwrap_err()[0].code, 12);
+        assert!(!repl.variables.contains_key("y"));
+    }
+
+    #[test]
+    fn load_file() {
+        let mut repl = Repl::default();
+        eval(&mut repl, ":load test_files/variable_locality_test.st");
+        assert_eq!(repl.variables["i"].0, Primitive::F32(2.1));
+    }
+
+    #[test]
+    fn structs() {
+        let mut repl = Repl::default();
+        eval(&mut repl, "struct Point {\n    x: i32,\n    y: i32,\n}");
+        eval(&mut repl, "let p: Point = Point { x: 1, y: 2 };");
+        eval(&mut repl, "p.y = p.x + 5;");
+        assert_eq!(eval(&mut repl, "p"), Some("Point { x: 1, y: 6 }".to_string()));
+        assert_eq!(eval(&mut repl, ":type p.x"), Some("i32".to_string()));
+        assert_eq!(repl.eval("struct Line { from: Spot }").unwrap_err()[0].code, 14);
+        assert_eq!(repl.eval("Line { from: p }").unwrap_err()[0].code, 14);
+        assert_eq!(eval(&mut repl, "parse_i32(\"7\").value"), Some("7".to_string()));
+        assert_eq!(repl.eval("struct ParsedI32 { ok: Bool }").unwrap_err()[0].code, 58);
+    }
+
+    #[test]
+    fn builtins() {
+        let mut repl = Repl::default();
+        assert_eq!(eval(&mut repl, "print(1)"), None);
+        assert_eq!(eval(&mut repl, "rand_int(4, 4)"), Some("4".to_string()));
+        assert_eq!(eval(&mut repl, "rand_int(4, 4);"), None);
+        eval(&mut repl, "let m: Map<String, i32> = {\"a\": 1};");
+        assert_eq!(repl.eval("remove(m, \"b\");").unwrap_err()[0].code, 63);
+        assert_eq!(eval(&mut repl, "m"), Some("{a: 1}".to_string()));
+        assert_eq!(repl.eval("let x: i32 = print(1);").unwrap_err()[0].code, 30);
+    }
+
+    #[test]
+    fn continuation() {
+        assert!(is_incomplete("if true {\n"));
+        assert!(is_incomplete("while x {\n    if y {\n    }\n"));
+        assert!(!is_incomplete("let s: String = \"{\";"));
+        assert!(!is_incomplete("let x: i32 = 1; // {"));
+        assert!(!is_incomplete("if true {\n}\n"));
+        assert!(!is_incomplete("let s: String = r\"C:\\\";"));
+        assert!(is_incomplete("if s == \"\\\"}\" {"));
+    }
+}
@@ -1,110 +1,333 @@
-use std::{collections::HashMap, sync::{Arc, Mutex, mpsc}, time::Duration};
-use chrono::Local;
-use crate::{interpreter::{interpret, Primitive, Type}, parse::parse, token_block::TokenBlock};
+use std::{any::Any, cell::Cell, collections::{HashMap, HashSet, VecDeque}, panic::{self, AssertUnwindSafe}, sync::{Arc, Mutex, Once, mpsc}, thread};
+use chrono::{DateTime, Local};
+use crate::{dag::Dag, diagnostic::Diagnostic, interpreter::{interpret, Primitive, Structs, Type}, parse::Statement, token_block::import_variables};
 
 const PURPLE: &str = "\x1b[35m";
 const RESET: &str = "\x1b[0m";
+//Interpreted calls recurse on the Rust stack, so workers get enough room for the interpreter's call depth limit.
+pub(crate) const WORKER_STACK_SIZE: usize = 64 * 1024 * 1024;
 
-pub fn parallel(dag: HashMap<String, TokenBlock>, verbose: bool) {
-    let master_variable_map: Arc<Mutex<HashMap<String, HashMap<String, (Primitive, Type)>>>> =
-        Arc::new(Mutex::new(HashMap::new()));
-    let mut in_deg: HashMap<String, usize> = HashMap::new();
-    let mut children_map: HashMap<String, Vec<String>> = HashMap::new();
-    for (id, block) in dag.iter() {
-        in_deg.insert(id.clone(), block.requires.len());
-        children_map.insert(id.clone(), Vec::new());
-    }
-    for (_id, block) in dag.iter() {
-        for required_id in block.requires.keys() {
-            children_map.entry(required_id.clone()).or_insert_with(Vec::new).push(block.id.clone());
-        }
+type BlockVariables = HashMap<String, HashMap<String, (Primitive, Type)>>;
+type Variables = HashMap<String, (Primitive, Type)>;
+
+//A ready block handed to a worker along with the variables it inherits.
+struct Job {
+    id: String,
+    statements: VecDeque<Statement>,
+    inherited: Vec<Variables>,
+}
+
+//Sent back to the scheduler by a worker once a block has run.
+struct Completion {
+    id: String,
+    result: Result<Variables, Diagnostic>,
+}
+
+//What the scheduler does once a block has failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailurePolicy {
+    //Stop starting new blocks and stop waiting on the ones still running.
+    FailFast,
+    //Keep running every block that does not depend on a failed one.
+    KeepGoing,
+}
+
+//The outcome of every block in a run.
+#[derive(Debug, Default)]
+pub struct RunReport {
+    pub succeeded: Vec<String>,
+    pub failed: Vec<(String, Diagnostic)>,
+    //Blocks that never started because a block they depend on failed, or because the run failed fast.
+    pub skipped: Vec<String>,
+    //Blocks that were still running when the run failed fast.
+    pub cancelled: Vec<String>,
+}
+
+impl RunReport {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
     }
-    let in_degree: Arc<Mutex<HashMap<String, usize>>> = Arc::new(Mutex::new(in_deg));
-    let children: Arc<Mutex<HashMap<String, Vec<String>>>> = Arc::new(Mutex::new(children_map));
-    let dag = Arc::new(dag);
-    let total_tasks = dag.len();
-    let (tx, rx) = mpsc::channel();
-    {
-        let in_degree_lock = in_degree.lock().unwrap();
-        for (id, &deg) in in_degree_lock.iter() {
-            if deg == 0 {
-                tx.send(id.clone()).unwrap();
+
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} succeeded, {} failed, {} skipped", self.succeeded.len(), self.failed.len(), self.skipped.len());
+        if !self.cancelled.is_empty() {
+            summary += &format!(", {} cancelled", self.cancelled.len());
+        }
+        let failed: Vec<&str> = self.failed.iter().map(|(id, _)| id.as_str()).collect();
+        for (label, ids) in [("failed", failed), ("skipped", self.skipped.iter().map(String::as_str).collect()), ("cancelled", self.cancelled.iter().map(String::as_str).collect())] {
+            if !ids.is_empty() {
+                summary += &format!("\n  {}: {}", label, ids.join(", "));
             }
         }
+        summary
     }
-    let tasks_done: Arc<Mutex<usize>> = Arc::new(Mutex::new(0));
+}
+
+//The number of workers to use when --jobs is not given.
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+//Runs the parsed statements of every block on a pool of `jobs` worker threads as soon as the blocks it requires have finished.
+//A block that fails or panics never unblocks its dependents, they are reported as skipped.
+//Returns: RunReport - which blocks succeeded, failed, were skipped or were cancelled.
+pub fn parallel(dag: Dag, mut programs: HashMap<String, VecDeque<Statement>>, jobs: usize, policy: FailurePolicy, verbose: bool) -> RunReport {
     let global_start = Local::now();
-    while *tasks_done.lock().unwrap() < total_tasks {
-        match rx.recv_timeout(Duration::from_millis(100)) {
-            Ok(task_id) => {
-                let tx_inner = tx.clone();
-                let master_var_map_clone = Arc::clone(&master_variable_map);
-                let in_degree_clone = Arc::clone(&in_degree);
-                let children_clone = Arc::clone(&children);
-                let dag_clone = Arc::clone(&dag);
-                let tasks_done_clone = Arc::clone(&tasks_done);
-                std::thread::spawn(move || {
-                    let start_time = Local::now();
-                    if verbose {
-                        println!("Block {} starting at {}", task_id, start_time.format("%H:%M:%S"));
-                    }
-                    let block = dag_clone.get(&task_id).unwrap().clone();
-                    let mut statements = parse(&mut block.tokens.clone());
-                    let mut inherited_variable_map = Vec::new();
-                    for req_id in block.requires.keys() {
-                        if block.requires[req_id].is_empty() {
-                            continue;
-                        }
-                        if let Some(map) = master_var_map_clone.lock().unwrap().get(req_id) {
-                            inherited_variable_map.push(map.clone());
-                        }
-                    }
-                    let local_variable_map = interpret(&mut statements, inherited_variable_map);
-                    {
-                        let mut master = master_var_map_clone.lock().unwrap();
-                        master.insert(task_id.clone(), local_variable_map);
-                    }
-                    let now = Local::now();
-                    if verbose {
-                        let elapsed_ms = now.signed_duration_since(start_time).num_microseconds().unwrap_or(0) as f64 / 1000.0;
-                        if elapsed_ms > 1000.0 {
-                            let elapsed_sec = elapsed_ms / 1000.0;
-                            println!("Block {} finished at {} ({:.3}s)", task_id, now.format("%H:%M:%S"), elapsed_sec);
-                        } else {
-                            println!("Block {} finished at {} ({:.3}ms)", task_id, now.format("%H:%M:%S"), elapsed_ms);
-                        }
-                    }
-                    {
-                        let mut in_deg_lock = in_degree_clone.lock().unwrap();
-                        let children_map = children_clone.lock().unwrap();
-                        if let Some(child_ids) = children_map.get(&task_id) {
-                            for child in child_ids {
-                                if let Some(count) = in_deg_lock.get_mut(child) {
-                                    *count -= 1;
-                                    if *count == 0 {
-                                        tx_inner.send(child.clone()).unwrap();
-                                    }
-                                }
-                            }
+    let (job_tx, job_rx) = mpsc::channel::<Job>();
+    let (done_tx, done_rx) = mpsc::channel::<Completion>();
+    let job_rx = Arc::new(Mutex::new(job_rx));
+    let structs = Arc::new(dag.structs.clone());
+    let workers: Vec<_> = (0..jobs.clamp(1, dag.len().max(1)))
+        .map(|_| {
+            let job_rx = Arc::clone(&job_rx);
+            let done_tx = done_tx.clone();
+            let structs = Arc::clone(&structs);
+            thread::Builder::new()
+                .stack_size(WORKER_STACK_SIZE)
+                .spawn(move || worker(job_rx, done_tx, structs, verbose))
+                .expect("failed to spawn worker thread")
+        })
+        .collect();
+    drop(done_tx);
+
+    let mut in_degree: HashMap<String, usize> = dag.blocks.iter().map(|(id, block)| (id.clone(), block.requires.len())).collect();
+    let mut ready: VecDeque<String> = dag.order.iter().filter(|id| in_degree[*id] == 0).cloned().collect();
+    let mut variables: BlockVariables = HashMap::new();
+    let mut report = RunReport::default();
+    //Only hand out as many jobs as there are workers, so a queued job is always a running job.
+    let mut in_flight: HashSet<String> = HashSet::new();
+    loop {
+        let failing_fast = policy == FailurePolicy::FailFast && !report.is_success();
+        if !failing_fast {
+            while in_flight.len() < workers.len() {
+                let Some(id) = ready.pop_front() else {
+                    break;
+                };
+                let block = &dag.blocks[&id];
+                let inherited = match import_variables(block, &variables) {
+                    Ok(inherited) => inherited,
+                    Err(diagnostic) => {
+                        report.failed.push((id.clone(), diagnostic.with_note(format!("raised in block {}", id))));
+                        if policy == FailurePolicy::FailFast {
+                            break;
                         }
+                        continue;
                     }
-                    {
-                        let mut done = tasks_done_clone.lock().unwrap();
-                        *done += 1;
+                };
+                in_flight.insert(id.clone());
+                let statements = programs.remove(&id).unwrap_or_default();
+                job_tx.send(Job { id, statements, inherited: vec![inherited] }).unwrap();
+            }
+        }
+        if in_flight.is_empty() {
+            break;
+        }
+        if failing_fast {
+            //The workers cannot be interrupted, so leave them to finish on their own.
+            report.cancelled = dag.order.iter().filter(|id| in_flight.contains(*id)).cloned().collect();
+            break;
+        }
+        //Workers catch panics, so every job sends exactly one completion.
+        let Completion { id, result } = done_rx.recv().expect("worker pool hung up");
+        in_flight.remove(&id);
+        match result {
+            Ok(local_variables) => {
+                variables.insert(id.clone(), local_variables);
+                report.succeeded.push(id.clone());
+                for child in &dag.children[&id] {
+                    let count = in_degree.get_mut(child).unwrap();
+                    *count -= 1;
+                    if *count == 0 {
+                        ready.push_back(child.clone());
                     }
-                });
+                }
             }
-            Err(_) => { }
+            Err(diagnostic) => {
+                let diagnostic = diagnostic.or_span(&dag.blocks[&id].span).with_note(format!("raised in block {}", id));
+                report.failed.push((id, diagnostic));
+            }
+        }
+    }
+    drop(job_tx);
+    if report.cancelled.is_empty() {
+        for worker in workers {
+            worker.join().unwrap();
         }
     }
-    let global_end = Local::now();
-    let elapsed_ms = global_end.signed_duration_since(global_start).num_microseconds().unwrap_or(0) as f64 / 1000.0;
+    let finished: HashSet<&String> = report.succeeded.iter()
+        .chain(report.failed.iter().map(|(id, _)| id))
+        .chain(report.cancelled.iter())
+        .collect();
+    report.skipped = dag.order.iter().filter(|id| !finished.contains(id)).cloned().collect();
     if verbose {
-        if elapsed_ms > 1000.0 {
-            let elapsed_sec = elapsed_ms / 1000.0;
-            println!("{}Finished execution in {:.3}s{}", PURPLE, elapsed_sec, RESET);
-        } else {
-            println!("{}Finished execution in {:.3}ms{}", PURPLE, elapsed_ms, RESET);
+        println!("{}Finished execution in {}{}", PURPLE, format_elapsed(global_start, Local::now()), RESET);
+    }
+    report
+}
+
+//Pulls jobs off the shared queue until the scheduler closes it.
+fn worker(jobs: Arc<Mutex<mpsc::Receiver<Job>>>, done: mpsc::Sender<Completion>, structs: Arc<Structs>, verbose: bool) {
+    loop {
+        //The lock is only held while waiting for the next job, never while running one.
+        let job = jobs.lock().unwrap().recv();
+        let Ok(Job { id, statements, inherited }) = job else {
+            return;
+        };
+        let start_time = Local::now();
+        if verbose {
+            println!("Block {} starting at {}", id, start_time.format("%H:%M:%S"));
+        }
+        let result = catch_panic(&id, || interpret(&statements, inherited, &structs));
+        if verbose && result.is_ok() {
+            let now = Local::now();
+            println!("Block {} finished at {} ({})", id, now.format("%H:%M:%S"), format_elapsed(start_time, now));
         }
+        if done.send(Completion { id, result }).is_err() {
+            return;
+        }
+    }
+}
+
+thread_local! {
+    //Whether this thread is running a block inside catch_panic, which reports a panic as a diagnostic instead.
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+//Runs a block, turning a panic inside it into a diagnostic so one broken block cannot take down its worker.
+fn catch_panic<T>(id: &str, run: impl FnOnce() -> Result<T, Diagnostic>) -> Result<T, Diagnostic> {
+    quiet_caught_panics();
+    CATCHING.with(|catching| catching.set(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(run));
+    CATCHING.with(|catching| catching.set(false));
+    match result {
+        Ok(result) => result,
+        Err(payload) => Err(Diagnostic::new(26, format!("Block {} panicked: {}", id, panic_message(payload.as_ref())))
+            .with_note("this is a bug in the interpreter, not in the program")),
+    }
+}
+
+//Installs a panic hook, once, that stays silent for panics catch_panic turns into diagnostics, so the rendered diagnostic is the only report.
+//Any other panic is still printed by the hook that was there before.
+fn quiet_caught_panics() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CATCHING.with(Cell::get) {
+                previous(info);
+            }
+        }));
+    });
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+fn format_elapsed(start: DateTime<Local>, end: DateTime<Local>) -> String {
+    let elapsed_ms = end.signed_duration_since(start).num_microseconds().unwrap_or(0) as f64 / 1000.0;
+    if elapsed_ms > 1000.0 {
+        format!("{:.3}s", elapsed_ms / 1000.0)
+    } else {
+        format!("{:.3}ms", elapsed_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, collections::HashSet};
+    use crate::{dag::build_dag, diagnostic::{Diagnostic, SourceFile}, type_check::check_program, thread_handler::{catch_panic, parallel, FailurePolicy, RunReport, CATCHING}, token_block::{extract_block_meta, split_blocks, TokenBlock}, tokenizer::tokenize};
+
+    fn run(text: &str, jobs: usize) -> Result<RunReport, Diagnostic> {
+        run_with(text, jobs, FailurePolicy::FailFast)
+    }
+
+    fn run_with(text: &str, jobs: usize, policy: FailurePolicy) -> Result<RunReport, Diagnostic> {
+        let source = SourceFile::new("test.st", text.to_string());
+        let mut token_blocks = HashSet::new();
+        for (offset, block) in split_blocks(&source.text) {
+            let meta = extract_block_meta(&source, offset, &block)?;
+            let tokens = tokenize(&source, meta.content_offset, meta.content_offset + meta.content.len())?;
+            token_blocks.insert(TokenBlock::new(meta.id, meta.requires, tokens, meta.span));
+        }
+        let dag = build_dag(&token_blocks)?;
+        let programs = check_program(&dag).map_err(|mut errors| errors.remove(0))?;
+        Ok(parallel(dag, programs, jobs, policy, false))
+    }
+
+    #[test]
+    fn more_blocks_than_workers() {
+        let text: String = (0..50).map(|i| format!("block b{} {{\n    let x: i32 = {};\n}}\n", i, i)).collect();
+        let report = run(&text, 2).unwrap();
+        assert_eq!(report.succeeded.len(), 50);
+    }
+
+    #[test]
+    fn single_worker_runs_chain() {
+        let text = "block a {\n    let x: i32 = 1;\n}\nblock b requires [a] {\n}\nblock c requires [b] {\n}\n";
+        let report = run(text, 1).unwrap();
+        assert_eq!(report.succeeded, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn failure_is_reported() {
+        let text = "block a {\n    let x: i32 = 1 / 0;\n}\nblock b requires [a] {\n}\n";
+        let report = run(text, 4).unwrap();
+        let (id, error) = &report.failed[0];
+        assert_eq!(id, "a");
+        assert_eq!(error.code, 19);
+        assert!(error.notes.iter().any(|note| note == "raised in block a"));
+        assert_eq!(report.skipped, vec!["b"]);
+    }
+
+    #[test]
+    fn keep_going_skips_only_dependents() {
+        let text = "block a {\n    let x: i32 = 1 / 0;\n}\nblock b requires [a] {\n}\nblock c requires [b] {\n}\nblock d {\n}\nblock e requires [d] {\n}\n";
+        let report = run_with(text, 1, FailurePolicy::KeepGoing).unwrap();
+        assert_eq!(report.succeeded, vec!["d", "e"]);
+        assert_eq!(report.skipped, vec!["b", "c"]);
+        assert_eq!(report.summary(), "2 succeeded, 1 failed, 2 skipped\n  failed: a\n  skipped: b, c");
+    }
+
+    #[test]
+    fn fail_fast_skips_everything_not_started() {
+        let text = "block a {\n    let x: i32 = 1 / 0;\n}\nblock b {\n}\nblock c {\n}\n";
+        let report = run_with(text, 1, FailurePolicy::FailFast).unwrap();
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.skipped, vec!["b", "c"]);
+    }
+
+    #[test]
+    fn panics_become_diagnostics() {
+        let error = catch_panic::<()>("a", || panic!("boom")).unwrap_err();
+        assert_eq!(error.code, 26);
+        assert_eq!(error.message, "Block a panicked: boom");
+        assert!(!CATCHING.with(Cell::get), "panics outside a block are still reported");
+    }
+
+    #[test]
+    fn imports_listed_variables() {
+        let text = "block a {\n    let x: i32 = 1;\n    let y: i32 = 2;\n}\nblock b requires [a[x as z]] {\n    let w: i32 = z + 1;\n}\nblock c requires [a[*]] {\n    let w: i32 = x + y;\n}\n";
+        let report = run(text, 2).unwrap();
+        assert_eq!(report.succeeded.len(), 3);
+        let error = run("block a {\n    let x: i32 = 1;\n}\nblock b requires [a[x]] {\n    let w: i32 = y;\n}\n", 2).unwrap_err();
+        assert_eq!(error.code, 6);
+        let error = run("block a {\n    let x: i32 = 1;\n}\nblock b requires [a[y]] {\n}\n", 2).unwrap_err();
+        assert_eq!(error.code, 35);
+    }
+
+    #[test]
+    fn imports_are_not_passed_on() {
+        let text = "block a {\n    let x: i32 = 1;\n}\nblock b requires [a[x]] {\n    let y: i32 = x + 1;\n}\nblock c requires [a[*], b[*]] {\n    let z: i32 = x + y;\n}\n";
+        let report = run(text, 2).unwrap();
+        assert_eq!(report.succeeded, vec!["a", "b", "c"]);
+        let error = run("block a {\n    let x: i32 = 1;\n}\nblock b requires [a[x]] {\n}\nblock c requires [b[x]] {\n}\n", 2).unwrap_err();
+        assert_eq!(error.code, 35);
     }
 }
@@ -1,23 +1,96 @@
 use regex::Regex;
 use std::{collections::{HashMap, VecDeque}, hash::{Hash, Hasher}};
-use crate::tokenizer::Token;
+use crate::{diagnostic::{Diagnostic, SourceFile, Span}, tokenizer::SpannedToken};
 
-const RESET: &str = "\x1b[0m";
-const RED: &str = "\x1b[31m";
+//One entry in the variable list of a requires, such as `a[x]`, `a[x as y]` or `a[*]`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Import {
+    //Every variable of the required block, under its own name.
+    All(Span),
+    //A single variable of the required block, visible as alias.
+    Variable { name: String, alias: String, span: Span },
+}
+
+impl Import {
+    pub fn span(&self) -> &Span {
+        match self {
+            Import::All(span) | Import::Variable { span, .. } => span,
+        }
+    }
+}
+
+//Collects the variables a block imports from the blocks it requires.
+//Args: exports - the variables defined by each finished block. Blocks missing from it export nothing.
+//Returns: the imported variables, keyed by the name they are visible as.
+//Errors: If a listed variable was not defined by its block, or two imports bind the same name.
+pub fn import_variables<T: Clone>(block: &TokenBlock, exports: &HashMap<String, HashMap<String, T>>) -> Result<HashMap<String, T>, Diagnostic> {
+    let no_exports = HashMap::new();
+    //Where each imported name came from, as (block, variable), so conflicts can name both sides.
+    let mut sources: HashMap<String, (&str, String)> = HashMap::new();
+    let mut imported = HashMap::new();
+    let mut required_ids: Vec<&String> = block.requires.keys().collect();
+    required_ids.sort();
+    for required_id in required_ids {
+        let exported = exports.get(required_id).unwrap_or(&no_exports);
+        for import in &block.requires[required_id] {
+            for (name, alias) in import_names(required_id, import, exported)? {
+                let source = (required_id.as_str(), name.clone());
+                if let Some(first) = sources.get(&alias) {
+                    if *first != source {
+                        return Err(Diagnostic::new(36, format!("Variable {} is imported from both block {} and block {}", alias, first.0, required_id))
+                            .with_span(import.span().clone())
+                            .with_note(format!("rename one of them with `as`, for example `{}[{} as {}_{}]`", required_id, name, required_id, name)));
+                    }
+                }
+                imported.insert(alias.clone(), exported[&name].clone());
+                sources.insert(alias, source);
+            }
+        }
+    }
+    Ok(imported)
+}
+
+//Returns: the (name, alias) pairs one import binds, in a fixed order.
+fn import_names<T>(required_id: &str, import: &Import, exported: &HashMap<String, T>) -> Result<Vec<(String, String)>, Diagnostic> {
+    match import {
+        Import::All(_) => {
+            let mut names: Vec<(String, String)> = exported.keys().map(|name| (name.clone(), name.clone())).collect();
+            names.sort();
+            Ok(names)
+        }
+        Import::Variable { name, alias, span } => {
+            if !exported.contains_key(name) {
+                return Err(Diagnostic::new(35, format!("Block {} does not define variable {}", required_id, name))
+                    .with_span(span.clone()));
+            }
+            Ok(vec![(name.clone(), alias.clone())])
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct TokenBlock {
-    pub requires: HashMap<String, Vec<String>>,
+    pub requires: HashMap<String, Vec<Import>>,
     pub id: String,
-    pub tokens: VecDeque<Token>
+    pub tokens: VecDeque<SpannedToken>,
+    pub span: Span,
 }
 
 impl TokenBlock {
-    pub fn new(id: String, requires: HashMap<String, Vec<String>>, tokens: VecDeque<Token>) -> Self {
-        TokenBlock { requires, id, tokens }
+    pub fn new(id: String, requires: HashMap<String, Vec<Import>>, tokens: VecDeque<SpannedToken>, span: Span) -> Self {
+        TokenBlock { requires, id, tokens, span }
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockMeta {
+    pub id: String,
+    pub requires: HashMap<String, Vec<Import>>,
+    pub content: String,
+    pub content_offset: usize,
+    pub span: Span,
+}
+
 impl PartialEq for TokenBlock {
     fn eq(&self, other: &Self) -> bool {
         self.id == other.id
@@ -32,63 +105,268 @@ impl Hash for TokenBlock {
     }
 }
 
-pub fn split_blocks(text: &str) -> Vec<String> {
-    let re = Regex::new(r"(block\s+[A-Za-z_][A-Za-z0-9_]*\s*(?:requires\s*\[.*?\])?\s*\{)").unwrap();
-    let mut results = Vec::new();
-    let mut last_end = 0;
-    for cap in re.find_iter(text) {
-        let start = cap.start();
-        if start > last_end {
-            results.push(text[last_end..start].to_string());
+//Splits the raw file text into chunks that each start with a block header or a struct declaration.
+//Headers inside strings, comments or the body of another block do not start a chunk, and text before the first header is only kept if it is more than whitespace and comments.
+//Returns: Vec<(usize, String)> - the byte offset of each chunk in the file and its text.
+pub fn split_blocks(text: &str) -> Vec<(usize, String)> {
+    let re = Regex::new(r"^(block\s+[A-Za-z_][A-Za-z0-9_]*\s*(?:requires\s*\[.*?\])?\s*\{|struct\s+[A-Za-z_][A-Za-z0-9_]*\s*\{)").unwrap();
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        if let Some(end) = literal_end(text, i) {
+            i = end;
+            continue;
         }
-        last_end = start;
+        let header = re.find(&text[i..]).filter(|_| !starts_word(text, i));
+        match header {
+            Some(header) => {
+                starts.push(i);
+                //An unclosed block is reported by extract_block_meta, and any headers after it still start chunks.
+                i = closing_brace(text, i + header.end()).map_or(i + header.end(), |close| close + 1);
+            }
+            None => i += text[i..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    let mut results = Vec::new();
+    let first = starts.first().copied().unwrap_or(text.len());
+    if trailing_text(text, 0).is_some_and(|position| position < first) {
+        results.push((0, text[..first].to_string()));
     }
-    if last_end < text.len() {
-        results.push(text[last_end..].to_string());
+    for (n, start) in starts.iter().enumerate() {
+        let end = starts.get(n + 1).copied().unwrap_or(text.len());
+        results.push((*start, text[*start..end].to_string()));
     }
     results
 }
 
-pub fn extract_block_meta(block_text: &str) -> (String, HashMap<String, Vec<String>>, String) {
+//Whether the identifier character before i means a header at i is really the end of a longer word, as in mystruct.
+fn starts_word(text: &str, i: usize) -> bool {
+    text[..i].chars().next_back().is_some_and(|c| c.is_alphanumeric() || c == '_')
+}
+
+//Parses the header of a block and extracts the text between its braces.
+//Args: source: &SourceFile - the file the block came from, offset: usize - where block_text starts in the file.
+//Returns: BlockMeta - the block id, its requirements, its body with its offset in the file and the span of its id.
+pub fn extract_block_meta(source: &SourceFile, offset: usize, block_text: &str) -> Result<BlockMeta, Diagnostic> {
     let header_re = Regex::new(r"(?s)^(block\s+([A-Za-z_][A-Za-z0-9_]*)\s*(?:requires\s*\[(.*?)\])?\s*\{)").unwrap();
-    let header_caps = header_re.captures(block_text).expect(&format!("{}Error[7]: Invalid block format{}", RED, RESET));
-    let block_id = header_caps.get(2).unwrap().as_str().to_string();
-    let req_str = header_caps.get(3).map(|m| m.as_str()).unwrap_or("");
-    let mut requirements = HashMap::new();
-    if !req_str.trim().is_empty() {
-        let req_item_re = Regex::new(r"([A-Za-z_][A-Za-z0-9_]*)(?:\s*\[\s*(.*?)\s*\])?").unwrap();
-        for cap in req_item_re.captures_iter(req_str) {
-            let req_id = cap.get(1).unwrap().as_str().to_string();
-            let var_list_str = cap.get(2).map(|m| m.as_str()).unwrap_or("");
-            let var_names: Vec<String> = if var_list_str.trim().is_empty() {
-                Vec::new()
-            } else {
-                var_list_str.split(',')
-                    .map(|s| s.trim().to_string())
-                    .collect()
-            };
-            requirements.insert(req_id, var_names);
+    let leading = block_text.len() - block_text.trim_start().len();
+    let header_caps = match header_re.captures(block_text) {
+        Some(caps) => caps,
+        None => {
+            let line_end = block_text[leading..].find('\n').map(|i| leading + i).unwrap_or(block_text.len());
+            return Err(Diagnostic::new(7, "Invalid block format")
+                .with_span(source.span(offset + leading, offset + line_end))
+                .with_note("code must be inside a block such as `block name { ... }`"));
         }
-    }
+    };
+    let id_match = header_caps.get(2).unwrap();
+    let block_id = id_match.as_str().to_string();
+    let span = source.span(offset + id_match.start(), offset + id_match.end());
+    let requirements = match header_caps.get(3) {
+        Some(req_match) => parse_requirements(source, offset + req_match.start(), req_match.as_str())?,
+        None => HashMap::new(),
+    };
     let start_index = header_caps.get(0).unwrap().end();
+    let Some(end_index) = closing_brace(block_text, start_index) else {
+        return Err(Diagnostic::new(9, format!("Unmatched braces in block {}", block_id))
+            .with_span(source.span(offset + start_index - 1, offset + start_index)));
+    };
+    if let Some(position) = trailing_text(block_text, end_index + 1) {
+        return Err(unexpected_text(source, offset, block_text, position, &format!("block {}", block_id)));
+    }
+    let body = &block_text[start_index..end_index];
+    let content_offset = offset + start_index + (body.len() - body.trim_start().len());
+    let content = body.trim().to_string();
+    Ok(BlockMeta { id: block_id, requires: requirements, content, content_offset, span })
+}
+
+//Finds the struct declaration a chunk from split_blocks starts with, if it starts with one.
+//Args: offset: usize - where text starts in the file.
+//Returns: the start and end of `struct Name { ... }` in the file, or None if the chunk is not a struct.
+//Errors: If the braces of the struct are not closed.
+pub fn extract_struct(source: &SourceFile, offset: usize, text: &str) -> Result<Option<(usize, usize)>, Diagnostic> {
+    let header_re = Regex::new(r"^\s*struct\s+([A-Za-z_][A-Za-z0-9_]*)\s*\{").unwrap();
+    let Some(header_caps) = header_re.captures(text) else {
+        return Ok(None);
+    };
+    let start = text.len() - text.trim_start().len();
+    let open = header_caps.get(0).unwrap().end();
+    match closing_brace(text, open) {
+        Some(close) => match trailing_text(text, close + 1) {
+            Some(position) => Err(unexpected_text(source, offset, text, position, &format!("struct {}", &header_caps[1]))),
+            None => Ok(Some((offset + start, offset + close + 1))),
+        },
+        None => Err(Diagnostic::new(9, format!("Unmatched braces in struct {}", &header_caps[1]))
+            .with_span(source.span(offset + open - 1, offset + open))),
+    }
+}
+
+//Returns: the index of the } that closes the { just before start, None if it is never closed.
+//Braces inside strings and comments are skipped.
+fn closing_brace(text: &str, start: usize) -> Option<usize> {
     let mut depth = 1;
-    let mut end_index = start_index;
-    for (i, c) in block_text[start_index..].char_indices() {
-        if c == '{' {
-            depth += 1;
-        } else if c == '}' {
-            depth -= 1;
-            if depth == 0 {
-                end_index = start_index + i;
-                break;
+    let mut i = start;
+    while i < text.len() {
+        if let Some(end) = literal_end(text, i) {
+            i = end;
+            continue;
+        }
+        let c = text[i..].chars().next()?;
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += c.len_utf8();
+    }
+    None
+}
+
+//Returns: the index just after the string, raw string or comment that starts at i, or None if none starts there.
+//One that is never closed runs to the end of the text, where the tokenizer reports it.
+fn literal_end(text: &str, i: usize) -> Option<usize> {
+    let rest = &text[i..];
+    if rest.starts_with("//") {
+        return Some(rest.find('\n').map_or(text.len(), |end| i + end));
+    }
+    if let Some(comment) = rest.strip_prefix("/*") {
+        return Some(comment.find("*/").map_or(text.len(), |end| i + 2 + end + 2));
+    }
+    if rest.starts_with('"') {
+        let bytes = rest.as_bytes();
+        let mut j = 1;
+        while j < bytes.len() {
+            match bytes[j] {
+                b'\\' => j += 2,
+                b'"' => return Some(i + j + 1),
+                _ => j += 1,
             }
         }
+        return Some(text.len());
     }
-    if depth != 0 {
-        panic!("Unmatched braces in block");
+    let hashes = rest.strip_prefix('r')?.bytes().take_while(|c| *c == b'#').count();
+    if starts_word(text, i) || rest.as_bytes().get(1 + hashes) != Some(&b'"') {
+        return None;
     }
-    let content = block_text[start_index..end_index].trim().to_string();
-    (block_id, requirements, content)
+    let closing = format!("\"{}", "#".repeat(hashes));
+    Some(rest[2 + hashes..].find(&closing).map_or(text.len(), |end| i + 2 + hashes + end + closing.len()))
+}
+
+//Returns: where the first text other than whitespace and comments is at or after start, None if there is none.
+fn trailing_text(text: &str, start: usize) -> Option<usize> {
+    let mut i = start;
+    while i < text.len() {
+        let rest = &text[i..];
+        if rest.starts_with("//") || rest.starts_with("/*") {
+            i = literal_end(text, i).unwrap_or(text.len());
+        } else if let Some(c) = rest.chars().next().filter(|c| c.is_whitespace()) {
+            i += c.len_utf8();
+        } else {
+            return Some(i);
+        }
+    }
+    None
+}
+
+//Args: position: usize - where the unexpected text starts in text, which starts at offset in the file. what - the block or struct it follows.
+fn unexpected_text(source: &SourceFile, offset: usize, text: &str, position: usize, what: &str) -> Diagnostic {
+    let line_end = text[position..].find('\n').map_or(text.len(), |end| position + end);
+    Diagnostic::new(68, format!("Unexpected text after {}", what))
+        .with_span(source.span(offset + position, offset + line_end))
+        .with_note("code must be inside a block such as `block name { ... }`")
+}
+
+//Parses the comma separated list of required blocks in a requires, each with an optional variable list.
+//Args: list_offset: usize - where list starts in the file, used for the span of each requirement.
+//Errors: If an entry is not a block id, or the same block is required twice.
+fn parse_requirements(source: &SourceFile, list_offset: usize, list: &str) -> Result<HashMap<String, Vec<Import>>, Diagnostic> {
+    let item_re = Regex::new(r"^([A-Za-z_][A-Za-z0-9_]*)\s*(?:\[([^\]]*)\])?$").unwrap();
+    let mut requirements: HashMap<String, Vec<Import>> = HashMap::new();
+    let mut item_start = 0;
+    for item in top_level_items(list) {
+        let leading = item.len() - item.trim_start().len();
+        let trimmed = item.trim();
+        let start = list_offset + item_start + leading;
+        item_start += item.len() + 1;
+        if trimmed.is_empty() && list.trim().is_empty() {
+            continue;
+        }
+        let span = source.span(start, start + trimmed.len());
+        let Some(caps) = item_re.captures(trimmed) else {
+            return Err(Diagnostic::new(34, format!("Invalid import '{}'", trimmed))
+                .with_span(span)
+                .with_note("requirements look like `a`, `a[x]`, `a[x as y]` or `a[*]`"));
+        };
+        let imports = match caps.get(2) {
+            Some(imports) => parse_imports(source, start + imports.start(), imports.as_str())?,
+            None => Vec::new(),
+        };
+        if requirements.insert(caps[1].to_string(), imports).is_some() {
+            return Err(Diagnostic::new(69, format!("Block {} is required more than once", &caps[1]))
+                .with_span(span)
+                .with_note(format!("list every variable in one entry, as in `{}[x, y]`", &caps[1])));
+        }
+    }
+    Ok(requirements)
+}
+
+//Splits list at the commas that are not inside [ ].
+fn top_level_items(list: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in list.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(&list[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&list[start..]);
+    items
+}
+
+//Parses the comma separated variable list of one required block.
+//Args: list_offset: usize - where list starts in the file, used for the span of each import.
+fn parse_imports(source: &SourceFile, list_offset: usize, list: &str) -> Result<Vec<Import>, Diagnostic> {
+    let import_re = Regex::new(r"^([A-Za-z_][A-Za-z0-9_]*)(?:\s+as\s+([A-Za-z_][A-Za-z0-9_]*))?$").unwrap();
+    let mut imports = Vec::new();
+    let mut item_start = 0;
+    for item in list.split(',') {
+        let leading = item.len() - item.trim_start().len();
+        let trimmed = item.trim();
+        let span = source.span(list_offset + item_start + leading, list_offset + item_start + leading + trimmed.len());
+        item_start += item.len() + 1;
+        if trimmed.is_empty() && list.trim().is_empty() {
+            continue;
+        }
+        if trimmed == "*" {
+            imports.push(Import::All(span));
+            continue;
+        }
+        match import_re.captures(trimmed) {
+            Some(caps) => {
+                let name = caps[1].to_string();
+                let alias = caps.get(2).map(|alias| alias.as_str().to_string()).unwrap_or_else(|| name.clone());
+                imports.push(Import::Variable { name, alias, span });
+            }
+            None => {
+                return Err(Diagnostic::new(34, format!("Invalid import '{}'", trimmed))
+                    .with_span(span)
+                    .with_note("imports look like `a[x]`, `a[x as y]` or `a[*]`"));
+            }
+        }
+    }
+    Ok(imports)
 }
 
 #[cfg(test)]
@@ -103,7 +381,7 @@ mod tests {
             "block abc{stuff}",
             "block def requires[ghi[j, k]]{morestuff}",
         ];
-        let result = split_blocks(input);
+        let result: Vec<String> = split_blocks(input).into_iter().map(|(_, text)| text).collect();
         assert_eq!(result, expected);
     }
 
@@ -111,12 +389,13 @@ mod tests {
     fn test_extract_block_meta_no_requires() {
         let input = "block def {\n    some content here\n}";
         let expected_id = "def".to_string();
-        let expected_requirements: HashMap<String, Vec<String>> = HashMap::new();
+        let expected_requirements: HashMap<String, Vec<Import>> = HashMap::new();
         let expected_content = "some content here";
-        let (block_id, requirements, content) = extract_block_meta(input);
-        assert_eq!(block_id, expected_id);
-        assert_eq!(requirements, expected_requirements);
-        assert_eq!(content, expected_content);
+        let meta = extract_block_meta(&SourceFile::new("test.st", input.to_string()), 0, input).unwrap();
+        assert_eq!(meta.id, expected_id);
+        assert_eq!(meta.requires, expected_requirements);
+        assert_eq!(meta.content, expected_content);
+        assert_eq!(&input[meta.content_offset..meta.content_offset + meta.content.len()], expected_content);
     }
 
     #[test]
@@ -126,23 +405,163 @@ mod tests {
         let mut expected_requirements = HashMap::new();
         expected_requirements.insert("abc".to_string(), Vec::new());
         let expected_content = "";
-        let (block_id, requirements, content) = extract_block_meta(input);
-        assert_eq!(block_id, expected_id);
-        assert_eq!(requirements, expected_requirements);
-        assert_eq!(content, expected_content);
+        let meta = extract_block_meta(&SourceFile::new("test.st", input.to_string()), 0, input).unwrap();
+        assert_eq!(meta.id, expected_id);
+        assert_eq!(meta.requires, expected_requirements);
+        assert_eq!(meta.content, expected_content);
+    }
+
+    fn import_names(imports: &[Import]) -> Vec<(String, String)> {
+        imports.iter().map(|import| match import {
+            Import::All(_) => ("*".to_string(), "*".to_string()),
+            Import::Variable { name, alias, .. } => (name.clone(), alias.clone()),
+        }).collect()
     }
 
     #[test]
     fn test_extract_block_meta_with_vars() {
         let input = "block xyz requires[abc[a, b, c], def[d]] {\n    some content here\n}";
         let expected_id = "xyz".to_string();
-        let mut expected_requirements = HashMap::new();
-        expected_requirements.insert("abc".to_string(), vec!["a".to_string(), "b".to_string(), "c".to_string()]);
-        expected_requirements.insert("def".to_string(), vec!["d".to_string()]);
         let expected_content = "some content here";
-        let (block_id, requirements, content) = extract_block_meta(input);
-        assert_eq!(block_id, expected_id);
-        assert_eq!(requirements, expected_requirements);
-        assert_eq!(content, expected_content);
+        let meta = extract_block_meta(&SourceFile::new("test.st", input.to_string()), 0, input).unwrap();
+        assert_eq!(meta.id, expected_id);
+        assert_eq!(import_names(&meta.requires["abc"]), vec![("a".to_string(), "a".to_string()), ("b".to_string(), "b".to_string()), ("c".to_string(), "c".to_string())]);
+        assert_eq!(import_names(&meta.requires["def"]), vec![("d".to_string(), "d".to_string())]);
+        assert_eq!(meta.content, expected_content);
+    }
+
+    #[test]
+    fn test_extract_block_meta_alias_and_wildcard() {
+        let input = "block xyz requires[abc[a as x, b], def[*], ghi[]] {}";
+        let meta = extract_block_meta(&SourceFile::new("test.st", input.to_string()), 0, input).unwrap();
+        assert_eq!(import_names(&meta.requires["abc"]), vec![("a".to_string(), "x".to_string()), ("b".to_string(), "b".to_string())]);
+        assert_eq!(import_names(&meta.requires["def"]), vec![("*".to_string(), "*".to_string())]);
+        assert!(meta.requires["ghi"].is_empty());
+        let span = meta.requires["abc"][0].span();
+        assert_eq!(&input[span.start..span.end], "a as x");
+    }
+
+    #[test]
+    fn test_extract_block_meta_invalid_requirements() {
+        for (input, code, spanned) in [
+            ("block xyz requires[2] {}", 34, "2"),
+            ("block xyz requires[a, b c] {}", 34, "b c"),
+            ("block xyz requires[a[x], a[y]] {}", 69, "a[y]"),
+            ("block xyz requires[a,] {}", 34, ""),
+        ] {
+            let error = extract_block_meta(&SourceFile::new("test.st", input.to_string()), 0, input).unwrap_err();
+            assert_eq!(error.code, code, "{}", input);
+            let span = error.span.unwrap();
+            assert_eq!(&input[span.start..span.end], spanned, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_extract_block_meta_invalid_import() {
+        let input = "block xyz requires[abc[a as]] {}";
+        let error = extract_block_meta(&SourceFile::new("test.st", input.to_string()), 0, input).unwrap_err();
+        assert_eq!(error.code, 34);
+        let span = error.span.unwrap();
+        assert_eq!(&input[span.start..span.end], "a as");
+    }
+
+    #[test]
+    fn test_split_blocks_offsets() {
+        let input = "block a{}\nblock b{}";
+        let offsets: Vec<usize> = split_blocks(input).into_iter().map(|(offset, _)| offset).collect();
+        assert_eq!(offsets, vec![0, 10]);
+    }
+
+    #[test]
+    fn test_split_blocks_with_structs() {
+        let input = "struct Point { x: f64, y: f64 }\nblock a {\n    let mystruct Thing {\n}\nstruct Line{from: Point}";
+        let source = SourceFile::new("test.st", input.to_string());
+        let chunks = split_blocks(input);
+        let offsets: Vec<usize> = chunks.iter().map(|(offset, _)| *offset).collect();
+        assert_eq!(offsets, vec![0, 32, 69]);
+        let ranges: Vec<Option<(usize, usize)>> = chunks.iter().map(|(offset, text)| extract_struct(&source, *offset, text).unwrap()).collect();
+        assert_eq!(ranges, vec![Some((0, 31)), None, Some((69, 93))]);
+        assert_eq!(&input[69..93], "struct Line{from: Point}");
+        let error = extract_struct(&source, 0, "struct P { x: i32").unwrap_err();
+        assert_eq!(error.code, 9);
+    }
+
+    #[test]
+    fn test_braces_in_strings_and_comments() {
+        let input = "// block x {\nblock a { let s: String = \"}\"; // }\n    print(r#\"{\"#); /* } */ }\n/* between */ block b {}";
+        let chunks = split_blocks(input);
+        assert_eq!(chunks.iter().map(|(offset, _)| *offset).collect::<Vec<usize>>(), vec![13, 92]);
+        let source = SourceFile::new("test.st", input.to_string());
+        let meta = extract_block_meta(&source, chunks[0].0, &chunks[0].1).unwrap();
+        assert_eq!(meta.content, "let s: String = \"}\"; // }\n    print(r#\"{\"#); /* } */");
+        assert_eq!(extract_block_meta(&source, chunks[1].0, &chunks[1].1).unwrap().id, "b");
+    }
+
+    #[test]
+    fn test_text_after_block() {
+        let input = "block a { print(1); } print(2);";
+        let error = extract_block_meta(&SourceFile::new("test.st", input.to_string()), 0, input).unwrap_err();
+        assert_eq!(error.code, 68);
+        assert_eq!(error.span.unwrap().col, 23);
+        let source = SourceFile::new("test.st", "struct P { x: i32 } x".to_string());
+        assert_eq!(extract_struct(&source, 0, &source.text).unwrap_err().code, 68);
+        assert_eq!(split_blocks("print(1);\nblock a {}").len(), 2);
+    }
+
+    #[test]
+    fn test_extract_block_meta_offsets_in_file() {
+        let source = SourceFile::new("test.st", "block a {}\nblock b {\n    print(1);\n}".to_string());
+        let meta = extract_block_meta(&source, 11, &source.text[11..]).unwrap();
+        assert_eq!((meta.span.line, meta.span.col), (2, 7));
+        assert_eq!(meta.content_offset, 25);
+        assert_eq!(&source.text[meta.content_offset..meta.content_offset + meta.content.len()], "print(1);");
+    }
+
+    #[test]
+    fn test_extract_block_meta_invalid_format() {
+        let input = "blok a {}";
+        let error = extract_block_meta(&SourceFile::new("test.st", input.to_string()), 0, input).unwrap_err();
+        assert_eq!(error.code, 7);
+    }
+
+    #[test]
+    fn test_extract_block_meta_unmatched_braces() {
+        let source = SourceFile::new("test.st", "block a {}\nblock b {\n    print(1);\n".to_string());
+        let error = extract_block_meta(&source, 11, &source.text[11..]).unwrap_err();
+        assert_eq!(error.code, 9);
+        assert_eq!(error.span.unwrap().line, 2);
+    }
+
+    fn block_with(requires: &str) -> TokenBlock {
+        let input = format!("block xyz requires[{}] {{}}", requires);
+        let meta = extract_block_meta(&SourceFile::new("test.st", input.clone()), 0, &input).unwrap();
+        TokenBlock::new(meta.id, meta.requires, VecDeque::new(), meta.span)
+    }
+
+    #[test]
+    fn test_import_variables() {
+        let exports = HashMap::from([
+            ("abc".to_string(), HashMap::from([("a".to_string(), 1), ("b".to_string(), 2), ("c".to_string(), 3)])),
+            ("def".to_string(), HashMap::from([("d".to_string(), 4)])),
+        ]);
+        let imported = import_variables(&block_with("abc[a as x, b], def[*]"), &exports).unwrap();
+        assert_eq!(imported, HashMap::from([("x".to_string(), 1), ("b".to_string(), 2), ("d".to_string(), 4)]));
+        assert!(import_variables(&block_with("abc, def[]"), &exports).unwrap().is_empty());
+        assert!(import_variables(&block_with("abc[a, *]"), &exports).is_ok());
+        assert_eq!(import_variables(&block_with("def[z]"), &exports).unwrap_err().code, 35);
+    }
+
+    #[test]
+    fn test_conflicting_imports() {
+        let exports = HashMap::from([
+            ("abc".to_string(), HashMap::from([("x".to_string(), 1)])),
+            ("def".to_string(), HashMap::from([("x".to_string(), 2), ("y".to_string(), 3)])),
+        ]);
+        let error = import_variables(&block_with("def[x], abc[x]"), &exports).unwrap_err();
+        assert_eq!(error.code, 36);
+        assert_eq!(error.message, "Variable x is imported from both block abc and block def");
+        assert_eq!(import_variables(&block_with("abc[*], def[*]"), &exports).unwrap_err().code, 36);
+        assert_eq!(import_variables(&block_with("def[x as y, y]"), &exports).unwrap_err().code, 36);
+        assert!(import_variables(&block_with("abc[x], def[x as x2]"), &exports).is_ok());
     }
 }
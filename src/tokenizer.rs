@@ -1,5 +1,5 @@
 use std::collections::VecDeque;
-use regex::Regex;
+use crate::diagnostic::{Diagnostic, SourceFile, Span};
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Token {
@@ -21,13 +21,28 @@ pub enum Token {
     Decrement,
     Else,
     Elif,
-    Ignore,
     OpenBracket,
     CloseBracket,
     DefineFunction,
     Assign,
     Let,
     Colon,
+    Arrow,
+    Return,
+    Break,
+    Continue,
+    Label(String),
+    DotDot,
+    DotDotEq,
+    Dot,
+    In,
+    Struct,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -48,117 +63,325 @@ pub enum MathOp {
     Not,
 }
 
-pub fn tokenize(text: &str) -> VecDeque<Token> {
-    let comment_re = Regex::new(r"(?s)(//[^\n]*|/\*.*?\*/)").unwrap();
-    let text = comment_re.replace_all(text, "");
-    let token_re = Regex::new(r#"(?P<String>"(?:\\.|[^"\\])*")|(?P<Number>\d+(?:_\d+)*(?:\.\d+(?:_\d+)*)?)|(?P<Op>\+\+|--|==|!=|<=|>=|&&|\|\||[+\-*/%<>!])|(?P<Assign>=)|(?P<Comma>,)|(?P<Colon>:)|(?P<Semicolon>;)|(?P<OpenParen>\()|(?P<CloseParen>\))|(?P<OpenBlock>\{)|(?P<CloseBlock>\})|(?P<OpenBracket>\[)|(?P<CloseBracket>\])|(?P<Identifier>[A-Za-z_][A-Za-z0-9_<>\?]*)|(?P<Whitespace>\s+)"#).unwrap();
+//Tokenizes source.text[start..end], giving every token a span relative to the whole file.
+//Errors: If there is a character that cannot start a token, or a string or block comment is never closed.
+pub fn tokenize(source: &SourceFile, start: usize, end: usize) -> Result<VecDeque<SpannedToken>, Diagnostic> {
+    let mut lexer = Lexer { source, text: &source.text[..end], pos: start };
     let mut tokens = VecDeque::new();
-    for cap in token_re.captures_iter(&text) {
-        if cap.name("Whitespace").is_some() { continue; }
-        if let Some(m) = cap.name("String") {
-            tokens.push_back(Token::String(m.as_str()[1..m.as_str().len()-1].to_string()));
-            continue;
-        }
-        if let Some(m) = cap.name("Number") {
-            tokens.push_back(Token::ConstantNumber(m.as_str().replace("_", "")));
-            continue;
+    while let Some(token) = lexer.next_token()? {
+        tokens.push_back(token);
+    }
+    Ok(tokens)
+}
+
+//Walks the text one character at a time. Only ASCII can start a token, so most checks look at bytes.
+struct Lexer<'a> {
+    source: &'a SourceFile,
+    //The file text up to the end of the range being tokenized.
+    text: &'a str,
+    pos: usize,
+}
+
+impl Lexer<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn peek_at(&self, ahead: usize) -> Option<u8> {
+        self.text.as_bytes().get(self.pos + ahead).copied()
+    }
+
+    //Whether pos is at the r of r"..." or r#"..."#.
+    fn is_raw_string(&self) -> bool {
+        let hashes = self.text.as_bytes()[self.pos + 1..].iter().take_while(|c| **c == b'#').count();
+        self.peek_at(1 + hashes) == Some(b'"')
+    }
+
+    //Returns: the next token, or None once the end of the range is reached.
+    fn next_token(&mut self) -> Result<Option<SpannedToken>, Diagnostic> {
+        self.skip_whitespace_and_comments()?;
+        let Some(c) = self.peek() else {
+            return Ok(None);
+        };
+        let start = self.pos;
+        let token = match c {
+            b'"' => self.string()?,
+            b'r' if self.is_raw_string() => self.raw_string()?,
+            b'0'..=b'9' => self.number(),
+            b'A'..=b'Z' | b'a'..=b'z' | b'_' => self.identifier(),
+            b'\'' if self.peek_at(1).is_some_and(|c| c.is_ascii_alphabetic() || c == b'_') => self.label(),
+            _ => self.symbol()?,
+        };
+        Ok(Some(SpannedToken { token, span: self.source.span(start, self.pos) }))
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), Diagnostic> {
+        while let Some(c) = self.peek() {
+            match (c, self.peek_at(1)) {
+                (b' ' | b'\t' | b'\n' | b'\r', _) => self.pos += 1,
+                (b'/', Some(b'/')) => {
+                    self.pos = self.text[self.pos..].find('\n').map(|i| self.pos + i).unwrap_or(self.text.len());
+                }
+                (b'/', Some(b'*')) => match self.text[self.pos + 2..].find("*/") {
+                    Some(i) => self.pos += i + 4,
+                    None => {
+                        return Err(Diagnostic::new(45, "Unterminated block comment")
+                            .with_span(self.source.span(self.pos, self.pos + 2))
+                            .with_note("block comments end with */"));
+                    }
+                },
+                _ => break,
+            }
         }
-        if let Some(m) = cap.name("Op") {
-            let op = m.as_str();
-            let token = match op {
-                "++" => Token::Increment,
-                "--" => Token::Decrement,
-                "+" => Token::MathOp(MathOp::Add),
-                "*" => Token::MathOp(MathOp::Multiply),
-                "/" => Token::MathOp(MathOp::Divide),
-                "-" => Token::MathOp(MathOp::Subtract),
-                "%" => Token::MathOp(MathOp::Modulus),
-                "==" => Token::MathOp(MathOp::Equals),
-                "!=" => Token::MathOp(MathOp::NotEqual),
-                "<" => Token::MathOp(MathOp::LessThan),
-                ">" => Token::MathOp(MathOp::GreaterThan),
-                "<=" => Token::MathOp(MathOp::LessThanOrEqualTo),
-                ">=" => Token::MathOp(MathOp::GreaterThanOrEqualTo),
-                "&&" => Token::MathOp(MathOp::And),
-                "||" => Token::MathOp(MathOp::Or),
-                "!" => Token::MathOp(MathOp::Not),
-                _ => Token::Ignore,
-            };
-            tokens.push_back(token);
-            continue;
+        Ok(())
+    }
+
+    //Reads a string literal, decoding escape sequences such as \n and \u{1F600}.
+    fn string(&mut self) -> Result<Token, Diagnostic> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut value = String::new();
+        while let Some(c) = self.text[self.pos..].chars().next() {
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(Token::String(value)),
+                '\\' => value.push(self.escape(self.pos - 1)?),
+                c => value.push(c),
+            }
         }
-        if cap.name("Assign").is_some() {
-            tokens.push_back(Token::Assign);
-            continue;
+        Err(unterminated_string(self.source, start))
+    }
+
+    //Decodes the escape sequence after a backslash.
+    //Args: backslash: usize - where the backslash is, for error spans.
+    fn escape(&mut self, backslash: usize) -> Result<char, Diagnostic> {
+        let Some(c) = self.text[self.pos..].chars().next() else {
+            return Err(unterminated_string(self.source, backslash));
+        };
+        self.pos += c.len_utf8();
+        match c {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            '0' => Ok('\0'),
+            '\\' => Ok('\\'),
+            '"' => Ok('"'),
+            'u' => {
+                let digits = self.text[self.pos..].strip_prefix('{').and_then(|rest| rest.find('}').map(|end| &rest[..end]));
+                let decoded = digits
+                    .filter(|digits| (1..=6).contains(&digits.len()))
+                    .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                    .and_then(char::from_u32);
+                match (digits, decoded) {
+                    (Some(digits), Some(decoded)) => {
+                        self.pos += digits.len() + 2;
+                        Ok(decoded)
+                    }
+                    (digits, _) => {
+                        let end = digits.map(|digits| self.pos + digits.len() + 2).unwrap_or(self.pos);
+                        Err(Diagnostic::new(48, format!("Invalid unicode escape {}", &self.text[backslash..end]))
+                            .with_span(self.source.span(backslash, end))
+                            .with_note("unicode escapes are written \\u{...} with 1 to 6 hex digits of a valid code point"))
+                    }
+                }
+            }
+            c => Err(Diagnostic::new(47, format!("Unknown escape sequence \\{}", c))
+                .with_span(self.source.span(backslash, self.pos))
+                .with_note("valid escapes are \\n \\t \\r \\0 \\\\ \\\" and \\u{...}, or use a raw string r\"...\"")),
         }
-        if cap.name("Comma").is_some() {
-            tokens.push_back(Token::Comma);
-            continue;
+    }
+
+    //Reads a raw string such as r"C:\path" or r#"say "hi""#, where backslashes have no special meaning.
+    fn raw_string(&mut self) -> Result<Token, Diagnostic> {
+        let start = self.pos;
+        self.pos += 1;
+        let hashes = self.text[self.pos..].bytes().take_while(|c| *c == b'#').count();
+        self.pos += hashes + 1;
+        let terminator = format!("\"{}", "#".repeat(hashes));
+        match self.text[self.pos..].find(&terminator) {
+            Some(length) => {
+                let value = self.text[self.pos..self.pos + length].to_string();
+                self.pos += length + terminator.len();
+                Ok(Token::String(value))
+            }
+            None => Err(unterminated_string(self.source, start)),
         }
-        if cap.name("Colon").is_some() {
-            tokens.push_back(Token::Colon);
-            continue;
+    }
+
+    //Reads digits with optional _ separators and an optional fraction, such as 1_000.5.
+    fn number(&mut self) -> Token {
+        let start = self.pos;
+        if self.peek() == Some(b'0') && matches!(self.peek_at(1), Some(b'x' | b'b' | b'o')) {
+            self.pos += 2;
+        } else {
+            self.digits();
+            if self.peek() == Some(b'.') && self.peek_at(1).is_some_and(|c| c.is_ascii_digit()) {
+                self.pos += 1;
+                self.digits();
+            }
+            if matches!(self.peek(), Some(b'e' | b'E')) {
+                let sign = matches!(self.peek_at(1), Some(b'+' | b'-')) as usize;
+                if self.peek_at(1 + sign).is_some_and(|c| c.is_ascii_digit()) {
+                    self.pos += 1 + sign;
+                    self.digits();
+                }
+            }
         }
-        if cap.name("Semicolon").is_some() {
-            tokens.push_back(Token::EndLine);
-            continue;
+        //Hex digits and suffixes such as i64 are checked by the parser, so every letter and digit that follows belongs to the literal.
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_') {
+            self.pos += 1;
         }
-        if cap.name("OpenParen").is_some() {
-            tokens.push_back(Token::OpenParen);
-            continue;
+        Token::ConstantNumber(self.text[start..self.pos].replace('_', ""))
+    }
+
+    fn digits(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() || c == b'_' && self.peek_at(1).is_some_and(|next| next.is_ascii_digit()) {
+                self.pos += 1;
+            } else {
+                break;
+            }
         }
-        if cap.name("CloseParen").is_some() {
-            tokens.push_back(Token::CloseParen);
-            continue;
+    }
+
+    fn identifier(&mut self) -> Token {
+        let start = self.pos;
+        self.identifier_chars();
+        //Generic types such as Array<Array<i32>> or Map<String, i32> are read as a single identifier so the parser can treat them like any other type name.
+        if self.text.as_bytes()[start].is_ascii_uppercase() && self.peek() == Some(b'<') {
+            if let Some(end) = self.generic_arguments_end() {
+                self.pos = end;
+            }
         }
-        if cap.name("OpenBlock").is_some() {
-            tokens.push_back(Token::OpenBlock);
-            continue;
+        match &self.text[start..self.pos] {
+            "while" => Token::WhileLoop,
+            "if" => Token::If,
+            "elif" => Token::Elif,
+            "else" => Token::Else,
+            "for" => Token::ForLoop,
+            "fn" => Token::DefineFunction,
+            "true" => Token::Boolean(true),
+            "false" => Token::Boolean(false),
+            "let" => Token::Let,
+            "return" => Token::Return,
+            "break" => Token::Break,
+            "continue" => Token::Continue,
+            "in" => Token::In,
+            "struct" => Token::Struct,
+            id => Token::Identifier(id.to_string()),
         }
-        if cap.name("CloseBlock").is_some() {
-            tokens.push_back(Token::CloseBlock);
-            continue;
+    }
+
+    //Reads a loop label such as 'outer, keeping the name without the quote.
+    fn label(&mut self) -> Token {
+        self.pos += 1;
+        let start = self.pos;
+        self.identifier_chars();
+        Token::Label(self.text[start..self.pos].to_string())
+    }
+
+    fn identifier_chars(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_') {
+            self.pos += 1;
         }
-        if cap.name("OpenBracket").is_some() {
-            tokens.push_back(Token::OpenBracket);
-            continue;
+    }
+
+    //Returns: where the <...> starting at pos ends, or None if it is not a well formed list of type names.
+    fn generic_arguments_end(&self) -> Option<usize> {
+        let mut depth = 0;
+        for (i, c) in self.text.as_bytes()[self.pos..].iter().enumerate() {
+            match c {
+                b'<' => depth += 1,
+                b'>' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(self.pos + i + 1);
+                    }
+                }
+                c if c.is_ascii_alphanumeric() || *c == b'_' => {}
+                b',' | b' ' if depth > 0 => {}
+                _ => return None,
+            }
         }
-        if cap.name("CloseBracket").is_some() {
-            tokens.push_back(Token::CloseBracket);
-            continue;
+        None
+    }
+
+    fn symbol(&mut self) -> Result<Token, Diagnostic> {
+        if self.text.as_bytes()[self.pos..].starts_with(b"..=") {
+            self.pos += 3;
+            return Ok(Token::DotDotEq);
         }
-        if let Some(m) = cap.name("Identifier") {
-            let id = m.as_str().to_string();
-            let token = match id.as_str() {
-                "while" => Token::WhileLoop,
-                "if" => Token::If,
-                "elif" => Token::Elif,
-                "else" => Token::Else,
-                "for" => Token::ForLoop,
-                "fn" => Token::DefineFunction,
-                "true" => Token::Boolean(true),
-                "false" => Token::Boolean(false),
-                "let" => Token::Let,
-                _ => Token::Identifier(id),
-            };
-            tokens.push_back(token);
-            continue;
+        let two = match (self.peek(), self.peek_at(1)) {
+            (Some(b'-'), Some(b'>')) => Some(Token::Arrow),
+            (Some(b'+'), Some(b'+')) => Some(Token::Increment),
+            (Some(b'-'), Some(b'-')) => Some(Token::Decrement),
+            (Some(b'='), Some(b'=')) => Some(Token::MathOp(MathOp::Equals)),
+            (Some(b'!'), Some(b'=')) => Some(Token::MathOp(MathOp::NotEqual)),
+            (Some(b'<'), Some(b'=')) => Some(Token::MathOp(MathOp::LessThanOrEqualTo)),
+            (Some(b'>'), Some(b'=')) => Some(Token::MathOp(MathOp::GreaterThanOrEqualTo)),
+            (Some(b'&'), Some(b'&')) => Some(Token::MathOp(MathOp::And)),
+            (Some(b'|'), Some(b'|')) => Some(Token::MathOp(MathOp::Or)),
+            (Some(b'.'), Some(b'.')) => Some(Token::DotDot),
+            _ => None,
+        };
+        if let Some(token) = two {
+            self.pos += 2;
+            return Ok(token);
         }
+        let token = match self.peek() {
+            Some(b'+') => Token::MathOp(MathOp::Add),
+            Some(b'-') => Token::MathOp(MathOp::Subtract),
+            Some(b'*') => Token::MathOp(MathOp::Multiply),
+            Some(b'/') => Token::MathOp(MathOp::Divide),
+            Some(b'%') => Token::MathOp(MathOp::Modulus),
+            Some(b'<') => Token::MathOp(MathOp::LessThan),
+            Some(b'>') => Token::MathOp(MathOp::GreaterThan),
+            Some(b'!') => Token::MathOp(MathOp::Not),
+            Some(b'=') => Token::Assign,
+            Some(b',') => Token::Comma,
+            Some(b':') => Token::Colon,
+            Some(b';') => Token::EndLine,
+            Some(b'(') => Token::OpenParen,
+            Some(b')') => Token::CloseParen,
+            Some(b'{') => Token::OpenBlock,
+            Some(b'}') => Token::CloseBlock,
+            Some(b'[') => Token::OpenBracket,
+            Some(b']') => Token::CloseBracket,
+            Some(b'.') => Token::Dot,
+            _ => return Err(unexpected_character(self.source, self.pos, &self.text[self.pos..])),
+        };
+        self.pos += 1;
+        Ok(token)
     }
-    tokens.retain(|t| *t != Token::Ignore);
-    tokens
+}
+
+fn unterminated_string(source: &SourceFile, start: usize) -> Diagnostic {
+    Diagnostic::new(46, "Unterminated string literal")
+        .with_span(source.span(start, start + 1))
+        .with_note("strings end with a \"")
+}
+
+fn unexpected_character(source: &SourceFile, offset: usize, rest: &str) -> Diagnostic {
+    let unexpected = rest.chars().next().unwrap();
+    Diagnostic::new(10, format!("Unexpected character {}", unexpected))
+        .with_span(source.span(offset, offset + unexpected.len_utf8()))
 }
 
 #[cfg(test)]
 mod test {
 
-    use crate::tokenizer::{tokenize, MathOp};
+    use crate::{diagnostic::SourceFile, tokenizer::{tokenize, MathOp}};
 
     use super::Token;
 
+    fn tokenize_text(text: &str) -> Vec<Token> {
+        let source = SourceFile::new("test.st", text.to_string());
+        tokenize(&source, 0, text.len()).unwrap().into_iter().map(|spanned| spanned.token).collect()
+    }
+
     #[test]
     fn hello_world() {
-        let actual = tokenize("print(\"hello world\");");
+        let actual = tokenize_text("print(\"hello world\");");
         let expected = vec![
             Token::Identifier("print".to_string()),
             Token::OpenParen,
@@ -170,7 +393,7 @@ mod test {
     }
     #[test]
     fn integer_variable_test() {
-        let actual = tokenize("i32 eeebo = 6;");
+        let actual = tokenize_text("i32 eeebo = 6;");
         let expected = vec![
             Token::Identifier("i32".to_string()),
             Token::Identifier("eeebo".to_string()),
@@ -182,7 +405,7 @@ mod test {
     }
     #[test]
     fn string_variable_test() {
-        let actual = tokenize("String beebo = \"carbon monoxide\";");
+        let actual = tokenize_text("String beebo = \"carbon monoxide\";");
         let expected = vec![
             Token::Identifier("String".to_string()),
             Token::Identifier("beebo".to_string()),
@@ -194,7 +417,7 @@ mod test {
     }
     #[test]
     fn bool_variable_test() {
-        let actual = tokenize("Bool feefoo = false;");
+        let actual = tokenize_text("Bool feefoo = false;");
         let expected = vec![
             Token::Identifier("Bool".to_string()),
             Token::Identifier("feefoo".to_string()),
@@ -206,7 +429,7 @@ mod test {
     }
     #[test]
     fn i32_variable_test() {
-        let actual = tokenize("i32 furfu = 42;");
+        let actual = tokenize_text("i32 furfu = 42;");
         let expected = vec![
             Token::Identifier("i32".to_string()),
             Token::Identifier("furfu".to_string()),
@@ -218,7 +441,7 @@ mod test {
     }
     #[test]
     fn print_variable_test() {
-        let actual = tokenize("Bool eee = true;\nprint(eee);");
+        let actual = tokenize_text("Bool eee = true;\nprint(eee);");
         let expected = vec![
             Token::Identifier("Bool".to_string()),
             Token::Identifier("eee".to_string()),
@@ -235,7 +458,7 @@ mod test {
     }
     #[test]
     fn print_string_variable() {
-        let actual = tokenize("String ee = \"should?\";\nprint(ee);");
+        let actual = tokenize_text("String ee = \"should?\";\nprint(ee);");
         let expected = vec![
             Token::Identifier("String".to_string()),
             Token::Identifier("ee".to_string()),
@@ -252,7 +475,7 @@ mod test {
     }
     #[test]
     fn while_true() {
-        let actual = tokenize("while (true){\nprint(6);\n}");
+        let actual = tokenize_text("while (true){\nprint(6);\n}");
         let expected = vec![
             Token::WhileLoop,
             Token::OpenParen,
@@ -270,7 +493,7 @@ mod test {
     }
     #[test]
     fn format_tab() {
-        let actual = tokenize(
+        let actual = tokenize_text(
             "
 i32 e = 6;
 while (true){
@@ -299,7 +522,7 @@ while (true){
     }
     #[test]
     fn change_variable() {
-        let actual = tokenize("i32 i = 0;\ni = 1;\nString e = \"hello\";\ne = \"bye\";\nBool yes = true;\nyes = false;");
+        let actual = tokenize_text("i32 i = 0;\ni = 1;\nString e = \"hello\";\ne = \"bye\";\nBool yes = true;\nyes = false;");
         let expected = vec![
             Token::Identifier("i32".to_string()),
             Token::Identifier("i".to_string()),
@@ -333,7 +556,7 @@ while (true){
     }
     #[test]
     fn simple_math() {
-        let actual = tokenize("i32 e = 4 + 3;\ni32 ee = 4 - 3;\ni32 eee = 8 / 2;\ni32 eeee = 8 * 2;");
+        let actual = tokenize_text("i32 e = 4 + 3;\ni32 ee = 4 - 3;\ni32 eee = 8 / 2;\ni32 eeee = 8 * 2;");
         let expected = vec![
             Token::Identifier("i32".to_string()),
             Token::Identifier("e".to_string()),
@@ -368,7 +591,7 @@ while (true){
     }
     #[test]
     fn multi_term_simple_math() {
-        let actual = tokenize("i32 foo = 3 + 5 / 4 * 68;");
+        let actual = tokenize_text("i32 foo = 3 + 5 / 4 * 68;");
         let expected = vec![
             Token::Identifier("i32".to_string()),
             Token::Identifier("foo".to_string()),
@@ -386,7 +609,7 @@ while (true){
     }
     #[test]
     fn print_equation() {
-        let actual = tokenize("print(1 + 6);");
+        let actual = tokenize_text("print(1 + 6);");
         let expected = vec![
             Token::Identifier("print".to_string()),
             Token::OpenParen,
@@ -400,7 +623,7 @@ while (true){
     }
     #[test]
     fn variable_adding() {
-        let actual = tokenize("i32 e = 1;i32 ee = 2;print(e + ee);");
+        let actual = tokenize_text("i32 e = 1;i32 ee = 2;print(e + ee);");
         let expected = vec![
             Token::Identifier("i32".to_string()),
             Token::Identifier("e".to_string()),
@@ -424,7 +647,7 @@ while (true){
     }
     #[test]
     fn basic_if() {
-        let actual = tokenize("i32 e = 6;if(e == 6){print(e);}");
+        let actual = tokenize_text("i32 e = 6;if(e == 6){print(e);}");
         let expected: Vec<Token> = vec![
             Token::Identifier("i32".to_string()),
             Token::Identifier("e".to_string()),
@@ -449,19 +672,21 @@ while (true){
     }
     #[test]
     fn for_loop() {
-        let actual = tokenize("for(i32 i = 0, i < 10, i++){\nprint(i);\n}");
+        let actual = tokenize_text("for(let i: i32 = 0; i < 10; i++){\nprint(i);\n}");
         let expected = vec![
             Token::ForLoop,
             Token::OpenParen,
-            Token::Identifier("i32".to_string()),
+            Token::Let,
             Token::Identifier("i".to_string()),
+            Token::Colon,
+            Token::Identifier("i32".to_string()),
             Token::Assign,
             Token::ConstantNumber("0".to_string()),
-            Token::Comma,
+            Token::EndLine,
             Token::Identifier("i".to_string()),
             Token::MathOp(MathOp::LessThan),
             Token::ConstantNumber("10".to_string()),
-            Token::Comma,
+            Token::EndLine,
             Token::Identifier("i".to_string()),
             Token::Increment,
             Token::CloseParen,
@@ -477,7 +702,7 @@ while (true){
     }
     #[test]
     fn double_if() {
-        let actual = tokenize("if(true){if(false){print(\"a\");}}");
+        let actual = tokenize_text("if(true){if(false){print(\"a\");}}");
         let expected = vec![
             Token::If,
             Token::OpenParen,
@@ -501,7 +726,7 @@ while (true){
     }
     #[test]
     fn basic_comment() {
-        let actual = tokenize("i32 i = 10;\n//i32 e = 9;\ni32 g = 8;");
+        let actual = tokenize_text("i32 i = 10;\n//i32 e = 9;\ni32 g = 8;");
         let expected = vec![
             Token::Identifier("i32".to_string()),
             Token::Identifier("i".to_string()),
@@ -518,7 +743,7 @@ while (true){
     }
     #[test]
     fn multi_line_comment() {
-        let actual = tokenize("i32 i = 10;\n/*unga\nbunga\nwunga\n*/i32 e = 0;");
+        let actual = tokenize_text("i32 i = 10;\n/*unga\nbunga\nwunga\n*/i32 e = 0;");
         let expected = vec![
             Token::Identifier("i32".to_string()),
             Token::Identifier("i".to_string()),
@@ -535,7 +760,7 @@ while (true){
     }
     #[test]
     fn else_elif_test() {
-        let actual = tokenize("if(i == 6){}elif(i == 7){}else{print(\"e\");}");
+        let actual = tokenize_text("if(i == 6){}elif(i == 7){}else{print(\"e\");}");
         let expected = vec![
             Token::If,
             Token::OpenParen,
@@ -566,7 +791,7 @@ while (true){
     }
     #[test]
     fn i32_i64_f32_f64() {
-        let actual = tokenize("i32 i = 31;i64 e = 63;f32 f = 32; f64 g = 64;");
+        let actual = tokenize_text("i32 i = 31;i64 e = 63;f32 f = 32; f64 g = 64;");
         let expected = vec![
             Token::Identifier("i32".to_string()),
             Token::Identifier("i".to_string()),
@@ -593,7 +818,7 @@ while (true){
     }
     #[test]
     fn one_dim_array() {
-        let actual = tokenize("Array<i32> a = [];Array<i64> b=[100, 200];");
+        let actual = tokenize_text("Array<i32> a = [];Array<i64> b=[100, 200];");
         let expected = vec![
             Token::Identifier("Array<i32>".to_string()),
             Token::Identifier("a".to_string()),
@@ -615,7 +840,7 @@ while (true){
     }
     #[test]
     fn define_function() {
-        let actual = tokenize("fn pwint(){print(\"i\");}pwint();");
+        let actual = tokenize_text("fn pwint(){print(\"i\");}pwint();");
         let expected = vec![
             Token::DefineFunction,
             Token::Identifier("pwint".to_string()),
@@ -637,7 +862,7 @@ while (true){
     }
     #[test]
     fn complex_logic() {
-        let actual = tokenize("print((false || true) && true);");
+        let actual = tokenize_text("print((false || true) && true);");
         let expected = vec![
             Token::Identifier("print".to_string()),
             Token::OpenParen,
@@ -655,7 +880,7 @@ while (true){
     }
     #[test]
     fn float_input_test() {
-        let actual = tokenize("3.1415;");
+        let actual = tokenize_text("3.1415;");
         let expected = vec![
             Token::ConstantNumber("3.1415".to_string()),
             Token::EndLine,
@@ -664,7 +889,7 @@ while (true){
     }
     #[test]
     fn let_test() {
-        let actual = tokenize("let a = 10;");
+        let actual = tokenize_text("let a = 10;");
         let expected = vec![
             Token::Let,
             Token::Identifier("a".to_string()),
@@ -676,7 +901,7 @@ while (true){
     }
     #[test]
     fn colon_test() {
-        let actual = tokenize("let a: i32 = 10;");
+        let actual = tokenize_text("let a: i32 = 10;");
         let expected = vec![
             Token::Let,
             Token::Identifier("a".to_string()),
@@ -688,4 +913,181 @@ while (true){
         ];
         assert_eq!(actual, expected);
     }
-}
\ No newline at end of file
+    #[test]
+    fn unexpected_character() {
+        let source = SourceFile::new("test.st", "let a: i32 = 5 @ 3;".to_string());
+        let error = tokenize(&source, 0, source.text.len()).unwrap_err();
+        assert_eq!(error.code, 10);
+        assert_eq!(error.span.unwrap().col, 16);
+    }
+    #[test]
+    fn token_spans() {
+        let source = SourceFile::new("test.st", "block a {\n    print(x);\n}".to_string());
+        let tokens = tokenize(&source, 14, 23).unwrap();
+        assert_eq!(tokens[0].token, Token::Identifier("print".to_string()));
+        assert_eq!((tokens[0].span.start, tokens[0].span.end), (14, 19));
+        assert_eq!((tokens[0].span.line, tokens[0].span.col), (2, 5));
+        assert_eq!((tokens[2].span.line, tokens[2].span.col), (2, 11));
+    }
+    #[test]
+    fn spans_after_comment() {
+        let source = SourceFile::new("test.st", "/* a\nb */ let x".to_string());
+        let tokens = tokenize(&source, 0, source.text.len()).unwrap();
+        assert_eq!(tokens[0].token, Token::Let);
+        assert_eq!((tokens[0].span.line, tokens[0].span.col), (2, 6));
+    }
+    #[test]
+    fn function_signature() {
+        let actual = tokenize_text("fn add(a: i32) -> i32 { return a; }");
+        let expected = vec![
+            Token::DefineFunction,
+            Token::Identifier("add".to_string()),
+            Token::OpenParen,
+            Token::Identifier("a".to_string()),
+            Token::Colon,
+            Token::Identifier("i32".to_string()),
+            Token::CloseParen,
+            Token::Arrow,
+            Token::Identifier("i32".to_string()),
+            Token::OpenBlock,
+            Token::Return,
+            Token::Identifier("a".to_string()),
+            Token::EndLine,
+            Token::CloseBlock,
+        ];
+        assert_eq!(actual, expected);
+    }
+    #[test]
+    fn comments_inside_strings() {
+        let actual = tokenize_text("print(\"http://a /* b */\"); // done\n/* \"not a string */");
+        let expected = vec![
+            Token::Identifier("print".to_string()),
+            Token::OpenParen,
+            Token::String("http://a /* b */".to_string()),
+            Token::CloseParen,
+            Token::EndLine,
+        ];
+        assert_eq!(actual, expected);
+    }
+    #[test]
+    fn comparisons_are_not_generic_types() {
+        let actual = tokenize_text("a<b Array<Array<i32>> x Map<String, Array<i32>>");
+        let expected = vec![
+            Token::Identifier("a".to_string()),
+            Token::MathOp(MathOp::LessThan),
+            Token::Identifier("b".to_string()),
+            Token::Identifier("Array<Array<i32>>".to_string()),
+            Token::Identifier("x".to_string()),
+            Token::Identifier("Map<String, Array<i32>>".to_string()),
+        ];
+        assert_eq!(actual, expected);
+    }
+    #[test]
+    fn lexer_errors() {
+        for (text, code, col) in [("a & b", 10, 3), ("a | b", 10, 3), ("let s = $;", 10, 9), ("print(\"abc);", 46, 7), ("x; /* open", 45, 4), ("\"a\\\"", 46, 1), ("\"a\\qb\"", 47, 3), ("\"\\u{110000}\"", 48, 2), ("\"\\u{}\"", 48, 2), ("\"\\u41\"", 48, 2), ("r\"open", 46, 1)] {
+            let source = SourceFile::new("test.st", text.to_string());
+            let error = tokenize(&source, 0, source.text.len()).unwrap_err();
+            assert_eq!((error.code, error.span.unwrap().col), (code, col), "{}", text);
+        }
+    }
+    #[test]
+    fn escape_sequences() {
+        assert_eq!(tokenize_text(r#""say \"hi\"";"#), vec![Token::String(r#"say "hi""#.to_string()), Token::EndLine]);
+        assert_eq!(tokenize_text(r#""a\nb\tc\rd\0e\\f";"#), vec![Token::String("a\nb\tc\rd\0e\\f".to_string()), Token::EndLine]);
+        assert_eq!(tokenize_text(r#""\u{48}\u{e9}\u{1F600}";"#), vec![Token::String("H\u{e9}\u{1F600}".to_string()), Token::EndLine]);
+        assert_eq!(tokenize_text(r#""héllo";"#), vec![Token::String("héllo".to_string()), Token::EndLine]);
+    }
+    #[test]
+    fn number_formats() {
+        let numbers = ["0xFF", "1e-9", "2.5f32", "10000i64", "1.5E+3", "0b10", "0o17", "1e"];
+        let tokens = tokenize_text("0xFF 1e-9 2.5f32 10_000i64 1.5E+3 0b1_0 0o17 1e;");
+        assert_eq!(tokens[..numbers.len()], numbers.map(|number| Token::ConstantNumber(number.to_string())));
+        assert_eq!(tokenize_text("1-2;"), vec![Token::ConstantNumber("1".to_string()), Token::MathOp(MathOp::Subtract), Token::ConstantNumber("2".to_string()), Token::EndLine]);
+    }
+    #[test]
+    fn loop_labels() {
+        assert_eq!(tokenize_text("'outer: while true { break 'outer; continue; }"), vec![
+            Token::Label("outer".to_string()),
+            Token::Colon,
+            Token::WhileLoop,
+            Token::Boolean(true),
+            Token::OpenBlock,
+            Token::Break,
+            Token::Label("outer".to_string()),
+            Token::EndLine,
+            Token::Continue,
+            Token::EndLine,
+            Token::CloseBlock,
+        ]);
+        assert_eq!(tokenize(&SourceFile::new("test.st", "' a".to_string()), 0, 3).unwrap_err().code, 10);
+    }
+    #[test]
+    fn ranges() {
+        assert_eq!(tokenize_text("a[1..3];"), vec![
+            Token::Identifier("a".to_string()),
+            Token::OpenBracket,
+            Token::ConstantNumber("1".to_string()),
+            Token::DotDot,
+            Token::ConstantNumber("3".to_string()),
+            Token::CloseBracket,
+            Token::EndLine,
+        ]);
+        assert_eq!(tokenize_text("1.5..x"), vec![Token::ConstantNumber("1.5".to_string()), Token::DotDot, Token::Identifier("x".to_string())]);
+        assert_eq!(tokenize_text("for i in (0..=n).step(2)"), vec![
+            Token::ForLoop,
+            Token::Identifier("i".to_string()),
+            Token::In,
+            Token::OpenParen,
+            Token::ConstantNumber("0".to_string()),
+            Token::DotDotEq,
+            Token::Identifier("n".to_string()),
+            Token::CloseParen,
+            Token::Dot,
+            Token::Identifier("step".to_string()),
+            Token::OpenParen,
+            Token::ConstantNumber("2".to_string()),
+            Token::CloseParen,
+        ]);
+    }
+    #[test]
+    fn raw_strings() {
+        assert_eq!(tokenize_text(r#"r"C:\new\dir";"#), vec![Token::String(r"C:\new\dir".to_string()), Token::EndLine]);
+        assert_eq!(tokenize_text(r##"r#"say "hi""#;"##), vec![Token::String(r#"say "hi""#.to_string()), Token::EndLine]);
+        assert_eq!(tokenize_text("r + r;"), vec![Token::Identifier("r".to_string()), Token::MathOp(MathOp::Add), Token::Identifier("r".to_string()), Token::EndLine]);
+    }
+
+    //Builds a program of roughly the given size out of every kind of token.
+    fn generate_program(bytes: usize) -> String {
+        let lines = [
+            "let total: i64 = 1_000_000; // running total\n",
+            "let name: String = \"block // not a comment\";\n",
+            "while total >= 10 && !(total == 3) || false {\n",
+            "    total = total / i64(2) - 1 * (3 % 2);\n",
+            "}\n",
+            "/* a block\n   comment */ let values: Array<f64> = [1.5, 2.25];\n",
+            "fn add(a: i32, b: i32) -> i32 { return a + b; }\n",
+        ];
+        let mut text = String::with_capacity(bytes + 100);
+        while text.len() < bytes {
+            for line in lines {
+                text.push_str(line);
+            }
+        }
+        text
+    }
+
+    //Run with cargo test --release bench_tokenize -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_tokenize() {
+        for megabytes in [1, 10, 50] {
+            let text = generate_program(megabytes * 1024 * 1024);
+            let source = SourceFile::new("bench.st", text);
+            let start = std::time::Instant::now();
+            let tokens = tokenize(&source, 0, source.text.len()).unwrap();
+            let elapsed = start.elapsed().as_secs_f64();
+            let size = source.text.len() as f64 / (1024.0 * 1024.0);
+            println!("{:>5.1} MiB, {:>9} tokens in {:>7.3}s: {:>7.1} MiB/s", size, tokens.len(), elapsed, size / elapsed);
+        }
+    }
+}
@@ -0,0 +1,846 @@
+use std::collections::{HashMap, VecDeque};
+use crate::{builtins::{lookup, Builtin, Param, Returns}, dag::Dag, diagnostic::{Diagnostic, Span}, interpreter::{changed_place, collect_functions, invalid_key, missing_field, no_field, not_a_struct, outside_loop, repeated_field, unknown_label, Functions, Scopes, Structs, Type}, parse::{parse, BinaryOperator, Expression, Function, Iterable, LoopVariables, Statement, UnaryOperator}, token_block::import_variables};
+
+//The type of an expression as far as the checker can tell.
+//Unknown is used for empty arrays and maps and after an error so one mistake is not reported over and over.
+#[derive(PartialEq, Debug, Clone)]
+enum Ty {
+    Bool,
+    String,
+    I32,
+    I64,
+    F32,
+    F64,
+    Array(Box<Ty>),
+    Struct(String),
+    Map(Box<Ty>, Box<Ty>),
+    Unknown,
+}
+
+impl From<&Type> for Ty {
+    fn from(ty: &Type) -> Ty {
+        match ty {
+            Type::Bool => Ty::Bool,
+            Type::String => Ty::String,
+            Type::I32 => Ty::I32,
+            Type::I64 => Ty::I64,
+            Type::F32 => Ty::F32,
+            Type::F64 => Ty::F64,
+            Type::Array(inner) => Ty::Array(Box::new(Ty::from(inner.as_ref()))),
+            Type::Struct(name) => Ty::Struct(name.clone()),
+            Type::Map(key, value) => Ty::Map(Box::new(Ty::from(key.as_ref())), Box::new(Ty::from(value.as_ref()))),
+        }
+    }
+}
+
+impl std::fmt::Display for Ty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ty::Bool => write!(f, "Bool"),
+            Ty::String => write!(f, "String"),
+            Ty::I32 => write!(f, "i32"),
+            Ty::I64 => write!(f, "i64"),
+            Ty::F32 => write!(f, "f32"),
+            Ty::F64 => write!(f, "f64"),
+            Ty::Array(inner) => write!(f, "Array<{}>", inner),
+            Ty::Struct(name) => write!(f, "{}", name),
+            Ty::Map(key, value) => write!(f, "Map<{}, {}>", key, value),
+            Ty::Unknown => write!(f, "_"),
+        }
+    }
+}
+
+impl Ty {
+    //Checks a found type against a declared one, letting unknown parts match anything.
+    fn fits(&self, expected: &Ty) -> bool {
+        match (self, expected) {
+            (Ty::Unknown, _) | (_, Ty::Unknown) => true,
+            (Ty::Array(found), Ty::Array(expected)) => found.fits(expected),
+            (Ty::Map(found_key, found_value), Ty::Map(key, value)) => found_key.fits(key) && found_value.fits(value),
+            (found, expected) => found == expected,
+        }
+    }
+
+    //The declared type a value of this type can be stored as. Unknown parts become i32, as they do for empty arrays in the LLVM backend.
+    fn to_type(&self) -> Type {
+        match self {
+            Ty::Bool => Type::Bool,
+            Ty::String => Type::String,
+            Ty::I32 | Ty::Unknown => Type::I32,
+            Ty::I64 => Type::I64,
+            Ty::F32 => Type::F32,
+            Ty::F64 => Type::F64,
+            Ty::Array(inner) => Type::Array(Box::new(inner.to_type())),
+            Ty::Struct(name) => Type::Struct(name.clone()),
+            Ty::Map(key, value) => Type::Map(Box::new(key.to_type()), Box::new(value.to_type())),
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, Ty::I32 | Ty::I64 | Ty::F32 | Ty::F64 | Ty::Unknown)
+    }
+}
+
+pub type VariableTypes = HashMap<String, Type>;
+
+//Everything the checker knows while walking one block or function body.
+struct Checker<'a> {
+    functions: &'a Functions<'a>,
+    structs: &'a Structs,
+    //The function whose body is being checked, None at the top level of a block.
+    function: Option<&'a Function>,
+    variables: Scopes<Type>,
+    //The labels of the loops around the statement being checked, innermost last.
+    loops: Vec<Option<String>>,
+    errors: Vec<Diagnostic>,
+}
+
+//Parses and type checks every block, in dependency order, before any of them run.
+//Returns: the parsed statements of every block.
+//Errors: every parse and type error in the program. Blocks that import from a block with errors are not checked.
+pub fn check_program(dag: &Dag) -> Result<HashMap<String, VecDeque<Statement>>, Vec<Diagnostic>> {
+    let mut programs = HashMap::new();
+    //Only blocks without errors export their variables.
+    let mut exports: HashMap<String, VariableTypes> = HashMap::new();
+    let mut errors = check_structs(&dag.structs);
+    for id in &dag.order {
+        let block = &dag.blocks[id];
+        let in_block = |error: Diagnostic| error.or_span(&block.span).with_note(format!("raised in block {}", id));
+        let statements = match parse(&mut block.tokens.clone()) {
+            Ok(statements) => statements,
+            Err(error) => {
+                errors.push(in_block(error));
+                continue;
+            }
+        };
+        if block.requires.keys().any(|required_id| !exports.contains_key(required_id)) {
+            continue;
+        }
+        let imported = match import_variables(block, &exports) {
+            Ok(imported) => imported,
+            Err(error) => {
+                errors.push(in_block(error));
+                continue;
+            }
+        };
+        match check_block(&statements, imported, &dag.structs) {
+            Ok(variables) => {
+                exports.insert(id.clone(), variables);
+            }
+            Err(block_errors) => errors.extend(block_errors.into_iter().map(in_block)),
+        }
+        programs.insert(id.clone(), statements);
+    }
+    if errors.is_empty() {
+        Ok(programs)
+    } else {
+        Err(errors)
+    }
+}
+
+//Checks that the fields of every struct have types that exist.
+//Errors: one for each field whose type is an undeclared struct.
+pub fn check_structs(structs: &Structs) -> Vec<Diagnostic> {
+    let mut errors: Vec<Diagnostic> = structs.values()
+        .flat_map(|declared| declared.fields.iter().filter_map(|(_, ty)| unknown_type(ty, structs).map(|error| error.with_span(declared.span.clone()))))
+        .collect();
+    errors.sort_by_key(|error| error.span.as_ref().map(|span| span.start));
+    errors
+}
+
+//Type checks a block before it runs.
+//Args: statements: the parsed block, imported: the variables the block inherits from the blocks it requires, structs: the structs declared in the file.
+//Returns: the types of every variable the block defines, so blocks that require it can be checked.
+//Errors: every type error found in the block.
+pub fn check_block(statements: &VecDeque<Statement>, imported: VariableTypes, structs: &Structs) -> Result<VariableTypes, Vec<Diagnostic>> {
+    let functions = collect_functions(statements).map_err(|error| vec![error])?;
+    let mut variables = Scopes::new(imported);
+    variables.enter_top_level();
+    check_scopes(statements, variables, &functions, structs)
+}
+
+//Type checks statements that can call functions defined outside of them, such as earlier lines typed into the REPL.
+//Args: variables: the variables already defined, functions: every function the statements can call, structs: every struct they can build.
+//Returns: the types of every variable once the statements have run.
+//Errors: every type error found in the statements or the functions.
+pub fn check_statements(statements: &VecDeque<Statement>, variables: VariableTypes, functions: &Functions, structs: &Structs) -> Result<VariableTypes, Vec<Diagnostic>> {
+    check_scopes(statements, Scopes::new(variables), functions, structs)
+}
+
+//Returns: the types of the variables in the top level of variables once the statements have run.
+fn check_scopes(statements: &VecDeque<Statement>, variables: Scopes<Type>, functions: &Functions, structs: &Structs) -> Result<VariableTypes, Vec<Diagnostic>> {
+    let mut checker = Checker { functions, structs, function: None, variables, loops: Vec::new(), errors: Vec::new() };
+    for function in functions.values() {
+        checker.check_function(function);
+    }
+    checker.check_statements(statements);
+    if checker.errors.is_empty() {
+        Ok(checker.variables.into_top_level())
+    } else {
+        checker.errors.sort_by_key(|error| error.span.as_ref().map(|span| span.start));
+        Err(checker.errors)
+    }
+}
+
+//Works out the type of one expression, for tools such as the REPL that are not checking a whole block.
+//Returns: the name of the type, with _ for anything that cannot be known such as the elements of an empty array.
+pub fn expression_type(expression: &Expression, variables: VariableTypes, functions: &Functions, structs: &Structs) -> Result<String, Diagnostic> {
+    let checker = Checker { functions, structs, function: None, variables: Scopes::new(variables), loops: Vec::new(), errors: Vec::new() };
+    checker.check_expression(expression).map(|ty| ty.to_string())
+}
+
+impl<'a> Checker<'a> {
+    fn check_function(&mut self, function: &'a Function) {
+        let types = function.params.iter().map(|(_, ty)| ty).chain(&function.return_type);
+        self.errors.extend(types.filter_map(|ty| unknown_type(ty, self.structs)));
+        let variables = Scopes::new(function.params.iter().cloned().collect());
+        let mut body = Checker { functions: self.functions, structs: self.structs, function: Some(function), variables, loops: Vec::new(), errors: Vec::new() };
+        body.check_statements(&function.block);
+        self.errors.append(&mut body.errors);
+    }
+
+    fn check_loop_body(&mut self, block: &VecDeque<Statement>, label: &Option<String>) {
+        self.loops.push(label.clone());
+        self.check_scope(block);
+        self.loops.pop();
+    }
+
+    //Checks the body of an if or a loop, forgetting the variables it defines once it ends.
+    fn check_scope(&mut self, block: &VecDeque<Statement>) {
+        self.variables.enter();
+        self.check_statements(block);
+        self.variables.exit();
+    }
+
+    fn check_statements(&mut self, statements: &VecDeque<Statement>) {
+        for statement in statements {
+            if let Err(error) = self.check_statement(statement) {
+                self.errors.push(error.or_span(statement.span()));
+            }
+        }
+    }
+
+    fn check_statement(&mut self, statement: &Statement) -> Result<(), Diagnostic> {
+        match statement {
+            Statement::DefineVariable(name, expression, ty, _) => {
+                let result = unknown_type(ty, self.structs).map_or(Ok(()), Err)
+                    .and_then(|_| self.check_expression(expression))
+                    .and_then(|found| expect_type(&found, &Ty::from(ty), &format!("variable {}", name), expression));
+                //The variable is defined even if its value is wrong so later uses are still checked.
+                self.variables.define(name, ty.clone());
+                result
+            }
+            Statement::ModifyVariable(name, expression, _) => {
+                let Some(ty) = self.variables.get(name).map(Ty::from) else {
+                    return Err(Diagnostic::new(6, format!("Variable {} does not exist", name)));
+                };
+                match expression {
+                    Expression::Increment(_) | Expression::Decrement(_) if !ty.is_numeric() => {
+                        let operator = if matches!(expression, Expression::Increment(_)) { "++" } else { "--" };
+                        Err(Diagnostic::new(17, format!("Operator {} is not defined for {}", operator, ty)))
+                    }
+                    Expression::Increment(_) | Expression::Decrement(_) => Ok(()),
+                    _ => {
+                        let found = self.check_expression(expression)?;
+                        expect_type(&found, &ty, &format!("variable {}", name), expression)
+                    }
+                }
+            }
+            Statement::ModifyElement(target, expression, _) => {
+                let ty = self.check_expression(target)?;
+                let what = match target {
+                    Expression::Field(inner, field, _) => format!("field {} of {}", field, self.check_expression(inner)?),
+                    _ => format!("element of {}", target.place().map_or("", |(name, _)| name)),
+                };
+                let found = self.check_expression(expression)?;
+                expect_type(&found, &ty, &what, expression)
+            }
+            Statement::WhileLoop(condition, block, label, _) => {
+                self.check_condition(condition);
+                self.check_loop_body(block, label);
+                Ok(())
+            }
+            Statement::If(condition, block, elifs, else_block, _) => {
+                self.check_condition(condition);
+                self.check_scope(block);
+                self.check_statements(elifs);
+                if let Some(else_block) = else_block {
+                    self.check_scope(else_block);
+                }
+                Ok(())
+            }
+            Statement::Elif(condition, block, _) => {
+                self.check_condition(condition);
+                self.check_scope(block);
+                Ok(())
+            }
+            Statement::ForLoop(define_variable, condition, increment, block, label, _) => {
+                self.variables.enter();
+                self.check_statements(&VecDeque::from([*define_variable.clone()]));
+                self.check_condition(condition);
+                self.check_statements(&VecDeque::from([*increment.clone()]));
+                self.check_loop_body(block, label);
+                self.variables.exit();
+                Ok(())
+            }
+            Statement::ForEach(variables, iterable, block, label, _) => {
+                let items = self.item_types(iterable, variables);
+                //Like a definition, the variables exist even if the iterable is wrong so the body is still checked.
+                self.variables.enter();
+                for (i, variable) in variables.names().into_iter().enumerate() {
+                    let ty = items.as_ref().map_or(Type::I32, |items| items[i].to_type());
+                    self.variables.define(variable, ty);
+                }
+                self.check_loop_body(block, label);
+                self.variables.exit();
+                items.map(|_| ())
+            }
+            Statement::Break(target, _) | Statement::Continue(target, _) => {
+                let keyword = if matches!(statement, Statement::Break(..)) { "break" } else { "continue" };
+                match target {
+                    _ if self.loops.is_empty() => Err(outside_loop(keyword)),
+                    Some(label) if !self.loops.contains(target) => Err(unknown_label(label)),
+                    _ => Ok(()),
+                }
+            }
+            Statement::FunctionCall(name, args, span) => self.check_call(name, args, span).map(|_| ()),
+            //Function bodies are checked once, up front, by check_block.
+            Statement::DefineFunction(..) => Ok(()),
+            Statement::Return(expression, _) => {
+                let Some(function) = self.function else {
+                    return Err(Diagnostic::new(27, "return can only be used inside a function"));
+                };
+                let what = format!("function {}", function.name);
+                match (&function.return_type, expression) {
+                    (Some(ty), Some(expression)) => {
+                        let found = self.check_expression(expression)?;
+                        expect_type(&found, &Ty::from(ty), &what, expression)
+                    }
+                    (None, Some(expression)) => {
+                        let found = self.check_expression(expression)?;
+                        Err(mismatched_type(&what, "no return value", &found.to_string()).with_span(expression.span().clone()))
+                    }
+                    (Some(ty), None) => Err(mismatched_type(&what, &ty.to_string(), "no return value")),
+                    (None, None) => Ok(()),
+                }
+            }
+        }
+    }
+
+    //Records an error instead of returning it so the body of the loop or if is still checked.
+    fn check_condition(&mut self, condition: &Expression) {
+        match self.check_expression(condition) {
+            Ok(Ty::Bool | Ty::Unknown) => {}
+            Ok(found) => self.errors.push(Diagnostic::new(18, format!("Condition must be a Bool but found {}", found)).with_span(condition.span().clone())),
+            Err(error) => self.errors.push(error),
+        }
+    }
+
+    fn check_expression(&self, expression: &Expression) -> Result<Ty, Diagnostic> {
+        self.expression_type(expression).map_err(|error| error.or_span(expression.span()))
+    }
+
+    fn expression_type(&self, expression: &Expression) -> Result<Ty, Diagnostic> {
+        match expression {
+            Expression::String(..) => Ok(Ty::String),
+            Expression::Bool(..) => Ok(Ty::Bool),
+            Expression::I32(..) => Ok(Ty::I32),
+            Expression::I64(..) => Ok(Ty::I64),
+            Expression::F32(..) => Ok(Ty::F32),
+            Expression::F64(..) => Ok(Ty::F64),
+            Expression::Variable(name, _) => match self.variables.get(name) {
+                Some(ty) => Ok(Ty::from(ty)),
+                None => Err(Diagnostic::new(6, format!("Variable {} does not exist", name))),
+            },
+            Expression::Array(items, _) => {
+                let mut element = Ty::Unknown;
+                for item in items {
+                    let found = self.check_expression(item)?;
+                    if !found.fits(&element) {
+                        return Err(mismatched_type("array", &format!("elements of type {}", element), &found.to_string()).with_span(item.span().clone()));
+                    }
+                    if element == Ty::Unknown {
+                        element = found;
+                    }
+                }
+                Ok(Ty::Array(Box::new(element)))
+            }
+            Expression::Complete(complete, _) => {
+                let left = self.check_expression(&complete.left)?;
+                let right = self.check_expression(&complete.right)?;
+                binary_type(&complete.operator, &left, &right)
+            }
+            Expression::CompleteU(complete, _) => {
+                let child = self.check_expression(&complete.child)?;
+                match (&complete.operator, child) {
+                    (UnaryOperator::Parenthesis, child) => Ok(child),
+                    (UnaryOperator::Not, Ty::Bool | Ty::Unknown) => Ok(Ty::Bool),
+                    (UnaryOperator::Negate, child @ (Ty::I32 | Ty::I64 | Ty::F32 | Ty::F64 | Ty::Unknown)) => Ok(child),
+                    (operator, child) => Err(Diagnostic::new(17, format!("Operator {:?} is not defined for {}", operator, child))),
+                }
+            }
+            Expression::FunctionCall(name, args, span) => {
+                self.check_call(name, args, span)?
+                    .ok_or_else(|| Diagnostic::new(30, format!("Function {} does not return a value", name)))
+            }
+            Expression::Index(target, index, _) => {
+                let target = self.check_expression(target)?;
+                self.element_type(&target, index)
+            }
+            Expression::Field(target, field, _) => match self.check_expression(target)? {
+                Ty::Struct(name) => match self.structs.get(&name).and_then(|declared| declared.field(field)) {
+                    Some(ty) => Ok(Ty::from(ty)),
+                    None => Err(no_field(&name, field)),
+                },
+                Ty::Unknown => Ok(Ty::Unknown),
+                other => Err(not_a_struct(field, &other.to_string())),
+            },
+            Expression::Struct(name, fields, _) => self.struct_type(name, fields),
+            Expression::Map(entries, _) => {
+                let (mut key_type, mut value_type) = (Ty::Unknown, Ty::Unknown);
+                for (key, value) in entries {
+                    let found = self.check_expression(key)?;
+                    if !found.to_type().is_map_key() {
+                        return Err(invalid_key(&found.to_string()).with_span(key.span().clone()));
+                    }
+                    expect_type(&found, &key_type, "map", key)?;
+                    if key_type == Ty::Unknown {
+                        key_type = found;
+                    }
+                    let found = self.check_expression(value)?;
+                    expect_type(&found, &value_type, "map", value)?;
+                    if value_type == Ty::Unknown {
+                        value_type = found;
+                    }
+                }
+                Ok(Ty::Map(Box::new(key_type), Box::new(value_type)))
+            }
+            Expression::Slice(target, start, end, _) => {
+                let target = self.check_expression(target)?;
+                for bound in start.iter().chain(end) {
+                    self.check_index(bound)?;
+                }
+                match target {
+                    Ty::Array(_) | Ty::Unknown => Ok(target),
+                    other => Err(Diagnostic::new(55, format!("Cannot index into {}", other))),
+                }
+            }
+            Expression::Increment(_) | Expression::Decrement(_) => {
+                Err(Diagnostic::new(11, format!("Unexpected expression {:?}", expression)))
+            }
+        }
+    }
+
+    //Checks a struct literal against the declaration of its struct.
+    fn struct_type(&self, name: &str, fields: &[(String, Expression)]) -> Result<Ty, Diagnostic> {
+        let Some(declared) = self.structs.get(name) else {
+            return Err(Diagnostic::new(14, format!("Unknown type {}", name)));
+        };
+        for (i, (field, expression)) in fields.iter().enumerate() {
+            if fields[..i].iter().any(|(earlier, _)| earlier == field) {
+                return Err(repeated_field(name, field).with_span(expression.span().clone()));
+            }
+            let Some(ty) = declared.field(field) else {
+                return Err(no_field(name, field).with_span(expression.span().clone()));
+            };
+            let found = self.check_expression(expression)?;
+            expect_type(&found, &Ty::from(ty), &format!("field {} of {}", field, name), expression)?;
+        }
+        match declared.fields.iter().find(|(field, _)| !fields.iter().any(|(given, _)| given == field)) {
+            Some((field, _)) => Err(missing_field(name, field)),
+            None => Ok(Ty::Struct(name.to_string())),
+        }
+    }
+
+    //Returns: the types of the variables of a for-each loop over iterable, in the order they are named. Ranges are i64 if any bound or the step is.
+    fn item_types(&self, iterable: &Iterable, variables: &LoopVariables) -> Result<Vec<Ty>, Diagnostic> {
+        let range = match (iterable, variables) {
+            (Iterable::Array(expression), _) => {
+                return match (self.check_expression(expression)?, variables) {
+                    (Ty::Array(element), LoopVariables::Single(_)) => Ok(vec![*element]),
+                    (Ty::Map(key, _), LoopVariables::Single(_)) => Ok(vec![*key]),
+                    (Ty::Map(key, value), LoopVariables::Pair(..)) => Ok(vec![*key, *value]),
+                    (Ty::Unknown, _) => Ok(vec![Ty::Unknown; variables.names().len()]),
+                    (found, LoopVariables::Single(_)) => Err(mismatched_type("for loop", "an array, a map or a range", &found.to_string()).with_span(expression.span().clone())),
+                    (found, LoopVariables::Pair(..)) => Err(mismatched_type("for loop", "a map", &found.to_string()).with_span(expression.span().clone())),
+                };
+            }
+            (Iterable::Range(range), LoopVariables::Pair(..)) => return Err(mismatched_type("for loop", "a map", "a range").with_span(range.start.span().to(range.end.span()))),
+            (Iterable::Range(range), LoopVariables::Single(_)) => range,
+        };
+        let mut ty = Ty::I32;
+        for bound in [&range.start, &range.end].into_iter().chain(&range.step) {
+            match self.check_expression(bound)? {
+                Ty::I64 => ty = Ty::I64,
+                Ty::I32 | Ty::Unknown => {}
+                found => return Err(mismatched_type("range", "i32 or i64 bounds", &found.to_string()).with_span(bound.span().clone())),
+            }
+        }
+        Ok(vec![ty])
+    }
+
+    //Returns: the type of target[index], where index is an integer for an array and a key for a map.
+    fn element_type(&self, target: &Ty, index: &Expression) -> Result<Ty, Diagnostic> {
+        match target {
+            Ty::Map(key, value) => {
+                let found = self.check_expression(index)?;
+                expect_type(&found, key, "map key", index)?;
+                Ok(*value.clone())
+            }
+            Ty::Unknown => {
+                self.check_expression(index)?;
+                Ok(Ty::Unknown)
+            }
+            _ => {
+                self.check_index(index)?;
+                match target {
+                    Ty::Array(element) => Ok(*element.clone()),
+                    other => Err(Diagnostic::new(55, format!("Cannot index into {}", other))),
+                }
+            }
+        }
+    }
+
+    fn check_index(&self, index: &Expression) -> Result<(), Diagnostic> {
+        match self.check_expression(index)? {
+            Ty::I32 | Ty::I64 | Ty::Unknown => Ok(()),
+            found => Err(mismatched_type("array index", "i32 or i64", &found.to_string()).with_span(index.span().clone())),
+        }
+    }
+
+    //Checks a call to a builtin or to a user defined function.
+    //Returns: the type the call returns, None for functions that do not return a value.
+    fn check_call(&self, name: &str, args: &[Expression], span: &Span) -> Result<Option<Ty>, Diagnostic> {
+        if let Some(builtin) = lookup(name) {
+            return self.check_builtin(builtin, args);
+        }
+        let Some(function) = self.functions.get(name) else {
+            return Err(Diagnostic::new(5, format!("Function: {} does not exist", name)).with_span(span.clone()));
+        };
+        expect_arg_count(name, args, function.params.len())?;
+        for ((param, ty), arg) in function.params.iter().zip(args) {
+            let found = self.check_expression(arg)?;
+            expect_type(&found, &Ty::from(ty), &format!("parameter {} of {}", param, name), arg)?;
+        }
+        Ok(function.return_type.as_ref().map(Ty::from))
+    }
+
+    //Checks a call to a builtin against its parameters, and then its own checks.
+    //Returns: the type the builtin returns, which can depend on the type of the first argument.
+    fn check_builtin(&self, builtin: &Builtin, args: &[Expression]) -> Result<Option<Ty>, Diagnostic> {
+        builtin.expect_arg_count(args.len())?;
+        if builtin.changes_first {
+            changed_place(builtin.name, &args[0])?;
+        }
+        let mut found = Vec::new();
+        for arg in args {
+            found.push(self.check_expression(arg)?);
+        }
+        let first = found.first().cloned().unwrap_or(Ty::Unknown);
+        for (i, (param, ty)) in builtin.params.iter().zip(&found).enumerate() {
+            if let Err(expected) = fits_param(param, ty, &first) {
+                return Err(builtin.mismatch(i, &expected, &ty.to_string()).with_span(args[i].span().clone()));
+            }
+        }
+        if let Some(check) = builtin.check {
+            check(args)?;
+        }
+        Ok(match (&builtin.returns, first) {
+            (Returns::Nothing, _) => None,
+            (Returns::Is(ty), _) => Some(Ty::from(ty)),
+            (Returns::Element, Ty::Array(element)) => Some(*element),
+            (Returns::Keys, Ty::Map(key, _)) => Some(Ty::Array(key)),
+            (Returns::Keys, _) => Some(Ty::Array(Box::new(Ty::Unknown))),
+            (Returns::Value, Ty::Map(_, value)) => Some(*value),
+            (Returns::Element | Returns::Value, _) => Some(Ty::Unknown),
+        })
+    }
+}
+
+//Checks the type of an argument against a builtin parameter.
+//Args: first - the type of the first argument, which element and key parameters depend on.
+//Errors: with what the parameter expects, if found does not fit it.
+fn fits_param(param: &Param, found: &Ty, first: &Ty) -> Result<(), String> {
+    let expected = match (param, first) {
+        (Param::Is(ty), _) => Ty::from(ty),
+        (Param::Element, Ty::Array(element)) => *element.clone(),
+        (Param::Key, Ty::Map(key, _)) => *key.clone(),
+        (Param::Key, Ty::String) => Ty::String,
+        _ => {
+            let fits = match param {
+                Param::Number => found.is_numeric(),
+                Param::Scalar => !matches!(found, Ty::Array(_) | Ty::Struct(_) | Ty::Map(..)),
+                Param::Array => matches!(found, Ty::Array(_) | Ty::Unknown),
+                Param::Map => matches!(found, Ty::Map(..) | Ty::Unknown),
+                Param::Sized => matches!(found, Ty::Array(_) | Ty::Map(..) | Ty::String | Ty::Unknown),
+                Param::Searchable => matches!(found, Ty::Map(..) | Ty::String | Ty::Unknown),
+                Param::Is(_) | Param::Any | Param::Element | Param::Key => true,
+            };
+            return if fits { Ok(()) } else { Err(param.describe()) };
+        }
+    };
+    if found.fits(&expected) {
+        Ok(())
+    } else {
+        Err(expected.to_string())
+    }
+}
+
+fn binary_type(operator: &BinaryOperator, left: &Ty, right: &Ty) -> Result<Ty, Diagnostic> {
+    let arithmetic = matches!(operator, BinaryOperator::Add | BinaryOperator::Subtract | BinaryOperator::Multiply | BinaryOperator::Divide | BinaryOperator::Modulus);
+    let logical = matches!(operator, BinaryOperator::And | BinaryOperator::Or);
+    let comparison = !arithmetic && !logical;
+    match (left, right) {
+        (Ty::Unknown, _) | (_, Ty::Unknown) => Ok(if arithmetic { Ty::Unknown } else { Ty::Bool }),
+        (Ty::Bool, Ty::Bool) if logical => Ok(Ty::Bool),
+        (Ty::String, Ty::String) if matches!(operator, BinaryOperator::Add) => Ok(Ty::String),
+        (Ty::String, Ty::String) if comparison => Ok(Ty::Bool),
+        (Ty::I32, Ty::I32) | (Ty::I64, Ty::I64) | (Ty::F32, Ty::F32) | (Ty::F64, Ty::F64) if arithmetic => Ok(left.clone()),
+        (Ty::I32, Ty::I32) | (Ty::I64, Ty::I64) | (Ty::F32, Ty::F32) | (Ty::F64, Ty::F64) | (Ty::I64, Ty::I32) if comparison => Ok(Ty::Bool),
+        _ => Err(Diagnostic::new(17, format!("Operator {:?} is not defined for {} and {}", operator, left, right))),
+    }
+}
+
+//Returns: an error if ty is or contains a struct that is not declared.
+fn unknown_type(ty: &Type, structs: &Structs) -> Option<Diagnostic> {
+    match ty {
+        Type::Array(inner) => unknown_type(inner, structs),
+        Type::Map(_, value) => unknown_type(value, structs),
+        Type::Struct(name) if !structs.contains_key(name) => Some(Diagnostic::new(14, format!("Unknown type {}", name))),
+        _ => None,
+    }
+}
+
+fn expect_type(found: &Ty, expected: &Ty, what: &str, expression: &Expression) -> Result<(), Diagnostic> {
+    if found.fits(expected) {
+        Ok(())
+    } else {
+        Err(mismatched_type(what, &expected.to_string(), &found.to_string()).with_span(expression.span().clone()))
+    }
+}
+
+fn expect_arg_count(name: &str, args: &[Expression], count: usize) -> Result<(), Diagnostic> {
+    if args.len() != count {
+        return Err(Diagnostic::new(21, format!("Function {} expects {} argument(s) but found {}", name, count, args.len())));
+    }
+    Ok(())
+}
+
+fn mismatched_type(what: &str, expected: &str, found: &str) -> Diagnostic {
+    Diagnostic::new(28, format!("Mismatched type: {} expects {} but found {}", what, expected, found))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use crate::{builtins::builtin_structs, diagnostic::{Diagnostic, SourceFile}, interpreter::{Structs, Type}, parse::{parse, parse_struct}, tokenizer::tokenize, type_check::{check_block, check_structs, VariableTypes}};
+
+    fn check(text: &str, imported: VariableTypes) -> Result<VariableTypes, Vec<Diagnostic>> {
+        check_with_structs(text, imported, &builtin_structs())
+    }
+
+    fn check_with_structs(text: &str, imported: VariableTypes, structs: &Structs) -> Result<VariableTypes, Vec<Diagnostic>> {
+        let source = SourceFile::new("test.st", text.to_string());
+        let mut tokens = tokenize(&source, 0, text.len()).unwrap();
+        let statements = parse(&mut tokens).unwrap();
+        check_block(&statements, imported, structs)
+    }
+
+    //Args: declarations - struct declarations such as "struct P { x: i32 }".
+    fn structs(declarations: &[&str]) -> Structs {
+        let mut structs = Structs::new();
+        for declaration in declarations {
+            let source = SourceFile::new("test.st", declaration.to_string());
+            let declared = parse_struct(&mut tokenize(&source, 0, declaration.len()).unwrap()).unwrap();
+            structs.insert(declared.name.clone(), declared);
+        }
+        structs
+    }
+
+    fn codes(text: &str) -> Vec<u32> {
+        match check(text, HashMap::new()) {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors.iter().map(|error| error.code).collect(),
+        }
+    }
+
+    #[test]
+    fn valid_block() {
+        let variables = check("
+            let target: i64 = 600851475143;
+            while target % i64(2) == 0 {
+                target = target / i64(2);
+            }
+            let a: Array<Array<i32>> = [[1, 2], []];
+            let b: Bool = (false || true) && !false;
+            let r: i32 = rand_int(1, 6);
+            print(string(r));
+        ", HashMap::new()).unwrap();
+        assert_eq!(variables["target"], Type::I64);
+        assert_eq!(variables["b"], Type::Bool);
+    }
+
+    #[test]
+    fn declarations_and_assignments() {
+        assert_eq!(codes("let a: i32 = \"a\";"), vec![28]);
+        assert_eq!(codes("let a: i32 = 1;\na = true;"), vec![28]);
+        assert_eq!(codes("let a: String = \"a\";\na++;"), vec![17]);
+        assert_eq!(codes("b = 1;"), vec![6]);
+        assert_eq!(codes("let a: Array<i32> = [1, \"b\"];"), vec![28]);
+    }
+
+    #[test]
+    fn operators_and_conditions() {
+        assert_eq!(codes("let f: f32 = 2.5;\nlet a: i32 = 1 + f;"), vec![17]);
+        assert_eq!(codes("let a: i64 = 1;\nlet b: Bool = a < 2;"), Vec::<u32>::new());
+        assert_eq!(codes("let a: Bool = 1 && true;"), vec![17]);
+        assert_eq!(codes("if 1 {\n}\nwhile \"a\" {\n}"), vec![18, 18]);
+        assert_eq!(codes("for (let i: i32 = 0; i; i++) {\n}"), vec![18]);
+    }
+
+    #[test]
+    fn builtin_and_user_calls() {
+        assert_eq!(codes("let a: i32 = i32(\"a\");"), vec![8]);
+        assert_eq!(codes("let a: i32 = rand_int(1, \"6\");"), vec![28]);
+        assert_eq!(codes("let a: f32 = rand_float(1);"), vec![21]);
+        assert_eq!(codes("let a: i32 = missing();"), vec![5]);
+        assert_eq!(codes("fn f(a: i32) -> String {\n    return a;\n}\nlet b: i32 = f(true);"), vec![28, 28]);
+        assert_eq!(codes("fn f() {\n}\nlet b: i32 = f();"), vec![30]);
+        assert_eq!(codes("rand_int(1, 2);\nlet a: i32 = print(1);\nlet b: Bool = sleep(1) == 0;"), vec![30, 30]);
+        assert_eq!(codes("print(string({1: 2}));\nprint(f64([1]));\nlet a: i32 = len(1);"), vec![8, 8, 28]);
+        assert_eq!(codes("fn len(a: i32) {\n}"), vec![31]);
+    }
+
+    #[test]
+    fn arrays() {
+        assert_eq!(codes("let a: Array<Array<i32>> = [[1]];\nlet b: i32 = a[0][0] + len(a);\na[0][0] = b;\npush(a[0], 2);\nlet c: Array<i32> = a[0][..1];\nlet d: i32 = pop(a[0]);"), Vec::<u32>::new());
+        assert_eq!(codes("let a: Array<i32> = [1];\nlet b: String = a[0];"), vec![28]);
+        assert_eq!(codes("let a: Array<i32> = [1];\na[0] = \"b\";"), vec![28]);
+        assert_eq!(codes("let a: Array<i32> = [1];\nprint(a[\"0\"]);\nprint(a[1.5..]);"), vec![28, 28]);
+        assert_eq!(codes("let a: Array<i32> = [1];\npush(a, true);\nlet b: Bool = pop(a);"), vec![28, 28]);
+        assert_eq!(codes("let a: i32 = 1;\nprint(a[0]);\na[0] = 1;\nprint(len(a));"), vec![55, 55, 28]);
+        assert_eq!(codes("push([1], 2);\nlet a: i32 = pop([1]);\nlet c: Array<i32> = [];\nlet b: i32 = push(c, 1);"), vec![56, 56, 30]);
+        assert_eq!(codes("b[0] = 1;"), vec![6]);
+    }
+
+    #[test]
+    fn for_each_loops() {
+        assert_eq!(codes("let a: Array<String> = [\"a\"];\nfor s in a {\n    let t: String = s;\n}\nlet n: i64 = 5;\nfor i in 0..n {\n    let j: i64 = i;\n}\nfor i in (0..=3).step(1) {\n    let j: i32 = i;\n}"), Vec::<u32>::new());
+        assert_eq!(codes("for s in [\"a\"] {\n    let t: i32 = s;\n}"), vec![28]);
+        assert_eq!(codes("for i in 0..3 {\n}\nprint(i);"), vec![6]);
+        assert_eq!(codes("let i: String = \"a\";\nfor i in 0..3 {\n}\nlet s: String = i;"), Vec::<u32>::new());
+        assert_eq!(codes("for i in 1 {\n    break;\n}\nfor i in 0..\"3\" {\n}\nfor i in (0..3).step(1.5) {\n}"), vec![28, 28, 28]);
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let errors = check("let a: i32 = \"a\";\nlet b: Bool = 1;\nprint(c);", HashMap::new()).unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|error| error.span.as_ref().unwrap().line).collect();
+        assert_eq!(lines, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn break_and_continue_need_a_loop() {
+        assert_eq!(codes("break;"), vec![50]);
+        assert_eq!(codes("if true {\n    continue;\n}"), vec![50]);
+        assert_eq!(codes("while true {\n    fn_call();\n}\nfn fn_call() {\n    break;\n}"), vec![50]);
+        assert_eq!(codes("'a: while true {\n    while true {\n        break 'b;\n    }\n}"), vec![51]);
+        assert_eq!(codes("'a: while true {\n    for (let i: i32 = 0; i < 2; i++) {\n        if i == 1 {\n            continue 'a;\n        }\n        break;\n    }\n}"), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn block_scopes() {
+        let variables = check("let x: i32 = 1;\nif true {\n    let x: String = \"a\";\n    let s: String = x;\n    let y: i32 = 2;\n}\nlet z: i32 = x;", HashMap::new()).unwrap();
+        assert_eq!(variables.keys().count(), 2);
+        assert_eq!(codes("if true {\n    let y: i32 = 2;\n} else {\n    let y: String = \"b\";\n}\nlet z: i32 = y;"), vec![6]);
+        assert_eq!(codes("for (let i: i32 = 0; i < 3; i++) {\n}\ni = 1;"), vec![6]);
+        assert_eq!(codes("while false {\n    let w: i32 = 0;\n}\nprint(w);"), vec![6]);
+    }
+
+    #[test]
+    fn imported_variables() {
+        let imported = HashMap::from([("i".to_string(), Type::F32)]);
+        assert!(check("let j: f32 = i;", imported.clone()).is_ok());
+        assert_eq!(check("let j: i32 = i;", imported).unwrap_err()[0].code, 28);
+    }
+
+    #[test]
+    fn structs_are_checked() {
+        let declared = structs(&["struct Point { x: f64, y: f64 }", "struct Shape { name: String, corners: Array<Point> }"]);
+        let variables = check_with_structs("
+            let s: Shape = Shape { corners: [Point { x: 1.0, y: 2.0 }], name: \"dot\" };
+            s.corners[0].x = 3.0;
+            let x: f64 = s.corners[0].x + s.corners[0].y;
+            fn origin() -> Point {
+                return Point { x: 0.0, y: 0.0 };
+            }
+            let o: Point = origin();
+        ", HashMap::new(), &declared).unwrap();
+        assert_eq!(variables["s"], Type::Struct("Shape".to_string()));
+        assert_eq!(variables["x"], Type::F64);
+        let codes = |text: &str| match check_with_structs(text, HashMap::new(), &declared) {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors.iter().map(|error| error.code).collect(),
+        };
+        assert_eq!(codes("let p: Point = Point { x: 1.0 };"), vec![60]);
+        assert_eq!(codes("let p: Point = Point { x: 1.0, y: 2.0, z: 3.0 };"), vec![59]);
+        assert_eq!(codes("let p: Point = Point { x: 1.0, x: 1.0, y: 2.0 };"), vec![61]);
+        assert_eq!(codes("let p: Point = Point { x: 1, y: 2.0 };"), vec![28]);
+        assert_eq!(codes("let p: Point = Shape { name: \"a\", corners: [] };"), vec![28]);
+        assert_eq!(codes("let p: Nope = 1;\nlet q: Point = Nope { a: 1 };"), vec![14, 14]);
+        assert_eq!(codes("fn f(p: Nope) {\n}"), vec![14]);
+        assert_eq!(codes("let a: i32 = 1;\nprint(a.x);"), vec![62]);
+        assert_eq!(codes("let p: Point = Point { x: 1.0, y: 2.0 };\nprint(p.z);\np.x = true;\nprint(string(p));"), vec![59, 28, 8]);
+        assert_eq!(check_structs(&structs(&["struct Bad { inner: Array<Missing> }"]))[0].code, 14);
+    }
+
+    #[test]
+    fn maps_are_checked() {
+        let variables = check("
+            let m: Map<String, Array<i32>> = {\"a\": [1], \"b\": []};
+            let empty: Map<i64, Bool> = {};
+            m[\"c\"] = [2, 3];
+            let first: i32 = m[\"a\"][0];
+            let found: Bool = contains(m, \"a\");
+            let names: Array<String> = keys(m);
+            let gone: Array<i32> = remove(m, \"b\");
+            for (name, values) in m {
+                let total: i32 = len(values) + len(name);
+            }
+            for name in m {
+                let copy: String = name;
+            }
+            let size: i32 = len(m);
+        ", HashMap::new()).unwrap();
+        assert_eq!(variables["m"].to_string(), "Map<String, Array<i32>>");
+        assert_eq!(variables["names"], Type::Array(Box::new(Type::String)));
+        assert_eq!(codes("let m: Map<String, i32> = {\"a\": 1, 2: 2};"), vec![28]);
+        assert_eq!(codes("let m: Map<String, i32> = {\"a\": 1, \"b\": true};"), vec![28]);
+        assert_eq!(codes("let m: Map<String, i32> = {\"a\": \"b\"};"), vec![28]);
+        assert_eq!(codes("print({[1]: 1});"), vec![64]);
+        assert_eq!(codes("let m: Map<String, i32> = {};\nprint(m[1]);\nm[\"a\"] = 1.5;"), vec![28, 28]);
+        assert_eq!(codes("let m: Map<String, i32> = {};\nprint(contains(m, 1));\nprint(keys([1]));\nremove(keys(m), \"a\");"), vec![28, 28, 56]);
+        assert_eq!(codes("let m: Map<String, i32> = {};\nfor (k, v) in [1] {\n}\nfor (k, v) in 0..2 {\n}\nprint(string(m));"), vec![28, 28, 8]);
+        assert_eq!(codes("let m: Map<String, i32> = {};\nfor (k, v) in m {\n    let x: i32 = k;\n}"), vec![28]);
+    }
+
+    #[test]
+    fn strings_are_checked() {
+        let variables = check("
+            let name: String = \"a\" + \"b\";
+            let before: Bool = name < \"c\";
+            let parts: Array<String> = split(trim(name), \",\");
+            let line: String = format(\"{} of {}: {{}}\", len(parts), parts);
+            let parsed: ParsedF64 = parse_f64(substring(name, 0, 1));
+            let value: f64 = parsed.value;
+            let found: Bool = contains(name, \"a\") && starts_with(to_upper(name), \"A\");
+        ", HashMap::new()).unwrap();
+        assert_eq!(variables["parsed"], Type::Struct("ParsedF64".to_string()));
+        assert_eq!(codes("print(\"a\" * \"b\");\nprint(\"a\" + 1);"), vec![17, 17]);
+        assert_eq!(codes("print(substring(\"a\", 0, i64(1)));\nprint(join(\"a\", \"b\"));"), vec![28, 28]);
+        assert_eq!(codes("print(format(\"{}\"));\nprint(format(\"{} {}\", 1));\nprint(format(\"{\", 1));"), vec![66, 66, 67]);
+        assert_eq!(codes("print(format());\nprint(trim(\"a\", \"b\"));"), vec![21, 21]);
+        assert_eq!(codes("let n: i32 = parse_i32(\"1\");\nprint(parse_i32(\"1\").nope);"), vec![28, 59]);
+        assert_eq!(codes("fn split(s: String) {\n}"), vec![31]);
+    }
+}